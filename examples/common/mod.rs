@@ -0,0 +1,22 @@
+use fx_mq_building_blocks::models::Message;
+use serde::{Deserialize, Serialize};
+
+/// The message published by the producer example and handled by the worker example
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Greeting {
+    pub name: String,
+}
+
+impl Message for Greeting {
+    const NAME: &str = "Greeting";
+}
+
+pub async fn connect() -> anyhow::Result<sqlx::PgPool> {
+    let database_url = std::env::var("DATABASE_URL")
+        .map_err(|_| anyhow::anyhow!("DATABASE_URL environment variable not set"))?;
+
+    Ok(sqlx::postgres::PgPoolOptions::new()
+        .max_connections(5)
+        .connect(&database_url)
+        .await?)
+}
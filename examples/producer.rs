@@ -0,0 +1,44 @@
+//! Publishes greetings into a schema, migrating it first.
+//!
+//! `DATABASE_URL=... cargo run --example producer -- <schema> <name>...`
+
+mod common;
+
+use common::Greeting;
+use fx_mq_building_blocks::migrator::run_migrations;
+use fx_mq_building_blocks::models::RawMessage;
+use fx_mq_building_blocks::queries::Queries;
+use sqlx::PgPool;
+use tracing::info;
+
+pub async fn run(pool: &PgPool, schema: &str, names: &[String]) -> anyhow::Result<Vec<RawMessage>> {
+    run_migrations(pool, schema).await?;
+    let queries = Queries::new(schema)?;
+
+    let messages = names
+        .iter()
+        .map(|name| RawMessage::from_message(&Greeting { name: name.clone() }))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut tx = pool.begin().await?;
+    let published = queries.publish_many_messages(&mut tx, &messages).await?;
+    tx.commit().await?;
+
+    info!(count = published.len(), schema, "Published greetings");
+    Ok(published)
+}
+
+#[allow(dead_code)]
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt().init();
+
+    let mut args = std::env::args().skip(1);
+    let schema = args.next().unwrap_or_else(|| "examples".to_string());
+    let names: Vec<String> = args.collect();
+
+    let pool = common::connect().await?;
+    run(&pool, &schema, &names).await?;
+
+    Ok(())
+}
@@ -0,0 +1,180 @@
+//! Handles greetings until interrupted with ctrl-c, then finishes the greetings it already claimed.
+//!
+//! `DATABASE_URL=... cargo run --example worker -- <schema>`
+//!
+//! Greetings to "flaky" fail on their first attempt and are retried with exponential backoff, greetings with
+//! an empty name are reported dead.
+
+mod common;
+
+use chrono::Utc;
+use common::Greeting;
+use futures::FutureExt;
+use fx_mq_building_blocks::backoff::ExponentialBackoff;
+use fx_mq_building_blocks::host_identity::HostIdentity;
+use fx_mq_building_blocks::listener::{
+    HandlerNames, HandlerResult, UnhandledMessagePolicy, WorkQueue, WorkQueueConfig,
+};
+use fx_mq_building_blocks::models::{Message, RawMessage};
+use fx_mq_building_blocks::queries::{Queries, set_schema_for_transaction};
+use sqlx::PgPool;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+const HOLD_FOR: Duration = Duration::from_secs(30);
+
+fn greet(message: &RawMessage, backoff: &ExponentialBackoff) -> HandlerResult {
+    let greeting: Greeting = match serde_json::from_value(message.payload.clone()) {
+        Ok(greeting) => greeting,
+        Err(error) => {
+            return HandlerResult::Dead {
+                reason: error.to_string(),
+                category: Some("validation".to_string()),
+            };
+        }
+    };
+
+    match greeting.name.as_str() {
+        "" => HandlerResult::Dead {
+            reason: "Can't greet nobody".to_string(),
+            category: Some("validation".to_string()),
+        },
+        "flaky" if message.attempted == 0 => {
+            let now = Utc::now();
+            let after = (backoff.try_at(message.attempted + 1, now) - now)
+                .to_std()
+                .unwrap_or_default();
+            HandlerResult::Retry {
+                reason: "Flaky greeting failed".to_string(),
+                after,
+                category: None,
+            }
+        }
+        name => {
+            info!(name, "Hello!");
+            HandlerResult::Success
+        }
+    }
+}
+
+async fn handle(
+    pool: &PgPool,
+    queries: &Queries,
+    names: &HandlerNames,
+    backoff: &ExponentialBackoff,
+    message: RawMessage,
+) -> anyhow::Result<()> {
+    let host_id = HostIdentity::shared().id();
+    let now = Utc::now();
+
+    let mut tx = pool.begin().await?;
+    set_schema_for_transaction(&mut tx, queries.schema()).await?;
+
+    if names.contains(&message.name) {
+        greet(&message, backoff)
+            .report(&mut *tx, &message, now, host_id)
+            .await?;
+    } else {
+        UnhandledMessagePolicy::Dead
+            .apply(&mut *tx, &message, now, host_id)
+            .await?;
+    }
+
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn run(pool: PgPool, schema: &str, shutdown: CancellationToken) -> anyhow::Result<()> {
+    let queries = Arc::new(Queries::new(schema)?);
+    let host = HostIdentity::shared();
+
+    let mut tx = pool.begin().await?;
+    host.register(&mut tx, &queries, Utc::now()).await?;
+    tx.commit().await?;
+
+    let names = HandlerNames::new();
+    names.register(Greeting::NAME);
+
+    let claim = {
+        let (pool, queries, names) = (pool.clone(), queries.clone(), names.clone());
+        move || {
+            let (pool, queries, names) = (pool.clone(), queries.clone(), names.clone());
+            async move {
+                let mut tx = pool.begin().await?;
+                let now = Utc::now();
+                let claimed = match names
+                    .claim_next(&mut tx, &queries, now, host.id(), HOLD_FOR)
+                    .await?
+                {
+                    Some(message) => Some(message),
+                    None => {
+                        queries
+                            .get_next_retryable(&mut tx, now, host.id(), HOLD_FOR)
+                            .await?
+                    }
+                };
+                tx.commit().await?;
+                Ok(claimed)
+            }
+            .boxed()
+        }
+    };
+
+    let handler = {
+        let backoff = Arc::new(ExponentialBackoff::new(2, Duration::from_millis(100)));
+        let (pool, queries, names) = (pool.clone(), queries.clone(), names.clone());
+        move |message: RawMessage| {
+            let (pool, queries, names, backoff) = (
+                pool.clone(),
+                queries.clone(),
+                names.clone(),
+                backoff.clone(),
+            );
+            async move {
+                let id = message.id;
+                if let Err(error) = handle(&pool, &queries, &names, &backoff, message).await {
+                    warn!(%error, message_id = %id, "Could not report, the lease will expire");
+                }
+            }
+            .boxed()
+        }
+    };
+
+    let config = WorkQueueConfig {
+        capacity: 8,
+        workers: 4,
+        idle_interval: Duration::from_millis(100),
+        claim_timeout: Some(Duration::from_secs(5)),
+    };
+    let work_queue = WorkQueue::spawn(config, claim, handler);
+    info!(schema, host_id = %host.id(), "Worker started");
+
+    shutdown.cancelled().await;
+    info!("Shutting down, finishing claimed messages");
+    work_queue.shutdown().await;
+
+    Ok(())
+}
+
+#[allow(dead_code)]
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt().init();
+
+    let schema = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "examples".to_string());
+    let pool = common::connect().await?;
+
+    let shutdown = CancellationToken::new();
+    let on_ctrl_c = shutdown.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            on_ctrl_c.cancel();
+        }
+    });
+
+    run(pool, &schema, shutdown).await
+}
@@ -0,0 +1,7 @@
+DROP INDEX IF EXISTS idx_errors_message_id;
+DROP INDEX IF EXISTS idx_attempts_dead_dead_at;
+DROP INDEX IF EXISTS idx_attempts_succeeded_succeeded_at;
+DROP INDEX IF EXISTS idx_messages_attempted_published_at;
+DROP INDEX IF EXISTS idx_messages_unattempted_published_at;
+
+DROP TABLE IF EXISTS daily_aggregates;
@@ -0,0 +1,20 @@
+-- Per-day, per-name aggregates maintained by a compaction worker.
+-- Computing these on demand over the raw tables is too slow for large queues,
+-- so `compact_daily_aggregates` recomputes a single day and upserts the result.
+CREATE TABLE daily_aggregates (
+    day DATE NOT NULL,
+    name TEXT NOT NULL,
+    published BIGINT NOT NULL,
+    succeeded BIGINT NOT NULL,
+    dead BIGINT NOT NULL,
+    attempts BIGINT NOT NULL, -- total attempts of messages that succeeded or died on this day
+    compacted_at TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (day, name)
+);
+
+-- Range scans used by the compaction worker
+CREATE INDEX idx_messages_unattempted_published_at ON messages_unattempted (published_at);
+CREATE INDEX idx_messages_attempted_published_at ON messages_attempted (published_at);
+CREATE INDEX idx_attempts_succeeded_succeeded_at ON attempts_succeeded (succeeded_at);
+CREATE INDEX idx_attempts_dead_dead_at ON attempts_dead (dead_at);
+CREATE INDEX idx_errors_message_id ON errors (message_id);
@@ -0,0 +1,3 @@
+-- The pg_trgm extension is left installed as it may be used by other schemas
+DROP INDEX IF EXISTS idx_errors_reported_at;
+DROP INDEX IF EXISTS idx_errors_error_trgm;
@@ -0,0 +1,6 @@
+-- Trigram index for searching errors by text with ILIKE, used by search_errors.
+-- The extension is shared by all schemas in the database so it is always installed into public.
+CREATE EXTENSION IF NOT EXISTS pg_trgm WITH SCHEMA public;
+
+CREATE INDEX idx_errors_error_trgm ON errors USING GIN (error public.gin_trgm_ops);
+CREATE INDEX idx_errors_reported_at ON errors (reported_at);
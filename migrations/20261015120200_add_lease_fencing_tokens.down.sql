@@ -0,0 +1,3 @@
+ALTER TABLE leases DROP COLUMN IF EXISTS fencing_token;
+
+DROP SEQUENCE IF EXISTS lease_fencing_token_seq;
@@ -0,0 +1,7 @@
+-- Fencing tokens are handed out with every lease and increase monotonically per schema.
+-- Reports carrying a token that no longer matches the lease of the message are rejected,
+-- protecting against hosts whose expired lease was taken over by another host.
+CREATE SEQUENCE lease_fencing_token_seq;
+
+ALTER TABLE leases
+    ADD COLUMN fencing_token BIGINT NOT NULL DEFAULT nextval('lease_fencing_token_seq');
@@ -0,0 +1,3 @@
+DROP INDEX IF EXISTS idx_leases_acquired_by;
+
+DROP TABLE IF EXISTS hosts;
@@ -0,0 +1,9 @@
+-- Human-readable identities of the hosts acquiring leases, leases.acquired_by refers to hosts.id.
+-- Registration is optional, leases of unregistered hosts simply have no label.
+CREATE TABLE hosts (
+    id UUID PRIMARY KEY,
+    label TEXT NOT NULL,
+    registered_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX idx_leases_acquired_by ON leases (acquired_by);
@@ -0,0 +1 @@
+DROP INDEX IF EXISTS idx_leases_acquired_at;
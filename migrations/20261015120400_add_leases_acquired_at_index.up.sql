@@ -0,0 +1,2 @@
+-- Supports finding the most recent claim when detecting claim starvation
+CREATE INDEX idx_leases_acquired_at ON leases (acquired_at);
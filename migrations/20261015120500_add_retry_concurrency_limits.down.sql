@@ -0,0 +1,3 @@
+DROP FUNCTION IF EXISTS retry_slot_available(TEXT, TIMESTAMPTZ);
+
+DROP TABLE IF EXISTS retry_concurrency_limits;
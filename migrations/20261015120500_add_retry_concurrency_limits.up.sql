@@ -0,0 +1,40 @@
+-- Caps how many retries of a message name may be in progress at the same time, so that a mass failure
+-- doesn't turn into a synchronized mass retry. Names without a row are not capped.
+CREATE TABLE retry_concurrency_limits (
+    name TEXT PRIMARY KEY,
+    max_in_progress INTEGER NOT NULL CHECK (max_in_progress > 0)
+);
+
+-- Returns true if another retry of the name may be claimed.
+--
+-- Claims of a capped name are serialized by a transaction scoped advisory lock, which is held until the claim
+-- commits so that the next claimer counts its lease. A claimer that can't take the lock skips the name, much like
+-- SKIP LOCKED skips rows, which keeps concurrent claims from waiting on or deadlocking each other.
+CREATE FUNCTION retry_slot_available(p_name TEXT, p_now TIMESTAMPTZ) RETURNS BOOLEAN
+LANGUAGE plpgsql VOLATILE COST 1000 AS $$
+DECLARE
+    v_limit INTEGER;
+    v_in_progress BIGINT;
+BEGIN
+    SELECT max_in_progress INTO v_limit
+    FROM retry_concurrency_limits
+    WHERE name = p_name;
+
+    IF v_limit IS NULL THEN
+        RETURN TRUE;
+    END IF;
+
+    IF NOT pg_try_advisory_xact_lock(hashtext('fx_mq_retry_slot'), hashtext(current_schema() || '.' || p_name)) THEN
+        RETURN FALSE;
+    END IF;
+
+    SELECT COUNT(DISTINCT l.message_id) INTO v_in_progress
+    FROM leases l
+    JOIN messages_attempted ma ON ma.id = l.message_id
+    WHERE ma.name = p_name
+      AND l.expires_at > p_now
+      AND EXISTS (SELECT 1 FROM attempts_failed fa WHERE fa.message_id = l.message_id);
+
+    RETURN v_in_progress < v_limit;
+END
+$$;
@@ -0,0 +1,6 @@
+-- Payloads offloaded from messages that exceed a size threshold, messages refer to them by id
+CREATE TABLE message_blobs (
+    id UUID PRIMARY KEY,
+    data JSONB NOT NULL,
+    stored_at TIMESTAMPTZ NOT NULL
+);
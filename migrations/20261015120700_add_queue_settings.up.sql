@@ -0,0 +1,10 @@
+-- Retry and lease settings per message name, read by workers at runtime so that they can be changed without a
+-- redeploy. Names without a row use the defaults the workers are configured with.
+CREATE TABLE queue_settings (
+    name TEXT PRIMARY KEY,
+    backoff_base INTEGER NOT NULL CHECK (backoff_base > 0),
+    backoff_base_delay_ms BIGINT NOT NULL CHECK (backoff_base_delay_ms >= 0),
+    max_attempts INTEGER NOT NULL CHECK (max_attempts > 0),
+    hold_for_ms BIGINT NOT NULL CHECK (hold_for_ms > 0),
+    updated_at TIMESTAMPTZ NOT NULL
+);
@@ -0,0 +1,10 @@
+-- Reports rejected because the message was reclaimed by another lease while the reporting host was still
+-- handling it, i.e. windows in which a message was processed more than once.
+CREATE TABLE claim_conflicts (
+    id UUID PRIMARY KEY,
+    message_id UUID NOT NULL,
+    fencing_token BIGINT NOT NULL,
+    reported_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX idx_claim_conflicts_reported_at ON claim_conflicts (reported_at);
@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS idx_attempts_dead_unreviewed;
+ALTER TABLE attempts_dead DROP COLUMN IF EXISTS reviewed_at;
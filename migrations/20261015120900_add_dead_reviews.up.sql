@@ -0,0 +1,4 @@
+-- Dead messages are closed once reviewed, or once their review grace period has passed
+ALTER TABLE attempts_dead ADD COLUMN reviewed_at TIMESTAMPTZ;
+
+CREATE INDEX idx_attempts_dead_unreviewed ON attempts_dead (dead_at) WHERE reviewed_at IS NULL;
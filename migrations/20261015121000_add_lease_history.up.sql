@@ -0,0 +1,12 @@
+-- Leases taken over after they expired, i.e. the host holding them failed to report in time
+CREATE TABLE lease_history (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    message_id UUID NOT NULL,
+    host_id UUID NOT NULL,
+    acquired_at TIMESTAMPTZ NOT NULL,
+    expired_at TIMESTAMPTZ NOT NULL,
+    taken_over_at TIMESTAMPTZ NOT NULL,
+    taken_over_by UUID NOT NULL
+);
+
+CREATE INDEX idx_lease_history_taken_over_at ON lease_history (taken_over_at);
@@ -0,0 +1,4 @@
+DROP INDEX IF EXISTS idx_leases_expires_at;
+DROP INDEX IF EXISTS idx_attempts_failed_message_id;
+DROP INDEX IF EXISTS idx_attempts_failed_retry_earliest_at;
+DROP INDEX IF EXISTS idx_messages_unattempted_claim;
@@ -0,0 +1,11 @@
+-- Indexes for the predicates of the claim queries, see tests/claim_plans.rs
+
+-- Unattempted claims take the oldest message, ordered by (published_at, id)
+CREATE INDEX idx_messages_unattempted_claim ON messages_unattempted (published_at, id);
+
+-- Retryable claims filter on retry_earliest_at and look up the latest failed attempt of each message
+CREATE INDEX idx_attempts_failed_retry_earliest_at ON attempts_failed (retry_earliest_at);
+CREATE INDEX idx_attempts_failed_message_id ON attempts_failed (message_id, failed_at DESC);
+
+-- Missing claims look for expired leases
+CREATE INDEX idx_leases_expires_at ON leases (expires_at);
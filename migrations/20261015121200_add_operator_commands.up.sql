@@ -0,0 +1,9 @@
+-- Commands operators issue to running workers, e.g. to pause claiming across the fleet. Workers read the commands
+-- issued after the last one they applied whenever a notification arrives on the command channel of the schema
+CREATE TABLE operator_commands (
+    id BIGSERIAL PRIMARY KEY,
+    command JSONB NOT NULL,
+    -- The host the command is for, NULL for every host
+    target_host UUID,
+    issued_at TIMESTAMPTZ NOT NULL
+);
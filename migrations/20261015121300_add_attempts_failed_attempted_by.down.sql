@@ -0,0 +1 @@
+ALTER TABLE attempts_failed DROP COLUMN IF EXISTS attempted_by;
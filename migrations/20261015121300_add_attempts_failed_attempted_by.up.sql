@@ -0,0 +1,3 @@
+-- The host whose lease the failed attempt was made under, taken from the lease deleted by the report. NULL for
+-- attempts reported without a lease and attempts failed before this column was added.
+ALTER TABLE attempts_failed ADD COLUMN attempted_by UUID;
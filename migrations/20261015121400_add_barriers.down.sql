@@ -0,0 +1,7 @@
+DROP TRIGGER IF EXISTS release_barriers_on_dead ON attempts_dead;
+DROP TRIGGER IF EXISTS release_barriers_on_succeeded ON attempts_succeeded;
+
+DROP FUNCTION IF EXISTS release_barriers_on_terminal();
+DROP FUNCTION IF EXISTS release_barriers();
+
+DROP TABLE IF EXISTS barriers;
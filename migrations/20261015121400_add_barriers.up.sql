@@ -0,0 +1,66 @@
+-- Barrier messages held back until every message published before them has succeeded or is dead. Once released,
+-- a barrier is moved to messages_unattempted with its original publish time, where it is claimed like any message
+CREATE TABLE barriers (
+    id UUID PRIMARY KEY,
+    name TEXT NOT NULL,
+    hash INTEGER NOT NULL,
+    payload JSONB NOT NULL,
+    published_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX idx_barriers_published_at ON barriers (published_at, id);
+
+-- Releases the oldest barrier if every message published before it is terminal, returning the number released.
+--
+-- Releases are serialized by a transaction scoped advisory lock while barriers are pending. Without it, two
+-- transactions reporting the last messages before a barrier would each see the other's message as unfinished,
+-- and neither would release the barrier.
+CREATE FUNCTION release_barriers() RETURNS INTEGER
+LANGUAGE plpgsql VOLATILE AS $$
+DECLARE
+    v_released INTEGER;
+BEGIN
+    IF NOT EXISTS (SELECT 1 FROM barriers) THEN
+        RETURN 0;
+    END IF;
+
+    PERFORM pg_advisory_xact_lock(hashtext('fx_mq_barriers'), hashtext(current_schema()));
+
+    WITH released AS (
+        DELETE FROM barriers b
+        WHERE b.id = (SELECT id FROM barriers ORDER BY published_at ASC, id ASC LIMIT 1)
+          AND NOT EXISTS (
+              SELECT 1 FROM messages_unattempted mu WHERE mu.published_at < b.published_at
+          )
+          AND NOT EXISTS (
+              SELECT 1 FROM messages_attempted ma
+              WHERE ma.published_at < b.published_at
+                AND NOT EXISTS (SELECT 1 FROM attempts_succeeded s WHERE s.message_id = ma.id)
+                AND NOT EXISTS (SELECT 1 FROM attempts_dead d WHERE d.message_id = ma.id)
+          )
+        RETURNING *
+    )
+    INSERT INTO messages_unattempted (id, name, hash, payload, published_at)
+    SELECT id, name, hash, payload, published_at
+    FROM released;
+
+    GET DIAGNOSTICS v_released = ROW_COUNT;
+    RETURN v_released;
+END
+$$;
+
+CREATE FUNCTION release_barriers_on_terminal() RETURNS TRIGGER
+LANGUAGE plpgsql AS $$
+BEGIN
+    PERFORM release_barriers();
+    RETURN NULL;
+END
+$$;
+
+CREATE TRIGGER release_barriers_on_succeeded
+AFTER INSERT ON attempts_succeeded
+FOR EACH STATEMENT EXECUTE FUNCTION release_barriers_on_terminal();
+
+CREATE TRIGGER release_barriers_on_dead
+AFTER INSERT ON attempts_dead
+FOR EACH STATEMENT EXECUTE FUNCTION release_barriers_on_terminal();
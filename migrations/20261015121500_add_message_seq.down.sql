@@ -0,0 +1,46 @@
+-- Barriers wait for the messages published before them
+CREATE OR REPLACE FUNCTION release_barriers() RETURNS INTEGER
+LANGUAGE plpgsql VOLATILE AS $$
+DECLARE
+    v_released INTEGER;
+BEGIN
+    IF NOT EXISTS (SELECT 1 FROM barriers) THEN
+        RETURN 0;
+    END IF;
+
+    PERFORM pg_advisory_xact_lock(hashtext('fx_mq_barriers'), hashtext(current_schema()));
+
+    WITH released AS (
+        DELETE FROM barriers b
+        WHERE b.id = (SELECT id FROM barriers ORDER BY published_at ASC, id ASC LIMIT 1)
+          AND NOT EXISTS (
+              SELECT 1 FROM messages_unattempted mu WHERE mu.published_at < b.published_at
+          )
+          AND NOT EXISTS (
+              SELECT 1 FROM messages_attempted ma
+              WHERE ma.published_at < b.published_at
+                AND NOT EXISTS (SELECT 1 FROM attempts_succeeded s WHERE s.message_id = ma.id)
+                AND NOT EXISTS (SELECT 1 FROM attempts_dead d WHERE d.message_id = ma.id)
+          )
+        RETURNING *
+    )
+    INSERT INTO messages_unattempted (id, name, hash, payload, published_at)
+    SELECT id, name, hash, payload, published_at
+    FROM released;
+
+    GET DIAGNOSTICS v_released = ROW_COUNT;
+    RETURN v_released;
+END
+$$;
+
+DROP INDEX IF EXISTS idx_barriers_seq;
+CREATE INDEX IF NOT EXISTS idx_barriers_published_at ON barriers (published_at, id);
+
+DROP INDEX IF EXISTS idx_messages_unattempted_claim;
+CREATE INDEX IF NOT EXISTS idx_messages_unattempted_claim ON messages_unattempted (published_at, id);
+
+ALTER TABLE barriers DROP COLUMN IF EXISTS seq;
+ALTER TABLE messages_attempted DROP COLUMN IF EXISTS seq;
+ALTER TABLE messages_unattempted DROP COLUMN IF EXISTS seq;
+
+DROP SEQUENCE IF EXISTS messages_seq;
@@ -0,0 +1,73 @@
+-- A strictly monotonic sequence number assigned at publish, ordering messages globally. Ties of published_at are
+-- broken on id, which doesn't follow publish order for UUIDs generated by different hosts
+CREATE SEQUENCE messages_seq;
+
+ALTER TABLE messages_unattempted ADD COLUMN seq BIGINT;
+ALTER TABLE messages_attempted ADD COLUMN seq BIGINT;
+ALTER TABLE barriers ADD COLUMN seq BIGINT;
+
+-- Existing messages are numbered in the order claims took them until now
+CREATE TEMPORARY TABLE messages_seq_backfill AS
+SELECT id, row_number() OVER (ORDER BY published_at ASC, id ASC) seq
+FROM (
+    SELECT id, published_at FROM messages_unattempted
+    UNION ALL
+    SELECT id, published_at FROM messages_attempted
+    UNION ALL
+    SELECT id, published_at FROM barriers
+) m;
+
+UPDATE messages_unattempted m SET seq = b.seq FROM messages_seq_backfill b WHERE b.id = m.id;
+UPDATE messages_attempted m SET seq = b.seq FROM messages_seq_backfill b WHERE b.id = m.id;
+UPDATE barriers m SET seq = b.seq FROM messages_seq_backfill b WHERE b.id = m.id;
+
+SELECT setval('messages_seq', COALESCE((SELECT MAX(seq) FROM messages_seq_backfill), 0) + 1, false);
+DROP TABLE messages_seq_backfill;
+
+-- Claimed messages keep the number they were published with, the defaults number messages inserted directly
+ALTER TABLE messages_unattempted ALTER COLUMN seq SET DEFAULT nextval('messages_seq'), ALTER COLUMN seq SET NOT NULL;
+ALTER TABLE messages_attempted ALTER COLUMN seq SET DEFAULT nextval('messages_seq'), ALTER COLUMN seq SET NOT NULL;
+ALTER TABLE barriers ALTER COLUMN seq SET DEFAULT nextval('messages_seq'), ALTER COLUMN seq SET NOT NULL;
+ALTER SEQUENCE messages_seq OWNED BY messages_unattempted.seq;
+
+-- Unattempted claims take the message with the lowest number
+DROP INDEX IF EXISTS idx_messages_unattempted_claim;
+CREATE UNIQUE INDEX idx_messages_unattempted_claim ON messages_unattempted (seq);
+
+DROP INDEX IF EXISTS idx_barriers_published_at;
+CREATE UNIQUE INDEX idx_barriers_seq ON barriers (seq);
+
+-- Barriers wait for the messages numbered before them
+CREATE OR REPLACE FUNCTION release_barriers() RETURNS INTEGER
+LANGUAGE plpgsql VOLATILE AS $$
+DECLARE
+    v_released INTEGER;
+BEGIN
+    IF NOT EXISTS (SELECT 1 FROM barriers) THEN
+        RETURN 0;
+    END IF;
+
+    PERFORM pg_advisory_xact_lock(hashtext('fx_mq_barriers'), hashtext(current_schema()));
+
+    WITH released AS (
+        DELETE FROM barriers b
+        WHERE b.seq = (SELECT MIN(seq) FROM barriers)
+          AND NOT EXISTS (
+              SELECT 1 FROM messages_unattempted mu WHERE mu.seq < b.seq
+          )
+          AND NOT EXISTS (
+              SELECT 1 FROM messages_attempted ma
+              WHERE ma.seq < b.seq
+                AND NOT EXISTS (SELECT 1 FROM attempts_succeeded s WHERE s.message_id = ma.id)
+                AND NOT EXISTS (SELECT 1 FROM attempts_dead d WHERE d.message_id = ma.id)
+          )
+        RETURNING *
+    )
+    INSERT INTO messages_unattempted (id, name, hash, payload, published_at, seq)
+    SELECT id, name, hash, payload, published_at, seq
+    FROM released;
+
+    GET DIAGNOSTICS v_released = ROW_COUNT;
+    RETURN v_released;
+END
+$$;
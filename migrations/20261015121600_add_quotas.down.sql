@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS publish_counts;
+DROP TABLE IF EXISTS quotas;
@@ -0,0 +1,17 @@
+-- Publish quotas per message name, and for all names of the schema under the name '*', protecting the capacity of
+-- a shared database from a single runaway publisher. Names without a row are not limited.
+CREATE TABLE quotas (
+    name TEXT PRIMARY KEY,
+    max_pending BIGINT CHECK (max_pending >= 0),
+    max_daily_publishes BIGINT CHECK (max_daily_publishes >= 0),
+    updated_at TIMESTAMPTZ NOT NULL
+);
+
+-- Publishes per day of the names with a daily quota, counted on publish as counting the messages of a day would
+-- scan them on every publish
+CREATE TABLE publish_counts (
+    day DATE NOT NULL,
+    name TEXT NOT NULL,
+    published BIGINT NOT NULL,
+    PRIMARY KEY (day, name)
+);
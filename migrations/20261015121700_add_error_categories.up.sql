@@ -0,0 +1,2 @@
+-- Optional category of an error supplied by the handler, e.g. timeout or validation, for aggregating what fails
+ALTER TABLE errors ADD COLUMN category TEXT;
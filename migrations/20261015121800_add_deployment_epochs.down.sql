@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS active_epoch;
+ALTER TABLE hosts DROP COLUMN IF EXISTS epoch;
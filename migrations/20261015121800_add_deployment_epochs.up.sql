@@ -0,0 +1,11 @@
+-- The deployment epoch a host registered with, such as the version of a blue/green deployment, NULL if it
+-- registered without one
+ALTER TABLE hosts ADD COLUMN epoch BIGINT;
+
+-- The deployment epoch allowed to claim, at most one row. Claims of hosts restricted to another epoch are refused,
+-- so that a cutover drains the hosts of the old epoch rather than racing both epochs for the same messages.
+CREATE TABLE active_epoch (
+    singleton BOOLEAN PRIMARY KEY DEFAULT TRUE CHECK (singleton),
+    epoch BIGINT NOT NULL,
+    activated_at TIMESTAMPTZ NOT NULL
+);
@@ -0,0 +1,3 @@
+DROP INDEX IF EXISTS idx_claim_conflicts_message_id;
+DROP INDEX IF EXISTS idx_lease_history_message_id;
+DROP INDEX IF EXISTS idx_messages_attempted_seq;
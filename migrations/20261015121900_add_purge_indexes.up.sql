@@ -0,0 +1,5 @@
+-- Purges walk finished messages in publish order and delete their history by message, which would otherwise scan
+-- these tables on every batch
+CREATE UNIQUE INDEX idx_messages_attempted_seq ON messages_attempted (seq);
+CREATE INDEX idx_lease_history_message_id ON lease_history (message_id);
+CREATE INDEX idx_claim_conflicts_message_id ON claim_conflicts (message_id);
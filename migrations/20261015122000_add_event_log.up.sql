@@ -0,0 +1,12 @@
+-- Succeeded messages appended per aggregate in the order they succeeded, for consumers to rebuild read models from.
+-- Holds the message and its resolved payload only, so that the log outlives purges of the messages it came from.
+CREATE TABLE event_log (
+    aggregate_id TEXT NOT NULL,
+    sequence BIGINT NOT NULL CHECK (sequence > 0),
+    message_id UUID NOT NULL UNIQUE,
+    name TEXT NOT NULL,
+    payload JSONB NOT NULL,
+    published_at TIMESTAMPTZ NOT NULL,
+    appended_at TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (aggregate_id, sequence)
+);
@@ -0,0 +1,6 @@
+-- Marks the schema as the warm standby of a queue mirrored from another region, at most one row. Queries with
+-- standby fencing don't claim while the row exists, promoting the standby deletes it.
+CREATE TABLE standby (
+    singleton BOOLEAN PRIMARY KEY DEFAULT TRUE CHECK (singleton),
+    since TIMESTAMPTZ NOT NULL
+);
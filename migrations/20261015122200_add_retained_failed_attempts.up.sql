@@ -0,0 +1,12 @@
+-- The most recent failed attempts of finished messages, kept for postmortems when reports are configured to retain
+-- them. Kept apart from attempts_failed, whose rows make a message retryable.
+CREATE TABLE attempts_failed_retained (
+    id UUID PRIMARY KEY,
+    message_id UUID NOT NULL REFERENCES messages_attempted(id),
+    failed_at TIMESTAMPTZ NOT NULL,
+    attempted INTEGER NOT NULL,
+    retry_earliest_at TIMESTAMPTZ NOT NULL,
+    attempted_by UUID
+);
+
+CREATE INDEX idx_attempts_failed_retained_message_id ON attempts_failed_retained (message_id, failed_at DESC);
@@ -0,0 +1,2 @@
+ALTER TABLE attempts_succeeded DROP COLUMN IF EXISTS attempted;
+ALTER TABLE attempts_dead DROP COLUMN IF EXISTS attempted;
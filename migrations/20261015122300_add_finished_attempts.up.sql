@@ -0,0 +1,11 @@
+-- The number of attempts of a finished message, including the last. Reports delete the failed attempts of messages
+-- they finish, and errors may be capped, so neither can be counted afterwards.
+ALTER TABLE attempts_succeeded ADD COLUMN attempted INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE attempts_dead ADD COLUMN attempted INTEGER NOT NULL DEFAULT 1;
+
+-- Messages finished until now are counted from their errors, as before
+UPDATE attempts_succeeded s
+SET attempted = (SELECT COUNT(*) FROM errors e WHERE e.message_id = s.message_id) + 1;
+
+UPDATE attempts_dead d
+SET attempted = GREATEST((SELECT COUNT(*) FROM errors e WHERE e.message_id = d.message_id), 1);
@@ -0,0 +1,2 @@
+DROP FUNCTION IF EXISTS claim_unattempted(UUID, TIMESTAMPTZ, UUID, TIMESTAMPTZ);
+DROP FUNCTION IF EXISTS claim_attempted(UUID, INTEGER, TIMESTAMPTZ, UUID, TIMESTAMPTZ);
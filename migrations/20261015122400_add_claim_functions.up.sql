@@ -0,0 +1,86 @@
+-- The shared steps of claiming a message, so that claim queries only differ in how they select the messages they
+-- claim. The caller must have locked the message, e.g. with FOR UPDATE SKIP LOCKED, and calls the function for each
+-- selected message, e.g. through LATERAL.
+--
+-- Expired leases of the message, such as one requested while it was pending, are deleted so that they don't remain
+-- next to the new lease once it expires.
+
+-- Moves an unattempted message to messages_attempted and leases it, returning the claimed message
+CREATE FUNCTION claim_unattempted(
+    p_id UUID,
+    p_now TIMESTAMPTZ,
+    p_host_id UUID,
+    p_expires_at TIMESTAMPTZ
+) RETURNS TABLE (
+    id UUID,
+    name TEXT,
+    hash INTEGER,
+    payload JSONB,
+    attempted INTEGER,
+    fencing_token BIGINT,
+    seq BIGINT,
+    max_attempts INTEGER
+)
+LANGUAGE plpgsql VOLATILE AS $$
+#variable_conflict use_column
+BEGIN
+    WITH moved AS (
+        DELETE FROM messages_unattempted mu
+        WHERE mu.id = p_id
+        RETURNING mu.id, mu.name, mu.hash, mu.payload, mu.published_at, mu.seq
+    )
+    INSERT INTO messages_attempted (id, name, hash, payload, published_at, seq)
+    SELECT id, name, hash, payload, published_at, seq
+    FROM moved;
+
+    IF NOT FOUND THEN
+        RETURN;
+    END IF;
+
+    RETURN QUERY SELECT * FROM claim_attempted(p_id, 0, p_now, p_host_id, p_expires_at);
+END
+$$;
+
+-- Leases an attempted message, returning the claimed message with the given attempt count
+CREATE FUNCTION claim_attempted(
+    p_id UUID,
+    p_attempted INTEGER,
+    p_now TIMESTAMPTZ,
+    p_host_id UUID,
+    p_expires_at TIMESTAMPTZ
+) RETURNS TABLE (
+    id UUID,
+    name TEXT,
+    hash INTEGER,
+    payload JSONB,
+    attempted INTEGER,
+    fencing_token BIGINT,
+    seq BIGINT,
+    max_attempts INTEGER
+)
+LANGUAGE plpgsql VOLATILE AS $$
+#variable_conflict use_column
+DECLARE
+    v_fencing_token BIGINT;
+BEGIN
+    DELETE FROM leases l
+    WHERE l.message_id = p_id AND l.expires_at <= p_now;
+
+    INSERT INTO leases (message_id, acquired_at, acquired_by, expires_at)
+    VALUES (p_id, p_now, p_host_id, p_expires_at)
+    RETURNING leases.fencing_token INTO v_fencing_token;
+
+    RETURN QUERY
+    SELECT
+        ma.id,
+        ma.name,
+        ma.hash,
+        ma.payload,
+        p_attempted,
+        v_fencing_token,
+        ma.seq,
+        (SELECT qs.max_attempts FROM queue_settings qs WHERE qs.name = ma.name)
+    FROM messages_attempted ma
+    WHERE ma.id = p_id;
+END
+$$;
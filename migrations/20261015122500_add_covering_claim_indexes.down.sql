@@ -0,0 +1,8 @@
+DROP INDEX IF EXISTS idx_leases_expires_at;
+CREATE INDEX idx_leases_expires_at ON leases (expires_at);
+
+DROP INDEX IF EXISTS idx_lease_history_message_id;
+CREATE INDEX idx_lease_history_message_id ON lease_history (message_id);
+
+DROP INDEX IF EXISTS idx_attempts_failed_message_id;
+CREATE INDEX idx_attempts_failed_message_id ON attempts_failed (message_id, failed_at DESC);
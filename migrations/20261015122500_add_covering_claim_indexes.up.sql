@@ -0,0 +1,14 @@
+-- Covers the lookups claim queries make for each candidate message, so that they are answered from the index alone.
+-- The candidates themselves are locked FOR UPDATE, which reads the table regardless of the columns an index holds.
+
+-- Missing claims count the failed attempts of a message from its latest failure
+DROP INDEX IF EXISTS idx_attempts_failed_message_id;
+CREATE INDEX idx_attempts_failed_message_id ON attempts_failed (message_id, failed_at DESC) INCLUDE (attempted);
+
+-- Missing claims count the leases of a message lost since its latest failure
+DROP INDEX IF EXISTS idx_lease_history_message_id;
+CREATE INDEX idx_lease_history_message_id ON lease_history (message_id, taken_over_at);
+
+-- Retryable claims of names with a retry concurrency limit count the messages of unexpired leases
+DROP INDEX IF EXISTS idx_leases_expires_at;
+CREATE INDEX idx_leases_expires_at ON leases (expires_at) INCLUDE (message_id);
@@ -0,0 +1,226 @@
+use crate::migrator::PgIdentifierParsingError;
+use crate::models::RawMessage;
+use crate::queries::Queries;
+use chrono::Utc;
+use futures::future::BoxFuture;
+use serde_json::{Value, json};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Key of the reference that replaces an offloaded payload
+pub const BLOB_REFERENCE_KEY: &str = "$fx_mq_blob";
+
+pub type BlobStoreError = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum AttachmentError {
+    #[error("SerializationError: {0}")]
+    Serialization(#[from] serde_json::Error),
+    #[error("BlobStoreError: {0}")]
+    Store(BlobStoreError),
+    #[error("Blob {0} referenced by a message does not exist")]
+    MissingBlob(Uuid),
+}
+
+/// Storage of offloaded payloads, such as the `message_blobs` table or object storage
+pub trait BlobStore: Send + Sync {
+    fn put<'a>(&'a self, id: Uuid, data: &'a Value) -> BoxFuture<'a, Result<(), BlobStoreError>>;
+
+    fn get(&self, id: Uuid) -> BoxFuture<'_, Result<Option<Value>, BlobStoreError>>;
+
+    /// Deletes a blob, succeeding if there is no such blob
+    fn delete(&self, id: Uuid) -> BoxFuture<'_, Result<(), BlobStoreError>>;
+}
+
+/// Stores offloaded payloads in the `message_blobs` table of a schema
+#[derive(Debug)]
+pub struct PgBlobStore {
+    pool: PgPool,
+    queries: Queries,
+}
+
+impl PgBlobStore {
+    pub fn new(pool: PgPool, schema: &str) -> Result<Self, PgIdentifierParsingError> {
+        Ok(Self {
+            pool,
+            queries: Queries::new(schema)?,
+        })
+    }
+}
+
+impl BlobStore for PgBlobStore {
+    fn put<'a>(&'a self, id: Uuid, data: &'a Value) -> BoxFuture<'a, Result<(), BlobStoreError>> {
+        Box::pin(async move {
+            let mut tx = self.pool.begin().await?;
+            self.queries.put_blob(&mut tx, id, data, Utc::now()).await?;
+            tx.commit().await?;
+            Ok(())
+        })
+    }
+
+    fn get(&self, id: Uuid) -> BoxFuture<'_, Result<Option<Value>, BlobStoreError>> {
+        Box::pin(async move {
+            let mut tx = self.pool.begin().await?;
+            let data = self.queries.get_blob(&mut tx, id).await?;
+            tx.commit().await?;
+            Ok(data)
+        })
+    }
+
+    fn delete(&self, id: Uuid) -> BoxFuture<'_, Result<(), BlobStoreError>> {
+        Box::pin(async move {
+            let mut tx = self.pool.begin().await?;
+            self.queries.delete_blob(&mut tx, id).await?;
+            tx.commit().await?;
+            Ok(())
+        })
+    }
+}
+
+/// Offloads large payloads to a [`BlobStore`] before publishing and rehydrates them after claiming.
+///
+/// Payloads that serialize to more than `threshold` bytes are replaced by a reference, `{"$fx_mq_blob": "<id>"}`.
+/// Blobs are stored before the message is published, so a publish that is rolled back leaves an unreferenced blob.
+///
+/// Claims return the reference, handlers must rehydrate claimed messages before decoding them. The typed claims of
+/// a [`Queue`](crate::queue::Queue) do so when given the store, see
+/// [`Queue::with_blob_store`](crate::queue::Queue::with_blob_store). Blobs of succeeded messages are deleted with
+/// [`release`](Self::release), the blobs of other messages are kept for retries, reviews and replays until purged.
+pub struct Attachments<S: BlobStore> {
+    store: S,
+    threshold: usize,
+}
+
+impl<S: BlobStore> Attachments<S> {
+    pub fn new(store: S, threshold: usize) -> Self {
+        Self { store, threshold }
+    }
+
+    /// Replaces the payload with a reference to a stored blob if it exceeds the threshold
+    pub async fn offload(&self, mut message: RawMessage) -> Result<RawMessage, AttachmentError> {
+        if serde_json::to_vec(&message.payload)?.len() <= self.threshold {
+            return Ok(message);
+        }
+
+        let id = Uuid::now_v7();
+        self.store
+            .put(id, &message.payload)
+            .await
+            .map_err(AttachmentError::Store)?;
+        message.payload = json!({ BLOB_REFERENCE_KEY: id });
+
+        Ok(message)
+    }
+
+    /// Replaces a reference to a stored blob with the blob, messages with inline payloads are returned as is
+    pub async fn rehydrate(&self, mut message: RawMessage) -> Result<RawMessage, AttachmentError> {
+        if let Some(payload) = rehydrate_payload(&self.store, &message.payload).await? {
+            message.payload = payload;
+        }
+
+        Ok(message)
+    }
+
+    /// Deletes the blob referenced by `message`, once its success was committed. Messages with inline payloads are
+    /// left as is.
+    pub async fn release(&self, message: &RawMessage) -> Result<(), AttachmentError> {
+        release(&self.store, message).await
+    }
+}
+
+/// Rehydrates `payload` from `store` if it is a blob reference
+pub(crate) async fn rehydrate_payload(
+    store: &(impl BlobStore + ?Sized),
+    payload: &Value,
+) -> Result<Option<Value>, AttachmentError> {
+    let Some(id) = blob_reference(payload) else {
+        return Ok(None);
+    };
+
+    let data = store
+        .get(id)
+        .await
+        .map_err(AttachmentError::Store)?
+        .ok_or(AttachmentError::MissingBlob(id))?;
+
+    Ok(Some(data))
+}
+
+/// Deletes the blob referenced by `message` from `store`, if any
+pub(crate) async fn release(
+    store: &(impl BlobStore + ?Sized),
+    message: &RawMessage,
+) -> Result<(), AttachmentError> {
+    if let Some(id) = blob_reference(&message.payload) {
+        store.delete(id).await.map_err(AttachmentError::Store)?;
+    }
+
+    Ok(())
+}
+
+/// Returns the id of the blob if the payload is a blob reference
+pub fn blob_reference(payload: &Value) -> Option<Uuid> {
+    let object = payload.as_object()?;
+    if object.len() != 1 {
+        return None;
+    }
+
+    object.get(BLOB_REFERENCE_KEY)?.as_str()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queries::{get_next_unattempted, publish_message};
+    use crate::testing_tools::TestMessage;
+    use std::time::Duration;
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_offloads_and_rehydrates_large_payloads(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let attachments = Attachments::new(PgBlobStore::new(pool.clone(), "public")?, 16);
+        let raw = TestMessage::new("a large message body".to_string(), 1).to_raw()?;
+
+        let offloaded = attachments.offload(raw.clone()).await?;
+        assert!(blob_reference(&offloaded.payload).is_some());
+
+        publish_message(&pool, &offloaded).await?;
+        let claimed =
+            get_next_unattempted(&pool, Utc::now(), Uuid::now_v7(), Duration::from_mins(1))
+                .await?
+                .unwrap();
+
+        let rehydrated = attachments.rehydrate(claimed).await?;
+        assert_eq!(rehydrated.payload, raw.payload);
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_keeps_small_payloads_inline(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let attachments = Attachments::new(PgBlobStore::new(pool, "public")?, 1024);
+        let raw = TestMessage::default().to_raw()?;
+
+        let offloaded = attachments.offload(raw.clone()).await?;
+        assert_eq!(offloaded.payload, raw.payload);
+
+        let rehydrated = attachments.rehydrate(offloaded).await?;
+        assert_eq!(rehydrated.payload, raw.payload);
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_errors_on_missing_blobs(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let attachments = Attachments::new(PgBlobStore::new(pool, "public")?, 1024);
+        let id = Uuid::now_v7();
+        let raw = RawMessage {
+            payload: json!({ BLOB_REFERENCE_KEY: id }),
+            ..TestMessage::default().to_raw()?
+        };
+
+        let result = attachments.rehydrate(raw).await;
+        assert!(matches!(result, Err(AttachmentError::MissingBlob(missing)) if missing == id));
+
+        Ok(())
+    }
+}
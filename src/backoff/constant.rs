@@ -3,11 +3,25 @@ use std::time::Duration;
 #[derive(Debug)]
 pub struct ConstantBackoff {
     base_delay: Duration,
+    give_up_after: Option<Duration>,
 }
 
 impl ConstantBackoff {
     pub fn new(base_delay: Duration) -> Self {
-        Self { base_delay }
+        Self {
+            base_delay,
+            give_up_after: None,
+        }
+    }
+
+    /// Stops retrying once a retry would be later than `window` after the message was published
+    pub fn with_give_up_after(mut self, window: Duration) -> Self {
+        self.give_up_after = Some(window);
+        self
+    }
+
+    pub fn give_up_after(&self) -> Option<Duration> {
+        self.give_up_after
     }
 
     pub fn try_at(
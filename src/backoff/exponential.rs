@@ -5,11 +5,34 @@ use std::time::Duration;
 pub struct ExponentialBackoff {
     base: u32,
     base_delay: Duration,
+    max_delay: Option<Duration>,
+    give_up_after: Option<Duration>,
 }
 
 impl ExponentialBackoff {
     pub fn new(base: u32, base_delay: Duration) -> Self {
-        Self { base, base_delay }
+        Self {
+            base,
+            base_delay,
+            max_delay: None,
+            give_up_after: None,
+        }
+    }
+
+    /// Clamps delays to at most `max_delay`, without a cap the delays of late attempts grow to days or weeks
+    pub fn with_max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = Some(max_delay);
+        self
+    }
+
+    /// Stops retrying once a retry would be later than `window` after the message was published
+    pub fn with_give_up_after(mut self, window: Duration) -> Self {
+        self.give_up_after = Some(window);
+        self
+    }
+
+    pub fn give_up_after(&self) -> Option<Duration> {
+        self.give_up_after
     }
 
     pub fn try_at(&self, attempted: i32, attempted_at: DateTime<Utc>) -> DateTime<Utc> {
@@ -17,7 +40,12 @@ impl ExponentialBackoff {
             attempted_at // No delay for zero attempts
         } else {
             let attempted = attempted as u32;
-            attempted_at + self.base_delay * self.base.pow(attempted - 1)
+            let delay = self
+                .base
+                .checked_pow(attempted - 1)
+                .and_then(|factor| self.base_delay.checked_mul(factor))
+                .unwrap_or(Duration::MAX);
+            super::delayed(attempted_at, delay, self.max_delay)
         }
     }
 }
@@ -73,4 +101,28 @@ mod tests {
         // Zero attempts should return the same timestamp (no delay)
         assert_eq!(actual, attempted_at);
     }
+
+    #[test]
+    fn it_clamps_delays_to_the_max_delay() {
+        let attempted_at = DateTime::parse_from_rfc3339("2025-01-01T12:00:00-00:00")
+            .expect("Expected to parse the timestsamp")
+            .to_utc();
+
+        let backoff = ExponentialBackoff::new(2, Duration::from_mins(1))
+            .with_max_delay(Duration::from_hours(1));
+
+        assert_eq!(
+            backoff.try_at(3, attempted_at),
+            attempted_at + Duration::from_mins(4)
+        );
+        assert_eq!(
+            backoff.try_at(20, attempted_at),
+            attempted_at + Duration::from_hours(1)
+        );
+        // Attempts whose delay would overflow are clamped too
+        assert_eq!(
+            backoff.try_at(100, attempted_at),
+            attempted_at + Duration::from_hours(1)
+        );
+    }
 }
@@ -3,18 +3,45 @@ use std::time::Duration;
 #[derive(Debug)]
 pub struct LinearBackoff {
     base_delay: Duration,
+    max_delay: Option<Duration>,
+    give_up_after: Option<Duration>,
 }
 
 impl LinearBackoff {
     pub fn new(base_delay: Duration) -> Self {
-        Self { base_delay }
+        Self {
+            base_delay,
+            max_delay: None,
+            give_up_after: None,
+        }
     }
+
+    /// Clamps delays to at most `max_delay`
+    pub fn with_max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = Some(max_delay);
+        self
+    }
+
+    /// Stops retrying once a retry would be later than `window` after the message was published
+    pub fn with_give_up_after(mut self, window: Duration) -> Self {
+        self.give_up_after = Some(window);
+        self
+    }
+
+    pub fn give_up_after(&self) -> Option<Duration> {
+        self.give_up_after
+    }
+
     pub fn try_at(
         &self,
         attempted: u32,
         attempted_at: chrono::DateTime<chrono::Utc>,
     ) -> chrono::DateTime<chrono::Utc> {
-        attempted_at + self.base_delay * attempted
+        let delay = self
+            .base_delay
+            .checked_mul(attempted)
+            .unwrap_or(Duration::MAX);
+        super::delayed(attempted_at, delay, self.max_delay)
     }
 }
 
@@ -56,4 +83,20 @@ mod tests {
         assert_eq!(actual_3, expected_3);
         assert_eq!(actual_4, expected_4);
     }
+
+    #[test]
+    fn it_clamps_delays_to_the_max_delay() {
+        let attempted_at = chrono::Utc::now();
+        let backoff =
+            LinearBackoff::new(Duration::from_mins(1)).with_max_delay(Duration::from_mins(3));
+
+        assert_eq!(
+            backoff.try_at(2, attempted_at),
+            attempted_at + Duration::from_mins(2)
+        );
+        assert_eq!(
+            backoff.try_at(10, attempted_at),
+            attempted_at + Duration::from_mins(3)
+        );
+    }
 }
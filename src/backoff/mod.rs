@@ -2,6 +2,149 @@ mod constant;
 mod exponential;
 mod linear;
 
+use chrono::{DateTime, Utc};
+use std::time::Duration;
+
 pub use constant::ConstantBackoff;
 pub use exponential::ExponentialBackoff;
 pub use linear::LinearBackoff;
+
+/// Common interface of the backoff strategies, for APIs that accept any of them
+pub trait Backoff {
+    /// Returns the earliest time at which a message that has been attempted `attempted` times,
+    /// last at `attempted_at`, may be attempted again.
+    fn try_at(&self, attempted: i32, attempted_at: DateTime<Utc>) -> DateTime<Utc>;
+
+    /// The window after publishing within which a message is retried, None to retry without a time limit
+    fn give_up_after(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Returns the time of the next attempt of a message published at `published_at`, or None if the attempt would
+    /// fall outside the [`give_up_after`](Self::give_up_after) window and the message should be given up on.
+    fn retry_at(
+        &self,
+        attempted: i32,
+        attempted_at: DateTime<Utc>,
+        published_at: DateTime<Utc>,
+    ) -> Option<DateTime<Utc>> {
+        let try_at = self.try_at(attempted, attempted_at);
+        match self.give_up_after() {
+            Some(window) if try_at > published_at + window => None,
+            _ => Some(try_at),
+        }
+    }
+}
+
+/// Simulates the retries `policy` schedules for a message first attempted at `first_attempt_at` that fails every
+/// attempt, returning the time of each retry until it has been attempted `attempts` times in total.
+///
+/// Each retry is assumed to run as soon as it is due. The schedule ends early if a retry would fall outside the
+/// [`give_up_after`](Backoff::give_up_after) window, taking the first attempt as the publish time.
+pub fn simulate_retry_schedule<B: Backoff + ?Sized>(
+    policy: &B,
+    attempts: i32,
+    first_attempt_at: DateTime<Utc>,
+) -> Vec<DateTime<Utc>> {
+    if attempts < 1 {
+        return Vec::new();
+    }
+    retries_after(policy, 1, first_attempt_at, first_attempt_at, attempts)
+}
+
+/// Returns the retries that follow the failed attempt number `attempted`, made at `attempted_at`, up to
+/// `max_attempts` attempts in total, see [`simulate_retry_schedule`]
+pub(crate) fn retries_after<B: Backoff + ?Sized>(
+    policy: &B,
+    attempted: i32,
+    attempted_at: DateTime<Utc>,
+    published_at: DateTime<Utc>,
+    max_attempts: i32,
+) -> Vec<DateTime<Utc>> {
+    let mut schedule = Vec::new();
+    let mut attempt_at = attempted_at;
+    for attempted in attempted..max_attempts {
+        match policy.retry_at(attempted, attempt_at, published_at) {
+            Some(at) => {
+                schedule.push(at);
+                attempt_at = at;
+            }
+            None => break,
+        }
+    }
+    schedule
+}
+
+/// Returns `attempted_at` delayed by `delay` clamped to `max_delay`, saturating rather than overflowing
+fn delayed(
+    attempted_at: DateTime<Utc>,
+    delay: Duration,
+    max_delay: Option<Duration>,
+) -> DateTime<Utc> {
+    let delay = max_delay.map_or(delay, |max_delay| delay.min(max_delay));
+    chrono::TimeDelta::from_std(delay)
+        .ok()
+        .and_then(|delay| attempted_at.checked_add_signed(delay))
+        .unwrap_or(DateTime::<Utc>::MAX_UTC)
+}
+
+impl Backoff for ConstantBackoff {
+    fn try_at(&self, attempted: i32, attempted_at: DateTime<Utc>) -> DateTime<Utc> {
+        ConstantBackoff::try_at(self, attempted, attempted_at)
+    }
+
+    fn give_up_after(&self) -> Option<Duration> {
+        ConstantBackoff::give_up_after(self)
+    }
+}
+
+impl Backoff for ExponentialBackoff {
+    fn try_at(&self, attempted: i32, attempted_at: DateTime<Utc>) -> DateTime<Utc> {
+        ExponentialBackoff::try_at(self, attempted, attempted_at)
+    }
+
+    fn give_up_after(&self) -> Option<Duration> {
+        ExponentialBackoff::give_up_after(self)
+    }
+}
+
+impl Backoff for LinearBackoff {
+    fn try_at(&self, attempted: i32, attempted_at: DateTime<Utc>) -> DateTime<Utc> {
+        LinearBackoff::try_at(self, attempted.max(0) as u32, attempted_at)
+    }
+
+    fn give_up_after(&self) -> Option<Duration> {
+        LinearBackoff::give_up_after(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_simulates_retry_schedules() {
+        let first_attempt_at = DateTime::parse_from_rfc3339("2025-01-01T12:00:00-00:00")
+            .expect("Expected to parse the timestamp")
+            .to_utc();
+        let minutes = |m| first_attempt_at + chrono::TimeDelta::minutes(m);
+        let backoff = ExponentialBackoff::new(2, Duration::from_mins(1));
+
+        assert_eq!(
+            simulate_retry_schedule(&backoff, 4, first_attempt_at),
+            [minutes(1), minutes(3), minutes(7)]
+        );
+        assert_eq!(
+            simulate_retry_schedule(&backoff, 1, first_attempt_at),
+            Vec::<DateTime<Utc>>::new()
+        );
+        assert!(simulate_retry_schedule(&backoff, 0, first_attempt_at).is_empty());
+
+        // Retries past the give up window are not scheduled
+        let backoff = backoff.with_give_up_after(Duration::from_mins(5));
+        assert_eq!(
+            simulate_retry_schedule(&backoff, 4, first_attempt_at),
+            [minutes(1), minutes(3)]
+        );
+    }
+}
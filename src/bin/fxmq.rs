@@ -4,11 +4,17 @@ use tracing::info;
 
 #[derive(Parser, Debug)]
 #[command(name = "fxmq")]
-#[command(about = "Migrate fx-mq-building-blocks database schema")]
+#[command(about = "Migrate or diagnose fx-mq-building-blocks database schema")]
 struct Args {
     /// Schema name to create and migrate
     #[arg(long)]
     schema_name: String,
+    /// Table in which applied migrations are recorded, defaults to the sqlx migrations table
+    #[arg(long)]
+    migrations_table: Option<String>,
+    /// Diagnose the schema instead of migrating it, exiting with an error if a check fails
+    #[arg(long)]
+    doctor: bool,
 }
 
 #[tokio::main]
@@ -28,8 +34,35 @@ async fn main() -> anyhow::Result<()> {
         .connect(&database_url)
         .await?;
 
+    if args.doctor {
+        info!("Diagnosing schema: {}", args.schema_name);
+        let table = args
+            .migrations_table
+            .as_deref()
+            .unwrap_or(fx_mq_building_blocks::migrator::DEFAULT_MIGRATIONS_TABLE);
+        let report =
+            fx_mq_building_blocks::diagnostics::run_with_table(&pool, &args.schema_name, table)
+                .await?;
+        print!("{report}");
+
+        if !report.is_healthy() {
+            anyhow::bail!("Diagnostics failed");
+        }
+        return Ok(());
+    }
+
     info!("Running migrations for schema: {}", args.schema_name);
-    fx_mq_building_blocks::migrator::run_migrations(&pool, &args.schema_name).await?;
+    match &args.migrations_table {
+        Some(table) => {
+            fx_mq_building_blocks::migrator::run_migrations_with_table(
+                &pool,
+                &args.schema_name,
+                table,
+            )
+            .await?
+        }
+        None => fx_mq_building_blocks::migrator::run_migrations(&pool, &args.schema_name).await?,
+    }
 
     info!("Migrations completed successfully");
 
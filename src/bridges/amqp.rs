@@ -0,0 +1,212 @@
+use crate::backoff::ExponentialBackoff;
+use crate::migrator::PgIdentifierParsingError;
+use crate::models::RawMessage;
+use crate::queries::{Queries, ReportError};
+use chrono::Utc;
+use lapin::options::BasicPublishOptions;
+use lapin::{BasicProperties, Channel};
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::time::Duration;
+use uuid::Uuid;
+
+// AMQP delivery mode of messages that survive broker restarts
+const PERSISTENT: u8 = 2;
+
+#[derive(Debug, thiserror::Error)]
+pub enum AmqpBridgeError {
+    #[error("AmqpError: {0}")]
+    Amqp(#[from] lapin::Error),
+    #[error("DatabaseError: {0}")]
+    Database(#[from] sqlx::Error),
+    #[error("ReportError: {0}")]
+    Report(#[from] ReportError),
+    #[error("SerializationError: {0}")]
+    Serialization(#[from] serde_json::Error),
+    #[error("The broker did not confirm message {0}")]
+    NotConfirmed(Uuid),
+}
+
+/// Where messages of a name are published to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AmqpRoute {
+    pub exchange: String,
+    pub routing_key: String,
+}
+
+/// Claims messages of the routed names and republishes them to AMQP exchanges, such as RabbitMQ.
+///
+/// Each message is claimed and the claim committed before it is published, so no transaction is held open while
+/// waiting for the broker. Once the broker confirmed the publish, the message is reported as succeeded in a second
+/// transaction. If the broker rejects the publish, or doesn't confirm it within the lease, the failed attempt is
+/// reported with a backoff, so that a message the broker keeps rejecting isn't republished in a tight loop.
+/// Unattempted, retryable and missing messages are claimed, in that order.
+///
+/// Messages are delivered at least once: a failure to report after the broker confirmed the publish leads to the
+/// message being published again once its lease expires.
+///
+/// The channel must be in confirm mode, see `Channel::confirm_select`.
+pub struct AmqpEgressBridge {
+    channel: Channel,
+    pool: PgPool,
+    queries: Queries,
+    routes: HashMap<String, AmqpRoute>,
+    host_id: Uuid,
+    hold_for: Duration,
+    backoff: ExponentialBackoff,
+}
+
+impl AmqpEgressBridge {
+    pub fn new(
+        channel: Channel,
+        pool: PgPool,
+        schema: &str,
+        host_id: Uuid,
+        hold_for: Duration,
+    ) -> Result<Self, PgIdentifierParsingError> {
+        Ok(Self {
+            channel,
+            pool,
+            queries: Queries::new(schema)?,
+            routes: HashMap::new(),
+            host_id,
+            hold_for,
+            backoff: ExponentialBackoff::new(2, Duration::from_secs(1))
+                .with_max_delay(Duration::from_mins(5)),
+        })
+    }
+
+    /// Routes messages named `name` to `exchange` with `routing_key`
+    pub fn with_route(mut self, name: &str, exchange: &str, routing_key: &str) -> Self {
+        self.routes.insert(
+            name.to_string(),
+            AmqpRoute {
+                exchange: exchange.to_string(),
+                routing_key: routing_key.to_string(),
+            },
+        );
+        self
+    }
+
+    /// Sets the backoff of messages the broker rejected or didn't confirm
+    pub fn with_backoff(mut self, backoff: ExponentialBackoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Claims and forwards a single message, returning its id or None if no routed message was claimable
+    pub async fn forward_one(&self) -> Result<Option<Uuid>, AmqpBridgeError> {
+        let Some(message) = self.claim().await? else {
+            return Ok(None);
+        };
+
+        // Only routed names are claimed
+        let route = &self.routes[&message.name];
+        let (payload, properties) = to_delivery(&message)?;
+
+        let publish = async {
+            let confirmation = self
+                .channel
+                .basic_publish(
+                    &route.exchange,
+                    &route.routing_key,
+                    BasicPublishOptions::default(),
+                    &payload,
+                    properties,
+                )
+                .await?
+                .await?;
+            Ok::<_, lapin::Error>(confirmation.is_ack())
+        };
+        // Past the lease the message may be claimed and published by another bridge
+        let failure = match tokio::time::timeout(self.hold_for, publish).await {
+            Ok(Ok(true)) => None,
+            Ok(Ok(false)) | Err(_) => Some(AmqpBridgeError::NotConfirmed(message.id)),
+            Ok(Err(error)) => Some(AmqpBridgeError::Amqp(error)),
+        };
+
+        let now = Utc::now();
+        let mut tx = self.pool.begin().await?;
+        if let Some(failure) = failure {
+            let retry_at = self.backoff.try_at(message.attempted + 1, now);
+            self.queries
+                .report_retryable(&mut tx, &message, now, retry_at, &failure.to_string())
+                .await?;
+            tx.commit().await?;
+            return Err(failure);
+        }
+
+        self.queries.report_success(&mut tx, &message, now).await?;
+        tx.commit().await?;
+
+        Ok(Some(message.id))
+    }
+
+    /// Claims the next unattempted, retryable or missing message of a routed name, and commits the claim
+    async fn claim(&self) -> Result<Option<RawMessage>, sqlx::Error> {
+        let names: Vec<String> = self.routes.keys().cloned().collect();
+        let now = Utc::now();
+
+        let mut tx = self.pool.begin().await?;
+        let mut message = self
+            .queries
+            .get_next_unattempted_named(&mut tx, now, self.host_id, self.hold_for, &names)
+            .await?;
+        if message.is_none() {
+            message = self
+                .queries
+                .get_next_retryable_named(&mut tx, now, self.host_id, self.hold_for, &names)
+                .await?;
+        }
+        if message.is_none() {
+            message = self
+                .queries
+                .get_next_missing_named(&mut tx, now, self.host_id, self.hold_for, &names)
+                .await?;
+        }
+        tx.commit().await?;
+
+        Ok(message)
+    }
+}
+
+/// Serializes the payload of a message and sets its id and name as the AMQP message id and type
+pub fn to_delivery(message: &RawMessage) -> Result<(Vec<u8>, BasicProperties), serde_json::Error> {
+    let payload = serde_json::to_vec(&message.payload)?;
+
+    let properties = BasicProperties::default()
+        .with_message_id(message.id.to_string().into())
+        .with_type(message.name.as_str().into())
+        .with_content_type("application/json".into())
+        .with_delivery_mode(PERSISTENT);
+
+    Ok((payload, properties))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Message;
+    use crate::testing_tools::TestMessage;
+
+    #[test]
+    fn it_maps_messages_to_deliveries() -> anyhow::Result<()> {
+        let message = TestMessage::default().to_raw()?;
+        let (payload, properties) = to_delivery(&message)?;
+
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&payload)?,
+            message.payload
+        );
+        assert_eq!(
+            properties.message_id().as_ref().map(|id| id.as_str()),
+            Some(message.id.to_string().as_str())
+        );
+        assert_eq!(
+            properties.kind().as_ref().map(|kind| kind.as_str()),
+            Some(TestMessage::NAME)
+        );
+
+        Ok(())
+    }
+}
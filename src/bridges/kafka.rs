@@ -0,0 +1,168 @@
+use crate::migrator::PgIdentifierParsingError;
+use crate::models::RawMessage;
+use crate::queries::{PublishError, Queries};
+use const_fnv1a_hash::fnv1a_hash_str_32;
+use rdkafka::Message;
+use rdkafka::consumer::{CommitMode, Consumer, StreamConsumer};
+use rdkafka::error::KafkaError;
+use rdkafka::message::Headers;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Header overriding the message name, which defaults to the topic
+pub const NAME_HEADER: &str = "fx-mq-name";
+
+#[derive(Debug, thiserror::Error)]
+pub enum KafkaBridgeError {
+    #[error("KafkaError: {0}")]
+    Kafka(#[from] KafkaError),
+    #[error("DatabaseError: {0}")]
+    Database(#[from] sqlx::Error),
+    #[error("PublishError: {0}")]
+    Publish(#[from] PublishError),
+}
+
+/// Maps a Kafka record to a message: the name is taken from the [`NAME_HEADER`] header or else the topic,
+/// and the payload must be JSON.
+///
+/// Returns None for records without a payload or with a payload that is not JSON.
+pub fn to_raw_message(record: &impl Message) -> Option<RawMessage> {
+    let name = record
+        .headers()
+        .and_then(|headers| {
+            headers
+                .iter()
+                .find(|header| header.key == NAME_HEADER)
+                .and_then(|header| header.value)
+                .and_then(|value| std::str::from_utf8(value).ok())
+                .map(str::to_string)
+        })
+        .unwrap_or_else(|| record.topic().to_string());
+
+    let payload = serde_json::from_slice(record.payload()?).ok()?;
+
+    Some(RawMessage {
+        id: Uuid::now_v7(),
+        hash: fnv1a_hash_str_32(&name) as i32,
+        name,
+        payload,
+        attempted: 0,
+        fencing_token: None,
+        seq: None,
+        max_attempts: None,
+    })
+}
+
+/// Consumes Kafka topics and publishes their records into the queue of a schema.
+///
+/// The offset of a record is committed only after the message has been published and its transaction committed,
+/// so records are delivered at least once: a crash in between publishes the record again after a restart.
+/// The consumer should be created with `enable.auto.commit=false`.
+pub struct KafkaIngestBridge {
+    consumer: StreamConsumer,
+    pool: PgPool,
+    queries: Queries,
+}
+
+impl KafkaIngestBridge {
+    pub fn new(
+        consumer: StreamConsumer,
+        pool: PgPool,
+        schema: &str,
+    ) -> Result<Self, PgIdentifierParsingError> {
+        Ok(Self {
+            consumer,
+            pool,
+            queries: Queries::new(schema)?,
+        })
+    }
+
+    /// Receives, publishes and commits a single record, mapping it with `map`.
+    ///
+    /// Records that `map` returns None for are skipped, their offsets are committed without publishing.
+    pub async fn ingest_one<F>(&self, map: &F) -> Result<Option<RawMessage>, KafkaBridgeError>
+    where
+        F: Fn(&rdkafka::message::BorrowedMessage<'_>) -> Option<RawMessage>,
+    {
+        let record = self.consumer.recv().await?;
+
+        let published = match map(&record) {
+            Some(message) => {
+                let mut tx = self.pool.begin().await?;
+                let published = self.queries.publish_message(&mut tx, message).await?;
+                tx.commit().await?;
+                Some(published)
+            }
+            None => {
+                tracing::warn!(
+                    topic = record.topic(),
+                    partition = record.partition(),
+                    offset = record.offset(),
+                    "Skipping record that could not be mapped to a message"
+                );
+                None
+            }
+        };
+
+        self.consumer.commit_message(&record, CommitMode::Sync)?;
+
+        Ok(published)
+    }
+
+    /// Ingests records until an error occurs, mapping them with [`to_raw_message`]
+    pub async fn run(&self) -> Result<(), KafkaBridgeError> {
+        loop {
+            self.ingest_one(&|record| to_raw_message(record)).await?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rdkafka::Timestamp;
+    use rdkafka::message::{Header, OwnedHeaders, OwnedMessage};
+
+    fn record(payload: &[u8], headers: Option<OwnedHeaders>) -> OwnedMessage {
+        OwnedMessage::new(
+            Some(payload.to_vec()),
+            None,
+            "orders".to_string(),
+            Timestamp::NotAvailable,
+            0,
+            42,
+            headers,
+        )
+    }
+
+    #[test]
+    fn it_names_messages_after_the_topic() -> anyhow::Result<()> {
+        let message = to_raw_message(&record(br#"{"id":1}"#, None)).unwrap();
+
+        assert_eq!(message.name, "orders");
+        assert_eq!(message.hash, fnv1a_hash_str_32("orders") as i32);
+        assert_eq!(message.payload, serde_json::json!({ "id": 1 }));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_names_messages_after_the_name_header() -> anyhow::Result<()> {
+        let headers = OwnedHeaders::new().insert(Header {
+            key: NAME_HEADER,
+            value: Some("OrderPlaced"),
+        });
+        let message = to_raw_message(&record(br#"{"id":1}"#, Some(headers))).unwrap();
+
+        assert_eq!(message.name, "OrderPlaced");
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_skips_records_that_are_not_json() -> anyhow::Result<()> {
+        assert!(to_raw_message(&record(b"not json", None)).is_none());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,6 @@
+//! Bridges between this queue and other messaging systems, each behind a feature flag
+
+#[cfg(feature = "amqp")]
+pub mod amqp;
+#[cfg(feature = "kafka")]
+pub mod kafka;
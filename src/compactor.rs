@@ -0,0 +1,115 @@
+//! Paced compaction of the attempts of recent days into daily aggregates, so that metrics don't have to scan the raw
+//! attempt rows
+
+use crate::queries::Queries;
+use chrono::{Days, Utc};
+use sqlx::PgPool;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+/// Scheduling of a [`Compactor`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompactorConfig {
+    /// How long the compactor sleeps between rounds
+    pub interval: Duration,
+    /// The number of UTC days compacted every round, counting back from the current one. Two days, the default,
+    /// also finishes the previous day once it is over.
+    pub days: u32,
+}
+
+impl Default for CompactorConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_mins(5),
+            days: 2,
+        }
+    }
+}
+
+/// Compacts recent days into daily aggregates periodically, see
+/// [`compact_daily_aggregates`](crate::queries::compact_daily_aggregates).
+///
+/// Every day is compacted in a transaction of its own, and compaction is idempotent, so a compactor may be cancelled
+/// at any time and several hosts may run one against the same schema.
+#[derive(Debug, Clone, Default)]
+pub struct Compactor {
+    config: CompactorConfig,
+}
+
+impl Compactor {
+    pub fn new(config: CompactorConfig) -> Self {
+        Self { config }
+    }
+
+    /// Compacts the recent days of the schema of `queries` once, returning the number of (day, name) rows written
+    pub async fn compact<S>(
+        &self,
+        pool: &PgPool,
+        queries: &Queries<S>,
+    ) -> Result<u64, sqlx::Error> {
+        let now = Utc::now();
+        let today = now.date_naive();
+        let mut written = 0;
+
+        for days_ago in 0..self.config.days.max(1) {
+            let Some(day) = today.checked_sub_days(Days::new(u64::from(days_ago))) else {
+                break;
+            };
+            let mut tx = pool.begin().await?;
+            written += queries.compact_daily_aggregates(&mut tx, day, now).await?;
+            tx.commit().await?;
+        }
+
+        Ok(written)
+    }
+
+    /// Compacts the recent days of the schema of `queries` every [`interval`](CompactorConfig::interval) until
+    /// cancelled, calling `on_compacted` with the number of rows written after every round.
+    pub async fn run<S>(
+        &self,
+        pool: &PgPool,
+        queries: &Queries<S>,
+        cancellation: CancellationToken,
+        mut on_compacted: impl FnMut(u64),
+    ) -> Result<(), sqlx::Error> {
+        loop {
+            let written = self.compact(pool, queries).await?;
+            on_compacted(written);
+
+            tokio::select! {
+                _ = cancellation.cancelled() => return Ok(()),
+                _ = tokio::time::sleep(self.config.interval) => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queries::{get_daily_aggregates, publish_message};
+    use crate::testing_tools::TestMessage;
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_compacts_until_cancelled(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+        let queries = Queries::new("public")?;
+        let cancellation = CancellationToken::new();
+
+        let mut rounds = Vec::new();
+        Compactor::default()
+            .run(&pool, &queries, cancellation.clone(), |written| {
+                rounds.push(written);
+                cancellation.cancel();
+            })
+            .await?;
+
+        assert_eq!(rounds, [1]);
+        let today = Utc::now().date_naive();
+        let aggregates = get_daily_aggregates(&pool, today, today).await?;
+        assert_eq!(aggregates.len(), 1);
+        assert_eq!(aggregates[0].published, 1);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,171 @@
+//! Detection of the Postgres server a queue runs on, so that an unsupported server is refused up front rather than
+//! failing part way through a migration or a claim
+
+use crate::queries::ErrorClass;
+use sqlx::PgExecutor;
+
+/// The oldest supported server, `gen_random_uuid` is built in from Postgres 13
+pub const MIN_SERVER_VERSION_NUM: i32 = 130000;
+
+/// The newest major the single statement claim is verified against, see
+/// [`ServerCapabilities::single_statement_claims`]
+pub const MAX_VERIFIED_MAJOR: i32 = 18;
+
+/// Extensions created by the migrations
+const REQUIRED_EXTENSIONS: &[&str] = &["pg_trgm"];
+
+#[derive(Debug, thiserror::Error)]
+pub enum CompatibilityError {
+    #[error("UnsupportedServerError: Postgres {version} is not supported, 13 or later is required")]
+    UnsupportedServer { version: String, version_num: i32 },
+    #[error("MissingExtensionError: the {0} extension is not available on the server")]
+    MissingExtension(&'static str),
+    #[error("DatabaseError: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+impl CompatibilityError {
+    pub fn class(&self) -> ErrorClass {
+        match self {
+            Self::Database(error) => ErrorClass::of(error),
+            Self::UnsupportedServer { .. } | Self::MissingExtension(_) => ErrorClass::Permanent,
+        }
+    }
+}
+
+/// The version and features of a Postgres server, see [`detect`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerCapabilities {
+    /// The version as reported by `server_version_num`, e.g. 150004 for 15.4
+    pub version_num: i32,
+    /// The version as reported by `server_version`
+    pub version: String,
+    /// The extensions required by the migrations that may be installed on the server
+    pub available_extensions: Vec<String>,
+}
+
+impl ServerCapabilities {
+    pub fn major(&self) -> i32 {
+        self.version_num / 10000
+    }
+
+    /// Returns true from Postgres 15, which added `MERGE`
+    pub fn supports_merge(&self) -> bool {
+        self.version_num >= 150000
+    }
+
+    /// Returns true if the single statement claim of
+    /// [`get_next_unattempted`](crate::queries::get_next_unattempted) is verified against the major of the server.
+    ///
+    /// That claim relies on how data-modifying CTEs share their rows, which is not guaranteed across majors. On
+    /// newer majors [`Queries::with_server_capabilities`](crate::queries::Queries::with_server_capabilities) falls
+    /// back to the claim in two statements until verified.
+    pub fn single_statement_claims(&self) -> bool {
+        self.major() <= MAX_VERIFIED_MAJOR
+    }
+
+    /// Fails if the server is older than [`MIN_SERVER_VERSION_NUM`] or lacks an extension the migrations create
+    pub fn ensure_supported(&self) -> Result<(), CompatibilityError> {
+        if self.version_num < MIN_SERVER_VERSION_NUM {
+            return Err(CompatibilityError::UnsupportedServer {
+                version: self.version.clone(),
+                version_num: self.version_num,
+            });
+        }
+        if let Some(missing) = REQUIRED_EXTENSIONS
+            .iter()
+            .find(|name| !self.available_extensions.iter().any(|a| a == *name))
+        {
+            return Err(CompatibilityError::MissingExtension(missing));
+        }
+        Ok(())
+    }
+}
+
+/// Detects the version of the server and which of the extensions required by the migrations it can install
+pub async fn detect<'tx, E: PgExecutor<'tx>>(tx: E) -> Result<ServerCapabilities, sqlx::Error> {
+    let (version_num, version, available_extensions): (i32, String, Vec<String>) = sqlx::query_as(
+        r#"
+        SELECT
+            current_setting('server_version_num')::INT,
+            current_setting('server_version'),
+            ARRAY(SELECT name::TEXT FROM pg_available_extensions WHERE name = ANY($1))
+        "#,
+    )
+    .bind(REQUIRED_EXTENSIONS)
+    .fetch_one(tx)
+    .await?;
+
+    Ok(ServerCapabilities {
+        version_num,
+        version,
+        available_extensions,
+    })
+}
+
+/// Detects the capabilities of the server, failing if it is not supported
+pub async fn ensure_supported<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+) -> Result<ServerCapabilities, CompatibilityError> {
+    let capabilities = detect(tx).await?;
+    capabilities.ensure_supported()?;
+    Ok(capabilities)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server(version_num: i32) -> ServerCapabilities {
+        ServerCapabilities {
+            version_num,
+            version: format!("{}.{}", version_num / 10000, version_num % 10000),
+            available_extensions: vec!["pg_trgm".to_string()],
+        }
+    }
+
+    #[test]
+    fn it_refuses_servers_older_than_the_minimum() {
+        assert!(matches!(
+            server(120017).ensure_supported(),
+            Err(CompatibilityError::UnsupportedServer {
+                version_num: 120017,
+                ..
+            })
+        ));
+        assert!(server(130000).ensure_supported().is_ok());
+    }
+
+    #[test]
+    fn it_refuses_servers_without_required_extensions() {
+        let mut capabilities = server(160000);
+        capabilities.available_extensions.clear();
+
+        assert!(matches!(
+            capabilities.ensure_supported(),
+            Err(CompatibilityError::MissingExtension("pg_trgm"))
+        ));
+    }
+
+    #[test]
+    fn it_selects_features_by_version() {
+        assert!(!server(140011).supports_merge());
+        assert!(server(150004).supports_merge());
+        assert!(server(180000).single_statement_claims());
+        assert!(!server(190000).single_statement_claims());
+    }
+
+    #[sqlx::test]
+    async fn it_detects_the_connected_server(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let capabilities = ensure_supported(&pool).await?;
+
+        assert!(capabilities.version_num >= MIN_SERVER_VERSION_NUM);
+        assert!(
+            capabilities
+                .version
+                .starts_with(&capabilities.major().to_string())
+        );
+
+        Ok(())
+    }
+}
@@ -1 +1,107 @@
+use const_fnv1a_hash::fnv1a_hash_str_32;
+
 pub const FX_MQ_MESSAGE_NOTIFICATION_CHANNEL: &str = "fx-mq-messages";
+
+/// Prefix of per-schema notification channels, see [`notification_channel_for_schema`]
+pub const FX_MQ_SCHEMA_CHANNEL_PREFIX: &str = "fx_mq_";
+
+/// The longest channel name Postgres accepts, NAMEDATALEN - 1 bytes with the default NAMEDATALEN
+const MAX_CHANNEL_LEN: usize = 63;
+
+/// Returns the notification channel of a schema, `fx_mq_<schema>`.
+///
+/// When several schemas share a database, publishing on a channel per schema lets listeners wake only
+/// the poll loop of the schema that received messages, rather than every loop on the shared channel.
+///
+/// Channel names longer than 63 bytes are truncated by `LISTEN` but rejected by `pg_notify`, so for long schema
+/// names the end of the channel is replaced by a hash of the schema, keeping it within 63 bytes.
+pub fn notification_channel_for_schema(schema: &str) -> String {
+    channel_for_schema(FX_MQ_SCHEMA_CHANNEL_PREFIX, schema)
+}
+
+/// Returns `<prefix><schema>`, or if that exceeds 63 bytes, as much of it as fits followed by `_` and the hex
+/// fnv1a hash of the schema, so that schemas sharing a long prefix still get distinct channels.
+fn channel_for_schema(prefix: &str, schema: &str) -> String {
+    let channel = format!("{prefix}{schema}");
+    if channel.len() <= MAX_CHANNEL_LEN {
+        return channel;
+    }
+
+    let suffix = format!("_{:08x}", fnv1a_hash_str_32(schema));
+    let mut end = MAX_CHANNEL_LEN - prefix.len() - suffix.len();
+    while !schema.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{prefix}{}{suffix}", &schema[..end])
+}
+
+/// Prefix of per-schema operator command channels, see [`command_channel_for_schema`]. It doesn't start with
+/// [`FX_MQ_SCHEMA_CHANNEL_PREFIX`], so that no schema name gives a notification channel that is a command channel.
+pub const FX_MQ_COMMAND_CHANNEL_PREFIX: &str = "fx-mq-commands-";
+
+/// Returns the operator command channel of a schema, `fx-mq-commands-<schema>`, fitted within 63 bytes like the
+/// [notification channel](notification_channel_for_schema)
+pub fn command_channel_for_schema(schema: &str) -> String {
+    channel_for_schema(FX_MQ_COMMAND_CHANNEL_PREFIX, schema)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queries::notify;
+    use futures::StreamExt;
+
+    #[test]
+    fn it_fits_channels_of_long_schemas() {
+        let schema = "s".repeat(63);
+        let channel = notification_channel_for_schema(&schema);
+        assert_eq!(channel.len(), MAX_CHANNEL_LEN);
+        assert!(channel.starts_with(FX_MQ_SCHEMA_CHANNEL_PREFIX));
+
+        let other = format!("{}t", "s".repeat(62));
+        assert_ne!(channel, notification_channel_for_schema(&other));
+
+        assert_eq!(notification_channel_for_schema("public"), "fx_mq_public");
+        assert!(notification_channel_for_schema(&"🐍".repeat(15)).len() <= MAX_CHANNEL_LEN);
+
+        let channel = command_channel_for_schema(&schema);
+        assert_eq!(channel.len(), MAX_CHANNEL_LEN);
+        assert!(channel.starts_with(FX_MQ_COMMAND_CHANNEL_PREFIX));
+        assert_eq!(
+            command_channel_for_schema("public"),
+            "fx-mq-commands-public"
+        );
+    }
+
+    #[test]
+    fn it_separates_command_channels_from_notification_channels() {
+        assert_ne!(
+            notification_channel_for_schema("commands_x"),
+            command_channel_for_schema("x")
+        );
+        assert_ne!(
+            notification_channel_for_schema("commands-x"),
+            command_channel_for_schema("x")
+        );
+        assert!(!FX_MQ_COMMAND_CHANNEL_PREFIX.starts_with(FX_MQ_SCHEMA_CHANNEL_PREFIX));
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_notifies_on_channels_of_long_schemas(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let channel = notification_channel_for_schema(&"s".repeat(63));
+
+        let mut listener = sqlx::postgres::PgListener::connect_with(&pool).await?;
+        listener.listen(&channel).await?;
+        let mut notifications = listener.into_stream();
+
+        notify(&pool, &channel, 1).await?;
+
+        let notification = notifications
+            .next()
+            .await
+            .expect("expected a pg_notify to be received")?;
+        assert_eq!(notification.channel(), channel);
+
+        Ok(())
+    }
+}
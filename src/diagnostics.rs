@@ -0,0 +1,445 @@
+//! Diagnostics of a queue schema, for triaging incidents
+
+use crate::compatibility::detect;
+use crate::migrator::{DEFAULT_MIGRATIONS_TABLE, PgIdentifier, PgIdentifierParsingError, migrator};
+use crate::queries::set_schema_for_transaction;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use sqlx::postgres::PgListener;
+use std::collections::HashSet;
+use std::fmt;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Indexes of the claim and report paths, without which claims degrade to sequential scans
+const EXPECTED_INDEXES: &[&str] = &[
+    "idx_messages_unattempted_claim",
+    "idx_attempts_failed_retry_earliest_at",
+    "idx_attempts_failed_message_id",
+    "idx_leases_expires_at",
+    "idx_leases_acquired_by",
+    "idx_errors_message_id",
+];
+
+/// Messages claimable for longer than this are reported as stuck
+const STUCK_AFTER: Duration = Duration::from_mins(15);
+
+/// Clock differences between the host and the database beyond this are reported, leases are computed with the
+/// clock of the host
+const MAX_CLOCK_SKEW: Duration = Duration::from_secs(1);
+
+/// How long to wait for a notification sent to self
+const NOTIFY_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckStatus {
+    Ok,
+    /// Works, but degraded or likely to cause trouble
+    Warning,
+    Failed,
+}
+
+/// The outcome of a single check
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    /// What was found, for the operator
+    pub detail: String,
+}
+
+impl Check {
+    fn new(name: &'static str, status: CheckStatus, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status,
+            detail: detail.into(),
+        }
+    }
+
+    fn from_result(name: &'static str, result: Result<(CheckStatus, String), sqlx::Error>) -> Self {
+        match result {
+            Ok((status, detail)) => Self::new(name, status, detail),
+            Err(error) => Self::new(
+                name,
+                CheckStatus::Failed,
+                format!("Could not check: {error}"),
+            ),
+        }
+    }
+}
+
+/// The outcome of [`run`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiagnosticsReport {
+    pub checks: Vec<Check>,
+}
+
+impl DiagnosticsReport {
+    /// Returns true if no check failed
+    pub fn is_healthy(&self) -> bool {
+        self.checks
+            .iter()
+            .all(|check| check.status != CheckStatus::Failed)
+    }
+
+    pub fn check(&self, name: &str) -> Option<&Check> {
+        self.checks.iter().find(|check| check.name == name)
+    }
+}
+
+impl fmt::Display for DiagnosticsReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for check in &self.checks {
+            let status = match check.status {
+                CheckStatus::Ok => "ok",
+                CheckStatus::Warning => "warning",
+                CheckStatus::Failed => "FAILED",
+            };
+            writeln!(f, "[{status}] {}: {}", check.name, check.detail)?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks the server, the migrations, indexes, leases and messages of `schema`, the clock of this host against the
+/// database and that notifications are delivered, returning a report rather than failing on the first problem found.
+pub async fn run(
+    pool: &PgPool,
+    schema: &str,
+) -> Result<DiagnosticsReport, PgIdentifierParsingError> {
+    run_with_table(pool, schema, DEFAULT_MIGRATIONS_TABLE).await
+}
+
+/// Runs the diagnostics like [`run`], for migrations recorded in `table`, see
+/// [`run_migrations_with_table`](crate::migrator::run_migrations_with_table)
+pub async fn run_with_table(
+    pool: &PgPool,
+    schema: &str,
+    table: &str,
+) -> Result<DiagnosticsReport, PgIdentifierParsingError> {
+    let schema = PgIdentifier::parse(schema)?;
+    let table = PgIdentifier::parse(table)?;
+
+    let checks = vec![
+        Check::from_result("server", check_server(pool).await),
+        Check::from_result("migrations", check_migrations(pool, &schema, &table).await),
+        Check::from_result("indexes", check_indexes(pool, &schema).await),
+        Check::from_result(
+            "orphaned_leases",
+            check_orphaned_leases(pool, &schema).await,
+        ),
+        Check::from_result("stuck_messages", check_stuck_messages(pool, &schema).await),
+        Check::from_result("clock_skew", check_clock_skew(pool).await),
+        Check::from_result("notify", check_notify(pool).await),
+    ];
+
+    Ok(DiagnosticsReport { checks })
+}
+
+async fn check_server(pool: &PgPool) -> Result<(CheckStatus, String), sqlx::Error> {
+    let capabilities = detect(pool).await?;
+
+    Ok(match capabilities.ensure_supported() {
+        Err(error) => (CheckStatus::Failed, error.to_string()),
+        Ok(()) if !capabilities.single_statement_claims() => (
+            CheckStatus::Warning,
+            format!(
+                "Postgres {} is newer than the claims are verified against, use Queries::with_server_capabilities",
+                capabilities.version
+            ),
+        ),
+        Ok(()) => (
+            CheckStatus::Ok,
+            format!("Postgres {}", capabilities.version),
+        ),
+    })
+}
+
+async fn check_migrations(
+    pool: &PgPool,
+    schema: &PgIdentifier,
+    table: &PgIdentifier,
+) -> Result<(CheckStatus, String), sqlx::Error> {
+    let exists: Option<String> = sqlx::query_scalar("SELECT to_regclass($1)::TEXT")
+        .bind(format!("{schema}.{table}"))
+        .fetch_one(pool)
+        .await?;
+    if exists.is_none() {
+        return Ok((
+            CheckStatus::Failed,
+            format!("No migrations table {schema}.{table}"),
+        ));
+    }
+
+    let applied: Vec<(i64, bool)> =
+        sqlx::query_as(&format!("SELECT version, success FROM {schema}.{table}"))
+            .fetch_all(pool)
+            .await?;
+
+    if let Some((version, _)) = applied.iter().find(|(_, success)| !success) {
+        return Ok((
+            CheckStatus::Failed,
+            format!("Migration {version} is partially applied"),
+        ));
+    }
+
+    let applied: HashSet<i64> = applied.into_iter().map(|(version, _)| version).collect();
+    let pending: Vec<i64> = migrator()
+        .iter()
+        .filter(|m| !m.migration_type.is_down_migration())
+        .map(|m| m.version)
+        .filter(|version| !applied.contains(version))
+        .collect();
+
+    Ok(match pending.as_slice() {
+        [] => (
+            CheckStatus::Ok,
+            format!("{} migrations applied", applied.len()),
+        ),
+        pending => (
+            CheckStatus::Failed,
+            format!("Pending migrations: {pending:?}"),
+        ),
+    })
+}
+
+async fn check_indexes(
+    pool: &PgPool,
+    schema: &PgIdentifier,
+) -> Result<(CheckStatus, String), sqlx::Error> {
+    let indexes: Vec<(String, bool)> = sqlx::query_as(
+        r#"
+        SELECT c.relname::TEXT, i.indisvalid
+        FROM pg_index i
+        JOIN pg_class c ON c.oid = i.indexrelid
+        JOIN pg_namespace n ON n.oid = c.relnamespace
+        WHERE n.nspname = $1
+        "#,
+    )
+    .bind(schema.unquoted())
+    .fetch_all(pool)
+    .await?;
+
+    let invalid: Vec<&str> = indexes
+        .iter()
+        .filter(|(_, valid)| !valid)
+        .map(|(name, _)| name.as_str())
+        .collect();
+    let missing: Vec<&str> = EXPECTED_INDEXES
+        .iter()
+        .copied()
+        .filter(|expected| !indexes.iter().any(|(name, _)| name == expected))
+        .collect();
+
+    Ok(match (missing.as_slice(), invalid.as_slice()) {
+        ([], []) => (CheckStatus::Ok, format!("{} indexes", indexes.len())),
+        (missing, invalid) => (
+            CheckStatus::Warning,
+            format!("Missing indexes: {missing:?}, invalid indexes: {invalid:?}"),
+        ),
+    })
+}
+
+async fn check_orphaned_leases(
+    pool: &PgPool,
+    schema: &PgIdentifier,
+) -> Result<(CheckStatus, String), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    set_schema_for_transaction(&mut tx, schema).await?;
+    // Reports remove the leases of a message, so leases of finished or unknown messages were left behind
+    let orphaned: i64 = sqlx::query_scalar(
+        r#"
+        SELECT COUNT(*)
+        FROM leases l
+        WHERE EXISTS (SELECT 1 FROM attempts_succeeded s WHERE s.message_id = l.message_id)
+           OR EXISTS (SELECT 1 FROM attempts_dead d WHERE d.message_id = l.message_id)
+           OR (
+               NOT EXISTS (SELECT 1 FROM messages_unattempted mu WHERE mu.id = l.message_id)
+               AND NOT EXISTS (SELECT 1 FROM messages_attempted ma WHERE ma.id = l.message_id)
+           )
+        "#,
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(match orphaned {
+        0 => (CheckStatus::Ok, "No orphaned leases".to_string()),
+        orphaned => (
+            CheckStatus::Warning,
+            format!("{orphaned} leases of finished or deleted messages"),
+        ),
+    })
+}
+
+async fn check_stuck_messages(
+    pool: &PgPool,
+    schema: &PgIdentifier,
+) -> Result<(CheckStatus, String), sqlx::Error> {
+    let now = Utc::now();
+    let stuck_since = now - STUCK_AFTER;
+
+    let mut tx = pool.begin().await?;
+    set_schema_for_transaction(&mut tx, schema).await?;
+    let (pending, retryable): (i64, i64) = sqlx::query_as(
+        r#"
+        SELECT
+            (
+                SELECT COUNT(*)
+                FROM messages_unattempted mu
+                WHERE mu.published_at < $1
+                  AND NOT EXISTS (SELECT 1 FROM leases l WHERE l.message_id = mu.id AND l.expires_at > $2)
+            ),
+            (
+                SELECT COUNT(DISTINCT fa.message_id)
+                FROM attempts_failed fa
+                WHERE fa.retry_earliest_at < $1
+                  AND NOT EXISTS (SELECT 1 FROM leases l WHERE l.message_id = fa.message_id AND l.expires_at > $2)
+                  AND fa.failed_at = (
+                      SELECT MAX(fa2.failed_at) FROM attempts_failed fa2 WHERE fa2.message_id = fa.message_id
+                  )
+            )
+        "#,
+    )
+    .bind(stuck_since)
+    .bind(now)
+    .fetch_one(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(match pending + retryable {
+        0 => (CheckStatus::Ok, "No stuck messages".to_string()),
+        _ => (
+            CheckStatus::Warning,
+            format!(
+                "{pending} pending and {retryable} retryable messages claimable for over {STUCK_AFTER:?}, \
+                 are workers running?"
+            ),
+        ),
+    })
+}
+
+async fn check_clock_skew(pool: &PgPool) -> Result<(CheckStatus, String), sqlx::Error> {
+    let before = Utc::now();
+    let database_now: DateTime<Utc> = sqlx::query_scalar("SELECT clock_timestamp()")
+        .fetch_one(pool)
+        .await?;
+    let after = Utc::now();
+
+    // Compared with the middle of the round trip
+    let local_now = before + (after - before) / 2;
+    let skew = (database_now - local_now)
+        .abs()
+        .to_std()
+        .unwrap_or_default();
+
+    Ok(match skew > MAX_CLOCK_SKEW {
+        false => (CheckStatus::Ok, format!("Clock skew of {skew:?}")),
+        true => (
+            CheckStatus::Warning,
+            format!("Clock skew of {skew:?}, leases expire early or late"),
+        ),
+    })
+}
+
+async fn check_notify(pool: &PgPool) -> Result<(CheckStatus, String), sqlx::Error> {
+    let channel = format!("fx_mq_diagnostics_{}", Uuid::now_v7().simple());
+
+    let mut listener = PgListener::connect_with(pool).await?;
+    listener.listen(&channel).await?;
+    // Sent through the connection of the listener, the pool may not have another one to spare
+    sqlx::query("SELECT pg_notify($1, 'ping')")
+        .bind(&channel)
+        .execute(&mut listener)
+        .await?;
+
+    let received = tokio::time::timeout(NOTIFY_TIMEOUT, listener.recv()).await;
+    Ok(match received {
+        Ok(Ok(_)) => (CheckStatus::Ok, "Notifications are delivered".to_string()),
+        Ok(Err(error)) => return Err(error),
+        Err(_) => (
+            CheckStatus::Failed,
+            format!(
+                "No notification received within {NOTIFY_TIMEOUT:?}, is a pooler in transaction mode?"
+            ),
+        ),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migrator::run_migrations;
+    use crate::queries::{get_next_unattempted, publish_message};
+    use crate::testing_tools::TestMessage;
+
+    #[sqlx::test]
+    async fn it_reports_a_healthy_schema(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        run_migrations(&pool, "doctor").await?;
+
+        let report = run(&pool, "doctor").await?;
+
+        assert!(report.is_healthy(), "{report}");
+        assert!(
+            report
+                .checks
+                .iter()
+                .all(|check| check.status == CheckStatus::Ok),
+            "{report}"
+        );
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_reports_stuck_messages_and_orphaned_leases(
+        pool: sqlx::PgPool,
+    ) -> anyhow::Result<()> {
+        let stale = Utc::now() - Duration::from_hours(1);
+        publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+        sqlx::query("UPDATE messages_unattempted SET published_at = $1")
+            .bind(stale)
+            .execute(&pool)
+            .await?;
+        sqlx::query(
+            "INSERT INTO leases (message_id, acquired_at, acquired_by, expires_at) VALUES ($1, $2, $1, $2)",
+        )
+        .bind(Uuid::now_v7())
+        .bind(stale)
+        .execute(&pool)
+        .await?;
+
+        let report = run(&pool, "public").await?;
+        assert_eq!(
+            report.check("stuck_messages").map(|check| check.status),
+            Some(CheckStatus::Warning)
+        );
+        assert_eq!(
+            report.check("orphaned_leases").map(|check| check.status),
+            Some(CheckStatus::Warning)
+        );
+
+        get_next_unattempted(&pool, Utc::now(), Uuid::now_v7(), Duration::from_mins(1)).await?;
+        let report = run(&pool, "public").await?;
+        assert_eq!(
+            report.check("stuck_messages").map(|check| check.status),
+            Some(CheckStatus::Ok)
+        );
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn it_fails_for_unmigrated_schemas(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let report = run(&pool, "unmigrated").await?;
+
+        assert!(!report.is_healthy());
+        assert_eq!(
+            report.check("migrations").map(|check| check.status),
+            Some(CheckStatus::Failed)
+        );
+
+        Ok(())
+    }
+}
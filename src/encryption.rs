@@ -0,0 +1,238 @@
+//! Field-level payload encryption, for messages where only a few fields are sensitive
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use serde::de::{DeserializeOwned, Error as _};
+use serde::ser::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, RwLock};
+
+static KEY_PROVIDER: RwLock<Option<Arc<dyn KeyProvider>>> = RwLock::new(None);
+
+/// Supplies the 256-bit keys [`Encrypted`] fields are encrypted with, by id so that keys can be rotated.
+pub trait KeyProvider: Send + Sync {
+    /// The id of the key to encrypt new values with
+    fn current_key_id(&self) -> String;
+    /// The key with the given id, None if it is unknown
+    fn key(&self, id: &str) -> Option<[u8; 32]>;
+}
+
+/// A [`KeyProvider`] of a fixed set of keys
+#[derive(Clone)]
+pub struct StaticKeys {
+    current: String,
+    keys: HashMap<String, [u8; 32]>,
+}
+
+impl StaticKeys {
+    /// Creates a provider encrypting with `key`
+    pub fn new(id: impl Into<String>, key: [u8; 32]) -> Self {
+        let current = id.into();
+        Self {
+            keys: HashMap::from([(current.clone(), key)]),
+            current,
+        }
+    }
+
+    /// Adds a key to decrypt with, e.g. a key rotated out
+    pub fn with_key(mut self, id: impl Into<String>, key: [u8; 32]) -> Self {
+        self.keys.insert(id.into(), key);
+        self
+    }
+}
+
+impl KeyProvider for StaticKeys {
+    fn current_key_id(&self) -> String {
+        self.current.clone()
+    }
+
+    fn key(&self, id: &str) -> Option<[u8; 32]> {
+        self.keys.get(id).copied()
+    }
+}
+
+/// Configures the key provider of the process, replacing any configured before
+pub fn set_key_provider(provider: impl KeyProvider + 'static) {
+    *KEY_PROVIDER.write().expect("poisoned") = Some(Arc::new(provider));
+}
+
+fn key_provider() -> Option<Arc<dyn KeyProvider>> {
+    KEY_PROVIDER.read().expect("poisoned").clone()
+}
+
+/// The form an [`Encrypted`] value takes in a payload
+#[derive(Serialize, Deserialize)]
+struct Envelope {
+    kid: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+/// A payload field that is encrypted when serialized and decrypted when deserialized, with the keys of the
+/// provider configured with [`set_key_provider`].
+///
+/// Derefs to the value, so handlers use it like the plain field. Its `Debug` output is redacted, so it isn't
+/// leaked to logs. Serializing or deserializing fails if no provider is configured or the key is unknown.
+///
+/// ```ignore
+/// #[derive(Clone, Serialize, Deserialize)]
+/// struct SignedUp {
+///     user_id: Uuid,
+///     email: Encrypted<String>,
+/// }
+/// ```
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Encrypted<T>(pub T);
+
+impl<T> Encrypted<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> From<T> for Encrypted<T> {
+    fn from(value: T) -> Self {
+        Self(value)
+    }
+}
+
+impl<T> Deref for Encrypted<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for Encrypted<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl<T> std::fmt::Debug for Encrypted<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Encrypted(..)")
+    }
+}
+
+impl<T: Serialize> Serialize for Encrypted<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let provider =
+            key_provider().ok_or_else(|| S::Error::custom("No key provider configured"))?;
+        let kid = provider.current_key_id();
+        let key = provider
+            .key(&kid)
+            .ok_or_else(|| S::Error::custom(format!("Unknown key {kid}")))?;
+
+        let plaintext = serde_json::to_vec(&self.0).map_err(S::Error::custom)?;
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key))
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: &plaintext,
+                    aad: kid.as_bytes(),
+                },
+            )
+            .map_err(|_| S::Error::custom("Could not encrypt"))?;
+
+        Envelope {
+            nonce: STANDARD.encode(nonce),
+            ciphertext: STANDARD.encode(ciphertext),
+            kid,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de, T: DeserializeOwned> Deserialize<'de> for Encrypted<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let envelope = Envelope::deserialize(deserializer)?;
+        let provider =
+            key_provider().ok_or_else(|| D::Error::custom("No key provider configured"))?;
+        let key = provider
+            .key(&envelope.kid)
+            .ok_or_else(|| D::Error::custom(format!("Unknown key {}", envelope.kid)))?;
+
+        let nonce = STANDARD.decode(&envelope.nonce).map_err(D::Error::custom)?;
+        if nonce.len() != 12 {
+            return Err(D::Error::custom("Invalid nonce"));
+        }
+        let ciphertext = STANDARD
+            .decode(&envelope.ciphertext)
+            .map_err(D::Error::custom)?;
+        let plaintext = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key))
+            .decrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: &ciphertext,
+                    aad: envelope.kid.as_bytes(),
+                },
+            )
+            .map_err(|_| D::Error::custom("Could not decrypt"))?;
+
+        serde_json::from_slice(&plaintext)
+            .map(Self)
+            .map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Message, RawMessage};
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    struct SignedUp {
+        user: u32,
+        email: Encrypted<String>,
+    }
+
+    impl Message for SignedUp {
+        const NAME: &str = "SignedUp";
+    }
+
+    fn keys() -> StaticKeys {
+        StaticKeys::new("2026-10", [7; 32]).with_key("2026-09", [3; 32])
+    }
+
+    #[test]
+    fn it_encrypts_only_the_annotated_fields() -> anyhow::Result<()> {
+        set_key_provider(keys());
+
+        let message = SignedUp {
+            user: 42,
+            email: "ada@example.com".to_string().into(),
+        };
+        let raw = RawMessage::from_message(&message)?;
+
+        assert_eq!(raw.payload["user"], 42);
+        assert_eq!(raw.payload["email"]["kid"], "2026-10");
+        assert!(!raw.payload.to_string().contains("ada@example.com"));
+        assert_eq!(format!("{:?}", message.email), "Encrypted(..)");
+
+        let decoded: SignedUp = serde_json::from_value(raw.payload)?;
+        assert_eq!(*decoded.email, "ada@example.com");
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_fails_to_decrypt_with_unknown_or_tampered_keys() -> anyhow::Result<()> {
+        set_key_provider(keys());
+        let mut payload = serde_json::to_value(Encrypted(1))?;
+
+        payload["kid"] = "2026-09".into();
+        assert!(serde_json::from_value::<Encrypted<i32>>(payload.clone()).is_err());
+
+        payload["kid"] = "unknown".into();
+        assert!(serde_json::from_value::<Encrypted<i32>>(payload).is_err());
+
+        Ok(())
+    }
+}
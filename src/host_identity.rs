@@ -0,0 +1,206 @@
+use crate::queries::Queries;
+use chrono::{DateTime, Utc};
+use sqlx::PgTransaction;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::OnceLock;
+use uuid::Uuid;
+
+/// Environment variable read by [`HostIdentity::shared`] for the host id
+pub const FX_MQ_HOST_ID_ENV: &str = "FX_MQ_HOST_ID";
+
+static SHARED: OnceLock<HostIdentity> = OnceLock::new();
+
+#[derive(Debug, thiserror::Error)]
+pub enum HostIdentityError {
+    #[error("IoError: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("InvalidHostId: {origin} does not contain a valid uuid: {source}")]
+    InvalidId { origin: String, source: uuid::Error },
+}
+
+/// The identity of this process as a host acquiring leases.
+///
+/// A stable host id lets lease holders be recognized across restarts. Install one identity per process with
+/// [`install`](Self::install) and pass [`shared`](Self::shared) to all listeners, instead of generating ids per
+/// listener.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HostIdentity {
+    id: Uuid,
+    metadata: BTreeMap<String, String>,
+}
+
+impl HostIdentity {
+    /// An identity with a new id, which changes on every restart
+    pub fn ephemeral() -> Self {
+        Self::with_id(Uuid::now_v7())
+    }
+
+    pub fn with_id(id: Uuid) -> Self {
+        Self {
+            id,
+            metadata: BTreeMap::new(),
+        }
+    }
+
+    /// Reads the id from the environment variable `var`, returns None if it is not set
+    pub fn from_env(var: &str) -> Result<Option<Self>, HostIdentityError> {
+        let Ok(raw) = std::env::var(var) else {
+            return Ok(None);
+        };
+
+        let id = raw
+            .trim()
+            .parse()
+            .map_err(|source| HostIdentityError::InvalidId {
+                origin: var.to_string(),
+                source,
+            })?;
+
+        Ok(Some(Self::with_id(id)))
+    }
+
+    /// Reads the id from the file at `path`, or generates one and writes it there if the file does not exist
+    pub fn load_or_create(path: impl AsRef<Path>) -> Result<Self, HostIdentityError> {
+        let path = path.as_ref();
+
+        match std::fs::read_to_string(path) {
+            Ok(raw) => {
+                let id = raw
+                    .trim()
+                    .parse()
+                    .map_err(|source| HostIdentityError::InvalidId {
+                        origin: path.display().to_string(),
+                        source,
+                    })?;
+                Ok(Self::with_id(id))
+            }
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+                let identity = Self::ephemeral();
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                std::fs::write(path, identity.id.to_string())?;
+                Ok(identity)
+            }
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    /// Attaches worker metadata, such as the hostname or version, used for the label of the host
+    pub fn with_metadata(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.metadata.insert(key.into(), value.into());
+        self
+    }
+
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    pub fn metadata(&self) -> &BTreeMap<String, String> {
+        &self.metadata
+    }
+
+    /// The metadata as a label, `key=value` pairs separated by spaces in key order
+    pub fn label(&self) -> String {
+        self.metadata
+            .iter()
+            .map(|(key, value)| format!("{key}={value}"))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Registers the host with its label, see [`Queries::register_host`]
+    pub async fn register<S>(
+        &self,
+        tx: &mut PgTransaction<'_>,
+        queries: &Queries<S>,
+        now: DateTime<Utc>,
+    ) -> Result<(), sqlx::Error> {
+        queries.register_host(tx, self.id, &self.label(), now).await
+    }
+
+    /// Installs the identity shared by the process. Fails, returning the identity, if one was already installed
+    /// or [`shared`](Self::shared) was already called.
+    pub fn install(self) -> Result<(), Self> {
+        SHARED.set(self)
+    }
+
+    /// The identity shared by the process.
+    ///
+    /// Unless one was installed, it is read from [`FX_MQ_HOST_ID_ENV`] on first use, falling back to an ephemeral
+    /// identity if the variable is not set or invalid.
+    pub fn shared() -> &'static Self {
+        SHARED.get_or_init(|| match Self::from_env(FX_MQ_HOST_ID_ENV) {
+            Ok(Some(identity)) => identity,
+            Ok(None) => Self::ephemeral(),
+            Err(error) => {
+                tracing::warn!(%error, "Using an ephemeral host id");
+                Self::ephemeral()
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queries::{get_lease_holder, get_next_unattempted, publish_message};
+    use crate::testing_tools::TestMessage;
+    use std::time::Duration;
+
+    #[test]
+    fn it_persists_the_id() -> anyhow::Result<()> {
+        let path = std::env::temp_dir()
+            .join(Uuid::now_v7().to_string())
+            .join("host_id");
+
+        let created = HostIdentity::load_or_create(&path)?;
+        let loaded = HostIdentity::load_or_create(&path)?;
+        assert_eq!(created.id(), loaded.id());
+
+        std::fs::write(&path, "not a uuid")?;
+        assert!(matches!(
+            HostIdentity::load_or_create(&path),
+            Err(HostIdentityError::InvalidId { .. })
+        ));
+
+        std::fs::remove_dir_all(path.parent().unwrap())?;
+        Ok(())
+    }
+
+    #[test]
+    fn it_shares_one_identity() {
+        assert_eq!(HostIdentity::shared().id(), HostIdentity::shared().id());
+        assert!(HostIdentity::ephemeral().install().is_err());
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_registers_the_metadata_as_label(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let queries = Queries::new("public")?;
+        let identity = HostIdentity::ephemeral()
+            .with_metadata("version", "1.2.3")
+            .with_metadata("hostname", "worker-0");
+        assert_eq!(identity.label(), "hostname=worker-0 version=1.2.3");
+
+        let mut tx = pool.begin().await?;
+        identity.register(&mut tx, &queries, Utc::now()).await?;
+        tx.commit().await?;
+
+        publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+        let claimed =
+            get_next_unattempted(&pool, Utc::now(), identity.id(), Duration::from_mins(1))
+                .await?
+                .expect("Expected a message");
+
+        let holder = get_lease_holder(&pool, claimed.id, Utc::now())
+            .await?
+            .expect("Expected a lease holder");
+        assert_eq!(
+            holder.label.as_deref(),
+            Some("hostname=worker-0 version=1.2.3")
+        );
+
+        Ok(())
+    }
+}
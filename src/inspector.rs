@@ -0,0 +1,265 @@
+//! Read-only inspection of a queue, for admin dashboards and TUIs
+
+use crate::listener::DefaultQueueSettings;
+use crate::migrator::PgIdentifierParsingError;
+use crate::models::{
+    DailyAggregate, ErrorRecord, MessageStatus, MessageSummary, NameLag, RetryForecast,
+    TimelineEvent,
+};
+use crate::queries::Queries;
+use chrono::{DateTime, NaiveDate, Utc};
+use futures::future::BoxFuture;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+pub type InspectorError = Box<dyn std::error::Error + Send + Sync>;
+
+/// The read-only operations of a queue.
+///
+/// Tools inspecting a queue code against this trait rather than against [`Queries`], so that they can be reused
+/// with other backends. None of the operations change the queue, so they are safe to expose to operators.
+pub trait QueueInspector: Send + Sync {
+    /// The current status of a message, None if it does not exist
+    fn status(
+        &self,
+        message_id: Uuid,
+    ) -> BoxFuture<'_, Result<Option<MessageStatus>, InspectorError>>;
+
+    /// Up to `limit` messages published before the message with the `seq` passed as `before`, most recent first,
+    /// optionally of a single name
+    fn list<'a>(
+        &'a self,
+        name: Option<&'a str>,
+        before: Option<i64>,
+        limit: i64,
+    ) -> BoxFuture<'a, Result<Vec<MessageSummary>, InspectorError>>;
+
+    /// Daily counts per message name within `[from, to]`
+    fn stats(
+        &self,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> BoxFuture<'_, Result<Vec<DailyAggregate>, InspectorError>>;
+
+    /// Up to `limit` errors reported within `[from, to)` containing `query`, most recent first
+    fn errors<'a>(
+        &'a self,
+        query: &'a str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        limit: i64,
+    ) -> BoxFuture<'a, Result<Vec<ErrorRecord>, InspectorError>>;
+
+    /// What happened to a message, in order
+    fn timeline(
+        &self,
+        message_id: Uuid,
+    ) -> BoxFuture<'_, Result<Vec<TimelineEvent>, InspectorError>>;
+
+    /// The unprocessed messages per message name
+    fn lag(&self) -> BoxFuture<'_, Result<Vec<NameLag>, InspectorError>>;
+
+    /// When a message will be attempted next and how many attempts it has left, None if it does not exist or its
+    /// name has no settings to forecast with
+    fn retry_forecast(
+        &self,
+        message_id: Uuid,
+    ) -> BoxFuture<'_, Result<Option<RetryForecast>, InspectorError>>;
+}
+
+/// Inspects the queue of a Postgres schema
+#[derive(Debug)]
+pub struct PgQueueInspector {
+    pool: PgPool,
+    queries: Queries,
+    defaults: Option<DefaultQueueSettings>,
+}
+
+impl PgQueueInspector {
+    pub fn new(pool: PgPool, schema: &str) -> Result<Self, PgIdentifierParsingError> {
+        Ok(Self {
+            pool,
+            queries: Queries::new(schema)?,
+            defaults: None,
+        })
+    }
+
+    /// Forecasts the retries of messages of names without stored settings with `defaults`, which should match the
+    /// defaults of the workers. Without defaults such messages have no forecast.
+    pub fn with_default_settings(mut self, defaults: DefaultQueueSettings) -> Self {
+        self.defaults = Some(defaults);
+        self
+    }
+}
+
+impl QueueInspector for PgQueueInspector {
+    fn status(
+        &self,
+        message_id: Uuid,
+    ) -> BoxFuture<'_, Result<Option<MessageStatus>, InspectorError>> {
+        Box::pin(async move {
+            let mut tx = self.pool.begin().await?;
+            let status = self
+                .queries
+                .get_message_status(&mut tx, message_id, Utc::now())
+                .await?;
+            tx.commit().await?;
+            Ok(status)
+        })
+    }
+
+    fn list<'a>(
+        &'a self,
+        name: Option<&'a str>,
+        before: Option<i64>,
+        limit: i64,
+    ) -> BoxFuture<'a, Result<Vec<MessageSummary>, InspectorError>> {
+        Box::pin(async move {
+            let mut tx = self.pool.begin().await?;
+            let messages = self
+                .queries
+                .list_messages(&mut tx, name, before, limit, Utc::now())
+                .await?;
+            tx.commit().await?;
+            Ok(messages)
+        })
+    }
+
+    fn stats(
+        &self,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> BoxFuture<'_, Result<Vec<DailyAggregate>, InspectorError>> {
+        Box::pin(async move {
+            let mut tx = self.pool.begin().await?;
+            let aggregates = self.queries.get_daily_aggregates(&mut tx, from, to).await?;
+            tx.commit().await?;
+            Ok(aggregates)
+        })
+    }
+
+    fn errors<'a>(
+        &'a self,
+        query: &'a str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        limit: i64,
+    ) -> BoxFuture<'a, Result<Vec<ErrorRecord>, InspectorError>> {
+        Box::pin(async move {
+            let mut tx = self.pool.begin().await?;
+            let errors = self
+                .queries
+                .search_errors(&mut tx, query, from, to, limit)
+                .await?;
+            tx.commit().await?;
+            Ok(errors)
+        })
+    }
+
+    fn timeline(
+        &self,
+        message_id: Uuid,
+    ) -> BoxFuture<'_, Result<Vec<TimelineEvent>, InspectorError>> {
+        Box::pin(async move {
+            let mut tx = self.pool.begin().await?;
+            let timeline = self
+                .queries
+                .get_message_timeline(&mut tx, message_id)
+                .await?;
+            tx.commit().await?;
+            Ok(timeline)
+        })
+    }
+
+    fn lag(&self) -> BoxFuture<'_, Result<Vec<NameLag>, InspectorError>> {
+        Box::pin(async move {
+            let mut tx = self.pool.begin().await?;
+            let lag = self.queries.lag_by_name(&mut tx, Utc::now()).await?;
+            tx.commit().await?;
+            Ok(lag)
+        })
+    }
+
+    fn retry_forecast(
+        &self,
+        message_id: Uuid,
+    ) -> BoxFuture<'_, Result<Option<RetryForecast>, InspectorError>> {
+        Box::pin(async move {
+            let now = Utc::now();
+            let mut tx = self.pool.begin().await?;
+            let state = self
+                .queries
+                .get_retry_state(&mut tx, message_id, now)
+                .await?;
+            tx.commit().await?;
+
+            let Some(state) = state else {
+                return Ok(None);
+            };
+            let settings = state.settings.clone().or_else(|| {
+                self.defaults
+                    .as_ref()
+                    .map(|defaults| defaults.for_name(&state.name))
+            });
+            Ok(settings
+                .map(|settings| state.forecast(&settings.backoff(), settings.max_attempts, now)))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::TimelineEventKind;
+    use crate::queries::publish_message;
+    use crate::testing_tools::TestMessage;
+
+    async fn inspect(
+        inspector: &dyn QueueInspector,
+        message_id: Uuid,
+    ) -> Result<(), InspectorError> {
+        assert_eq!(
+            inspector.status(message_id).await?,
+            Some(MessageStatus::Pending)
+        );
+
+        let listed = inspector.list(None, None, 10).await?;
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].id, message_id);
+
+        let timeline = inspector.timeline(message_id).await?;
+        assert_eq!(timeline[0].kind, TimelineEventKind::Published);
+
+        let lag = inspector.lag().await?;
+        assert_eq!(lag[0].unprocessed, 1);
+
+        let forecast = inspector
+            .retry_forecast(message_id)
+            .await?
+            .expect("Expected a forecast");
+        assert_eq!(forecast.remaining(), 3);
+
+        let now = Utc::now();
+        assert!(inspector.errors("", now, now, 10).await?.is_empty());
+        let today = now.date_naive();
+        assert!(inspector.stats(today, today).await?.is_empty());
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_inspects_a_postgres_queue(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let message = publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+        let inspector =
+            PgQueueInspector::new(pool, "public")?.with_default_settings(DefaultQueueSettings {
+                backoff_base: 2,
+                backoff_base_delay: std::time::Duration::from_secs(30),
+                max_attempts: 3,
+                hold_for: std::time::Duration::from_mins(5),
+            });
+
+        inspect(&inspector, message.id)
+            .await
+            .map_err(|error| anyhow::anyhow!(error))
+    }
+}
@@ -0,0 +1,255 @@
+use crate::diagnostics;
+use crate::listener::WorkerHealth;
+use crate::migrator::PgIdentifierParsingError;
+use crate::models::{Message, RawMessage};
+use crate::queries::Queries;
+use axum::Router;
+use axum::extract::{FromRef, FromRequestParts, State};
+use axum::http::StatusCode;
+use axum::http::request::Parts;
+use axum::routing::get;
+use chrono::Utc;
+use sqlx::PgPool;
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::Instrument;
+use uuid::Uuid;
+
+/// Header carrying the correlation id of a request
+pub const CORRELATION_ID_HEADER: &str = "x-correlation-id";
+
+#[derive(Debug, thiserror::Error)]
+pub enum PublishError {
+    #[error("SerializationError: {0}")]
+    Serialization(#[from] serde_json::Error),
+    #[error("DatabaseError: {0}")]
+    Database(#[from] sqlx::Error),
+    #[error("PublishError: {0}")]
+    Publish(#[from] crate::queries::PublishError),
+}
+
+/// Publishes messages from web handlers.
+///
+/// Add it to the application state and extract it in handlers. The extracted publisher is scoped to the request:
+/// it carries the correlation id of the [`CORRELATION_ID_HEADER`] header, or a new one if the header is missing,
+/// and records it on the tracing span of every publish.
+#[derive(Debug, Clone)]
+pub struct QueuePublisher {
+    pool: PgPool,
+    queries: Arc<Queries>,
+    correlation_id: Option<String>,
+}
+
+impl QueuePublisher {
+    pub fn new(pool: PgPool, schema: &str) -> Result<Self, PgIdentifierParsingError> {
+        Ok(Self {
+            pool,
+            queries: Arc::new(Queries::new(schema)?),
+            correlation_id: None,
+        })
+    }
+
+    /// Returns a publisher carrying the given correlation id
+    pub fn with_correlation_id(&self, correlation_id: impl Into<String>) -> Self {
+        Self {
+            correlation_id: Some(correlation_id.into()),
+            ..self.clone()
+        }
+    }
+
+    pub fn correlation_id(&self) -> Option<&str> {
+        self.correlation_id.as_deref()
+    }
+
+    /// Publishes a message in a transaction of its own and notifies listeners
+    pub async fn publish<T: Message>(&self, message: &T) -> Result<RawMessage, PublishError> {
+        let raw = RawMessage::from_message(message)?;
+        let span = tracing::info_span!(
+            "publish",
+            message_id = %raw.id,
+            name = T::NAME,
+            correlation_id = self.correlation_id.as_deref()
+        );
+
+        async {
+            let mut tx = self.pool.begin().await?;
+            let published = self.queries.publish_message(&mut tx, raw).await?;
+            tx.commit().await?;
+            Ok(published)
+        }
+        .instrument(span)
+        .await
+    }
+}
+
+impl<S> FromRequestParts<S> for QueuePublisher
+where
+    QueuePublisher: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let correlation_id = parts
+            .headers
+            .get(CORRELATION_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+            .unwrap_or_else(|| Uuid::now_v7().to_string());
+
+        Ok(QueuePublisher::from_ref(state).with_correlation_id(correlation_id))
+    }
+}
+
+/// State of the health probe routes, see [`health_router`]
+#[derive(Debug, Clone)]
+pub struct HealthState {
+    pub health: WorkerHealth,
+    pub pool: PgPool,
+    pub queries: Arc<Queries>,
+    /// Longest time without a poll before the worker is considered stuck
+    pub max_poll_age: Duration,
+}
+
+/// Routes for Kubernetes style probes: `/healthz` fails when the worker stopped polling and `/readyz` fails
+/// when the database is unreachable. Both respond with the health report as text.
+pub fn health_router(state: HealthState) -> Router {
+    Router::new()
+        .route("/healthz", get(liveness))
+        .route("/readyz", get(readiness))
+        .with_state(state)
+}
+
+/// State of the diagnostics route, see [`diagnostics_router`]
+#[derive(Debug, Clone)]
+pub struct DiagnosticsState {
+    pub pool: PgPool,
+    pub schema: String,
+}
+
+/// Route for incident triage: `/diagnostics` runs [`diagnostics::run`] and responds with the report as text, failing
+/// when a check fails. Runs several queries per request, so keep it off public listeners.
+pub fn diagnostics_router(state: DiagnosticsState) -> Router {
+    Router::new()
+        .route("/diagnostics", get(diagnose))
+        .with_state(state)
+}
+
+async fn diagnose(State(state): State<DiagnosticsState>) -> (StatusCode, String) {
+    match diagnostics::run(&state.pool, &state.schema).await {
+        Ok(report) if report.is_healthy() => (StatusCode::OK, report.to_string()),
+        Ok(report) => (StatusCode::SERVICE_UNAVAILABLE, report.to_string()),
+        Err(error) => (StatusCode::BAD_REQUEST, error.to_string()),
+    }
+}
+
+async fn liveness(State(state): State<HealthState>) -> (StatusCode, String) {
+    let now = Utc::now();
+    let report = state.health.report(&state.pool, &state.queries, now).await;
+    let status = match report.is_live(now, state.max_poll_age) {
+        true => StatusCode::OK,
+        false => StatusCode::SERVICE_UNAVAILABLE,
+    };
+
+    (status, format!("{report:?}"))
+}
+
+async fn readiness(State(state): State<HealthState>) -> (StatusCode, String) {
+    let report = state
+        .health
+        .report(&state.pool, &state.queries, Utc::now())
+        .await;
+    let status = match report.is_ready() {
+        true => StatusCode::OK,
+        false => StatusCode::SERVICE_UNAVAILABLE,
+    };
+
+    (status, format!("{report:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing_tools::{TestMessage, is_pending};
+    use axum::http::Request;
+
+    async fn extract(
+        publisher: &QueuePublisher,
+        request: Request<()>,
+    ) -> anyhow::Result<QueuePublisher> {
+        let (mut parts, _) = request.into_parts();
+        Ok(QueuePublisher::from_request_parts(&mut parts, publisher).await?)
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_uses_the_correlation_id_of_the_request(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let publisher = QueuePublisher::new(pool, "public")?;
+        let request = Request::builder()
+            .header(CORRELATION_ID_HEADER, "abc-123")
+            .body(())?;
+
+        let extracted = extract(&publisher, request).await?;
+        assert_eq!(extracted.correlation_id(), Some("abc-123"));
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_generates_missing_correlation_ids(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let publisher = QueuePublisher::new(pool, "public")?;
+
+        let extracted = extract(&publisher, Request::builder().body(())?).await?;
+        assert!(extracted.correlation_id().is_some());
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_publishes_messages(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let publisher = QueuePublisher::new(pool.clone(), "public")?;
+
+        let published = publisher.publish(&TestMessage::default()).await?;
+
+        assert_eq!(published.name, TestMessage::NAME);
+        assert!(is_pending(&pool, published.id, Utc::now()).await?);
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_probes_worker_health(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let state = HealthState {
+            health: WorkerHealth::new(),
+            pool,
+            queries: Arc::new(Queries::new("public")?),
+            max_poll_age: Duration::from_secs(30),
+        };
+
+        let (status, _) = liveness(State(state.clone())).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+
+        state.health.record_poll(Utc::now());
+        let (status, _) = liveness(State(state.clone())).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, _) = readiness(State(state)).await;
+        assert_eq!(status, StatusCode::OK);
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_serves_diagnostics(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let state = DiagnosticsState {
+            pool,
+            schema: "public".to_string(),
+        };
+
+        let (status, report) = diagnose(State(state)).await;
+        assert_eq!(status, StatusCode::OK, "{report}");
+        assert!(report.contains("notify"));
+
+        Ok(())
+    }
+}
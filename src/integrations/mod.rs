@@ -0,0 +1,4 @@
+//! Integrations with web frameworks, each behind a feature flag
+
+#[cfg(feature = "axum")]
+pub mod axum;
@@ -1,7 +1,26 @@
+pub mod attachments;
 pub mod backoff;
+pub mod bridges;
+pub mod compactor;
+pub mod compatibility;
 pub mod constants;
+pub mod diagnostics;
+#[cfg(feature = "encryption")]
+pub mod encryption;
+pub mod host_identity;
+pub mod inspector;
+pub mod integrations;
 pub mod listener;
 pub mod migrator;
 pub mod models;
+pub mod purger;
 pub mod queries;
+pub mod queue;
+pub mod replay;
+pub mod replication;
+pub mod router;
+#[cfg(feature = "schemas")]
+pub mod schema_registry;
 pub mod testing_tools;
+pub mod testkit;
+pub mod topology;
@@ -0,0 +1,126 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// Bounds and sensitivity of an [`AdaptiveHoldFor`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdaptiveHoldForConfig {
+    /// The lease duration of names without enough observed durations
+    pub initial: Duration,
+    /// The number of most recent durations kept per name
+    pub window: usize,
+    /// The number of durations a name needs before its lease duration adapts
+    pub min_samples: usize,
+    /// Multiplier applied to the p99 duration, leaving headroom for slower handling
+    pub factor: f64,
+    pub min: Duration,
+    pub max: Duration,
+}
+
+/// Lease durations per message name derived from the handler durations recently observed.
+///
+/// Leases are the p99 duration times a factor, capped to `min..=max`. Short leases for fast handlers make
+/// messages of crashed workers claimable sooner, while long enough leases for slow handlers avoid redelivering
+/// messages still being handled as missing. Clones share their observations.
+#[derive(Debug, Clone)]
+pub struct AdaptiveHoldFor {
+    config: AdaptiveHoldForConfig,
+    durations: Arc<RwLock<HashMap<String, VecDeque<Duration>>>>,
+}
+
+impl AdaptiveHoldFor {
+    pub fn new(config: AdaptiveHoldForConfig) -> Self {
+        Self {
+            config,
+            durations: Arc::default(),
+        }
+    }
+
+    /// Records how long handling a message named `name` took
+    pub fn record(&self, name: &str, duration: Duration) {
+        let mut durations = self.durations.write().expect("poisoned");
+        let recent = durations.entry(name.to_string()).or_default();
+
+        recent.push_back(duration);
+        while recent.len() > self.config.window.max(1) {
+            recent.pop_front();
+        }
+    }
+
+    /// The lease duration to claim messages named `name` with
+    pub fn hold_for(&self, name: &str) -> Duration {
+        let durations = self.durations.read().expect("poisoned");
+        let Some(recent) = durations.get(name) else {
+            return self.config.initial;
+        };
+        if recent.len() < self.config.min_samples.max(1) {
+            return self.config.initial;
+        }
+
+        let mut sorted: Vec<Duration> = recent.iter().copied().collect();
+        sorted.sort();
+        let p99 = sorted[(sorted.len() * 99).div_ceil(100) - 1];
+
+        p99.mul_f64(self.config.factor)
+            .clamp(self.config.min, self.config.max)
+    }
+
+    /// The lease duration for a claim that may return a message of any of `names`, the longest of theirs
+    pub fn hold_for_any(&self, names: &[String]) -> Duration {
+        names
+            .iter()
+            .map(|name| self.hold_for(name))
+            .max()
+            .unwrap_or(self.config.initial)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> AdaptiveHoldForConfig {
+        AdaptiveHoldForConfig {
+            initial: Duration::from_secs(30),
+            window: 100,
+            min_samples: 10,
+            factor: 2.0,
+            min: Duration::from_secs(1),
+            max: Duration::from_mins(5),
+        }
+    }
+
+    #[test]
+    fn it_adapts_to_observed_durations() {
+        let hold = AdaptiveHoldFor::new(config());
+        hold.record("fast", Duration::from_millis(100));
+        assert_eq!(hold.hold_for("fast"), Duration::from_secs(30));
+
+        for i in 1..=100 {
+            hold.record("fast", Duration::from_millis(10 * i));
+        }
+        // p99 of 10ms..=1000ms is 990ms, doubled
+        assert_eq!(hold.hold_for("fast"), Duration::from_millis(1980));
+
+        for _ in 0..100 {
+            hold.record("slow", Duration::from_mins(10));
+        }
+        assert_eq!(hold.hold_for("slow"), Duration::from_mins(5));
+
+        let names = ["fast".to_string(), "slow".to_string()];
+        assert_eq!(hold.hold_for_any(&names), Duration::from_mins(5));
+    }
+
+    #[test]
+    fn it_only_keeps_the_window() {
+        let hold = AdaptiveHoldFor::new(config());
+        for _ in 0..100 {
+            hold.record("a", Duration::from_mins(1));
+        }
+        for _ in 0..100 {
+            hold.record("a", Duration::from_secs(2));
+        }
+
+        assert_eq!(hold.hold_for("a"), Duration::from_secs(4));
+    }
+}
@@ -0,0 +1,274 @@
+use crate::listener::HandlerResult;
+use crate::models::RawMessage;
+use crate::queries::Queries;
+use chrono::Utc;
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use uuid::Uuid;
+
+/// A synchronous handler of CPU-bound work, run on the blocking thread pool
+pub type BlockingHandler = Arc<dyn Fn(&RawMessage) -> HandlerResult + Send + Sync>;
+
+/// Lease renewal and concurrency of [`BlockingHandlers`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockingConfig {
+    /// The duration of the leases renewed while a handler runs
+    pub hold_for: Duration,
+    /// How often the lease is renewed while a handler runs, well below `hold_for`
+    pub renew_every: Duration,
+    /// How many handlers may run at once, bounding the threads taken from the blocking pool
+    pub max_concurrent: usize,
+    /// How long to wait before retrying a message whose handler panicked
+    pub retry_panics_after: Duration,
+}
+
+/// The handlers of message names whose handling is CPU-bound, run with `spawn_blocking` rather than on the runtime.
+///
+/// A heavy handler run on the runtime starves the listener: claims, notifications and lease renewals of other
+/// messages wait for it to yield. Handlers registered here run on the blocking thread pool while the async side
+/// keeps renewing the lease of their message, so a long computation doesn't lose its lease.
+#[derive(Clone)]
+pub struct BlockingHandlers {
+    config: BlockingConfig,
+    handlers: HashMap<String, BlockingHandler>,
+    permits: Arc<Semaphore>,
+}
+
+impl BlockingHandlers {
+    pub fn new(config: BlockingConfig) -> Self {
+        let permits = Arc::new(Semaphore::new(config.max_concurrent.max(1)));
+        Self {
+            config,
+            handlers: HashMap::new(),
+            permits,
+        }
+    }
+
+    /// Registers a blocking handler for `name`, replacing any handler registered before
+    pub fn register<F>(mut self, name: impl Into<String>, handler: F) -> Self
+    where
+        F: Fn(&RawMessage) -> HandlerResult + Send + Sync + 'static,
+    {
+        self.handlers.insert(name.into(), Arc::new(handler));
+        self
+    }
+
+    /// Returns true if the messages named `name` are handled on the blocking pool
+    pub fn contains(&self, name: &str) -> bool {
+        self.handlers.contains_key(name)
+    }
+
+    /// The names of the messages handled on the blocking pool
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.handlers.keys().map(String::as_str)
+    }
+
+    /// Handles `message` on the blocking pool if a handler is registered for its name, None otherwise so that the
+    /// caller handles it on the runtime.
+    ///
+    /// The lease is renewed every `renew_every` until the handler returns. A lost lease is logged, the handler
+    /// can't be interrupted, and reporting its result is rejected by the fencing token. A panicking handler is
+    /// retried after `retry_panics_after`.
+    pub async fn handle<S>(
+        &self,
+        pool: &PgPool,
+        queries: &Queries<S>,
+        host_id: Uuid,
+        message: &RawMessage,
+    ) -> Option<HandlerResult> {
+        let handler = self.handlers.get(&message.name)?.clone();
+
+        let _permit = self
+            .permits
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("the semaphore is never closed");
+
+        let handled = {
+            let message = message.clone();
+            tokio::task::spawn_blocking(move || handler(&message))
+        };
+        tokio::pin!(handled);
+
+        let mut lease_lost = false;
+        let outcome = loop {
+            tokio::select! {
+                outcome = &mut handled => break outcome,
+                _ = tokio::time::sleep(self.config.renew_every), if !lease_lost => {
+                    lease_lost = !self.renew(pool, queries, host_id, message).await;
+                }
+            }
+        };
+
+        Some(match outcome {
+            Ok(result) => result,
+            Err(error) => {
+                tracing::error!(%error, message_id = %message.id, "Blocking handler panicked");
+                HandlerResult::Retry {
+                    reason: error.to_string(),
+                    after: self.config.retry_panics_after,
+                    category: Some("panic".to_string()),
+                }
+            }
+        })
+    }
+
+    /// Renews the lease of `message`, returns false once the lease is lost
+    async fn renew<S>(
+        &self,
+        pool: &PgPool,
+        queries: &Queries<S>,
+        host_id: Uuid,
+        message: &RawMessage,
+    ) -> bool {
+        let Some(fencing_token) = message.fencing_token else {
+            return true;
+        };
+
+        let renewed = async {
+            let mut tx = pool.begin().await?;
+            let expires_at = queries
+                .renew_lease(
+                    &mut tx,
+                    message.id,
+                    fencing_token,
+                    Utc::now(),
+                    self.config.hold_for,
+                )
+                .await?;
+            tx.commit().await?;
+            Ok::<_, sqlx::Error>(expires_at)
+        }
+        .await;
+
+        match renewed {
+            Ok(Some(_)) => true,
+            Ok(None) => {
+                tracing::warn!(message_id = %message.id, %host_id, "Lease lost while a blocking handler ran");
+                false
+            }
+            // Retried on the next tick, the lease may still be renewed before it expires
+            Err(error) => {
+                tracing::warn!(%error, message_id = %message.id, "Could not renew lease");
+                true
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing_tools::{TestMessage, is_failed, is_in_progress, publish_and_claim};
+
+    fn config() -> BlockingConfig {
+        BlockingConfig {
+            hold_for: Duration::from_millis(300),
+            renew_every: Duration::from_millis(50),
+            max_concurrent: 2,
+            retry_panics_after: Duration::from_secs(1),
+        }
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_renews_the_lease_while_a_handler_blocks(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let host_id = Uuid::now_v7();
+        let message = publish_and_claim(
+            &pool,
+            &TestMessage::default().to_raw()?,
+            host_id,
+            config().hold_for,
+        )
+        .await?;
+
+        let (check, checked) = std::sync::mpsc::channel::<()>();
+        let checked = std::sync::Mutex::new(checked);
+        let handlers = BlockingHandlers::new(config()).register(
+            message.name.clone(),
+            move |_: &RawMessage| {
+                // Blocks beyond the initial lease, until the lease was checked
+                checked
+                    .lock()
+                    .expect("poisoned")
+                    .recv()
+                    .expect("Expected a check");
+                HandlerResult::Success
+            },
+        );
+
+        let queries = Queries::new("public")?;
+        let handled = handlers.handle(&pool, &queries, host_id, &message);
+        let checking = async {
+            tokio::time::sleep(Duration::from_millis(600)).await;
+            let in_progress = is_in_progress(&pool, message.id, Utc::now()).await;
+            check.send(()).expect("Expected the handler to wait");
+            in_progress
+        };
+        let (result, in_progress) = tokio::join!(handled, checking);
+
+        assert!(in_progress?);
+        assert_eq!(result, Some(HandlerResult::Success));
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_skips_unregistered_names(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let host_id = Uuid::now_v7();
+        let message = publish_and_claim(
+            &pool,
+            &TestMessage::default().to_raw()?,
+            host_id,
+            config().hold_for,
+        )
+        .await?;
+
+        let handlers = BlockingHandlers::new(config())
+            .register("other", |_: &RawMessage| HandlerResult::Success);
+        assert!(!handlers.contains(&message.name));
+
+        let queries = Queries::new("public")?;
+        assert_eq!(
+            handlers.handle(&pool, &queries, host_id, &message).await,
+            None
+        );
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_retries_panicking_handlers(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let host_id = Uuid::now_v7();
+        let message = publish_and_claim(
+            &pool,
+            &TestMessage::default().to_raw()?,
+            host_id,
+            config().hold_for,
+        )
+        .await?;
+
+        let handlers =
+            BlockingHandlers::new(config()).register(message.name.clone(), |_: &RawMessage| {
+                panic!("Expected panic");
+            });
+
+        let queries = Queries::new("public")?;
+        let result = handlers
+            .handle(&pool, &queries, host_id, &message)
+            .await
+            .expect("Expected a result");
+        assert!(
+            matches!(result, HandlerResult::Retry { ref category, .. } if category.as_deref() == Some("panic"))
+        );
+
+        let now = Utc::now();
+        result.report(&pool, &message, now, host_id).await?;
+        assert!(is_failed(&pool, message.id, now).await?);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,145 @@
+use crate::models::RawMessage;
+use crate::queries::release_lease;
+use chrono::{DateTime, Utc};
+use sqlx::PgExecutor;
+use std::sync::Mutex;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Probabilities, between 0 and 1, of the faults injected by [`Chaos`]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChaosConfig {
+    /// Probability that a claim fails before reaching the database
+    pub claim_failure: f64,
+    /// Probability that a report is delayed by `report_delay`
+    pub delayed_report: f64,
+    pub report_delay: Duration,
+    /// Probability that the lease of a claimed message is dropped while it is being handled
+    pub dropped_lease: f64,
+}
+
+/// Injects faults into a listener, to verify that handlers and alerting behave under failure.
+///
+/// Call the hooks at the matching points of the poll loop: [`before_claim`](Self::before_claim) before claiming,
+/// [`after_claim`](Self::after_claim) once a message is claimed and [`before_report`](Self::before_report) before
+/// reporting its outcome. Faults are drawn from a seeded generator, so a run can be reproduced.
+#[derive(Debug)]
+pub struct Chaos {
+    config: ChaosConfig,
+    state: Mutex<u64>,
+}
+
+impl Chaos {
+    pub fn new(config: ChaosConfig, seed: u64) -> Self {
+        Self {
+            config,
+            // xorshift may not be seeded with zero
+            state: Mutex::new(seed.max(1)),
+        }
+    }
+
+    /// Fails with an injected error with probability `claim_failure`
+    pub fn before_claim(&self) -> Result<(), sqlx::Error> {
+        if self.roll(self.config.claim_failure) {
+            tracing::warn!("Chaos: injecting claim failure");
+            return Err(sqlx::Error::Protocol(
+                "chaos: injected claim failure".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Releases the lease of the message with probability `dropped_lease`, so that it may be claimed again while
+    /// it is still being handled. Returns true if the lease was dropped.
+    pub async fn after_claim<'tx, E: PgExecutor<'tx>>(
+        &self,
+        tx: E,
+        message: &RawMessage,
+        now: DateTime<Utc>,
+        host_id: Uuid,
+    ) -> Result<bool, sqlx::Error> {
+        if !self.roll(self.config.dropped_lease) {
+            return Ok(false);
+        }
+
+        tracing::warn!(message_id = %message.id, "Chaos: dropping lease");
+        release_lease(tx, message.id, message.fencing_token, now, host_id).await
+    }
+
+    /// Sleeps for `report_delay` with probability `delayed_report`
+    pub async fn before_report(&self) {
+        if self.roll(self.config.delayed_report) {
+            tracing::warn!(delay = ?self.config.report_delay, "Chaos: delaying report");
+            tokio::time::sleep(self.config.report_delay).await;
+        }
+    }
+
+    fn roll(&self, probability: f64) -> bool {
+        if probability <= 0.0 {
+            return false;
+        }
+
+        let mut state = self.state.lock().expect("poisoned");
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+
+        (*state >> 11) as f64 / (1u64 << 53) as f64 <= probability
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        queries::{get_next_unattempted, publish_message},
+        testing_tools::{TestMessage, is_missing},
+    };
+
+    #[test]
+    fn it_injects_faults_with_the_configured_probability() {
+        let chaos = Chaos::new(
+            ChaosConfig {
+                claim_failure: 0.25,
+                ..Default::default()
+            },
+            42,
+        );
+
+        let failures = (0..10_000)
+            .filter(|_| chaos.before_claim().is_err())
+            .count();
+        assert!((2_000..3_000).contains(&failures), "{failures} failures");
+    }
+
+    #[test]
+    fn it_never_injects_disabled_faults() {
+        let chaos = Chaos::new(ChaosConfig::default(), 42);
+
+        assert!((0..1_000).all(|_| chaos.before_claim().is_ok()));
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_drops_leases(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let chaos = Chaos::new(
+            ChaosConfig {
+                dropped_lease: 1.0,
+                ..Default::default()
+            },
+            42,
+        );
+        let host_id = Uuid::now_v7();
+
+        publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+        let now = Utc::now();
+        let claimed = get_next_unattempted(&pool, now, host_id, Duration::from_mins(1))
+            .await?
+            .unwrap();
+
+        assert!(chaos.after_claim(&pool, &claimed, now, host_id).await?);
+        assert!(is_missing(&pool, claimed.id, now).await?);
+
+        Ok(())
+    }
+}
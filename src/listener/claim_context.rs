@@ -0,0 +1,101 @@
+use chrono::{DateTime, Utc};
+use std::time::Duration;
+use tokio_util::sync::{CancellationToken, DropGuard};
+
+/// Information about a claimed message passed to handlers.
+///
+/// Carries the lease expiry and a cancellation token that is cancelled `margin` before the lease expires,
+/// allowing handlers to checkpoint or abort cleanly before the message is reclaimed by another host.
+#[derive(Debug)]
+pub struct ClaimContext {
+    lease_expires_at: DateTime<Utc>,
+    cancellation: CancellationToken,
+    _guard: DropGuard,
+}
+
+impl ClaimContext {
+    /// Creates a context for a lease expiring at `lease_expires_at`.
+    ///
+    /// Must be called from within a tokio runtime, the timer is stopped when the context is dropped.
+    pub fn new(lease_expires_at: DateTime<Utc>, margin: Duration) -> Self {
+        let cancellation = CancellationToken::new();
+        let stop = CancellationToken::new();
+
+        let cancel_in = (lease_expires_at - Utc::now())
+            .to_std()
+            .unwrap_or(Duration::ZERO)
+            .saturating_sub(margin);
+
+        let token = cancellation.clone();
+        let stopped = stop.clone();
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = tokio::time::sleep(cancel_in) => token.cancel(),
+                _ = stopped.cancelled() => {}
+            }
+        });
+
+        Self {
+            lease_expires_at,
+            cancellation,
+            _guard: stop.drop_guard(),
+        }
+    }
+
+    /// The time at which the lease of the claimed message expires
+    pub fn lease_expires_at(&self) -> DateTime<Utc> {
+        self.lease_expires_at
+    }
+
+    /// The time remaining until the lease expires, zero if it has already expired
+    pub fn remaining(&self, now: DateTime<Utc>) -> Duration {
+        (self.lease_expires_at - now)
+            .to_std()
+            .unwrap_or(Duration::ZERO)
+    }
+
+    /// Returns true once the lease is about to expire
+    pub fn is_cancelled(&self) -> bool {
+        self.cancellation.is_cancelled()
+    }
+
+    /// Resolves once the lease is about to expire
+    pub async fn cancelled(&self) {
+        self.cancellation.cancelled().await
+    }
+
+    /// A clone of the cancellation token, for passing into spawned tasks
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.cancellation.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn it_cancels_before_the_lease_expires() {
+        let lease_expires_at = Utc::now() + Duration::from_millis(30);
+        let context = ClaimContext::new(lease_expires_at, Duration::from_millis(20));
+
+        assert!(!context.is_cancelled());
+
+        tokio::time::timeout(Duration::from_millis(25), context.cancelled())
+            .await
+            .expect("Expected the token to be cancelled before the lease expired");
+
+        assert!(Utc::now() < lease_expires_at);
+    }
+
+    #[tokio::test]
+    async fn it_cancels_immediately_for_expired_leases() {
+        let context = ClaimContext::new(Utc::now(), Duration::from_millis(20));
+
+        tokio::time::timeout(Duration::from_millis(5), context.cancelled())
+            .await
+            .expect("Expected the token to be cancelled");
+
+        assert_eq!(context.remaining(Utc::now()), Duration::ZERO);
+    }
+}
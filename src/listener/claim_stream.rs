@@ -0,0 +1,242 @@
+use crate::models::RawMessage;
+use crate::queries::Queries;
+use chrono::Utc;
+use futures::Stream;
+use sqlx::PgPool;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_util::sync::{CancellationToken, DropGuard};
+use uuid::Uuid;
+
+/// Prefetching and lease renewal of a [`claim_stream`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClaimStreamConfig {
+    /// How many claimed messages may wait to be taken from the stream
+    pub prefetch: usize,
+    /// The duration of the leases of claimed messages
+    pub hold_for: Duration,
+    /// How often the leases of claimed messages are renewed, well below `hold_for`
+    pub renew_every: Duration,
+    /// How long to wait before claiming again when nothing was claimable
+    pub idle_interval: Duration,
+}
+
+/// A message claimed by a [`claim_stream`], whose lease is renewed until it is dropped
+#[derive(Debug)]
+pub struct ClaimedMessage {
+    pub message: RawMessage,
+    lost: CancellationToken,
+    _renewal: DropGuard,
+}
+
+impl ClaimedMessage {
+    /// Returns true once a renewal found the lease expired or replaced, after which the message may be handled by
+    /// another host and reports with its fencing token are rejected
+    pub fn is_lease_lost(&self) -> bool {
+        self.lost.is_cancelled()
+    }
+
+    /// Resolves once the lease is lost
+    pub async fn lease_lost(&self) {
+        self.lost.cancelled().await
+    }
+}
+
+/// Returns a stream of messages claimed from the schema of `queries`, so that consumers can handle messages with
+/// `while let Some(claimed) = stream.next().await` rather than a claim loop of their own.
+///
+/// A background task claims unattempted, then retryable messages, each in its own transaction, until `prefetch`
+/// claimed messages wait in the stream. Leases are renewed every `renew_every` from the claim until the
+/// [`ClaimedMessage`] is dropped, so report before dropping it. Claim errors are logged and retried after the idle
+/// interval. Dropping the stream stops claiming, and the leases of messages still waiting in it expire.
+pub fn claim_stream<S>(
+    pool: PgPool,
+    queries: Queries<S>,
+    host_id: Uuid,
+    config: ClaimStreamConfig,
+) -> impl Stream<Item = ClaimedMessage>
+where
+    S: Send + Sync + 'static,
+{
+    let (sender, mut receiver) = mpsc::channel::<ClaimedMessage>(config.prefetch.max(1));
+    let queries = Arc::new(queries);
+
+    tokio::spawn(async move {
+        loop {
+            let Ok(permit) = sender.reserve().await else {
+                break;
+            };
+
+            match claim(&pool, &queries, host_id, config.hold_for).await {
+                Ok(Some(message)) => {
+                    let claimed = renewed(&pool, &queries, host_id, &config, message);
+                    permit.send(claimed);
+                }
+                Ok(None) => {
+                    drop(permit);
+                    tokio::time::sleep(config.idle_interval).await;
+                }
+                Err(error) => {
+                    drop(permit);
+                    tracing::warn!(%error, "Could not claim a message");
+                    tokio::time::sleep(config.idle_interval).await;
+                }
+            }
+        }
+    });
+
+    futures::stream::poll_fn(move |cx| receiver.poll_recv(cx))
+}
+
+async fn claim<S>(
+    pool: &PgPool,
+    queries: &Queries<S>,
+    host_id: Uuid,
+    hold_for: Duration,
+) -> Result<Option<RawMessage>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let mut claimed = queries
+        .get_next_unattempted(&mut tx, Utc::now(), host_id, hold_for)
+        .await?;
+    if claimed.is_none() {
+        claimed = queries
+            .get_next_retryable(&mut tx, Utc::now(), host_id, hold_for)
+            .await?;
+    }
+    tx.commit().await?;
+
+    Ok(claimed)
+}
+
+/// Spawns the renewal of the lease of `message`, stopped when the returned message is dropped
+fn renewed<S>(
+    pool: &PgPool,
+    queries: &Arc<Queries<S>>,
+    host_id: Uuid,
+    config: &ClaimStreamConfig,
+    message: RawMessage,
+) -> ClaimedMessage
+where
+    S: Send + Sync + 'static,
+{
+    let lost = CancellationToken::new();
+    let stop = CancellationToken::new();
+
+    if let Some(fencing_token) = message.fencing_token {
+        let (pool, queries, lost, stop) =
+            (pool.clone(), queries.clone(), lost.clone(), stop.clone());
+        let (message_id, renew_every, hold_for) = (message.id, config.renew_every, config.hold_for);
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = stop.cancelled() => break,
+                    _ = tokio::time::sleep(renew_every) => {}
+                }
+
+                let renewed = async {
+                    let mut tx = pool.begin().await?;
+                    let expires_at = queries
+                        .renew_lease(&mut tx, message_id, fencing_token, Utc::now(), hold_for)
+                        .await?;
+                    tx.commit().await?;
+                    Ok::<_, sqlx::Error>(expires_at)
+                }
+                .await;
+
+                match renewed {
+                    Ok(Some(_)) => {}
+                    Ok(None) => {
+                        tracing::warn!(%message_id, %host_id, "Lease lost before the message was handled");
+                        lost.cancel();
+                        break;
+                    }
+                    // Retried on the next tick, the lease may still be renewed before it expires
+                    Err(error) => tracing::warn!(%error, %message_id, "Could not renew lease"),
+                }
+            }
+        });
+    }
+
+    ClaimedMessage {
+        message,
+        lost,
+        _renewal: stop.drop_guard(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queries::{publish_message, report_success};
+    use crate::testing_tools::{TestMessage, is_in_progress, is_missing, is_succeeded};
+    use futures::StreamExt;
+
+    fn config() -> ClaimStreamConfig {
+        ClaimStreamConfig {
+            prefetch: 2,
+            hold_for: Duration::from_millis(300),
+            renew_every: Duration::from_millis(50),
+            idle_interval: Duration::from_millis(10),
+        }
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_streams_claimed_messages(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let mut published = Vec::new();
+        for _ in 0..3 {
+            published.push(publish_message(&pool, &TestMessage::default().to_raw()?).await?);
+        }
+
+        let mut stream = Box::pin(claim_stream(
+            pool.clone(),
+            Queries::new("public")?,
+            Uuid::now_v7(),
+            config(),
+        ));
+
+        for expected in &published {
+            let claimed = tokio::time::timeout(Duration::from_secs(5), stream.next())
+                .await?
+                .expect("Expected a message");
+            assert_eq!(claimed.message.id, expected.id);
+            report_success(
+                &pool,
+                claimed.message.id,
+                claimed.message.fencing_token,
+                Utc::now(),
+            )
+            .await?;
+            assert!(is_succeeded(&pool, expected.id, Utc::now()).await?);
+        }
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_renews_leases_until_dropped(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let published = publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+
+        let mut stream = Box::pin(claim_stream(
+            pool.clone(),
+            Queries::new("public")?,
+            Uuid::now_v7(),
+            config(),
+        ));
+        let claimed = tokio::time::timeout(Duration::from_secs(5), stream.next())
+            .await?
+            .expect("Expected a message");
+
+        // Held beyond the initial lease
+        tokio::time::sleep(Duration::from_millis(600)).await;
+        assert!(is_in_progress(&pool, published.id, Utc::now()).await?);
+        assert!(!claimed.is_lease_lost());
+
+        drop(claimed);
+        drop(stream);
+        tokio::time::sleep(Duration::from_millis(600)).await;
+        assert!(is_missing(&pool, published.id, Utc::now()).await?);
+
+        Ok(())
+    }
+}
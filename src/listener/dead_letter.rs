@@ -0,0 +1,207 @@
+use crate::listener::HandlerResult;
+use crate::migrator::PgIdentifierParsingError;
+use crate::models::{PublishConflict, RawMessage};
+use crate::queries::{Queries, ReportError};
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+pub type SinkError = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum DeadLetterError {
+    #[error("ReportError: {0}")]
+    Report(#[from] ReportError),
+    #[error("SinkError: {0}")]
+    Sink(SinkError),
+}
+
+/// Destination for messages reported dead by a listener, such as object storage, a log pipeline or another queue.
+///
+/// Sinks are invoked by [`HandlerResult::report_with_dead_letters`] after the message has been reported dead
+/// but before the transaction is committed. A failing sink fails the report, so that rolling back the transaction
+/// keeps the message claimable and forwarding is retried. Sinks may therefore receive a message more than once.
+pub trait DeadLetterSink: Send + Sync {
+    fn forward<'a>(
+        &'a self,
+        message: &'a RawMessage,
+        reason: &'a str,
+        dead_at: DateTime<Utc>,
+    ) -> BoxFuture<'a, Result<(), SinkError>>;
+}
+
+/// Forwards dead messages to the queue of another schema, keeping their id, name and payload.
+///
+/// Forwarding commits on its own, before the report of the dead message. A message forwarded again because that
+/// report rolled back keeps the copy forwarded first, so the other queue receives each message once.
+#[derive(Debug)]
+pub struct QueueDeadLetterSink {
+    pool: PgPool,
+    queries: Queries,
+}
+
+impl QueueDeadLetterSink {
+    pub fn new(pool: PgPool, schema: &str) -> Result<Self, PgIdentifierParsingError> {
+        Ok(Self {
+            pool,
+            queries: Queries::new(schema)?,
+        })
+    }
+}
+
+impl DeadLetterSink for QueueDeadLetterSink {
+    fn forward<'a>(
+        &'a self,
+        message: &'a RawMessage,
+        _reason: &'a str,
+        _dead_at: DateTime<Utc>,
+    ) -> BoxFuture<'a, Result<(), SinkError>> {
+        Box::pin(async move {
+            let forwarded = RawMessage {
+                attempted: 0,
+                fencing_token: None,
+                max_attempts: None,
+                ..message.clone()
+            };
+
+            let mut tx = self.pool.begin().await?;
+            self.queries
+                .publish_message_on_conflict(&mut tx, &forwarded, PublishConflict::Ignore)
+                .await?;
+            tx.commit().await?;
+
+            Ok(())
+        })
+    }
+}
+
+impl HandlerResult {
+    /// Reports the outcome like [`report`](Self::report) and forwards messages reported dead to `sink`
+    pub async fn report_with_dead_letters<'tx, E: PgExecutor<'tx>>(
+        &self,
+        tx: E,
+        message: &RawMessage,
+        now: DateTime<Utc>,
+        host_id: Uuid,
+        sink: &dyn DeadLetterSink,
+    ) -> Result<(), DeadLetterError> {
+        self.report(tx, message, now, host_id).await?;
+
+        if let Self::Dead { reason, .. } = self {
+            sink.forward(message, reason, now)
+                .await
+                .map_err(DeadLetterError::Sink)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        migrator::run_migrations,
+        testing_tools::{claim, is_dead},
+    };
+
+    struct FailingSink;
+
+    impl DeadLetterSink for FailingSink {
+        fn forward<'a>(
+            &'a self,
+            _message: &'a RawMessage,
+            _reason: &'a str,
+            _dead_at: DateTime<Utc>,
+        ) -> BoxFuture<'a, Result<(), SinkError>> {
+            Box::pin(async { Err("unavailable".into()) })
+        }
+    }
+
+    fn dead() -> HandlerResult {
+        HandlerResult::Dead {
+            reason: "invalid".to_string(),
+            category: None,
+        }
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_forwards_dead_messages_to_another_queue(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        run_migrations(&pool, "dead_letters").await?;
+        let sink = QueueDeadLetterSink::new(pool.clone(), "dead_letters")?;
+
+        let host_id = Uuid::now_v7();
+        let message = claim(&pool, host_id).await?;
+        let now = Utc::now();
+
+        dead()
+            .report_with_dead_letters(&pool, &message, now, host_id, &sink)
+            .await?;
+
+        assert!(is_dead(&pool, message.id, now).await?);
+
+        let forwarded: (Uuid, String) =
+            sqlx::query_as("SELECT id, name FROM dead_letters.messages_unattempted")
+                .fetch_one(&pool)
+                .await?;
+        assert_eq!(forwarded, (message.id, message.name));
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_forwards_a_message_once(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        run_migrations(&pool, "dead_letters").await?;
+        let sink = QueueDeadLetterSink::new(pool.clone(), "dead_letters")?;
+
+        let message = claim(&pool, Uuid::now_v7()).await?;
+        let now = Utc::now();
+
+        // Forwarded again after the report of the dead message rolled back
+        sink.forward(&message, "invalid", now)
+            .await
+            .expect("Expected a forward");
+        sink.forward(&message, "invalid", now)
+            .await
+            .expect("Expected a forward");
+
+        let forwarded: i64 =
+            sqlx::query_scalar("SELECT COUNT(*) FROM dead_letters.messages_unattempted")
+                .fetch_one(&pool)
+                .await?;
+        assert_eq!(forwarded, 1);
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_fails_the_report_when_the_sink_fails(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let host_id = Uuid::now_v7();
+        let message = claim(&pool, host_id).await?;
+        let now = Utc::now();
+
+        let mut tx = pool.begin().await?;
+        let result = dead()
+            .report_with_dead_letters(&mut *tx, &message, now, host_id, &FailingSink)
+            .await;
+        assert!(matches!(result, Err(DeadLetterError::Sink(_))));
+        tx.rollback().await?;
+
+        assert!(!is_dead(&pool, message.id, now).await?);
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_does_not_forward_other_outcomes(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let host_id = Uuid::now_v7();
+        let message = claim(&pool, host_id).await?;
+
+        HandlerResult::Success
+            .report_with_dead_letters(&pool, &message, Utc::now(), host_id, &FailingSink)
+            .await?;
+
+        Ok(())
+    }
+}
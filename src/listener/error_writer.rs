@@ -0,0 +1,256 @@
+use crate::models::ErrorRecord;
+use crate::queries::Queries;
+use sqlx::PgPool;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+/// Capacity and batching of an [`ErrorWriter`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorWriterConfig {
+    /// How many errors may be buffered, bounding how far written errors may lag behind reports. Reports insert
+    /// their errors themselves while the buffer is full.
+    pub capacity: usize,
+    /// The maximum number of errors inserted in one statement
+    pub batch_size: usize,
+    /// How long the flusher waits before inserting a batch again after an insert failed
+    pub retry_interval: Duration,
+}
+
+impl Default for ErrorWriterConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 10_000,
+            batch_size: 500,
+            retry_interval: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Buffers the errors of reports to be inserted by an [`ErrorFlusher`] in the background, taking the error insert
+/// off the latency of every report during failure storms.
+///
+/// Enable it with [`Queries::with_error_writer`]: failed attempts, dead messages and leases are still reported
+/// synchronously, while their errors are written in batches. Errors are written at least once, they are kept
+/// until a batch inserting them succeeds, and are buffered as soon as the report succeeded, so an error may be
+/// written for a report whose transaction is rolled back. Once the flusher has stopped, errors are inserted with
+/// the report again, as they are while the buffer is full.
+#[derive(Debug, Clone)]
+pub struct ErrorWriter {
+    sender: mpsc::Sender<ErrorRecord>,
+}
+
+impl ErrorWriter {
+    /// Creates a writer and the flusher writing its errors, which must be [run](ErrorFlusher::run) for writes
+    /// to make progress
+    pub fn new(config: ErrorWriterConfig) -> (Self, ErrorFlusher) {
+        let (sender, receiver) = mpsc::channel(config.capacity.max(1));
+        (Self { sender }, ErrorFlusher { receiver, config })
+    }
+
+    /// Buffers `error`, waiting for room while the buffer is full. Returns the error back if the flusher has
+    /// stopped, so that the caller can insert it itself.
+    pub async fn write(&self, error: ErrorRecord) -> Result<(), ErrorRecord> {
+        self.sender.send(error).await.map_err(|e| e.0)
+    }
+
+    /// Buffers `error` if there is room. Returns the error back if the buffer is full or the flusher has stopped,
+    /// so that the caller can insert it itself.
+    pub fn try_write(&self, error: ErrorRecord) -> Result<(), ErrorRecord> {
+        self.sender.try_send(error).map_err(|e| e.into_inner())
+    }
+
+    /// The number of errors buffered and not yet taken by the flusher
+    pub fn buffered(&self) -> usize {
+        self.sender.max_capacity() - self.sender.capacity()
+    }
+}
+
+/// Inserts the errors buffered by an [`ErrorWriter`] in batches
+#[derive(Debug)]
+pub struct ErrorFlusher {
+    receiver: mpsc::Receiver<ErrorRecord>,
+    config: ErrorWriterConfig,
+}
+
+impl ErrorFlusher {
+    /// Inserts buffered errors into the schema of `queries` until cancelled, taking whatever is buffered up to the
+    /// batch size for each insert. Failed inserts are logged and retried after the retry interval.
+    ///
+    /// Once cancelled, further writes are refused and the errors still buffered are inserted before returning,
+    /// retrying until they are written.
+    pub async fn run<S>(
+        mut self,
+        pool: &PgPool,
+        queries: &Queries<S>,
+        cancellation: CancellationToken,
+    ) {
+        let batch_size = self.config.batch_size.max(1);
+        let mut batch = Vec::with_capacity(batch_size);
+
+        loop {
+            if batch.is_empty() {
+                let received = tokio::select! {
+                    _ = cancellation.cancelled() => break,
+                    received = self.receiver.recv_many(&mut batch, batch_size) => received,
+                };
+                if received == 0 {
+                    break;
+                }
+            }
+            if self.flush(pool, queries, &batch).await {
+                batch.clear();
+            } else {
+                tokio::select! {
+                    _ = cancellation.cancelled() => break,
+                    _ = tokio::time::sleep(self.config.retry_interval) => {}
+                }
+            }
+        }
+
+        self.receiver.close();
+        loop {
+            if batch.is_empty() && self.receiver.recv_many(&mut batch, batch_size).await == 0 {
+                break;
+            }
+            if self.flush(pool, queries, &batch).await {
+                batch.clear();
+            } else {
+                tokio::time::sleep(self.config.retry_interval).await;
+            }
+        }
+    }
+
+    /// Inserts `batch`, returning whether it was written
+    async fn flush<S>(&self, pool: &PgPool, queries: &Queries<S>, batch: &[ErrorRecord]) -> bool {
+        let inserted = async {
+            let mut tx = pool.begin().await?;
+            queries.insert_errors(&mut tx, batch).await?;
+            tx.commit().await
+        };
+
+        match inserted.await {
+            Ok(()) => true,
+            Err(error) => {
+                tracing::warn!(%error, errors = batch.len(), "Could not insert buffered errors");
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::MessageStatus;
+    use crate::queries::{get_message_status, get_next_unattempted, publish_message};
+    use crate::testing_tools::TestMessage;
+    use chrono::Utc;
+    use uuid::Uuid;
+
+    async fn count_errors(pool: &PgPool) -> anyhow::Result<i64> {
+        Ok(sqlx::query_scalar("SELECT COUNT(*) FROM errors")
+            .fetch_one(pool)
+            .await?)
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_writes_errors_of_reports_in_the_background(
+        pool: sqlx::PgPool,
+    ) -> anyhow::Result<()> {
+        let (writer, flusher) = ErrorWriter::new(ErrorWriterConfig::default());
+        let queries = Queries::new("public")?.with_error_writer(writer.clone());
+        let host_id = Uuid::now_v7();
+
+        let mut claimed = Vec::new();
+        for _ in 0..2 {
+            publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+            claimed.push(
+                get_next_unattempted(&pool, Utc::now(), host_id, Duration::from_mins(1))
+                    .await?
+                    .expect("Expected a message"),
+            );
+        }
+
+        let now = Utc::now();
+        let mut tx = pool.begin().await?;
+        queries
+            .report_retryable(&mut tx, &claimed[0], now, now, "timeout")
+            .await?;
+        queries
+            .report_dead(&mut tx, &claimed[1], now, "invalid")
+            .await?;
+        tx.commit().await?;
+
+        // The attempts are reported right away, the errors once flushed
+        let status = |id| get_message_status(&pool, id, now);
+        assert_eq!(status(claimed[0].id).await?, Some(MessageStatus::Failed));
+        assert_eq!(status(claimed[1].id).await?, Some(MessageStatus::Dead));
+        assert_eq!(count_errors(&pool).await?, 0);
+        assert_eq!(writer.buffered(), 2);
+
+        let cancellation = CancellationToken::new();
+        cancellation.cancel();
+        flusher.run(&pool, &queries, cancellation).await;
+        assert_eq!(count_errors(&pool).await?, 2);
+
+        // Once the flusher stopped, errors are inserted with the report
+        publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+        let message = get_next_unattempted(&pool, Utc::now(), host_id, Duration::from_mins(1))
+            .await?
+            .expect("Expected a message");
+        let mut tx = pool.begin().await?;
+        queries
+            .report_dead(&mut tx, &message, Utc::now(), "invalid")
+            .await?;
+        tx.commit().await?;
+        assert_eq!(count_errors(&pool).await?, 3);
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_inserts_capped_errors_with_the_report_while_the_buffer_is_full(
+        pool: sqlx::PgPool,
+    ) -> anyhow::Result<()> {
+        let (writer, flusher) = ErrorWriter::new(ErrorWriterConfig {
+            capacity: 1,
+            ..Default::default()
+        });
+        let queries = Queries::new("public")?
+            .with_error_writer(writer.clone())
+            .with_error_history_cap(Some(1));
+        let host_id = Uuid::now_v7();
+
+        publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+        let mut message = get_next_unattempted(&pool, Utc::now(), host_id, Duration::from_mins(1))
+            .await?
+            .expect("Expected a message");
+
+        let start = Utc::now();
+        for attempt in 1..=2 {
+            let failed_at = start + Duration::from_secs(attempt);
+            let mut tx = pool.begin().await?;
+            queries
+                .report_retryable(&mut tx, &message, failed_at, failed_at, "timeout")
+                .await?;
+            message = queries
+                .get_next_retryable(&mut tx, failed_at, host_id, Duration::from_mins(1))
+                .await?
+                .expect("Expected a retryable message");
+            tx.commit().await?;
+        }
+
+        // The first error is buffered, the second one inserted with its report
+        assert_eq!(writer.buffered(), 1);
+        assert_eq!(count_errors(&pool).await?, 1);
+
+        // The buffered error is older than the one kept by the cap
+        let cancellation = CancellationToken::new();
+        cancellation.cancel();
+        flusher.run(&pool, &queries, cancellation).await;
+        assert_eq!(count_errors(&pool).await?, 1);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,147 @@
+use crate::models::RawMessage;
+use crate::queries::Queries;
+use chrono::{DateTime, Utc};
+use sqlx::PgTransaction;
+use std::collections::BTreeSet;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use uuid::Uuid;
+
+#[derive(Debug, Default)]
+struct Registered {
+    names: BTreeSet<String>,
+    /// The claim filter, regenerated whenever a name is registered or deregistered
+    filter: Arc<[String]>,
+}
+
+/// The message names a listener has handlers for, used as the filter of its claims.
+///
+/// Handlers may be registered and deregistered at runtime, e.g. as plugins are loaded. The filter is cached and
+/// only regenerated when the set of names changes, so claiming does not allocate.
+#[derive(Debug, Clone, Default)]
+pub struct HandlerNames {
+    registered: Arc<RwLock<Registered>>,
+}
+
+impl HandlerNames {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a handler for `name`, returns false if one was already registered
+    pub fn register(&self, name: impl Into<String>) -> bool {
+        let mut registered = self.registered.write().expect("poisoned");
+        let inserted = registered.names.insert(name.into());
+        if inserted {
+            registered.filter = registered.names.iter().cloned().collect();
+        }
+        inserted
+    }
+
+    /// Deregisters the handler for `name`, returns false if none was registered
+    pub fn deregister(&self, name: &str) -> bool {
+        let mut registered = self.registered.write().expect("poisoned");
+        let removed = registered.names.remove(name);
+        if removed {
+            registered.filter = registered.names.iter().cloned().collect();
+        }
+        removed
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.registered
+            .read()
+            .expect("poisoned")
+            .names
+            .contains(name)
+    }
+
+    /// The names to filter claims by
+    pub fn filter(&self) -> Arc<[String]> {
+        self.registered.read().expect("poisoned").filter.clone()
+    }
+
+    /// Claims the next unattempted message that has a registered handler.
+    ///
+    /// Returns None without querying if no handlers are registered.
+    pub async fn claim_next<S>(
+        &self,
+        tx: &mut PgTransaction<'_>,
+        queries: &Queries<S>,
+        now: DateTime<Utc>,
+        host_id: Uuid,
+        hold_for: Duration,
+    ) -> Result<Option<RawMessage>, sqlx::Error> {
+        let filter = self.filter();
+        if filter.is_empty() {
+            return Ok(None);
+        }
+
+        queries
+            .get_next_unattempted_named(tx, now, host_id, hold_for, &filter)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Message;
+    use crate::testing_tools::TestMessage;
+
+    #[test]
+    fn it_regenerates_the_filter_on_changes() {
+        let names = HandlerNames::new();
+
+        assert!(names.register("b"));
+        assert!(names.register("a"));
+        assert!(!names.register("a"));
+        assert_eq!(&*names.filter(), ["a".to_string(), "b".to_string()]);
+
+        let filter = names.filter();
+        assert!(!names.deregister("c"));
+        assert!(Arc::ptr_eq(&filter, &names.filter()));
+
+        assert!(names.deregister("a"));
+        assert_eq!(&*names.filter(), ["b".to_string()]);
+        assert!(!names.contains("a"));
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_claims_registered_names_only(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let names = HandlerNames::new();
+        let queries = Queries::new("public")?;
+        let host_id = Uuid::now_v7();
+
+        let mut tx = pool.begin().await?;
+        queries
+            .publish_message(&mut tx, TestMessage::default().to_raw()?)
+            .await?;
+
+        let claimed = names
+            .claim_next(
+                &mut tx,
+                &queries,
+                Utc::now(),
+                host_id,
+                Duration::from_mins(1),
+            )
+            .await?;
+        assert!(claimed.is_none());
+
+        names.register(TestMessage::NAME);
+        let claimed = names
+            .claim_next(
+                &mut tx,
+                &queries,
+                Utc::now(),
+                host_id,
+                Duration::from_mins(1),
+            )
+            .await?;
+        assert!(claimed.is_some());
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,247 @@
+use crate::models::{FailureReason, RawMessage};
+use crate::queries::{
+    Queries, ReportError, release_lease, report_dead, report_deferred, report_retryable,
+    report_success,
+};
+use chrono::{DateTime, Utc};
+use sqlx::{PgExecutor, PgTransaction};
+use std::time::Duration;
+use uuid::Uuid;
+
+/// The outcome of handling a claimed message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HandlerResult {
+    /// The message was handled
+    Success,
+    /// Handling failed, the message may be retried after the given delay. The optional category, e.g. `timeout`,
+    /// is recorded with the error, see [`FailureReason`].
+    Retry {
+        reason: String,
+        after: Duration,
+        category: Option<String>,
+    },
+    /// Handling failed and should not be retried
+    Dead {
+        reason: String,
+        category: Option<String>,
+    },
+    /// The message can't be handled yet, claim it again from `until` without counting the attempt
+    Defer { until: DateTime<Utc> },
+    /// The message was not handled, release the lease so that it may be claimed again right away
+    Skip,
+}
+
+impl HandlerResult {
+    /// Reports the outcome of handling a message claimed by `host_id`, passing on its fencing token.
+    ///
+    /// Skipping a message whose lease is no longer held by the claim that returned it is rejected with
+    /// [`ReportError::StaleFencingToken`], like the reports of the other outcomes.
+    pub async fn report<'tx, E: PgExecutor<'tx>>(
+        &self,
+        tx: E,
+        message: &RawMessage,
+        now: DateTime<Utc>,
+        host_id: Uuid,
+    ) -> Result<(), ReportError> {
+        match self {
+            Self::Success => {
+                report_success(tx, message.id, message.fencing_token, now).await?;
+            }
+            Self::Retry {
+                reason,
+                after,
+                category,
+            } => {
+                report_retryable(
+                    tx,
+                    message.id,
+                    message.fencing_token,
+                    now,
+                    message.attempted + 1,
+                    now + *after,
+                    failure_reason(reason, category),
+                )
+                .await?;
+            }
+            Self::Dead { reason, category } => {
+                report_dead(
+                    tx,
+                    message.id,
+                    message.fencing_token,
+                    now,
+                    failure_reason(reason, category),
+                )
+                .await?;
+            }
+            Self::Defer { until } => {
+                report_deferred(
+                    tx,
+                    message.id,
+                    message.fencing_token,
+                    now,
+                    message.attempted,
+                    *until,
+                )
+                .await?;
+            }
+            Self::Skip => {
+                if !release_lease(tx, message.id, message.fencing_token, now, host_id).await? {
+                    return Err(ReportError::StaleFencingToken(message.id));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reports the outcome like [`report`](Self::report), through `queries` so that its schema and options apply
+    pub async fn report_with<S>(
+        &self,
+        tx: &mut PgTransaction<'_>,
+        queries: &Queries<S>,
+        message: &RawMessage,
+        now: DateTime<Utc>,
+        host_id: Uuid,
+    ) -> Result<(), ReportError> {
+        match self {
+            Self::Success => queries.report_success(tx, message, now).await,
+            Self::Retry {
+                reason,
+                after,
+                category,
+            } => {
+                queries
+                    .report_retryable(
+                        tx,
+                        message,
+                        now,
+                        now + *after,
+                        failure_reason(reason, category),
+                    )
+                    .await
+            }
+            Self::Dead { reason, category } => {
+                queries
+                    .report_dead(tx, message, now, failure_reason(reason, category))
+                    .await
+            }
+            Self::Defer { until } => queries.report_deferred(tx, message, now, *until).await,
+            Self::Skip => {
+                if !queries
+                    .release_lease(tx, message.id, message.fencing_token, now, host_id)
+                    .await?
+                {
+                    return Err(ReportError::StaleFencingToken(message.id));
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+fn failure_reason<'a>(reason: &'a str, category: &'a Option<String>) -> FailureReason<'a> {
+    FailureReason {
+        error: reason,
+        category: category.as_deref(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queries::get_next_missing;
+    use crate::testing_tools::{
+        claim, is_dead, is_failed, is_in_progress, is_missing, is_succeeded,
+    };
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_reports_each_outcome(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let host_id = Uuid::now_v7();
+        let outcomes = [
+            HandlerResult::Success,
+            HandlerResult::Retry {
+                reason: "timeout".to_string(),
+                after: Duration::from_secs(10),
+                category: Some("timeout".to_string()),
+            },
+            HandlerResult::Dead {
+                reason: "invalid".to_string(),
+                category: None,
+            },
+            HandlerResult::Defer {
+                until: Utc::now() + Duration::from_mins(5),
+            },
+            HandlerResult::Skip,
+        ];
+
+        for outcome in outcomes {
+            let message = claim(&pool, host_id).await?;
+            let now = Utc::now();
+            outcome.report(&pool, &message, now, host_id).await?;
+
+            let settled = match outcome {
+                HandlerResult::Success => is_succeeded(&pool, message.id, now).await?,
+                HandlerResult::Retry { .. } | HandlerResult::Defer { .. } => {
+                    is_failed(&pool, message.id, now).await?
+                }
+                HandlerResult::Dead { .. } => is_dead(&pool, message.id, now).await?,
+                HandlerResult::Skip => is_missing(&pool, message.id, now).await?,
+            };
+            assert!(settled, "unexpected state after {outcome:?}");
+        }
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_rejects_releasing_a_lost_lease(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let host_id = Uuid::now_v7();
+        let expired = Utc::now() + Duration::from_mins(2);
+
+        let message = claim(&pool, host_id).await?;
+        let result = HandlerResult::Skip
+            .report(&pool, &message, expired, host_id)
+            .await;
+        assert!(matches!(result, Err(ReportError::StaleFencingToken(id)) if id == message.id));
+
+        let message = claim(&pool, host_id).await?;
+        let mut tx = pool.begin().await?;
+        let result = HandlerResult::Skip
+            .report_with(
+                &mut tx,
+                &Queries::new("public")?,
+                &message,
+                expired,
+                host_id,
+            )
+            .await;
+        assert!(matches!(result, Err(ReportError::StaleFencingToken(id)) if id == message.id));
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_rejects_skipping_a_later_claim(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let host_id = Uuid::now_v7();
+        let stale = claim(&pool, host_id).await?;
+        let expired = Utc::now() + Duration::from_mins(2);
+        let current = get_next_missing(&pool, expired, host_id, Duration::from_mins(1))
+            .await?
+            .expect("Expected the expired message to be reclaimed");
+
+        let result = HandlerResult::Skip
+            .report(&pool, &stale, expired, host_id)
+            .await;
+        assert!(matches!(result, Err(ReportError::StaleFencingToken(id)) if id == stale.id));
+
+        let mut tx = pool.begin().await?;
+        let result = HandlerResult::Skip
+            .report_with(&mut tx, &Queries::new("public")?, &stale, expired, host_id)
+            .await;
+        assert!(matches!(result, Err(ReportError::StaleFencingToken(id)) if id == stale.id));
+        tx.rollback().await?;
+
+        assert!(is_in_progress(&pool, current.id, expired).await?);
+        Ok(())
+    }
+}
@@ -0,0 +1,156 @@
+use crate::queries::Queries;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Health data of a worker, for liveness and readiness probes
+#[derive(Debug, Clone, PartialEq)]
+pub struct HealthReport {
+    /// The time of the most recent poll, None if the worker never polled
+    pub last_poll_at: Option<DateTime<Utc>>,
+    /// The number of messages being handled
+    pub in_flight: usize,
+    /// Whether the database could be queried
+    pub database_reachable: bool,
+    /// How long the longest waiting claimable message has been waiting, None if nothing is claimable or the
+    /// database is unreachable
+    pub lag: Option<Duration>,
+}
+
+impl HealthReport {
+    /// A worker is live as long as it keeps polling, a stuck worker should be restarted
+    pub fn is_live(&self, now: DateTime<Utc>, max_poll_age: Duration) -> bool {
+        self.last_poll_at
+            .is_some_and(|last_poll_at| last_poll_at + max_poll_age >= now)
+    }
+
+    /// A worker is ready when it can reach the database
+    pub fn is_ready(&self) -> bool {
+        self.database_reachable
+    }
+}
+
+/// Tracks the activity of a worker, shared between its poll loop and the health probes.
+///
+/// The poll loop calls [`record_poll`](Self::record_poll) on every poll and holds an [`InFlight`] guard
+/// while handling a message.
+#[derive(Debug, Clone, Default)]
+pub struct WorkerHealth {
+    last_poll_at: Arc<Mutex<Option<DateTime<Utc>>>>,
+    in_flight: Arc<AtomicUsize>,
+}
+
+impl WorkerHealth {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record_poll(&self, at: DateTime<Utc>) {
+        *self.last_poll_at.lock().expect("poisoned") = Some(at);
+    }
+
+    /// Counts a message as in flight until the returned guard is dropped
+    pub fn start_handling(&self) -> InFlight {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        InFlight {
+            in_flight: self.in_flight.clone(),
+        }
+    }
+
+    pub fn last_poll_at(&self) -> Option<DateTime<Utc>> {
+        *self.last_poll_at.lock().expect("poisoned")
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Reports the health of the worker, querying the database of `queries` for connectivity and lag
+    pub async fn report<S>(
+        &self,
+        pool: &PgPool,
+        queries: &Queries<S>,
+        now: DateTime<Utc>,
+    ) -> HealthReport {
+        let oldest = async {
+            let mut tx = pool.begin().await?;
+            let oldest = queries.get_oldest_claimable(&mut tx, now).await?;
+            tx.rollback().await?;
+            Ok::<_, sqlx::Error>(oldest)
+        }
+        .await;
+
+        if let Err(error) = &oldest {
+            tracing::warn!(%error, "Health check could not query the database");
+        }
+
+        HealthReport {
+            last_poll_at: self.last_poll_at(),
+            in_flight: self.in_flight(),
+            database_reachable: oldest.is_ok(),
+            lag: oldest
+                .ok()
+                .flatten()
+                .and_then(|oldest| (now - oldest).to_std().ok()),
+        }
+    }
+}
+
+/// A message counted as in flight by [`WorkerHealth`], see [`WorkerHealth::start_handling`]
+#[derive(Debug)]
+pub struct InFlight {
+    in_flight: Arc<AtomicUsize>,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{queries::publish_message, testing_tools::TestMessage};
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_reports_worker_health(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let health = WorkerHealth::new();
+        let queries = Queries::new("public")?;
+
+        publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+        let now = Utc::now() + Duration::from_secs(5);
+
+        health.record_poll(now);
+        let in_flight = health.start_handling();
+
+        let report = health.report(&pool, &queries, now).await;
+        assert_eq!(report.in_flight, 1);
+        assert!(report.is_ready());
+        assert!(report.is_live(now, Duration::from_secs(30)));
+        assert!(report.lag.is_some_and(|lag| lag >= Duration::from_secs(5)));
+
+        drop(in_flight);
+        assert_eq!(health.in_flight(), 0);
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_is_not_live_without_recent_polls(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let health = WorkerHealth::new();
+        let queries = Queries::new("public")?;
+        let now = Utc::now();
+
+        let report = health.report(&pool, &queries, now).await;
+        assert!(!report.is_live(now, Duration::from_secs(30)));
+
+        health.record_poll(now - Duration::from_mins(1));
+        let report = health.report(&pool, &queries, now).await;
+        assert!(!report.is_live(now, Duration::from_secs(30)));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,300 @@
+use crate::listener::HandlerResult;
+use crate::models::RawMessage;
+use crate::queries::{PublishError, Queries, set_schema_for_transaction};
+use chrono::Utc;
+use futures::future::BoxFuture;
+use sqlx::{Connection, PgConnection, PgTransaction};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// A handler run within the transaction that publishes the message, see [`publish_and_process_inline`].
+///
+/// Handlers receive the application context of their [`InlineRegistry`], such as pools, clients and configuration.
+pub trait InlineHandler<C = ()>: Send + Sync {
+    fn handle<'a>(
+        &'a self,
+        context: Arc<C>,
+        conn: &'a mut PgConnection,
+        message: &'a RawMessage,
+    ) -> BoxFuture<'a, HandlerResult>;
+}
+
+impl<C, F> InlineHandler<C> for F
+where
+    F: for<'a> Fn(Arc<C>, &'a mut PgConnection, &'a RawMessage) -> BoxFuture<'a, HandlerResult>
+        + Send
+        + Sync,
+{
+    fn handle<'a>(
+        &'a self,
+        context: Arc<C>,
+        conn: &'a mut PgConnection,
+        message: &'a RawMessage,
+    ) -> BoxFuture<'a, HandlerResult> {
+        self(context, conn, message)
+    }
+}
+
+/// Handlers by message name for [`publish_and_process_inline`], the application context passed to them, and whether
+/// to run them inline at all
+pub struct InlineRegistry<C = ()> {
+    inline: bool,
+    context: Arc<C>,
+    handlers: HashMap<String, Arc<dyn InlineHandler<C>>>,
+}
+
+impl<C> Clone for InlineRegistry<C> {
+    fn clone(&self) -> Self {
+        Self {
+            inline: self.inline,
+            context: self.context.clone(),
+            handlers: self.handlers.clone(),
+        }
+    }
+}
+
+impl Default for InlineRegistry {
+    fn default() -> Self {
+        Self::with_context(Arc::new(()))
+    }
+}
+
+impl InlineRegistry {
+    /// Creates a registry without context, with inline mode disabled, publishing every message to the queue
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<C> InlineRegistry<C> {
+    /// Creates a registry passing `context` to its handlers, with inline mode disabled
+    pub fn with_context(context: Arc<C>) -> Self {
+        Self {
+            inline: false,
+            context,
+            handlers: HashMap::new(),
+        }
+    }
+
+    /// Runs registered handlers inline when enabled
+    pub fn with_inline(mut self, enabled: bool) -> Self {
+        self.inline = enabled;
+        self
+    }
+
+    /// Registers the handler of messages named `name`, replacing any handler registered for it
+    pub fn register(
+        mut self,
+        name: impl Into<String>,
+        handler: impl InlineHandler<C> + 'static,
+    ) -> Self {
+        self.handlers.insert(name.into(), Arc::new(handler));
+        self
+    }
+
+    pub fn is_inline(&self) -> bool {
+        self.inline
+    }
+
+    pub fn context(&self) -> &Arc<C> {
+        &self.context
+    }
+
+    /// The names of the messages with a registered handler, inline or not
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.handlers.keys().map(String::as_str)
+    }
+
+    fn handler(&self, name: &str) -> Option<&Arc<dyn InlineHandler<C>>> {
+        self.inline.then(|| self.handlers.get(name)).flatten()
+    }
+}
+
+/// Where [`publish_and_process_inline`] left a message
+#[derive(Debug, Clone)]
+pub enum InlineOutcome {
+    /// The message was handled inline and recorded as succeeded
+    Handled(RawMessage),
+    /// The message was published to the queue, either because it wasn't handled inline or the handler did not
+    /// succeed
+    Queued(RawMessage),
+}
+
+/// Publishes a message and, in inline mode, immediately runs its handler in the same transaction, bypassing the
+/// queue.
+///
+/// The handler runs in a savepoint. If it succeeds the message is recorded as published and succeeded, see
+/// [`publish_succeeded`](crate::queries::publish_succeeded). Otherwise the savepoint, with anything the handler
+/// wrote, is rolled back and the message is published to the queue, to be handled by the workers as usual. The
+/// same happens when inline mode is disabled or no handler is registered for the message.
+pub async fn publish_and_process_inline<S, C>(
+    tx: &mut PgTransaction<'_>,
+    queries: &Queries<S>,
+    message: RawMessage,
+    registry: &InlineRegistry<C>,
+) -> Result<InlineOutcome, PublishError> {
+    if let Some(handler) = registry.handler(&message.name) {
+        set_schema_for_transaction(tx, queries.schema()).await?;
+
+        let mut savepoint = tx.begin().await?;
+        let result = handler
+            .handle(registry.context.clone(), &mut savepoint, &message)
+            .await;
+
+        if result == HandlerResult::Success {
+            savepoint.commit().await?;
+            queries.publish_succeeded(tx, &message, Utc::now()).await?;
+            return Ok(InlineOutcome::Handled(message));
+        }
+
+        savepoint.rollback().await?;
+        tracing::debug!(message_id = %message.id, ?result, "Inline handling did not succeed, queueing");
+    }
+
+    let published = queries.publish_message(tx, message).await?;
+    Ok(InlineOutcome::Queued(published))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Message;
+    use crate::testing_tools::{TestMessage, is_pending, is_succeeded};
+    use futures::FutureExt;
+
+    fn succeeding<'a>(
+        _: Arc<()>,
+        conn: &'a mut PgConnection,
+        _: &'a RawMessage,
+    ) -> BoxFuture<'a, HandlerResult> {
+        async move {
+            sqlx::query("CREATE TABLE handled (id INT)")
+                .execute(conn)
+                .await
+                .expect("Expected to write");
+            HandlerResult::Success
+        }
+        .boxed()
+    }
+
+    fn failing<'a>(
+        _: Arc<()>,
+        conn: &'a mut PgConnection,
+        _: &'a RawMessage,
+    ) -> BoxFuture<'a, HandlerResult> {
+        async move {
+            sqlx::query("CREATE TABLE handled (id INT)")
+                .execute(conn)
+                .await
+                .expect("Expected to write");
+            HandlerResult::Dead {
+                reason: "failed".to_string(),
+                category: None,
+            }
+        }
+        .boxed()
+    }
+
+    async fn handled_table_exists(pool: &sqlx::PgPool) -> anyhow::Result<bool> {
+        Ok(
+            sqlx::query_scalar("SELECT to_regclass('handled') IS NOT NULL")
+                .fetch_one(pool)
+                .await?,
+        )
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_handles_messages_inline(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let queries = Queries::new("public")?;
+        let registry = InlineRegistry::new()
+            .with_inline(true)
+            .register(TestMessage::NAME, succeeding);
+
+        let message = TestMessage::default().to_raw()?;
+        let mut tx = pool.begin().await?;
+        let outcome =
+            publish_and_process_inline(&mut tx, &queries, message.clone(), &registry).await?;
+        tx.commit().await?;
+
+        assert!(matches!(outcome, InlineOutcome::Handled(_)));
+        assert!(is_succeeded(&pool, message.id, Utc::now()).await?);
+        assert!(handled_table_exists(&pool).await?);
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_queues_messages_the_handler_fails(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let queries = Queries::new("public")?;
+        let registry = InlineRegistry::new()
+            .with_inline(true)
+            .register(TestMessage::NAME, failing);
+
+        let message = TestMessage::default().to_raw()?;
+        let mut tx = pool.begin().await?;
+        let outcome =
+            publish_and_process_inline(&mut tx, &queries, message.clone(), &registry).await?;
+        tx.commit().await?;
+
+        assert!(matches!(outcome, InlineOutcome::Queued(_)));
+        assert!(is_pending(&pool, message.id, Utc::now()).await?);
+        assert!(!handled_table_exists(&pool).await?);
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_queues_messages_unless_inline(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let queries = Queries::new("public")?;
+        let registry = InlineRegistry::new().register(TestMessage::NAME, succeeding);
+
+        let message = TestMessage::default().to_raw()?;
+        let mut tx = pool.begin().await?;
+        let outcome =
+            publish_and_process_inline(&mut tx, &queries, message.clone(), &registry).await?;
+        tx.commit().await?;
+
+        assert!(matches!(outcome, InlineOutcome::Queued(_)));
+        assert!(is_pending(&pool, message.id, Utc::now()).await?);
+
+        Ok(())
+    }
+
+    struct AppState {
+        table: &'static str,
+    }
+
+    fn creating_the_table_of_the_context<'a>(
+        context: Arc<AppState>,
+        conn: &'a mut PgConnection,
+        _: &'a RawMessage,
+    ) -> BoxFuture<'a, HandlerResult> {
+        async move {
+            sqlx::query(&format!("CREATE TABLE {} (id INT)", context.table))
+                .execute(conn)
+                .await
+                .expect("Expected to write");
+            HandlerResult::Success
+        }
+        .boxed()
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_passes_the_context_to_handlers(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let queries = Queries::new("public")?;
+        let registry = InlineRegistry::with_context(Arc::new(AppState { table: "handled" }))
+            .with_inline(true)
+            .register(TestMessage::NAME, creating_the_table_of_the_context);
+
+        let message = TestMessage::default().to_raw()?;
+        let mut tx = pool.begin().await?;
+        let outcome =
+            publish_and_process_inline(&mut tx, &queries, message.clone(), &registry).await?;
+        tx.commit().await?;
+
+        assert!(matches!(outcome, InlineOutcome::Handled(_)));
+        assert!(handled_table_exists(&pool).await?);
+
+        Ok(())
+    }
+}
@@ -1,3 +1,45 @@
+mod adaptive_hold;
+mod blocking;
+#[cfg(feature = "chaos")]
+mod chaos;
+mod claim_context;
+mod claim_stream;
+mod dead_letter;
+mod error_writer;
+mod handler_names;
+mod handler_result;
+mod health;
+mod inline;
+mod multiplexer;
+mod notification;
+mod operator_control;
 mod poll_control;
+mod report_writer;
+mod resource_gate;
+mod settings;
+mod unhandled;
+mod weighted_lanes;
+mod work_queue;
 
-pub use poll_control::PollControlStream;
+pub use adaptive_hold::{AdaptiveHoldFor, AdaptiveHoldForConfig};
+pub use blocking::{BlockingConfig, BlockingHandler, BlockingHandlers};
+#[cfg(feature = "chaos")]
+pub use chaos::{Chaos, ChaosConfig};
+pub use claim_context::ClaimContext;
+pub use claim_stream::{ClaimStreamConfig, ClaimedMessage, claim_stream};
+pub use dead_letter::{DeadLetterError, DeadLetterSink, QueueDeadLetterSink, SinkError};
+pub use error_writer::{ErrorFlusher, ErrorWriter, ErrorWriterConfig};
+pub use handler_names::HandlerNames;
+pub use handler_result::HandlerResult;
+pub use health::{HealthReport, InFlight, WorkerHealth};
+pub use inline::{InlineHandler, InlineOutcome, InlineRegistry, publish_and_process_inline};
+pub use multiplexer::{ConnectionHealth, ConnectionHealthHandle, NotificationMultiplexer};
+pub use notification::{Notification, NotificationParseError, NotifiedQueue};
+pub use operator_control::{ControlState, OperatorControl};
+pub use poll_control::{PollControlStream, PollStats, PollStatsHandle, Wakeup, WakeupCause};
+pub use report_writer::{QueuedReport, ReportFlusher, ReportWriter, ReportWriterConfig};
+pub use resource_gate::ResourceGate;
+pub use settings::{DefaultQueueSettings, QueueSettingsCache};
+pub use unhandled::UnhandledMessagePolicy;
+pub use weighted_lanes::WeightedLanes;
+pub use work_queue::{WorkQueue, WorkQueueConfig, WorkQueueStats};
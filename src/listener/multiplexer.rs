@@ -0,0 +1,269 @@
+use crate::backoff::ExponentialBackoff;
+use crate::constants::notification_channel_for_schema;
+use crate::queries::ErrorClass;
+use chrono::Utc;
+use futures::channel::mpsc::{UnboundedReceiver, UnboundedSender, unbounded};
+use sqlx::PgPool;
+use sqlx::postgres::PgListener;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Health of the connection of a [`NotificationMultiplexer`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConnectionHealth {
+    /// Whether the connection is currently established
+    pub connected: bool,
+    /// The number of times the connection was lost
+    pub disconnects: u64,
+    /// The number of failed attempts to re-establish the connection
+    pub failed_reconnects: u64,
+    /// The error of the last failed attempt, cleared once connected again
+    pub last_error: Option<String>,
+}
+
+/// A handle to the [`ConnectionHealth`] of a [`NotificationMultiplexer`], readable while it runs
+#[derive(Debug, Clone)]
+pub struct ConnectionHealthHandle(Arc<Mutex<ConnectionHealth>>);
+
+impl ConnectionHealthHandle {
+    pub fn snapshot(&self) -> ConnectionHealth {
+        self.0.lock().expect("poisoned").clone()
+    }
+}
+
+/// Listens on many notification channels over a single connection and dispatches each notification
+/// to the subscribers of its channel.
+///
+/// Intended for hosts that run one poll loop per schema: each loop subscribes to the channel of its
+/// schema and passes the receiver to [`PollControlStream::with_inbound_stream`](super::PollControlStream::with_inbound_stream),
+/// while [`run`](Self::run) is spawned once to drive the shared connection.
+pub struct NotificationMultiplexer {
+    listener: PgListener,
+    subscribers: HashMap<String, Vec<UnboundedSender<String>>>,
+    backoff: ExponentialBackoff,
+    max_delay: Duration,
+    health: Arc<Mutex<ConnectionHealth>>,
+}
+
+impl NotificationMultiplexer {
+    pub fn new(listener: PgListener) -> Self {
+        Self {
+            listener,
+            subscribers: HashMap::new(),
+            backoff: ExponentialBackoff::new(2, Duration::from_millis(100)),
+            max_delay: Duration::from_secs(30),
+            health: Arc::new(Mutex::new(ConnectionHealth {
+                connected: true,
+                ..ConnectionHealth::default()
+            })),
+        }
+    }
+
+    /// Waits according to `backoff`, but at most `max_delay`, between attempts to re-establish a lost
+    /// connection. Starts at 100ms, doubling up to 30s, by default.
+    pub fn with_reconnect_backoff(
+        mut self,
+        backoff: ExponentialBackoff,
+        max_delay: Duration,
+    ) -> Self {
+        self.backoff = backoff;
+        self.max_delay = max_delay;
+        self
+    }
+
+    /// Returns a handle to the health of the connection
+    pub fn health(&self) -> ConnectionHealthHandle {
+        ConnectionHealthHandle(self.health.clone())
+    }
+
+    /// Creates a multiplexer listening on a connection of `pool`
+    pub async fn connect_with(pool: &PgPool) -> Result<Self, sqlx::Error> {
+        Ok(Self::new(PgListener::connect_with(pool).await?))
+    }
+
+    /// Subscribes to notification payloads of `channel`, issuing a `LISTEN` on the first subscription
+    pub async fn subscribe(
+        &mut self,
+        channel: &str,
+    ) -> Result<UnboundedReceiver<String>, sqlx::Error> {
+        if !self.subscribers.contains_key(channel) {
+            self.listener.listen(channel).await?;
+        }
+
+        let (sender, receiver) = unbounded();
+        self.subscribers
+            .entry(channel.to_string())
+            .or_default()
+            .push(sender);
+
+        Ok(receiver)
+    }
+
+    /// Subscribes to the notification channel of `schema`
+    pub async fn subscribe_schema(
+        &mut self,
+        schema: &str,
+    ) -> Result<UnboundedReceiver<String>, sqlx::Error> {
+        self.subscribe(&notification_channel_for_schema(schema))
+            .await
+    }
+
+    /// Receives notifications and dispatches them until every subscriber has been dropped.
+    ///
+    /// Channels are unlistened once all of their subscribers are gone. A lost connection is re-established with
+    /// backoff, listening on all channels again. Notifications sent while disconnected are lost, so once
+    /// reconnected every subscriber receives an empty payload, waking their poll loops to catch up on messages
+    /// published meanwhile. Returns errors that retrying can't resolve, see [`ErrorClass`].
+    pub async fn run(mut self) -> Result<(), sqlx::Error> {
+        while !self.subscribers.is_empty() {
+            let Some(notification) = self.listener.try_recv().await? else {
+                self.reconnect().await?;
+                self.dispatch_all("");
+                continue;
+            };
+            let channel = notification.channel();
+
+            let Some(subscribers) = self.subscribers.get_mut(channel) else {
+                continue;
+            };
+
+            subscribers.retain(|subscriber| {
+                subscriber
+                    .unbounded_send(notification.payload().to_string())
+                    .is_ok()
+            });
+
+            if subscribers.is_empty() {
+                tracing::debug!(channel, "No subscribers left, unlistening");
+                self.subscribers.remove(channel);
+                self.listener.unlisten(channel).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn reconnect(&mut self) -> Result<(), sqlx::Error> {
+        tracing::warn!("Notification connection lost, reconnecting");
+        {
+            let mut health = self.health.lock().expect("poisoned");
+            health.connected = false;
+            health.disconnects += 1;
+        }
+
+        let mut attempt = 0;
+        loop {
+            // Any statement re-establishes the connection of the listener, which listens on all channels again
+            match sqlx::query("SELECT 1").execute(&mut self.listener).await {
+                Ok(_) => break,
+                Err(error) if ErrorClass::of(&error).is_retryable() => {
+                    attempt += 1;
+                    tracing::warn!(%error, attempt, "Could not reconnect the notification connection");
+                    {
+                        let mut health = self.health.lock().expect("poisoned");
+                        health.failed_reconnects += 1;
+                        health.last_error = Some(error.to_string());
+                    }
+
+                    let now = Utc::now();
+                    let delay = (self.backoff.try_at(attempt.min(16), now) - now)
+                        .to_std()
+                        .unwrap_or(Duration::ZERO)
+                        .min(self.max_delay);
+                    tokio::time::sleep(delay).await;
+                }
+                Err(error) => return Err(error),
+            }
+        }
+
+        tracing::info!("Notification connection re-established");
+        let mut health = self.health.lock().expect("poisoned");
+        health.connected = true;
+        health.last_error = None;
+        Ok(())
+    }
+
+    // Sends `payload` to the subscribers of every channel, dropping subscribers that are gone
+    fn dispatch_all(&mut self, payload: &str) {
+        for subscribers in self.subscribers.values_mut() {
+            subscribers.retain(|subscriber| subscriber.unbounded_send(payload.to_string()).is_ok());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queries::notify;
+    use futures::StreamExt;
+    use std::time::Duration;
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_dispatches_to_the_subscribers_of_a_schema(
+        pool: sqlx::PgPool,
+    ) -> anyhow::Result<()> {
+        let mut multiplexer = NotificationMultiplexer::connect_with(&pool).await?;
+        let mut a = multiplexer.subscribe_schema("a").await?;
+        let mut b = multiplexer.subscribe_schema("b").await?;
+        tokio::spawn(multiplexer.run());
+
+        notify(&pool, &notification_channel_for_schema("a"), 3).await?;
+
+        let payload = tokio::time::timeout(Duration::from_secs(1), a.next()).await?;
+        assert_eq!(payload.as_deref(), Some("3"));
+
+        tokio::time::timeout(Duration::from_millis(100), b.next())
+            .await
+            .expect_err("expected no notification for another schema");
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_stops_when_all_subscribers_are_dropped(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let mut multiplexer = NotificationMultiplexer::connect_with(&pool).await?;
+        let a = multiplexer.subscribe_schema("a").await?;
+        let handle = tokio::spawn(multiplexer.run());
+
+        drop(a);
+        notify(&pool, &notification_channel_for_schema("a"), 1).await?;
+
+        tokio::time::timeout(Duration::from_secs(1), handle).await???;
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_reconnects_and_wakes_subscribers(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let mut multiplexer = NotificationMultiplexer::connect_with(&pool)
+            .await?
+            .with_reconnect_backoff(
+                ExponentialBackoff::new(2, Duration::from_millis(10)),
+                Duration::from_millis(100),
+            );
+        let mut a = multiplexer.subscribe_schema("a").await?;
+        let health = multiplexer.health();
+        tokio::spawn(multiplexer.run());
+
+        sqlx::query(
+            "SELECT pg_terminate_backend(pid) FROM pg_stat_activity WHERE datname = current_database() AND query LIKE 'LISTEN%'",
+        )
+        .execute(&pool)
+        .await?;
+
+        // The forced wakeup after reconnecting
+        let payload = tokio::time::timeout(Duration::from_secs(5), a.next()).await?;
+        assert_eq!(payload.as_deref(), Some(""));
+
+        notify(&pool, &notification_channel_for_schema("a"), 2).await?;
+        let payload = tokio::time::timeout(Duration::from_secs(1), a.next()).await?;
+        assert_eq!(payload.as_deref(), Some("2"));
+
+        let health = health.snapshot();
+        assert!(health.connected);
+        assert_eq!(health.disconnects, 1);
+
+        Ok(())
+    }
+}
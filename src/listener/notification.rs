@@ -0,0 +1,131 @@
+use serde::{Deserialize, Serialize};
+
+/// Where the messages announced by a [`Notification`] became claimable, to choose the claim to run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotifiedQueue {
+    /// Published or released messages, claimed with [`get_next_unattempted`](crate::queries::get_next_unattempted)
+    Unattempted,
+    /// Failed messages retryable right away, claimed with [`get_next_retryable`](crate::queries::get_next_retryable)
+    Retryable,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum NotificationParseError {
+    #[error("InvalidNotificationError: {0}")]
+    Invalid(String),
+}
+
+/// The payload of a notification on a message channel.
+///
+/// Payloads are either a bare count of messages, as sent by default, or a JSON object with the schema, queue and
+/// name of the messages when sent by [`Queries`](crate::queries::Queries) configured with
+/// [`with_notification_metadata`](crate::queries::Queries::with_notification_metadata). Fields a payload does
+/// not carry are None, an empty payload, as sent by the [`NotificationMultiplexer`](super::NotificationMultiplexer)
+/// after reconnecting, carries nothing.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Notification {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub queue: Option<NotifiedQueue>,
+    /// The message name, None if the messages have different names
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// The number of messages that became claimable
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub count: Option<i64>,
+}
+
+impl Notification {
+    pub fn parse(payload: &str) -> Result<Self, NotificationParseError> {
+        let payload = payload.trim();
+        if payload.is_empty() {
+            return Ok(Self::default());
+        }
+        if let Ok(count) = payload.parse() {
+            return Ok(Self {
+                count: Some(count),
+                ..Self::default()
+            });
+        }
+        serde_json::from_str(payload)
+            .map_err(|_| NotificationParseError::Invalid(payload.to_string()))
+    }
+
+    /// Serializes the notification as a JSON payload, see [`parse`](Self::parse)
+    pub fn payload(&self) -> String {
+        serde_json::to_string(self).expect("notifications serialize")
+    }
+
+    /// Returns false only if the notification is known to be about another schema
+    pub fn matches_schema(&self, schema: &str) -> bool {
+        self.schema.as_deref().is_none_or(|s| s == schema)
+    }
+
+    /// Returns false only if the notification is known to be about messages of a name not in `names`
+    pub fn matches_names<N: AsRef<str>>(&self, names: &[N]) -> bool {
+        self.name
+            .as_deref()
+            .is_none_or(|name| names.iter().any(|n| n.as_ref() == name))
+    }
+
+    /// Returns false only if the notification is known to be about another queue
+    pub fn matches_queue(&self, queue: NotifiedQueue) -> bool {
+        self.queue.is_none_or(|q| q == queue)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_counts_and_metadata() {
+        assert_eq!(Notification::parse("").unwrap(), Notification::default());
+        assert_eq!(Notification::parse("5").unwrap().count, Some(5));
+
+        let notification = Notification {
+            schema: Some("tenant_a".to_string()),
+            queue: Some(NotifiedQueue::Retryable),
+            name: Some("OrderPlaced".to_string()),
+            count: Some(1),
+        };
+        assert_eq!(
+            Notification::parse(&notification.payload()).unwrap(),
+            notification
+        );
+        assert_eq!(
+            Notification::parse(r#"{"queue":"unattempted"}"#).unwrap(),
+            Notification {
+                queue: Some(NotifiedQueue::Unattempted),
+                ..Notification::default()
+            }
+        );
+
+        assert!(matches!(
+            Notification::parse("ping"),
+            Err(NotificationParseError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn it_matches_unknown_fields() {
+        let unknown = Notification::default();
+        assert!(unknown.matches_schema("tenant_a"));
+        assert!(unknown.matches_names(&["OrderPlaced"]));
+        assert!(unknown.matches_queue(NotifiedQueue::Retryable));
+
+        let known = Notification {
+            schema: Some("tenant_a".to_string()),
+            queue: Some(NotifiedQueue::Unattempted),
+            name: Some("OrderPlaced".to_string()),
+            count: Some(1),
+        };
+        assert!(known.matches_schema("tenant_a"));
+        assert!(!known.matches_schema("tenant_b"));
+        assert!(known.matches_names(&["OrderPlaced", "OrderShipped"]));
+        assert!(!known.matches_names(&["OrderShipped"]));
+        assert!(!known.matches_queue(NotifiedQueue::Retryable));
+    }
+}
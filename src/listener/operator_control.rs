@@ -0,0 +1,239 @@
+use crate::listener::ResourceGate;
+use crate::models::OperatorCommand;
+use crate::queries::Queries;
+use futures::future::BoxFuture;
+use sqlx::PgPool;
+use sqlx::postgres::PgListener;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+/// The state of a worker as set by operator commands
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ControlState {
+    pub paused: bool,
+    /// Whether to drain the backlog, followed by
+    /// [`PollControlStream::with_operator_control`](super::PollControlStream::with_operator_control)
+    pub draining: bool,
+    /// The number of concurrent workers, None until set by a command. Followed by
+    /// [`WorkQueue::spawn_controlled`](super::WorkQueue::spawn_controlled)
+    pub workers: Option<u32>,
+    /// The number of settings reloads requested, incremented by every reload command
+    pub reloads: u64,
+    /// The id of the last command applied
+    pub last_command_id: i64,
+}
+
+impl ControlState {
+    fn apply(&mut self, command: &OperatorCommand) {
+        match command {
+            OperatorCommand::Pause => self.paused = true,
+            OperatorCommand::Resume => {
+                self.paused = false;
+                self.draining = false;
+            }
+            OperatorCommand::Drain => {
+                self.paused = false;
+                self.draining = true;
+            }
+            OperatorCommand::SetConcurrency { workers } => self.workers = Some(*workers),
+            OperatorCommand::ReloadSettings => self.reloads += 1,
+        }
+    }
+}
+
+/// Applies the operator commands issued with [`Queries::issue_command`] to the state of a worker, enabling
+/// fleet-wide control without restarting workers or giving each of them an HTTP surface.
+///
+/// [`run`](Self::run) applies commands as they are announced on the command channel. A
+/// [`WorkQueue`](super::WorkQueue) spawned with [`spawn_controlled`](super::WorkQueue::spawn_controlled) pauses and
+/// resizes its pool as commanded, and a [`PollControlStream`](super::PollControlStream) follows drains with
+/// [`with_operator_control`](super::PollControlStream::with_operator_control). Other components watch the state with
+/// [`subscribe`](Self::subscribe), or use the control as a [`ResourceGate`] that is closed while paused. All commands issued since the
+/// schema was created are applied at startup, so a worker started during a pause starts paused.
+#[derive(Debug, Clone)]
+pub struct OperatorControl {
+    state: Arc<watch::Sender<ControlState>>,
+}
+
+impl Default for OperatorControl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OperatorControl {
+    pub fn new() -> Self {
+        Self {
+            state: Arc::new(watch::Sender::new(ControlState::default())),
+        }
+    }
+
+    /// The current state
+    pub fn state(&self) -> ControlState {
+        self.state.borrow().clone()
+    }
+
+    /// Returns a receiver notified whenever a command changes the state
+    pub fn subscribe(&self) -> watch::Receiver<ControlState> {
+        self.state.subscribe()
+    }
+
+    /// Applies the commands for `host_id` issued since the last applied command
+    pub async fn refresh<S>(
+        &self,
+        pool: &PgPool,
+        queries: &Queries<S>,
+        host_id: Uuid,
+    ) -> Result<(), sqlx::Error> {
+        let after_id = self.state.borrow().last_command_id;
+
+        let mut tx = pool.begin().await?;
+        let commands = queries
+            .get_commands_after(&mut tx, after_id, host_id)
+            .await?;
+        tx.commit().await?;
+
+        if commands.is_empty() {
+            return Ok(());
+        }
+
+        self.state.send_modify(|state| {
+            for issued in &commands {
+                tracing::info!(id = issued.id, command = ?issued.command, "Applying operator command");
+                state.apply(&issued.command);
+                state.last_command_id = issued.id;
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Applies commands whenever they are announced on the command channel of `queries`, and every `interval` to
+    /// catch commands announced while disconnected, until cancelled. Failed refreshes are logged and retried on
+    /// the next announcement or interval.
+    pub async fn run<S>(
+        &self,
+        pool: &PgPool,
+        queries: &Queries<S>,
+        host_id: Uuid,
+        interval: Duration,
+        cancellation: CancellationToken,
+    ) -> Result<(), sqlx::Error> {
+        let mut listener = PgListener::connect_with(pool).await?;
+        listener.listen(&queries.command_channel()).await?;
+        let mut ticker = tokio::time::interval(interval);
+
+        loop {
+            if let Err(error) = self.refresh(pool, queries, host_id).await {
+                tracing::warn!(%error, "Could not refresh operator commands");
+            }
+
+            tokio::select! {
+                _ = cancellation.cancelled() => return Ok(()),
+                _ = ticker.tick() => {}
+                notification = listener.recv() => {
+                    if let Err(error) = notification {
+                        tracing::warn!(%error, "Could not receive operator command notifications");
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl ResourceGate for OperatorControl {
+    fn is_open(&self) -> BoxFuture<'_, bool> {
+        Box::pin(std::future::ready(!self.state.borrow().paused))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    async fn issue(
+        pool: &PgPool,
+        queries: &Queries,
+        command: OperatorCommand,
+        target_host: Option<Uuid>,
+    ) -> anyhow::Result<()> {
+        let mut tx = pool.begin().await?;
+        queries
+            .issue_command(&mut tx, &command, target_host, Utc::now())
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_applies_issued_commands(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let queries = Queries::new("public")?;
+        let host_id = Uuid::now_v7();
+        let control = OperatorControl::new();
+
+        issue(&pool, &queries, OperatorCommand::Pause, None).await?;
+        issue(
+            &pool,
+            &queries,
+            OperatorCommand::SetConcurrency { workers: 3 },
+            Some(host_id),
+        )
+        .await?;
+        issue(
+            &pool,
+            &queries,
+            OperatorCommand::Resume,
+            Some(Uuid::now_v7()),
+        )
+        .await?;
+        control.refresh(&pool, &queries, host_id).await?;
+
+        let state = control.state();
+        assert!(state.paused);
+        assert_eq!(state.workers, Some(3));
+        assert!(!control.is_open().await);
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_applies_commands_when_notified(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let queries = Queries::new("public")?;
+        let host_id = Uuid::now_v7();
+        let control = OperatorControl::new();
+        let mut changes = control.subscribe();
+
+        let cancellation = CancellationToken::new();
+        let running = {
+            let (control, pool, cancellation) =
+                (control.clone(), pool.clone(), cancellation.clone());
+            tokio::spawn(async move {
+                let queries = Queries::new("public")?;
+                control
+                    .run(
+                        &pool,
+                        &queries,
+                        host_id,
+                        Duration::from_secs(60),
+                        cancellation,
+                    )
+                    .await?;
+                Ok::<_, anyhow::Error>(())
+            })
+        };
+
+        // Applied after the notification, or at startup if issued before the worker listens
+        issue(&pool, &queries, OperatorCommand::Drain, None).await?;
+        tokio::time::timeout(Duration::from_secs(5), changes.changed()).await??;
+        assert!(changes.borrow().draining);
+
+        cancellation.cancel();
+        running.await??;
+
+        Ok(())
+    }
+}
@@ -2,23 +2,107 @@ use chrono::{DateTime, Utc};
 use futures::Stream;
 use std::{
     pin::Pin,
+    sync::{Arc, Mutex},
     task::{Context, Poll},
     time::Duration,
 };
 
+use super::notification::Notification;
+use super::operator_control::OperatorControl;
 use crate::backoff::ExponentialBackoff;
 
 type Inbound = Pin<Box<dyn Stream<Item = String> + Send + 'static>>;
+type DrainCommands = Pin<Box<dyn Stream<Item = bool> + Send + 'static>>;
+type NotificationFilter = Box<dyn Fn(&Notification) -> bool + Send + 'static>;
+
+/// Why a [`PollControlStream`] yielded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WakeupCause {
+    /// A poll forced with [`set_poll`](PollControlStream::set_poll), including the first poll
+    Forced,
+    /// A poll while draining, see [`start_drain`](PollControlStream::start_drain)
+    Drain,
+    /// A notification on the inbound stream
+    Notification,
+    /// The regular polling interval passed
+    Interval,
+    /// The backoff after failed attempts passed
+    Backoff,
+}
+
+/// The last wakeup of a [`PollControlStream`], see [`last_wakeup`](PollControlStream::last_wakeup)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Wakeup {
+    pub cause: WakeupCause,
+    /// The notification that caused the wakeup, None for other causes and payloads that could not be parsed
+    pub notification: Option<Notification>,
+}
+
+/// Counters of a [`PollControlStream`], for tuning polling intervals
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PollStats {
+    pub forced: u64,
+    pub drain: u64,
+    pub notification: u64,
+    pub interval: u64,
+    pub backoff: u64,
+    /// Time spent waiting for a wakeup
+    pub idle: Duration,
+    /// Messages claimed, as recorded with [`record_claims`](PollControlStream::record_claims)
+    pub claims: u64,
+}
+
+impl PollStats {
+    /// The number of wakeups of all causes
+    pub fn wakeups(&self) -> u64 {
+        self.forced + self.drain + self.notification + self.interval + self.backoff
+    }
+
+    /// The average number of messages claimed per wakeup, None before the first wakeup
+    pub fn claims_per_wakeup(&self) -> Option<f64> {
+        match self.wakeups() {
+            0 => None,
+            wakeups => Some(self.claims as f64 / wakeups as f64),
+        }
+    }
+
+    fn record(&mut self, cause: WakeupCause) {
+        let counter = match cause {
+            WakeupCause::Forced => &mut self.forced,
+            WakeupCause::Drain => &mut self.drain,
+            WakeupCause::Notification => &mut self.notification,
+            WakeupCause::Interval => &mut self.interval,
+            WakeupCause::Backoff => &mut self.backoff,
+        };
+        *counter += 1;
+    }
+}
+
+/// A handle to the counters of a [`PollControlStream`], readable while the stream is consumed elsewhere
+#[derive(Debug, Clone)]
+pub struct PollStatsHandle(Arc<Mutex<PollStats>>);
+
+impl PollStatsHandle {
+    pub fn snapshot(&self) -> PollStats {
+        self.0.lock().expect("poisoned").clone()
+    }
+}
 
 /// Stream that yields `true` when polling should occur.
 ///
 /// Coordinates multiple triggers: exponential backoff, PostgreSQL notifications, and immediate poll overrides.
 pub struct PollControlStream {
     inbound: Option<Inbound>,
+    drain_commands: Option<DrainCommands>,
     failed_attempts: i32,
     reference_time: DateTime<Utc>,
     backoff: ExponentialBackoff,
     poll: bool,
+    draining: bool,
+    stats: Arc<Mutex<PollStats>>,
+    idle_since: Option<DateTime<Utc>>,
+    filters: Vec<NotificationFilter>,
+    last_wakeup: Option<Wakeup>,
 }
 
 impl PollControlStream {
@@ -26,11 +110,78 @@ impl PollControlStream {
     pub fn new(backoff: ExponentialBackoff) -> Self {
         Self {
             inbound: None,
+            drain_commands: None,
             failed_attempts: 0,
             reference_time: Utc::now(),
             backoff,
             poll: true, // First poll returns immediately, bypassing backoff
+            draining: false,
+            stats: Arc::default(),
+            idle_since: None,
+            filters: Vec::new(),
+            last_wakeup: None,
+        }
+    }
+
+    /// Returns a handle to the counters of this stream
+    pub fn stats(&self) -> PollStatsHandle {
+        PollStatsHandle(self.stats.clone())
+    }
+
+    /// Records the number of messages claimed after a wakeup, for [`PollStats::claims_per_wakeup`]
+    pub fn record_claims(&mut self, claims: u64) {
+        self.stats.lock().expect("poisoned").claims += claims;
+    }
+
+    /// Returns the cause of the last wakeup, with the notification that caused it, so that the listener can choose
+    /// which claim to run. None before the first wakeup.
+    pub fn last_wakeup(&self) -> Option<&Wakeup> {
+        self.last_wakeup.as_ref()
+    }
+
+    // Counts a wakeup, adding the time since the stream started waiting to the idle time
+    fn wake(
+        &mut self,
+        now: DateTime<Utc>,
+        cause: WakeupCause,
+        notification: Option<Notification>,
+    ) -> Poll<Option<bool>> {
+        let mut stats = self.stats.lock().expect("poisoned");
+        stats.record(cause);
+        if let Some(idle_since) = self.idle_since.take() {
+            stats.idle += (now - idle_since).to_std().unwrap_or(Duration::ZERO);
         }
+        drop(stats);
+
+        self.reference_time = now;
+        self.last_wakeup = Some(Wakeup {
+            cause,
+            notification,
+        });
+        Poll::Ready(Some(true))
+    }
+
+    /// Skips notifications for which `filter` returns false, rather than waking on every notification.
+    ///
+    /// Filters combine, a notification wakes the stream only if it passes all of them. Payloads that can't be
+    /// parsed as a [`Notification`] always wake the stream.
+    pub fn with_notification_filter(
+        &mut self,
+        filter: impl Fn(&Notification) -> bool + Send + 'static,
+    ) {
+        self.filters.push(Box::new(filter))
+    }
+
+    /// Skips notifications known to be about another schema, see [`Notification::matches_schema`]
+    pub fn with_schema_filter(&mut self, schema: impl Into<String>) {
+        let schema = schema.into();
+        self.with_notification_filter(move |notification| notification.matches_schema(&schema))
+    }
+
+    /// Skips notifications known to be about messages of other names, see [`Notification::matches_names`]
+    pub fn with_name_filter<N: Into<String>>(&mut self, names: impl IntoIterator<Item = N>) {
+        let names: Vec<String> = names.into_iter().map(Into::into).collect();
+        self.with_notification_filter(move |notification| notification.matches_names(&names))
     }
 
     /// Sets the inbound notification stream.
@@ -44,6 +195,25 @@ impl PollControlStream {
         self.inbound = Some(Box::pin(inbound))
     }
 
+    /// Follows the drain commands applied to `control`: a [`Drain`](crate::models::OperatorCommand::Drain) starts a
+    /// drain, see [`start_drain`](Self::start_drain), and a [`Resume`](crate::models::OperatorCommand::Resume)
+    /// ends it. A stream waiting for its next wakeup wakes when a drain is started.
+    pub fn with_operator_control(&mut self, control: &OperatorControl) {
+        let mut changes = control.subscribe();
+        changes.mark_changed();
+        let commands =
+            futures::stream::unfold((changes, false), |(mut changes, draining)| async move {
+                loop {
+                    changes.changed().await.ok()?;
+                    let next = changes.borrow_and_update().draining;
+                    if next != draining {
+                        return Some((next, (changes, next)));
+                    }
+                }
+            });
+        self.drain_commands = Some(Box::pin(commands));
+    }
+
     /// Increments the failed attempts counter.
     ///
     /// Subsequent polls will use exponential backoff based on the attempt count.
@@ -68,6 +238,27 @@ impl PollControlStream {
         self.poll = true
     }
 
+    /// Enters drain mode, typically right after startup to catch up on an existing backlog.
+    ///
+    /// While draining the stream yields immediately on every poll, unless there are failed attempts,
+    /// until [`end_drain`](Self::end_drain) is called. Listeners should claim in batches with maximum
+    /// concurrency while [`is_draining`](Self::is_draining) and end the drain once a claim comes back empty.
+    #[tracing::instrument(skip(self), level = "debug")]
+    pub fn start_drain(&mut self) {
+        self.draining = true
+    }
+
+    /// Leaves drain mode, settling into the regular notification and polling cadence.
+    #[tracing::instrument(skip(self), level = "debug")]
+    pub fn end_drain(&mut self) {
+        self.draining = false
+    }
+
+    /// Returns true while in drain mode.
+    pub fn is_draining(&self) -> bool {
+        self.draining
+    }
+
     // Schedules a wakeup after the given duration
     #[tracing::instrument(
         skip(cx),
@@ -93,15 +284,16 @@ impl PollControlStream {
         cx: &mut Context<'_>,
         now: DateTime<Utc>,
         attempts: i32,
+        cause: WakeupCause,
     ) -> Poll<Option<bool>> {
         let try_at = self.backoff.try_at(attempts, self.reference_time);
 
         if now >= try_at {
-            self.reference_time = now;
-            Poll::Ready(Some(true))
+            self.wake(now, cause, None)
         } else {
             let remaining = (try_at - now).to_std().unwrap_or(Duration::ZERO);
             Self::wake_in(cx, remaining);
+            self.idle_since.get_or_insert(now);
             Poll::Pending
         }
     }
@@ -112,7 +304,7 @@ impl Stream for PollControlStream {
 
     #[tracing::instrument(
         skip(self, cx),
-        fields(failed_attempts = self.failed_attempts, poll = self.poll),
+        fields(failed_attempts = self.failed_attempts, poll = self.poll, draining = self.draining),
         level = "debug"
     )]
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
@@ -120,32 +312,61 @@ impl Stream for PollControlStream {
 
         let now = Utc::now();
 
+        // follow drain commands, keeping the waker registered for the next one
+        while let Some(ref mut commands) = slf.drain_commands {
+            match commands.as_mut().poll_next(cx) {
+                Poll::Ready(Some(draining)) => slf.draining = draining,
+                Poll::Ready(None) => {
+                    slf.drain_commands = None;
+                    break;
+                }
+                Poll::Pending => break,
+            }
+        }
+
         // check if there were failed attempts - use exponential backoff
         if slf.failed_attempts > 0 {
-            return slf.handle_backoff_timing(cx, now, slf.failed_attempts);
+            return slf.handle_backoff_timing(cx, now, slf.failed_attempts, WakeupCause::Backoff);
+        }
+
+        // drain the backlog without waiting for notifications or intervals
+        if slf.draining {
+            slf.poll = false;
+            return slf.wake(now, WakeupCause::Drain, None);
         }
 
         // check the poll flag
         if slf.poll {
             // set it back to false
             slf.poll = false;
-            slf.reference_time = now;
-            return Poll::Ready(Some(true));
+            return slf.wake(now, WakeupCause::Forced, None);
         }
 
         // if there is a notification stream, check for notifications
-        if let Some(ref mut inbound) = slf.inbound {
+        // skipping filtered notifications until one passes or the stream is pending, so that the waker stays
+        // registered
+        while let Some(ref mut inbound) = slf.inbound {
             match inbound.as_mut().poll_next(cx) {
-                Poll::Ready(Some(_message)) => {
+                Poll::Ready(Some(payload)) => {
                     // received a Pg notification
-                    slf.reference_time = now;
-                    return Poll::Ready(Some(true));
+                    let notification = match Notification::parse(&payload) {
+                        Ok(notification) => notification,
+                        Err(error) => {
+                            tracing::debug!(%error, "Waking on an unparsable notification");
+                            return slf.wake(now, WakeupCause::Notification, None);
+                        }
+                    };
+                    if slf.filters.iter().all(|filter| filter(&notification)) {
+                        return slf.wake(now, WakeupCause::Notification, Some(notification));
+                    }
                 }
                 Poll::Ready(None) => {
                     // ignore ended stream
+                    break;
                 }
                 Poll::Pending => {
                     // ignore pending state
+                    break;
                 }
             }
         }
@@ -153,14 +374,18 @@ impl Stream for PollControlStream {
         // fallback: regular polling interval (use base delay for regular polling)
         // Pass attempt=1 to get base_delay (attempt=0 would return immediately)
         // This ensures we poll at regular intervals when no failures or notifications occur
-        slf.handle_backoff_timing(cx, now, 1)
+        slf.handle_backoff_timing(cx, now, 1, WakeupCause::Interval)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::listener::NotifiedQueue;
+    use crate::models::OperatorCommand;
+    use crate::queries::Queries;
     use futures::StreamExt;
+    use uuid::Uuid;
 
     #[tokio::test]
     async fn test_yields_true() {
@@ -213,7 +438,7 @@ mod tests {
         // Iteration 2: wait 10ms (attempt 2: base_delay * 2^1 = 10ms)
         let mut n = 0;
         let now = Utc::now();
-        while let Some(_) = stream.next().await {
+        while stream.next().await.is_some() {
             // increment the failed count on each iteration
             stream.increment_failed_attempts();
             if n == iterations - 1 {
@@ -252,4 +477,161 @@ mod tests {
             "Expected elapsed to be smaller than duration"
         );
     }
+
+    #[tokio::test]
+    async fn test_drain_yields_immediately_until_ended() {
+        let duration = Duration::from_millis(50);
+
+        let mut stream = PollControlStream::new(ExponentialBackoff::new(2, duration));
+        stream.start_drain();
+        assert!(stream.is_draining());
+
+        let now = Utc::now();
+        for _ in 0..5 {
+            assert_eq!(stream.next().await, Some(true));
+        }
+
+        let elapsed = (Utc::now() - now).to_std().unwrap_or(Duration::ZERO);
+        assert!(
+            elapsed < duration,
+            "Expected draining polls to not wait for the polling interval"
+        );
+
+        stream.end_drain();
+        assert!(!stream.is_draining());
+
+        let now = Utc::now();
+        assert_eq!(stream.next().await, Some(true));
+
+        let elapsed = (Utc::now() - now).to_std().unwrap_or(Duration::ZERO);
+        assert!(
+            elapsed >= duration / 2,
+            "Expected the regular polling interval after the drain ended"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_counts_wakeups_by_cause() {
+        let duration = Duration::from_millis(5);
+
+        let (tx, rx) = futures::channel::mpsc::unbounded::<String>();
+        let mut stream = PollControlStream::new(ExponentialBackoff::new(2, duration));
+        stream.with_inbound_stream(rx);
+        let stats = stream.stats();
+
+        assert_eq!(stream.next().await, Some(true));
+        stream.record_claims(3);
+
+        tx.unbounded_send("notification".to_string()).unwrap();
+        assert_eq!(stream.next().await, Some(true));
+
+        assert_eq!(stream.next().await, Some(true));
+        stream.record_claims(1);
+
+        stream.increment_failed_attempts();
+        assert_eq!(stream.next().await, Some(true));
+
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.forced, 1);
+        assert_eq!(snapshot.notification, 1);
+        assert_eq!(snapshot.interval, 1);
+        assert_eq!(snapshot.backoff, 1);
+        assert_eq!(snapshot.claims_per_wakeup(), Some(1.0));
+        assert!(snapshot.idle >= duration);
+    }
+
+    #[tokio::test]
+    async fn test_skips_filtered_notifications() {
+        let duration = Duration::from_millis(50);
+
+        let (tx, rx) = futures::channel::mpsc::unbounded::<String>();
+        let mut stream = PollControlStream::new(ExponentialBackoff::new(2, duration));
+        stream.with_inbound_stream(rx);
+        stream.with_schema_filter("tenant_a");
+        stream.with_name_filter(["OrderPlaced"]);
+
+        assert_eq!(stream.next().await, Some(true));
+        assert_eq!(stream.last_wakeup().unwrap().cause, WakeupCause::Forced);
+
+        let notification = |schema: &str, name: &str| Notification {
+            schema: Some(schema.to_string()),
+            queue: Some(NotifiedQueue::Retryable),
+            name: Some(name.to_string()),
+            count: Some(1),
+        };
+        tx.unbounded_send(notification("tenant_b", "OrderPlaced").payload())
+            .unwrap();
+        tx.unbounded_send(notification("tenant_a", "OrderShipped").payload())
+            .unwrap();
+        tx.unbounded_send(notification("tenant_a", "OrderPlaced").payload())
+            .unwrap();
+
+        let now = Utc::now();
+        assert_eq!(stream.next().await, Some(true));
+        let elapsed = (Utc::now() - now).to_std().unwrap_or(Duration::ZERO);
+        assert!(elapsed < duration, "Expected to wake on the notification");
+        assert_eq!(
+            stream.last_wakeup(),
+            Some(&Wakeup {
+                cause: WakeupCause::Notification,
+                notification: Some(notification("tenant_a", "OrderPlaced")),
+            })
+        );
+
+        // Legacy count payloads carry no metadata and pass every filter
+        tx.unbounded_send("3".to_string()).unwrap();
+        assert_eq!(stream.next().await, Some(true));
+        let wakeup = stream.last_wakeup().unwrap();
+        assert_eq!(wakeup.cause, WakeupCause::Notification);
+        assert_eq!(wakeup.notification.as_ref().unwrap().count, Some(3));
+
+        let snapshot = stream.stats().snapshot();
+        assert_eq!(snapshot.notification, 2);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_follows_drain_commands(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let duration = Duration::from_secs(60);
+        let queries = Queries::new("public")?;
+        let host_id = Uuid::now_v7();
+        let control = OperatorControl::new();
+        let issue = async |command| -> anyhow::Result<()> {
+            let mut tx = pool.begin().await?;
+            queries
+                .issue_command(&mut tx, &command, None, Utc::now())
+                .await?;
+            tx.commit().await?;
+            control.refresh(&pool, &queries, host_id).await?;
+            Ok(())
+        };
+
+        let mut stream = PollControlStream::new(ExponentialBackoff::new(2, duration));
+        stream.with_operator_control(&control);
+        assert_eq!(stream.next().await, Some(true));
+        assert!(!stream.is_draining());
+
+        // Wakes the stream waiting for the polling interval
+        let (woken, issued) = tokio::join!(
+            tokio::time::timeout(Duration::from_secs(5), stream.next()),
+            async {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                issue(OperatorCommand::Drain).await
+            }
+        );
+        issued?;
+        assert_eq!(woken?, Some(true));
+        assert!(stream.is_draining());
+        assert_eq!(stream.last_wakeup().unwrap().cause, WakeupCause::Drain);
+
+        issue(OperatorCommand::Resume).await?;
+        assert!(
+            tokio::time::timeout(Duration::from_millis(100), stream.next())
+                .await
+                .is_err(),
+            "Expected the drain to end on resume"
+        );
+        assert!(!stream.is_draining());
+
+        Ok(())
+    }
 }
@@ -0,0 +1,399 @@
+use crate::listener::HandlerResult;
+use crate::models::RawMessage;
+use crate::queries::{Queries, ReportError};
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+/// Capacity and batching of a [`ReportWriter`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReportWriterConfig {
+    /// How many reports may be buffered. Writes wait for room while the buffer is full, so this bounds how many
+    /// handled messages may wait for their report, and should be well below what can be reported within a lease.
+    pub capacity: usize,
+    /// The maximum number of reports committed in one transaction
+    pub batch_size: usize,
+    /// How long the flusher waits before reporting a batch again after its transaction failed
+    pub retry_interval: Duration,
+    /// How many times a report may fail to commit before it is logged and dropped, leaving its message to be
+    /// handled again once its lease expires
+    pub max_retries: u32,
+    /// How long the flusher keeps reporting what is still buffered once cancelled
+    pub drain_timeout: Duration,
+}
+
+impl Default for ReportWriterConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 1_000,
+            batch_size: 100,
+            retry_interval: Duration::from_secs(1),
+            max_retries: 5,
+            drain_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// The outcome of handling a message, buffered to be reported by a [`ReportFlusher`]
+#[derive(Debug, Clone)]
+pub struct QueuedReport {
+    pub message: RawMessage,
+    pub result: HandlerResult,
+    /// When the message was handled, reported as the time of the outcome
+    pub handled_at: DateTime<Utc>,
+    pub host_id: Uuid,
+}
+
+/// Buffers the outcomes of handlers to be reported by a [`ReportFlusher`] in the background, so that tiny, fast
+/// handlers don't each wait for a report and a commit of their own.
+///
+/// Reports are at least once: a handler has finished when its outcome is buffered, but the message stays claimed
+/// until the batch holding its report commits. If the process dies before that, the buffered reports are lost, and
+/// the leases of their messages expire, after which the messages are handled again. Handlers must therefore be
+/// idempotent, and the lease should outlast the time a report may wait in the buffer. A report rejected because the
+/// lease was lost in the meantime is logged and dropped.
+#[derive(Debug, Clone)]
+pub struct ReportWriter {
+    sender: mpsc::Sender<QueuedReport>,
+}
+
+impl ReportWriter {
+    /// Creates a writer and the flusher reporting its outcomes, which must be [run](ReportFlusher::run) for writes
+    /// to make progress
+    pub fn new(config: ReportWriterConfig) -> (Self, ReportFlusher) {
+        let (sender, receiver) = mpsc::channel(config.capacity.max(1));
+        (Self { sender }, ReportFlusher { receiver, config })
+    }
+
+    /// Buffers `report`, waiting for room while the buffer is full. Returns the report back if the flusher has
+    /// stopped, so that the caller can report it itself.
+    pub async fn write(&self, report: QueuedReport) -> Result<(), QueuedReport> {
+        self.sender.send(report).await.map_err(|e| e.0)
+    }
+
+    /// The number of reports buffered and not yet taken by the flusher
+    pub fn buffered(&self) -> usize {
+        self.sender.max_capacity() - self.sender.capacity()
+    }
+}
+
+/// Reports the outcomes buffered by a [`ReportWriter`] in batches, one transaction per batch
+#[derive(Debug)]
+pub struct ReportFlusher {
+    receiver: mpsc::Receiver<QueuedReport>,
+    config: ReportWriterConfig,
+}
+
+/// A report waiting to be committed, and how many times it failed to
+#[derive(Debug)]
+struct PendingReport {
+    report: QueuedReport,
+    failures: u32,
+}
+
+impl ReportFlusher {
+    /// Reports buffered outcomes to the schema of `queries` until cancelled, taking whatever is buffered up to the
+    /// batch size for each transaction.
+    ///
+    /// When the transaction of a batch fails, its reports are committed one transaction each, so that a single
+    /// report that can't be committed doesn't hold back the others. Reports that still fail are retried after the
+    /// retry interval, and dropped once they failed `max_retries` times.
+    ///
+    /// Once cancelled, further writes are refused and the reports still buffered are committed for up to the drain
+    /// timeout before returning. Reports left after that are logged and lost, like on a crash.
+    pub async fn run<S>(
+        self,
+        pool: &PgPool,
+        queries: &Queries<S>,
+        cancellation: CancellationToken,
+    ) {
+        let Self {
+            mut receiver,
+            config,
+        } = self;
+        let batch_size = config.batch_size.max(1);
+        let mut received = Vec::with_capacity(batch_size);
+        let mut pending = Vec::with_capacity(batch_size);
+
+        loop {
+            if pending.is_empty() {
+                let count = tokio::select! {
+                    _ = cancellation.cancelled() => break,
+                    count = receiver.recv_many(&mut received, batch_size) => count,
+                };
+                if count == 0 {
+                    break;
+                }
+                pending.extend(received.drain(..).map(PendingReport::new));
+            }
+            if !flush_pending(pool, queries, &config, &mut pending).await {
+                tokio::select! {
+                    _ = cancellation.cancelled() => break,
+                    _ = tokio::time::sleep(config.retry_interval) => {}
+                }
+            }
+        }
+
+        receiver.close();
+        let drained = tokio::time::timeout(config.drain_timeout, async {
+            loop {
+                if pending.is_empty() {
+                    if receiver.recv_many(&mut received, batch_size).await == 0 {
+                        break;
+                    }
+                    pending.extend(received.drain(..).map(PendingReport::new));
+                }
+                if !flush_pending(pool, queries, &config, &mut pending).await {
+                    tokio::time::sleep(config.retry_interval).await;
+                }
+            }
+        })
+        .await;
+
+        if drained.is_err() {
+            tracing::error!(
+                reports = pending.len() + received.len() + receiver.len(),
+                "Dropped buffered reports not committed before the drain timeout"
+            );
+        }
+    }
+}
+
+impl PendingReport {
+    fn new(report: QueuedReport) -> Self {
+        Self {
+            report,
+            failures: 0,
+        }
+    }
+}
+
+/// Commits `pending` in one transaction, falling back to one transaction per report if that fails. Returns whether
+/// no reports are left to retry.
+async fn flush_pending<S>(
+    pool: &PgPool,
+    queries: &Queries<S>,
+    config: &ReportWriterConfig,
+    pending: &mut Vec<PendingReport>,
+) -> bool {
+    if flush(pool, queries, pending).await {
+        pending.clear();
+        return true;
+    }
+
+    let mut failed = Vec::new();
+    for mut report in pending.drain(..) {
+        if flush(pool, queries, std::slice::from_ref(&report)).await {
+            continue;
+        }
+        report.failures += 1;
+        if report.failures >= config.max_retries {
+            tracing::error!(
+                message_id = %report.report.message.id,
+                failures = report.failures,
+                "Dropped a report that could not be committed"
+            );
+        } else {
+            failed.push(report);
+        }
+    }
+    *pending = failed;
+    pending.is_empty()
+}
+
+/// Reports `reports` in one transaction, returning whether it committed. Rejected reports don't abort the
+/// transaction and are dropped, database errors roll back the whole transaction.
+async fn flush<S>(pool: &PgPool, queries: &Queries<S>, reports: &[PendingReport]) -> bool {
+    let reported = async {
+        let mut tx = pool.begin().await?;
+        for PendingReport { report, .. } in reports {
+            let reported = report
+                .result
+                .report_with(
+                    &mut tx,
+                    queries,
+                    &report.message,
+                    report.handled_at,
+                    report.host_id,
+                )
+                .await;
+            match reported {
+                Ok(()) => {}
+                Err(ReportError::Database(error)) => return Err(error),
+                Err(error) => {
+                    tracing::warn!(%error, message_id = %report.message.id, "Dropped a rejected report");
+                }
+            }
+        }
+        tx.commit().await
+    };
+
+    match reported.await {
+        Ok(()) => true,
+        Err(error) => {
+            tracing::warn!(%error, reports = reports.len(), "Could not commit buffered reports");
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing_tools::{claim, is_failed, is_in_progress, is_succeeded};
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_reports_buffered_outcomes_in_batches(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let (writer, flusher) = ReportWriter::new(ReportWriterConfig::default());
+        let queries = Queries::new("public")?;
+        let host_id = Uuid::now_v7();
+
+        let succeeded = claim(&pool, host_id).await?;
+        let failed = claim(&pool, host_id).await?;
+        let stale = RawMessage {
+            fencing_token: succeeded.fencing_token.map(|token| token + 100),
+            ..claim(&pool, host_id).await?
+        };
+
+        let now = Utc::now();
+        for (message, result) in [
+            (&succeeded, HandlerResult::Success),
+            (&stale, HandlerResult::Success),
+            (
+                &failed,
+                HandlerResult::Retry {
+                    reason: "timeout".to_string(),
+                    after: Duration::from_mins(1),
+                    category: None,
+                },
+            ),
+        ] {
+            let report = QueuedReport {
+                message: message.clone(),
+                result,
+                handled_at: now,
+                host_id,
+            };
+            writer
+                .write(report)
+                .await
+                .expect("Expected the flusher to run");
+        }
+
+        // Handled, but still claimed until flushed
+        assert_eq!(writer.buffered(), 3);
+        assert!(is_in_progress(&pool, succeeded.id, now).await?);
+
+        let cancellation = CancellationToken::new();
+        cancellation.cancel();
+        flusher.run(&pool, &queries, cancellation).await;
+
+        assert!(is_succeeded(&pool, succeeded.id, now).await?);
+        assert!(is_failed(&pool, failed.id, now).await?);
+        // The rejected report neither aborted the batch nor reported the message
+        assert!(is_in_progress(&pool, stale.id, now).await?);
+
+        // Once the flusher stopped, reports are handed back
+        let report = QueuedReport {
+            message: stale,
+            result: HandlerResult::Success,
+            handled_at: now,
+            host_id,
+        };
+        assert!(writer.write(report).await.is_err());
+
+        Ok(())
+    }
+
+    fn poisoned(message: &RawMessage, host_id: Uuid) -> QueuedReport {
+        QueuedReport {
+            message: message.clone(),
+            // Postgres refuses text with NUL bytes, failing every transaction the report is in
+            result: HandlerResult::Retry {
+                reason: "\0".to_string(),
+                after: Duration::from_mins(1),
+                category: None,
+            },
+            handled_at: Utc::now(),
+            host_id,
+        }
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_commits_the_rest_of_a_failed_batch(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let (writer, flusher) = ReportWriter::new(ReportWriterConfig {
+            retry_interval: Duration::from_millis(10),
+            max_retries: 2,
+            ..ReportWriterConfig::default()
+        });
+        let queries = Queries::new("public")?;
+        let host_id = Uuid::now_v7();
+
+        let first = claim(&pool, host_id).await?;
+        let poison = claim(&pool, host_id).await?;
+        let last = claim(&pool, host_id).await?;
+        let now = Utc::now();
+        for report in [
+            QueuedReport {
+                message: first.clone(),
+                result: HandlerResult::Success,
+                handled_at: now,
+                host_id,
+            },
+            poisoned(&poison, host_id),
+            QueuedReport {
+                message: last.clone(),
+                result: HandlerResult::Success,
+                handled_at: now,
+                host_id,
+            },
+        ] {
+            writer
+                .write(report)
+                .await
+                .expect("Expected the flusher to run");
+        }
+
+        let cancellation = CancellationToken::new();
+        cancellation.cancel();
+        flusher.run(&pool, &queries, cancellation).await;
+
+        assert!(is_succeeded(&pool, first.id, now).await?);
+        assert!(is_succeeded(&pool, last.id, now).await?);
+        // Dropped after its retries, the message is handled again once its lease expires
+        assert!(is_in_progress(&pool, poison.id, now).await?);
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_stops_draining_at_the_deadline(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let (writer, flusher) = ReportWriter::new(ReportWriterConfig {
+            retry_interval: Duration::from_millis(10),
+            max_retries: u32::MAX,
+            drain_timeout: Duration::from_millis(200),
+            ..ReportWriterConfig::default()
+        });
+        let queries = Queries::new("public")?;
+        let host_id = Uuid::now_v7();
+
+        let poison = claim(&pool, host_id).await?;
+        writer
+            .write(poisoned(&poison, host_id))
+            .await
+            .expect("Expected the flusher to run");
+
+        let cancellation = CancellationToken::new();
+        cancellation.cancel();
+        tokio::time::timeout(
+            Duration::from_secs(5),
+            flusher.run(&pool, &queries, cancellation),
+        )
+        .await?;
+
+        Ok(())
+    }
+}
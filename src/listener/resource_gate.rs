@@ -0,0 +1,19 @@
+use futures::future::BoxFuture;
+
+/// Consulted by a [`WorkQueue`](crate::listener::WorkQueue) before every claim, e.g. to check memory, disk space
+/// or the health of a downstream service.
+///
+/// While the gate is closed nothing is claimed, so a worker in a partial outage doesn't claim messages it can't
+/// handle. Messages already claimed are still handled, and their handlers keep renewing their leases as usual.
+pub trait ResourceGate: Send + Sync {
+    fn is_open(&self) -> BoxFuture<'_, bool>;
+}
+
+impl<F> ResourceGate for F
+where
+    F: Fn() -> bool + Send + Sync,
+{
+    fn is_open(&self) -> BoxFuture<'_, bool> {
+        Box::pin(std::future::ready(self()))
+    }
+}
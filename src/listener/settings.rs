@@ -0,0 +1,153 @@
+use crate::backoff::ExponentialBackoff;
+use crate::models::QueueSettings;
+use crate::queries::Queries;
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+/// The settings a worker uses for message names without stored settings
+#[derive(Debug, Clone, PartialEq)]
+pub struct DefaultQueueSettings {
+    pub backoff_base: u32,
+    pub backoff_base_delay: Duration,
+    pub max_attempts: i32,
+    pub hold_for: Duration,
+}
+
+impl DefaultQueueSettings {
+    pub fn for_name(&self, name: &str) -> QueueSettings {
+        QueueSettings {
+            name: name.to_string(),
+            backoff_base: self.backoff_base,
+            backoff_base_delay: self.backoff_base_delay,
+            max_attempts: self.max_attempts,
+            hold_for: self.hold_for,
+        }
+    }
+}
+
+/// A worker's copy of the `queue_settings` table.
+///
+/// Workers look settings up in the cache rather than querying them per message, and keep it current by calling
+/// [`refresh`](Self::refresh) periodically, e.g. with [`run`](Self::run). Changed settings thus take effect within
+/// one refresh interval.
+#[derive(Debug, Clone)]
+pub struct QueueSettingsCache {
+    defaults: DefaultQueueSettings,
+    settings: Arc<RwLock<HashMap<String, QueueSettings>>>,
+}
+
+impl QueueSettingsCache {
+    pub fn new(defaults: DefaultQueueSettings) -> Self {
+        Self {
+            defaults,
+            settings: Arc::default(),
+        }
+    }
+
+    /// Returns the settings of `name`, or the defaults if it has no stored settings
+    pub fn get(&self, name: &str) -> QueueSettings {
+        self.settings
+            .read()
+            .expect("poisoned")
+            .get(name)
+            .cloned()
+            .unwrap_or_else(|| self.defaults.for_name(name))
+    }
+
+    /// Returns true if `name` has stored settings, false if it falls back to the defaults
+    pub fn is_stored(&self, name: &str) -> bool {
+        self.settings.read().expect("poisoned").contains_key(name)
+    }
+
+    pub fn backoff(&self, name: &str) -> ExponentialBackoff {
+        self.get(name).backoff()
+    }
+
+    /// Replaces the cached settings with those stored in the schema of `queries`
+    pub async fn refresh<S>(&self, pool: &PgPool, queries: &Queries<S>) -> Result<(), sqlx::Error> {
+        let mut tx = pool.begin().await?;
+        let stored = queries.get_queue_settings(&mut tx).await?;
+        tx.commit().await?;
+
+        *self.settings.write().expect("poisoned") = stored
+            .into_iter()
+            .map(|settings| (settings.name.clone(), settings))
+            .collect();
+
+        Ok(())
+    }
+
+    /// Refreshes the cache every `interval` until cancelled. Failed refreshes are logged and keep the previous
+    /// settings.
+    pub async fn run<S>(
+        &self,
+        pool: &PgPool,
+        queries: &Queries<S>,
+        interval: Duration,
+        cancellation: CancellationToken,
+    ) {
+        let mut ticker = tokio::time::interval(interval);
+
+        loop {
+            tokio::select! {
+                _ = cancellation.cancelled() => return,
+                _ = ticker.tick() => {
+                    if let Err(error) = self.refresh(pool, queries).await {
+                        tracing::warn!(%error, "Could not refresh queue settings");
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn defaults() -> DefaultQueueSettings {
+        DefaultQueueSettings {
+            backoff_base: 2,
+            backoff_base_delay: Duration::from_secs(1),
+            max_attempts: 5,
+            hold_for: Duration::from_mins(1),
+        }
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_refreshes_settings(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let queries = Queries::new("public")?;
+        let cache = QueueSettingsCache::new(defaults());
+        assert_eq!(cache.get("a").max_attempts, 5);
+
+        let widened = QueueSettings {
+            name: "a".to_string(),
+            backoff_base: 3,
+            backoff_base_delay: Duration::from_mins(1),
+            max_attempts: 20,
+            hold_for: Duration::from_mins(10),
+        };
+        let mut tx = pool.begin().await?;
+        queries
+            .put_queue_settings(&mut tx, &widened, Utc::now())
+            .await?;
+        tx.commit().await?;
+
+        cache.refresh(&pool, &queries).await?;
+        assert_eq!(cache.get("a"), widened);
+        assert_eq!(cache.get("b"), defaults().for_name("b"));
+
+        let mut tx = pool.begin().await?;
+        queries.delete_queue_settings(&mut tx, "a").await?;
+        tx.commit().await?;
+
+        cache.refresh(&pool, &queries).await?;
+        assert_eq!(cache.get("a"), defaults().for_name("a"));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,165 @@
+use crate::models::RawMessage;
+use crate::queries::{ReportError, release_lease, report_dead, report_retryable};
+use chrono::{DateTime, Utc};
+use sqlx::PgExecutor;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// What a listener should do with a claimed message for which no handler is registered.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum UnhandledMessagePolicy {
+    /// Release the lease so that the message may be claimed again, possibly by a host that can handle it
+    #[default]
+    ReleaseLease,
+    /// Report the message as failed, retryable after the given delay
+    RetryAfter(Duration),
+    /// Report the message as dead
+    Dead,
+    /// Leave the message as claimed and route it to a fallback handler
+    Fallback,
+}
+
+impl UnhandledMessagePolicy {
+    /// Applies the policy to a message claimed by `host_id`.
+    ///
+    /// Returns true if the message was settled by the policy and false if it should be passed to the fallback handler.
+    /// A lease that can't be released since the claim that returned `message` no longer holds it is rejected with
+    /// [`ReportError::StaleFencingToken`], like the reports of the other policies.
+    pub async fn apply<'tx, E: PgExecutor<'tx>>(
+        &self,
+        tx: E,
+        message: &RawMessage,
+        now: DateTime<Utc>,
+        host_id: Uuid,
+    ) -> Result<bool, ReportError> {
+        let error = format!("No handler registered for message {}", message.name);
+
+        match self {
+            Self::ReleaseLease => {
+                if !release_lease(tx, message.id, message.fencing_token, now, host_id).await? {
+                    return Err(ReportError::StaleFencingToken(message.id));
+                }
+            }
+            Self::RetryAfter(delay) => {
+                report_retryable(
+                    tx,
+                    message.id,
+                    message.fencing_token,
+                    now,
+                    message.attempted + 1,
+                    now + *delay,
+                    &error,
+                )
+                .await?;
+            }
+            Self::Dead => {
+                report_dead(tx, message.id, message.fencing_token, now, &error).await?;
+            }
+            Self::Fallback => return Ok(false),
+        }
+
+        tracing::warn!(message_id = %message.id, name = %message.name, policy = ?self, "{error}");
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queries::get_next_missing;
+    use crate::testing_tools::{claim, is_dead, is_failed, is_in_progress, is_missing};
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_releases_the_lease(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let host_id = Uuid::now_v7();
+        let message = claim(&pool, host_id).await?;
+        let now = Utc::now();
+
+        let settled = UnhandledMessagePolicy::ReleaseLease
+            .apply(&pool, &message, now, host_id)
+            .await?;
+
+        assert!(settled);
+        assert!(is_missing(&pool, message.id, now + Duration::from_millis(1)).await?);
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_rejects_releasing_a_lost_lease(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let host_id = Uuid::now_v7();
+        let message = claim(&pool, host_id).await?;
+        let expired = Utc::now() + Duration::from_mins(2);
+
+        let result = UnhandledMessagePolicy::ReleaseLease
+            .apply(&pool, &message, expired, host_id)
+            .await;
+
+        assert!(matches!(result, Err(ReportError::StaleFencingToken(id)) if id == message.id));
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_rejects_releasing_a_later_claim(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let host_id = Uuid::now_v7();
+        let stale = claim(&pool, host_id).await?;
+        let expired = Utc::now() + Duration::from_mins(2);
+        let current = get_next_missing(&pool, expired, host_id, Duration::from_mins(1))
+            .await?
+            .expect("Expected the expired message to be reclaimed");
+        assert_eq!(current.id, stale.id);
+
+        let result = UnhandledMessagePolicy::ReleaseLease
+            .apply(&pool, &stale, expired, host_id)
+            .await;
+
+        assert!(matches!(result, Err(ReportError::StaleFencingToken(id)) if id == stale.id));
+        assert!(is_in_progress(&pool, current.id, expired).await?);
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_reports_retryable(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let host_id = Uuid::now_v7();
+        let message = claim(&pool, host_id).await?;
+        let now = Utc::now();
+
+        let settled = UnhandledMessagePolicy::RetryAfter(Duration::from_mins(1))
+            .apply(&pool, &message, now, host_id)
+            .await?;
+
+        assert!(settled);
+        assert!(is_failed(&pool, message.id, now).await?);
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_reports_dead(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let host_id = Uuid::now_v7();
+        let message = claim(&pool, host_id).await?;
+        let now = Utc::now();
+
+        let settled = UnhandledMessagePolicy::Dead
+            .apply(&pool, &message, now, host_id)
+            .await?;
+
+        assert!(settled);
+        assert!(is_dead(&pool, message.id, now).await?);
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_leaves_the_message_for_the_fallback(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let host_id = Uuid::now_v7();
+        let message = claim(&pool, host_id).await?;
+        let now = Utc::now();
+
+        let settled = UnhandledMessagePolicy::Fallback
+            .apply(&pool, &message, now, host_id)
+            .await?;
+
+        assert!(!settled);
+        assert!(is_in_progress(&pool, message.id, now).await?);
+        Ok(())
+    }
+}
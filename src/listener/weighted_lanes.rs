@@ -0,0 +1,154 @@
+use crate::models::RawMessage;
+use crate::queries::Queries;
+use chrono::{DateTime, Utc};
+use sqlx::PgTransaction;
+use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
+
+#[derive(Debug)]
+struct Lane {
+    label: String,
+    names: Arc<[String]>,
+    weight: i64,
+    /// The smooth weighted round-robin quota, raised by the weight on every claim and lowered by the total weight
+    /// whenever the lane is served
+    current: i64,
+}
+
+/// Claims across lanes of message names in one schema in proportion to their weights.
+///
+/// With lanes `interactive` weighted 3 and `batch` weighted 1, three of four claims go to interactive messages
+/// while both lanes have claimable messages, so a batch backlog can't crowd out interactive messages. When a
+/// lane has nothing claimable its turn passes to the next lane, so no capacity is left idle. Claims are
+/// interleaved evenly (smooth weighted round-robin) rather than in bursts.
+#[derive(Debug, Default)]
+pub struct WeightedLanes {
+    lanes: Vec<Lane>,
+}
+
+impl WeightedLanes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a lane claiming messages named any of `names`, a weight of 0 is treated as 1
+    pub fn with_lane(
+        mut self,
+        label: impl Into<String>,
+        names: impl IntoIterator<Item = impl Into<String>>,
+        weight: u32,
+    ) -> Self {
+        self.lanes.push(Lane {
+            label: label.into(),
+            names: names.into_iter().map(Into::into).collect(),
+            weight: i64::from(weight.max(1)),
+            current: 0,
+        });
+        self
+    }
+
+    /// Claims the next unattempted message of the lane whose turn it is, returning it with the label of the lane
+    /// that served it
+    pub async fn claim_next<S>(
+        &mut self,
+        tx: &mut PgTransaction<'_>,
+        queries: &Queries<S>,
+        now: DateTime<Utc>,
+        host_id: Uuid,
+        hold_for: Duration,
+    ) -> Result<Option<(String, RawMessage)>, sqlx::Error> {
+        let mut total: i64 = self.lanes.iter().map(|lane| lane.weight).sum();
+        for lane in &mut self.lanes {
+            lane.current += lane.weight;
+        }
+
+        let mut turns: Vec<usize> = (0..self.lanes.len()).collect();
+        turns.sort_by_key(|&i| std::cmp::Reverse(self.lanes[i].current));
+
+        for i in turns {
+            let claimed = queries
+                .get_next_unattempted_named(tx, now, host_id, hold_for, &self.lanes[i].names)
+                .await?;
+
+            let lane = &mut self.lanes[i];
+            match claimed {
+                Some(message) => {
+                    lane.current -= total;
+                    return Ok(Some((lane.label.clone(), message)));
+                }
+                None => {
+                    // An empty lane sits this turn out, rather than building up quota to burst with later
+                    lane.current -= lane.weight;
+                    total -= lane.weight;
+                }
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing_tools::TestMessage;
+
+    async fn publish(pool: &sqlx::PgPool, queries: &Queries, name: &str) -> anyhow::Result<()> {
+        let message = RawMessage {
+            name: name.to_string(),
+            ..TestMessage::default().to_raw()?
+        };
+        let mut tx = pool.begin().await?;
+        queries.publish_message(&mut tx, message).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_claims_in_proportion_to_weights(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let queries = Queries::new("public")?;
+        let host_id = Uuid::now_v7();
+        for _ in 0..4 {
+            publish(&pool, &queries, "Search").await?;
+            publish(&pool, &queries, "Export").await?;
+        }
+
+        let mut lanes = WeightedLanes::new()
+            .with_lane("interactive", ["Search"], 3)
+            .with_lane("batch", ["Export"], 1);
+
+        let mut served = Vec::new();
+        let mut tx = pool.begin().await?;
+        while let Some((lane, _)) = lanes
+            .claim_next(
+                &mut tx,
+                &queries,
+                Utc::now(),
+                host_id,
+                Duration::from_mins(1),
+            )
+            .await?
+        {
+            served.push(lane);
+        }
+        tx.commit().await?;
+
+        // 3:1 while both lanes have messages, then the batch lane takes the remaining turns
+        assert_eq!(
+            served,
+            [
+                "interactive",
+                "interactive",
+                "batch",
+                "interactive",
+                "interactive",
+                "batch",
+                "batch",
+                "batch"
+            ]
+        );
+
+        Ok(())
+    }
+}
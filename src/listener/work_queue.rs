@@ -0,0 +1,668 @@
+use crate::listener::{OperatorControl, ResourceGate};
+use crate::models::RawMessage;
+use futures::future::BoxFuture;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::sync::{Mutex, mpsc, watch};
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+
+/// Capacity and concurrency of a [`WorkQueue`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorkQueueConfig {
+    /// How many claimed messages may wait for a worker. The claimer stops claiming while the channel is full, so
+    /// this bounds how long a claimed message waits before its handling starts.
+    pub capacity: usize,
+    /// The number of workers handling messages concurrently, until changed with
+    /// [`set_workers`](WorkQueue::set_workers)
+    pub workers: usize,
+    /// How long the claimer waits before claiming again when nothing was claimable
+    pub idle_interval: Duration,
+    /// How long a claim may take before it is abandoned, e.g. under hot lock contention, None to wait for it
+    pub claim_timeout: Option<Duration>,
+}
+
+/// Counters of a [`WorkQueue`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WorkQueueStats {
+    pub claimed: u64,
+    /// Claims that failed with an error
+    pub claim_errors: u64,
+    /// Claims abandoned after the [`claim_timeout`](WorkQueueConfig::claim_timeout)
+    pub claim_timeouts: u64,
+}
+
+/// A claimer task feeding claimed messages through a bounded channel to a pool of workers.
+///
+/// Decouples claiming from handling: slow handlers occupy workers but don't stall the claimer, and a full channel
+/// pauses claiming rather than accumulating claimed messages whose leases run out. The claimer reserves a slot in
+/// the channel before it claims, so a message is never held claimed while waiting for room.
+pub struct WorkQueue {
+    cancellation: CancellationToken,
+    tasks: JoinSet<()>,
+    stats: Arc<std::sync::Mutex<WorkQueueStats>>,
+    workers: Arc<watch::Sender<usize>>,
+    live: Arc<AtomicUsize>,
+}
+
+impl WorkQueue {
+    /// Spawns the claimer and the workers.
+    ///
+    /// `claim` claims the next message, typically in its own transaction, and `handle` handles and reports it.
+    /// Claim errors are logged and retried after the idle interval.
+    ///
+    /// Claims that exceed the claim timeout are dropped, which rolls back a transaction they hold, and retried
+    /// after the idle interval plus jitter so that contending claimers spread out. A claim that committed just
+    /// before it was dropped leaves its message leased until the lease expires, after which it is claimed again.
+    ///
+    /// Dropping a claim only stops waiting for it, the server keeps running its statement until the connection is
+    /// used again. Claims should set the same timeout on the server, see
+    /// [`Queries::with_claim_timeout`](crate::queries::Queries::with_claim_timeout), so that a claim stuck on a lock
+    /// frees its backend.
+    pub fn spawn<C, H>(config: WorkQueueConfig, claim: C, handle: H) -> Self
+    where
+        C: FnMut() -> BoxFuture<'static, Result<Option<RawMessage>, sqlx::Error>> + Send + 'static,
+        H: Fn(RawMessage) -> BoxFuture<'static, ()> + Send + Sync + 'static,
+    {
+        Self::spawn_gated(config, || true, claim, handle)
+    }
+
+    /// Spawns the claimer and the workers like [`spawn`](Self::spawn), claiming only while `gate` is open.
+    ///
+    /// The gate is checked before every claim, while it is closed the claimer waits for the idle interval and
+    /// checks again. Workers keep handling the messages already claimed.
+    pub fn spawn_gated<G, C, H>(config: WorkQueueConfig, gate: G, mut claim: C, handle: H) -> Self
+    where
+        G: ResourceGate + 'static,
+        C: FnMut() -> BoxFuture<'static, Result<Option<RawMessage>, sqlx::Error>> + Send + 'static,
+        H: Fn(RawMessage) -> BoxFuture<'static, ()> + Send + Sync + 'static,
+    {
+        let cancellation = CancellationToken::new();
+        let (sender, receiver) = mpsc::channel::<RawMessage>(config.capacity.max(1));
+        let receiver = Arc::new(Mutex::new(receiver));
+        let handle = Arc::new(handle);
+        let mut tasks = JoinSet::new();
+
+        let stats = Arc::new(std::sync::Mutex::new(WorkQueueStats::default()));
+
+        let stop = cancellation.clone();
+        let idle_interval = config.idle_interval;
+        let claim_timeout = config.claim_timeout;
+        let claimer_stats = stats.clone();
+        tasks.spawn(async move {
+            let mut closed = false;
+            loop {
+                if !gate.is_open().await {
+                    if !closed {
+                        tracing::warn!("Resource gate closed, pausing claims");
+                        closed = true;
+                    }
+                    tokio::select! {
+                        _ = stop.cancelled() => break,
+                        _ = tokio::time::sleep(idle_interval) => continue,
+                    }
+                }
+                if closed {
+                    tracing::info!("Resource gate opened, resuming claims");
+                    closed = false;
+                }
+
+                let permit = tokio::select! {
+                    _ = stop.cancelled() => break,
+                    permit = sender.reserve() => match permit {
+                        Ok(permit) => permit,
+                        Err(_) => break,
+                    },
+                };
+
+                // Not raced against cancellation, a claim that commits must reach a worker
+                let claimed = match claim_timeout {
+                    Some(timeout) => tokio::time::timeout(timeout, claim()).await,
+                    None => Ok(claim().await),
+                };
+
+                let Ok(claimed) = claimed else {
+                    drop(permit);
+                    claimer_stats.lock().expect("poisoned").claim_timeouts += 1;
+                    tracing::warn!(?claim_timeout, "Claim timed out, backing off");
+                    tokio::select! {
+                        _ = stop.cancelled() => break,
+                        _ = tokio::time::sleep(idle_interval + jitter(idle_interval)) => continue,
+                    }
+                };
+
+                match claimed {
+                    Ok(Some(message)) => {
+                        claimer_stats.lock().expect("poisoned").claimed += 1;
+                        permit.send(message);
+                    }
+                    Ok(None) => {
+                        drop(permit);
+                        tokio::select! {
+                            _ = stop.cancelled() => break,
+                            _ = tokio::time::sleep(idle_interval) => {}
+                        }
+                    }
+                    Err(error) => {
+                        drop(permit);
+                        claimer_stats.lock().expect("poisoned").claim_errors += 1;
+                        tracing::warn!(%error, "Could not claim a message");
+                        tokio::select! {
+                            _ = stop.cancelled() => break,
+                            _ = tokio::time::sleep(idle_interval) => {}
+                        }
+                    }
+                }
+            }
+            // Dropping the sender lets the workers drain the channel and exit
+        });
+
+        // Spawns workers until there are as many as wanted, the surplus workers leave on their own
+        let (workers, mut wanted) = watch::channel(config.workers.max(1));
+        let live = Arc::new(AtomicUsize::new(0));
+        let supervisor_live = live.clone();
+        let stop = cancellation.clone();
+        tasks.spawn(async move {
+            let mut pool = JoinSet::new();
+            loop {
+                let target = *wanted.borrow_and_update();
+                while supervisor_live.load(Ordering::SeqCst) < target {
+                    supervisor_live.fetch_add(1, Ordering::SeqCst);
+                    pool.spawn(work(
+                        receiver.clone(),
+                        handle.clone(),
+                        supervisor_live.clone(),
+                        wanted.clone(),
+                    ));
+                }
+
+                tokio::select! {
+                    biased;
+                    _ = stop.cancelled() => break,
+                    changed = wanted.changed() => {
+                        if changed.is_err() {
+                            break;
+                        }
+                    }
+                    Some(_) = pool.join_next() => {}
+                }
+            }
+            // Once the claimer stopped the workers drain the channel and exit
+            while pool.join_next().await.is_some() {}
+        });
+
+        Self {
+            cancellation,
+            tasks,
+            stats,
+            workers: Arc::new(workers),
+            live,
+        }
+    }
+
+    /// Spawns the claimer and the workers like [`spawn`](Self::spawn), following the operator commands applied
+    /// to `control`.
+    ///
+    /// Claims pause while `control` is paused, and [`SetConcurrency`](crate::models::OperatorCommand::SetConcurrency)
+    /// resizes the worker pool, see [`set_workers`](Self::set_workers). Until a concurrency is set, the pool has
+    /// the size of the config.
+    pub fn spawn_controlled<C, H>(
+        config: WorkQueueConfig,
+        control: &OperatorControl,
+        claim: C,
+        handle: H,
+    ) -> Self
+    where
+        C: FnMut() -> BoxFuture<'static, Result<Option<RawMessage>, sqlx::Error>> + Send + 'static,
+        H: Fn(RawMessage) -> BoxFuture<'static, ()> + Send + Sync + 'static,
+    {
+        let mut queue = Self::spawn_gated(config, control.clone(), claim, handle);
+
+        let workers = queue.workers.clone();
+        let stop = queue.cancellation.clone();
+        let mut changes = control.subscribe();
+        changes.mark_changed();
+        queue.tasks.spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = stop.cancelled() => break,
+                    changed = changes.changed() => {
+                        if changed.is_err() {
+                            break;
+                        }
+                        let wanted = changes.borrow_and_update().workers;
+                        if let Some(wanted) = wanted {
+                            resize(&workers, wanted as usize);
+                        }
+                    }
+                }
+            }
+        });
+
+        queue
+    }
+
+    /// Resizes the worker pool to `workers`, at least one.
+    ///
+    /// Workers are added right away. When shrinking, surplus workers leave once they finished the message they are
+    /// handling, so the pool may be larger than `workers` until then.
+    pub fn set_workers(&self, workers: usize) {
+        resize(&self.workers, workers);
+    }
+
+    /// The number of workers currently running
+    pub fn workers(&self) -> usize {
+        self.live.load(Ordering::SeqCst)
+    }
+
+    pub fn stats(&self) -> WorkQueueStats {
+        self.stats.lock().expect("poisoned").clone()
+    }
+
+    /// Stops claiming, then waits for the workers to handle the messages already claimed
+    pub async fn shutdown(mut self) {
+        self.cancellation.cancel();
+        while self.tasks.join_next().await.is_some() {}
+    }
+}
+
+/// Sets the number of workers the pool should have, at least one, waking the pool if it changed
+fn resize(workers: &watch::Sender<usize>, wanted: usize) {
+    let wanted = wanted.max(1);
+    workers.send_if_modified(|current| {
+        if *current == wanted {
+            return false;
+        }
+        tracing::info!(from = *current, to = wanted, "Resizing the worker pool");
+        *current = wanted;
+        true
+    });
+}
+
+/// Handles messages from `receiver` until the channel is closed, or until there are more `live` workers than
+/// `wanted`
+async fn work<H>(
+    receiver: Arc<Mutex<mpsc::Receiver<RawMessage>>>,
+    handle: Arc<H>,
+    live: Arc<AtomicUsize>,
+    mut wanted: watch::Receiver<usize>,
+) where
+    H: Fn(RawMessage) -> BoxFuture<'static, ()> + Send + Sync + 'static,
+{
+    loop {
+        let target = *wanted.borrow_and_update();
+        let surplus = live.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |live| {
+            (live > target).then(|| live - 1)
+        });
+        if surplus.is_ok() {
+            return;
+        }
+
+        tokio::select! {
+            next = async { receiver.lock().await.recv().await } => match next {
+                Some(message) => handle(message).await,
+                None => break,
+            },
+            Ok(()) = wanted.changed() => {}
+        }
+    }
+    live.fetch_sub(1, Ordering::SeqCst);
+}
+
+/// A random duration of up to `max`
+fn jitter(max: Duration) -> Duration {
+    let random = RandomState::new().build_hasher().finish();
+    max.mul_f64(random as f64 / u64::MAX as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::OperatorCommand;
+    use crate::queries::{Queries, get_next_unattempted, publish_message, report_success};
+    use crate::testing_tools::{TestMessage, is_pending, is_succeeded};
+    use chrono::Utc;
+    use futures::FutureExt;
+    use std::sync::atomic::AtomicBool;
+    use tokio::sync::Semaphore;
+    use uuid::Uuid;
+
+    /// Counts the handlers running at once, each of them waiting for a permit of `permits` before it reports
+    #[derive(Clone)]
+    struct Concurrency {
+        running: Arc<AtomicUsize>,
+        max: Arc<AtomicUsize>,
+        handled: Arc<AtomicUsize>,
+        permits: Arc<Semaphore>,
+    }
+
+    impl Concurrency {
+        fn new() -> Self {
+            Self {
+                running: Arc::default(),
+                max: Arc::default(),
+                handled: Arc::default(),
+                permits: Arc::new(Semaphore::new(0)),
+            }
+        }
+
+        fn claim(
+            &self,
+            pool: &sqlx::PgPool,
+        ) -> impl FnMut() -> BoxFuture<'static, Result<Option<RawMessage>, sqlx::Error>> + use<>
+        {
+            let pool = pool.clone();
+            move || {
+                let pool = pool.clone();
+                async move {
+                    get_next_unattempted(&pool, Utc::now(), Uuid::now_v7(), Duration::from_mins(1))
+                        .await
+                }
+                .boxed()
+            }
+        }
+
+        fn handle(
+            &self,
+            pool: &sqlx::PgPool,
+        ) -> impl Fn(RawMessage) -> BoxFuture<'static, ()> + use<> {
+            let (pool, concurrency) = (pool.clone(), self.clone());
+            move |message: RawMessage| {
+                let (pool, concurrency) = (pool.clone(), concurrency.clone());
+                async move {
+                    let running = concurrency.running.fetch_add(1, Ordering::SeqCst) + 1;
+                    concurrency.max.fetch_max(running, Ordering::SeqCst);
+                    concurrency
+                        .permits
+                        .acquire()
+                        .await
+                        .expect("Expected the semaphore to be open")
+                        .forget();
+                    concurrency.running.fetch_sub(1, Ordering::SeqCst);
+                    report_success(&pool, message.id, message.fencing_token, Utc::now())
+                        .await
+                        .expect("Expected to report success");
+                    concurrency.handled.fetch_add(1, Ordering::SeqCst);
+                }
+                .boxed()
+            }
+        }
+
+        async fn wait_for(&self, counter: &AtomicUsize, n: usize) -> anyhow::Result<()> {
+            tokio::time::timeout(Duration::from_secs(10), async {
+                while counter.load(Ordering::SeqCst) < n {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            })
+            .await?;
+            Ok(())
+        }
+    }
+
+    fn config(workers: usize) -> WorkQueueConfig {
+        WorkQueueConfig {
+            capacity: 1,
+            workers,
+            idle_interval: Duration::from_millis(10),
+            claim_timeout: None,
+        }
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_handles_claimed_messages_with_a_worker_pool(
+        pool: sqlx::PgPool,
+    ) -> anyhow::Result<()> {
+        let mut published = Vec::new();
+        for _ in 0..5 {
+            published.push(publish_message(&pool, &TestMessage::default().to_raw()?).await?);
+        }
+
+        let host_id = Uuid::now_v7();
+        let handled = Arc::new(AtomicUsize::new(0));
+
+        let claim_pool = pool.clone();
+        let claim = move || {
+            let pool = claim_pool.clone();
+            async move {
+                get_next_unattempted(&pool, Utc::now(), host_id, Duration::from_mins(1)).await
+            }
+            .boxed()
+        };
+
+        let handle_pool = pool.clone();
+        let counter = handled.clone();
+        let handle = move |message: RawMessage| {
+            let pool = handle_pool.clone();
+            let counter = counter.clone();
+            async move {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                report_success(&pool, message.id, message.fencing_token, Utc::now())
+                    .await
+                    .expect("Expected to report success");
+                counter.fetch_add(1, Ordering::SeqCst);
+            }
+            .boxed()
+        };
+
+        let config = WorkQueueConfig {
+            capacity: 1,
+            workers: 2,
+            idle_interval: Duration::from_millis(10),
+            claim_timeout: None,
+        };
+        let work_queue = WorkQueue::spawn(config, claim, handle);
+
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while handled.load(Ordering::SeqCst) < published.len() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await?;
+        work_queue.shutdown().await;
+
+        for message in &published {
+            assert!(is_succeeded(&pool, message.id, Utc::now()).await?);
+        }
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_pauses_claims_while_the_gate_is_closed(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let published = publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+
+        let open = Arc::new(AtomicBool::new(false));
+        let gate = {
+            let open = open.clone();
+            move || open.load(Ordering::SeqCst)
+        };
+
+        let claim_pool = pool.clone();
+        let claim = move || {
+            let pool = claim_pool.clone();
+            async move {
+                get_next_unattempted(&pool, Utc::now(), Uuid::now_v7(), Duration::from_mins(1))
+                    .await
+            }
+            .boxed()
+        };
+
+        let handle_pool = pool.clone();
+        let handle = move |message: RawMessage| {
+            let pool = handle_pool.clone();
+            async move {
+                report_success(&pool, message.id, message.fencing_token, Utc::now())
+                    .await
+                    .expect("Expected to report success");
+            }
+            .boxed()
+        };
+
+        let config = WorkQueueConfig {
+            capacity: 1,
+            workers: 1,
+            idle_interval: Duration::from_millis(10),
+            claim_timeout: None,
+        };
+        let work_queue = WorkQueue::spawn_gated(config, gate, claim, handle);
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(is_pending(&pool, published.id, Utc::now()).await?);
+
+        open.store(true, Ordering::SeqCst);
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while !is_succeeded(&pool, published.id, Utc::now())
+                .await
+                .expect("Expected to query")
+            {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await?;
+        work_queue.shutdown().await;
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_abandons_claims_that_time_out(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let published = publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+
+        let stalled = Arc::new(AtomicBool::new(true));
+        let claim_pool = pool.clone();
+        let claim_stalled = stalled.clone();
+        let claim = move || {
+            let pool = claim_pool.clone();
+            let stalled = claim_stalled.swap(false, Ordering::SeqCst);
+            async move {
+                let mut tx = pool.begin().await?;
+                if stalled {
+                    sqlx::query("SELECT pg_sleep(1)").execute(&mut *tx).await?;
+                }
+                let claimed = get_next_unattempted(
+                    &mut *tx,
+                    Utc::now(),
+                    Uuid::now_v7(),
+                    Duration::from_mins(1),
+                )
+                .await?;
+                tx.commit().await?;
+                Ok(claimed)
+            }
+            .boxed()
+        };
+
+        let handle_pool = pool.clone();
+        let handle = move |message: RawMessage| {
+            let pool = handle_pool.clone();
+            async move {
+                report_success(&pool, message.id, message.fencing_token, Utc::now())
+                    .await
+                    .expect("Expected to report success");
+            }
+            .boxed()
+        };
+
+        let config = WorkQueueConfig {
+            capacity: 1,
+            workers: 1,
+            idle_interval: Duration::from_millis(10),
+            claim_timeout: Some(Duration::from_millis(100)),
+        };
+        let work_queue = WorkQueue::spawn(config, claim, handle);
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !is_succeeded(&pool, published.id, Utc::now())
+                .await
+                .expect("Expected to query")
+            {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await?;
+
+        let stats = work_queue.stats();
+        assert_eq!(stats.claim_timeouts, 1);
+        assert_eq!(stats.claimed, 1);
+        work_queue.shutdown().await;
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_resizes_the_worker_pool(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        for _ in 0..6 {
+            publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+        }
+
+        let concurrency = Concurrency::new();
+        let work_queue = WorkQueue::spawn(
+            config(1),
+            concurrency.claim(&pool),
+            concurrency.handle(&pool),
+        );
+        concurrency.wait_for(&concurrency.running, 1).await?;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(concurrency.max.load(Ordering::SeqCst), 1);
+
+        work_queue.set_workers(3);
+        concurrency.wait_for(&concurrency.running, 3).await?;
+        assert_eq!(work_queue.workers(), 3);
+
+        // The surplus workers leave after their message
+        work_queue.set_workers(1);
+        concurrency.permits.add_permits(3);
+        concurrency.wait_for(&concurrency.handled, 3).await?;
+        concurrency.max.store(0, Ordering::SeqCst);
+        concurrency.permits.add_permits(3);
+        concurrency.wait_for(&concurrency.handled, 6).await?;
+        assert_eq!(concurrency.max.load(Ordering::SeqCst), 1);
+        assert_eq!(work_queue.workers(), 1);
+
+        work_queue.shutdown().await;
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_follows_operator_commands(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let queries = Queries::new("public")?;
+        let host_id = Uuid::now_v7();
+        let control = OperatorControl::new();
+        let issue = async |command| -> anyhow::Result<()> {
+            let mut tx = pool.begin().await?;
+            queries
+                .issue_command(&mut tx, &command, None, Utc::now())
+                .await?;
+            tx.commit().await?;
+            control.refresh(&pool, &queries, host_id).await?;
+            Ok(())
+        };
+
+        issue(OperatorCommand::Pause).await?;
+        let concurrency = Concurrency::new();
+        let work_queue = WorkQueue::spawn_controlled(
+            config(1),
+            &control,
+            concurrency.claim(&pool),
+            concurrency.handle(&pool),
+        );
+        for _ in 0..3 {
+            publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(concurrency.running.load(Ordering::SeqCst), 0);
+
+        issue(OperatorCommand::SetConcurrency { workers: 3 }).await?;
+        issue(OperatorCommand::Resume).await?;
+        concurrency.wait_for(&concurrency.running, 3).await?;
+        assert_eq!(work_queue.workers(), 3);
+
+        concurrency.permits.add_permits(3);
+        concurrency.wait_for(&concurrency.handled, 3).await?;
+        work_queue.shutdown().await;
+        Ok(())
+    }
+}
@@ -1,8 +1,13 @@
-use sqlx::{Acquire, Postgres};
+use crate::compatibility::{CompatibilityError, ensure_supported};
+use const_fnv1a_hash::fnv1a_hash_str_64;
+use sqlx::migrate::{MigrateError, Migrator};
+use sqlx::{Acquire, PgConnection, Postgres};
+use std::collections::HashMap;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct PgIdentifier {
     ident: String,
+    unquoted: String,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -13,11 +18,102 @@ pub enum PgIdentifierParsingError {
     TooLarge,
     #[error("Identifiers must start with [A-Za-z] or \"_\"")]
     InvalidFirstChar,
+    #[error("Identifiers may not be a reserved word: {0}")]
+    Reserved(String),
+    #[error("Qualified identifiers must be of the form \"name\" or \"schema.name\"")]
+    InvalidQualification,
 }
 
 const DEFAULT_NAMEDATALEN: usize = 64; // Postgres default, compile time configured constant
 
+// Keywords that Postgres lists as reserved, which can't be used as unquoted table or column names
+const RESERVED_WORDS: &[&str] = &[
+    "all",
+    "analyse",
+    "analyze",
+    "and",
+    "any",
+    "array",
+    "as",
+    "asc",
+    "asymmetric",
+    "both",
+    "case",
+    "cast",
+    "check",
+    "collate",
+    "column",
+    "constraint",
+    "create",
+    "current_catalog",
+    "current_date",
+    "current_role",
+    "current_time",
+    "current_timestamp",
+    "current_user",
+    "default",
+    "deferrable",
+    "desc",
+    "distinct",
+    "do",
+    "else",
+    "end",
+    "except",
+    "false",
+    "fetch",
+    "for",
+    "foreign",
+    "from",
+    "grant",
+    "group",
+    "having",
+    "in",
+    "initially",
+    "intersect",
+    "into",
+    "lateral",
+    "leading",
+    "limit",
+    "localtime",
+    "localtimestamp",
+    "not",
+    "null",
+    "offset",
+    "on",
+    "only",
+    "or",
+    "order",
+    "placing",
+    "primary",
+    "references",
+    "returning",
+    "select",
+    "session_user",
+    "some",
+    "symmetric",
+    "system_user",
+    "table",
+    "then",
+    "to",
+    "trailing",
+    "true",
+    "union",
+    "unique",
+    "user",
+    "using",
+    "variadic",
+    "when",
+    "where",
+    "window",
+    "with",
+];
+
 impl PgIdentifier {
+    /// Validates `raw` and quotes it. Reserved words are rejected, even though quoting would allow them, so that the
+    /// names remain usable unquoted, e.g. from `psql`.
+    ///
+    /// Quoted identifiers are case sensitive: `MySchema` names the schema created by `CREATE SCHEMA "MySchema"`, not
+    /// the one created by `CREATE SCHEMA MySchema`, which Postgres folds to `myschema`.
     pub fn parse(raw: &str) -> Result<Self, PgIdentifierParsingError> {
         if raw.is_empty() {
             return Err(PgIdentifierParsingError::Empty);
@@ -32,16 +128,28 @@ impl PgIdentifier {
             return Err(PgIdentifierParsingError::InvalidFirstChar);
         }
 
+        if RESERVED_WORDS.contains(&raw.to_ascii_lowercase().as_str()) {
+            return Err(PgIdentifierParsingError::Reserved(raw.to_string()));
+        }
+
         // Escape internal double quotes
         let escaped = raw.replace('"', "\"\"");
 
         // Wrap in quotes for PostgreSQL
         Ok(PgIdentifier {
             ident: format!("\"{}\"", escaped),
+            unquoted: raw.to_string(),
         })
     }
 
-    pub fn as_ref(&self) -> &str {
+    /// The identifier as it was parsed, before quoting
+    pub fn unquoted(&self) -> &str {
+        &self.unquoted
+    }
+}
+
+impl AsRef<str> for PgIdentifier {
+    fn as_ref(&self) -> &str {
         &self.ident
     }
 }
@@ -52,9 +160,70 @@ impl std::fmt::Display for PgIdentifier {
     }
 }
 
+/// A possibly schema qualified identifier, such as `schema.table`
+#[derive(Debug)]
+pub struct PgQualifiedIdentifier {
+    schema: Option<PgIdentifier>,
+    name: PgIdentifier,
+}
+
+impl PgQualifiedIdentifier {
+    /// Parses `name` or `schema.name`, validating and quoting each part as a [`PgIdentifier`]
+    pub fn parse(raw: &str) -> Result<Self, PgIdentifierParsingError> {
+        let mut parts = raw.split('.');
+
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(name), None, None) => Ok(Self {
+                schema: None,
+                name: PgIdentifier::parse(name)?,
+            }),
+            (Some(schema), Some(name), None) => Ok(Self {
+                schema: Some(PgIdentifier::parse(schema)?),
+                name: PgIdentifier::parse(name)?,
+            }),
+            _ => Err(PgIdentifierParsingError::InvalidQualification),
+        }
+    }
+
+    pub fn schema(&self) -> Option<&PgIdentifier> {
+        self.schema.as_ref()
+    }
+
+    pub fn name(&self) -> &PgIdentifier {
+        &self.name
+    }
+}
+
+impl std::fmt::Display for PgQualifiedIdentifier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.schema {
+            Some(schema) => write!(f, "{}.{}", schema, self.name),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+/// Quotes a string for use as a literal in SQL that can't be parameterized, such as DDL.
+///
+/// Mirrors the Postgres `quote_literal` function: single quotes are doubled and, if the string
+/// contains backslashes, they are doubled too and the literal is written in escape string syntax.
+pub fn quote_literal(raw: &str) -> String {
+    let escaped = raw.replace('\'', "''");
+
+    if escaped.contains('\\') {
+        format!("E'{}'", escaped.replace('\\', "\\\\"))
+    } else {
+        format!("'{}'", escaped)
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::migrator::PgIdentifier;
+    use crate::migrator::{
+        MigrateError, MigratorError, PgIdentifier, PgQualifiedIdentifier, create_payload_index,
+        drop_payload_index, migrator, payload_index_name, quote_literal, run_migrations,
+        run_migrations_with_table,
+    };
 
     #[test]
     fn it_escapes_wierd_identifier_names() -> anyhow::Result<()> {
@@ -91,6 +260,127 @@ mod tests {
         assert!(PgIdentifier::parse(".").is_err());
         Ok(())
     }
+
+    #[test]
+    fn it_rejects_reserved_words() -> anyhow::Result<()> {
+        assert!(PgIdentifier::parse("select").is_err());
+        assert!(PgIdentifier::parse("User").is_err());
+        assert!(PgIdentifier::parse("users").is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn it_keeps_the_case_of_identifiers() -> anyhow::Result<()> {
+        assert_eq!(PgIdentifier::parse("MySchema")?.as_ref(), "\"MySchema\"");
+        Ok(())
+    }
+
+    #[test]
+    fn it_renders_qualified_identifiers() -> anyhow::Result<()> {
+        let ident = PgQualifiedIdentifier::parse("fx_mq.messages")?;
+        assert_eq!(ident.to_string(), "\"fx_mq\".\"messages\"");
+
+        let ident = PgQualifiedIdentifier::parse("messages")?;
+        assert_eq!(ident.to_string(), "\"messages\"");
+
+        assert!(PgQualifiedIdentifier::parse("a.b.c").is_err());
+        assert!(PgQualifiedIdentifier::parse(".messages").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn it_quotes_literals() -> anyhow::Result<()> {
+        assert_eq!(quote_literal("plain"), "'plain'");
+        assert_eq!(quote_literal("it's"), "'it''s'");
+        assert_eq!(quote_literal("a\\b"), "E'a\\\\b'");
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_records_migrations_in_a_custom_table(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        run_migrations_with_table(&pool, "fx_mq", "_fx_mq_migrations").await?;
+        // Running again is a no-op
+        run_migrations_with_table(&pool, "fx_mq", "_fx_mq_migrations").await?;
+
+        let recorded: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM fx_mq._fx_mq_migrations")
+            .fetch_one(&pool)
+            .await?;
+        let embedded = migrator()
+            .iter()
+            .filter(|m| !m.migration_type.is_down_migration())
+            .count();
+        assert_eq!(recorded as usize, embedded);
+
+        let default_table: Option<String> =
+            sqlx::query_scalar("SELECT to_regclass('fx_mq._sqlx_migrations')::TEXT")
+                .fetch_one(&pool)
+                .await?;
+        assert_eq!(default_table, None);
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_rejects_changed_migrations(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        run_migrations_with_table(&pool, "fx_mq", "_fx_mq_migrations").await?;
+
+        sqlx::query("UPDATE fx_mq._fx_mq_migrations SET checksum = '\\x00'")
+            .execute(&pool)
+            .await?;
+
+        let result = run_migrations_with_table(&pool, "fx_mq", "_fx_mq_migrations").await;
+        assert!(matches!(
+            result,
+            Err(MigratorError::Migrate(MigrateError::VersionMismatch(_)))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_names_payload_indexes_by_field() -> anyhow::Result<()> {
+        let name = |field| payload_index_name("messages_unattempted", field);
+
+        assert_eq!(
+            name("region")?.unquoted(),
+            "idx_messages_unattempted_payload_region"
+        );
+        assert_ne!(name("tenant id")?, name("tenant_id")?);
+        assert_ne!(name("tenant id")?, name("tenant-id")?);
+
+        let long = "a".repeat(40);
+        assert!(name(&long)?.unquoted().len() <= 63);
+        assert_ne!(name(&long)?, name(&format!("{long}b"))?);
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_creates_and_drops_payload_indexes(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        async fn field_indexes(pool: &sqlx::PgPool) -> anyhow::Result<i64> {
+            Ok(sqlx::query_scalar(
+                "SELECT COUNT(*) FROM pg_indexes WHERE schemaname = 'fx_mq' AND indexdef LIKE '%->>%'",
+            )
+            .fetch_one(pool)
+            .await?)
+        }
+
+        run_migrations(&pool, "fx_mq").await?;
+
+        create_payload_index(&pool, "fx_mq", "tenant's id").await?;
+        create_payload_index(&pool, "fx_mq", "region").await?;
+        // Creating again is a no-op
+        create_payload_index(&pool, "fx_mq", "region").await?;
+        assert_eq!(field_indexes(&pool).await?, 4);
+
+        drop_payload_index(&pool, "fx_mq", "tenant's id").await?;
+        assert_eq!(field_indexes(&pool).await?, 2);
+
+        drop_payload_index(&pool, "fx_mq", "region").await?;
+        drop_payload_index(&pool, "fx_mq", "region").await?;
+        assert_eq!(field_indexes(&pool).await?, 0);
+
+        Ok(())
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -101,10 +391,22 @@ pub enum MigratorError {
     Database(#[from] sqlx::Error),
     #[error("MigrateError: {0}")]
     Migrate(#[from] sqlx::migrate::MigrateError),
+    #[error("CompatibilityError: {0}")]
+    Compatibility(#[from] CompatibilityError),
 }
 
 // Embed the migrations directory at compile time
-static MIGRATOR: sqlx::migrate::Migrator = sqlx::migrate!();
+static MIGRATOR: Migrator = sqlx::migrate!();
+
+/// The name of the table in which sqlx records applied migrations by default
+pub const DEFAULT_MIGRATIONS_TABLE: &str = "_sqlx_migrations";
+
+/// Returns the embedded migrations of this crate.
+///
+/// Useful for downstream crates that want to inspect or run the migrations with their own tooling.
+pub fn migrator() -> &'static Migrator {
+    &MIGRATOR
+}
 
 /// Runs database migrations for the specified schema.
 ///
@@ -117,7 +419,9 @@ static MIGRATOR: sqlx::migrate::Migrator = sqlx::migrate!();
 ///
 /// # Errors
 ///
-/// Returns `sqlx::Error` if schema creation or migration execution fails.
+/// Returns `MigratorError::Compatibility` if the server is not supported, see
+/// [`ensure_supported`], and `sqlx::Error` if schema creation or migration
+/// execution fails.
 pub async fn run_migrations<'a, A>(conn: A, schema: &str) -> Result<(), MigratorError>
 where
     A: Acquire<'a, Database = Postgres>,
@@ -125,19 +429,215 @@ where
     let schema_ident = PgIdentifier::parse(schema)?;
 
     let mut tx = conn.begin().await?;
+    ensure_supported(&mut *tx).await?;
+    enter_schema(&mut tx, &schema_ident).await?;
+
+    // Run migrations within the schema
+    MIGRATOR.run(&mut *tx).await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Runs database migrations for the specified schema, recording applied versions in `table`.
+///
+/// Behaves like [`run_migrations`], but lets applications that embed this crate keep its migration
+/// history apart from their own when both use sqlx migrations in the same schema. The table is
+/// created within the schema if it doesn't exist and has the same layout as the one used by sqlx.
+///
+/// # Errors
+///
+/// Returns `MigratorError::Compatibility` if the server is not supported, `MigrateError::VersionMismatch` if an
+/// applied migration has changed since it was applied, and `MigrateError::Dirty` if a previous run left a migration
+/// partially applied.
+pub async fn run_migrations_with_table<'a, A>(
+    conn: A,
+    schema: &str,
+    table: &str,
+) -> Result<(), MigratorError>
+where
+    A: Acquire<'a, Database = Postgres>,
+{
+    let schema_ident = PgIdentifier::parse(schema)?;
+    let table_ident = PgIdentifier::parse(table)?;
+
+    let mut tx = conn.begin().await?;
+    ensure_supported(&mut *tx).await?;
+    enter_schema(&mut tx, &schema_ident).await?;
+
+    let create_table = format!(
+        r#"
+        CREATE TABLE IF NOT EXISTS {table_ident} (
+            version BIGINT PRIMARY KEY,
+            description TEXT NOT NULL,
+            installed_on TIMESTAMPTZ NOT NULL DEFAULT now(),
+            success BOOLEAN NOT NULL,
+            checksum BYTEA NOT NULL,
+            execution_time BIGINT NOT NULL
+        );
+        "#
+    );
+    sqlx::query(&create_table).execute(&mut *tx).await?;
 
+    // Serialize concurrent runs against the same table
+    let lock_table = format!("LOCK TABLE {table_ident} IN EXCLUSIVE MODE;");
+    sqlx::query(&lock_table).execute(&mut *tx).await?;
+
+    let select_applied = format!("SELECT version, checksum, success FROM {table_ident};");
+    let applied: HashMap<i64, (Vec<u8>, bool)> =
+        sqlx::query_as::<_, (i64, Vec<u8>, bool)>(&select_applied)
+            .fetch_all(&mut *tx)
+            .await?
+            .into_iter()
+            .map(|(version, checksum, success)| (version, (checksum, success)))
+            .collect();
+
+    if let Some(version) = applied
+        .iter()
+        .filter(|(_, (_, success))| !success)
+        .map(|(version, _)| *version)
+        .min()
+    {
+        return Err(MigrateError::Dirty(version).into());
+    }
+
+    for migration in MIGRATOR
+        .iter()
+        .filter(|m| !m.migration_type.is_down_migration())
+    {
+        match applied.get(&migration.version) {
+            Some((checksum, _)) if *checksum != *migration.checksum => {
+                return Err(MigrateError::VersionMismatch(migration.version).into());
+            }
+            Some(_) => continue,
+            None => {}
+        }
+
+        let start = std::time::Instant::now();
+
+        sqlx::raw_sql(&migration.sql)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| MigrateError::ExecuteMigration(e, migration.version))?;
+
+        let record = format!(
+            r#"
+            INSERT INTO {table_ident} (version, description, success, checksum, execution_time)
+            VALUES ($1, $2, TRUE, $3, $4);
+            "#
+        );
+        sqlx::query(&record)
+            .bind(migration.version)
+            .bind(&*migration.description)
+            .bind(&*migration.checksum)
+            .bind(start.elapsed().as_nanos() as i64)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Creates the schema if it doesn't exist and sets it as the search path of the current transaction
+async fn enter_schema(tx: &mut PgConnection, schema: &PgIdentifier) -> Result<(), sqlx::Error> {
     // Ensure the schema exists
-    let create_schema = format!("CREATE SCHEMA IF NOT EXISTS {};", schema_ident.as_ref());
+    let create_schema = format!("CREATE SCHEMA IF NOT EXISTS {};", schema.as_ref());
     sqlx::query(&create_schema).execute(&mut *tx).await?;
 
     // Temporarily set search_path for this transaction
-    let set_search_path = format!("SET LOCAL search_path TO {};", schema_ident.as_ref());
+    let set_search_path = format!("SET LOCAL search_path TO {};", schema.as_ref());
     sqlx::query(&set_search_path).execute(&mut *tx).await?;
 
-    // Run migrations within the schema
-    MIGRATOR.run(&mut *tx).await?;
+    Ok(())
+}
 
-    tx.commit().await?;
+/// Tables holding message payloads, covered by every payload index
+const PAYLOAD_TABLES: &[&str] = &["messages_unattempted", "messages_attempted"];
+
+/// The name of the index on `field` of `table`.
+///
+/// Fields of ASCII letters, digits and underscores are named as they are if they fit within the length limit, so that
+/// distinct fields get distinct indexes. Other fields are named by as much of them as fits, with other characters
+/// replaced by underscores, followed by `-` and a 64 bit hash of the field. Plain fields never contain `-`, so the two
+/// kinds of names can't collide.
+fn payload_index_name(table: &str, field: &str) -> Result<PgIdentifier, PgIdentifierParsingError> {
+    let prefix = format!("idx_{table}_payload_");
+    let max_len = DEFAULT_NAMEDATALEN - 1;
+
+    let plain = field
+        .bytes()
+        .all(|b| b.is_ascii_alphanumeric() || b == b'_');
+    if plain && prefix.len() + field.len() <= max_len {
+        return PgIdentifier::parse(&format!("{prefix}{field}"));
+    }
+
+    let hash = format!("-{:016x}", fnv1a_hash_str_64(field));
+    let sanitized: String = field
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .take(max_len - prefix.len() - hash.len())
+        .collect();
+    PgIdentifier::parse(&format!("{prefix}{sanitized}{hash}"))
+}
+
+/// Creates an index on the top level payload field `field`, `payload ->> 'field'`, on the message tables of
+/// `schema` unless it already exists. Serves claims filtered with a [`ClaimPredicate`](crate::models::ClaimPredicate)
+/// on the field, such as a tenant id. Containment searches such as
+/// [`Queries::search_pending`](crate::queries::Queries::search_pending) are served by the GIN indexes the
+/// migrations create instead.
+///
+/// Indexes are built concurrently so that publishing and claiming continue during the build, which means this
+/// can't run inside a transaction. A build that is interrupted leaves an invalid index behind, drop it with
+/// [`drop_payload_index`] before creating it again.
+pub async fn create_payload_index<'a, A>(
+    conn: A,
+    schema: &str,
+    field: &str,
+) -> Result<(), MigratorError>
+where
+    A: Acquire<'a, Database = Postgres>,
+{
+    let schema_ident = PgIdentifier::parse(schema)?;
+    let mut conn = conn.acquire().await?;
+
+    for table in PAYLOAD_TABLES {
+        let create_index = format!(
+            "CREATE INDEX CONCURRENTLY IF NOT EXISTS {} ON {}.{} ((payload ->> {}));",
+            payload_index_name(table, field)?,
+            schema_ident,
+            PgIdentifier::parse(table)?,
+            quote_literal(field)
+        );
+        sqlx::query(&create_index).execute(&mut *conn).await?;
+    }
+
+    Ok(())
+}
+
+/// Drops the index on `field` created with [`create_payload_index`] from the message tables of `schema`, if it
+/// exists
+pub async fn drop_payload_index<'a, A>(
+    conn: A,
+    schema: &str,
+    field: &str,
+) -> Result<(), MigratorError>
+where
+    A: Acquire<'a, Database = Postgres>,
+{
+    let schema_ident = PgIdentifier::parse(schema)?;
+    let mut conn = conn.acquire().await?;
+
+    for table in PAYLOAD_TABLES {
+        let drop_index = format!(
+            "DROP INDEX CONCURRENTLY IF EXISTS {}.{};",
+            schema_ident,
+            payload_index_name(table, field)?
+        );
+        sqlx::query(&drop_index).execute(&mut *conn).await?;
+    }
 
     Ok(())
 }
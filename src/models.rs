@@ -1,5 +1,5 @@
 use const_fnv1a_hash::fnv1a_hash_str_32;
-use serde::{Serialize, de::DeserializeOwned};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
 use uuid::Uuid;
 
 pub trait Message: Serialize + DeserializeOwned + Clone + Send + Sync + 'static {
@@ -19,4 +19,905 @@ pub struct RawMessage {
     pub payload: serde_json::Value,
     /// The number of times processing this message have been attempted
     pub attempted: i32,
+    /// Fencing token of the lease the message was claimed with, None when not claimed
+    pub fencing_token: Option<i64>,
+    /// Sequence number assigned at publish, ordering messages strictly in the order they were published. None
+    /// when the message was not read back from the database
+    pub seq: Option<i64>,
+    /// The number of attempts after which the message should be reported dead, from the stored
+    /// [`QueueSettings`] of its name. None when the name has no stored settings or the message was not read back
+    /// from the database
+    pub max_attempts: Option<i32>,
+}
+
+impl RawMessage {
+    /// Serializes a message into a new, unattempted raw message with a new id
+    pub fn from_message<M: Message>(message: &M) -> Result<Self, serde_json::Error> {
+        Ok(Self {
+            id: Uuid::now_v7(),
+            name: M::NAME.to_string(),
+            hash: M::HASH,
+            payload: serde_json::to_value(message)?,
+            attempted: 0,
+            fencing_token: None,
+            seq: None,
+            max_attempts: None,
+        })
+    }
+
+    /// Creates an unattempted raw message whose id is derived from `name`, `payload` and `namespace`, so that a
+    /// retried producer publishing the same payload generates the same id.
+    ///
+    /// The id is a UUIDv5 over the name and the payload with its object keys sorted, so equal payloads derive equal
+    /// ids whatever the order of their keys. Publish it with [`PublishConflict::Ignore`] to have a retried publish
+    /// keep the message published first, see
+    /// [`publish_message_on_conflict`](crate::queries::publish_message_on_conflict).
+    /// Use a namespace per producer or use case, as messages that are meant to be distinct must differ in payload.
+    pub fn with_derived_id(name: &str, payload: serde_json::Value, namespace: Uuid) -> Self {
+        let mut key = name.to_string();
+        key.push('\0');
+        write_canonical(&payload, &mut key);
+
+        Self {
+            id: Uuid::new_v5(&namespace, key.as_bytes()),
+            name: name.to_string(),
+            hash: fnv1a_hash_str_32(name) as i32,
+            payload,
+            attempted: 0,
+            fencing_token: None,
+            seq: None,
+            max_attempts: None,
+        }
+    }
+
+    /// Decodes the payload without cloning it, borrowing strings from it where `T` allows.
+    ///
+    /// For the fastest decode, claim the payload as JSON text with
+    /// [`get_next_unattempted_text`](crate::queries::get_next_unattempted_text) and decode it with
+    /// [`RawTextMessage::decode_borrowed`], which skips building a [`serde_json::Value`] altogether.
+    pub fn decode_borrowed<'a, T: Deserialize<'a>>(&'a self) -> Result<T, serde_json::Error> {
+        T::deserialize(&self.payload)
+    }
+}
+
+/// A claimed message whose payload was fetched as JSON text rather than as a [`serde_json::Value`].
+///
+/// Decoding from text parses the payload once, straight into the target type, where a [`RawMessage`] payload is
+/// first parsed into a tree of values, reducing the CPU spent per message for high-throughput consumers.
+#[derive(Debug, Clone)]
+pub struct RawTextMessage {
+    pub id: Uuid,
+    /// Event type name
+    pub name: String,
+    /// Hash of name for lookup performance
+    pub hash: i32,
+    /// The payload as JSON text
+    pub payload: String,
+    /// See [`RawMessage::attempted`]
+    pub attempted: i32,
+    /// See [`RawMessage::fencing_token`]
+    pub fencing_token: Option<i64>,
+    /// See [`RawMessage::seq`]
+    pub seq: Option<i64>,
+    /// See [`RawMessage::max_attempts`]
+    pub max_attempts: Option<i32>,
+}
+
+impl RawTextMessage {
+    /// Decodes the payload text, borrowing strings from it where `T` allows, e.g. `&'a str` fields without escapes
+    pub fn decode_borrowed<'a, T: Deserialize<'a>>(&'a self) -> Result<T, serde_json::Error> {
+        serde_json::from_str(&self.payload)
+    }
+
+    /// Parses the payload into a [`RawMessage`], e.g. to report with APIs that take one
+    pub fn into_raw(self) -> Result<RawMessage, serde_json::Error> {
+        Ok(RawMessage {
+            id: self.id,
+            name: self.name,
+            hash: self.hash,
+            payload: serde_json::from_str(&self.payload)?,
+            attempted: self.attempted,
+            fencing_token: self.fencing_token,
+            seq: self.seq,
+            max_attempts: self.max_attempts,
+        })
+    }
+}
+
+/// Writes `value` as JSON with the keys of objects sorted
+fn write_canonical(value: &serde_json::Value, out: &mut String) {
+    match value {
+        serde_json::Value::Object(object) => {
+            let mut entries: Vec<_> = object.iter().collect();
+            entries.sort_by_key(|(key, _)| *key);
+            out.push('{');
+            for (i, (key, value)) in entries.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                out.push_str(&serde_json::Value::String(key.clone()).to_string());
+                out.push(':');
+                write_canonical(value, out);
+            }
+            out.push('}');
+        }
+        serde_json::Value::Array(values) => {
+            out.push('[');
+            for (i, value) in values.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_canonical(value, out);
+            }
+            out.push(']');
+        }
+        scalar => out.push_str(&scalar.to_string()),
+    }
+}
+
+/// Aggregated counts for a single message name on a single (UTC) day
+#[derive(Debug, Clone, PartialEq)]
+pub struct DailyAggregate {
+    /// The UTC day the counts apply to
+    pub day: chrono::NaiveDate,
+    /// Event type name
+    pub name: String,
+    /// Number of messages published on this day
+    pub published: i64,
+    /// Number of messages that succeeded on this day
+    pub succeeded: i64,
+    /// Number of messages that were reported dead on this day
+    pub dead: i64,
+    /// Average number of attempts of the messages that succeeded or died on this day
+    pub avg_attempts: Option<f64>,
+}
+
+/// Result of rewriting a single batch of payloads
+#[derive(Debug, Clone, PartialEq)]
+pub struct PayloadRewrite {
+    /// Number of payloads that were changed by the rewrite
+    pub rewritten: u64,
+    /// Id of the last message of the batch, pass it to the next call to continue. None when there was nothing left.
+    pub cursor: Option<uuid::Uuid>,
+}
+
+/// An error reported for a message
+#[derive(Debug, Clone, PartialEq)]
+pub struct ErrorRecord {
+    /// Unique identifier
+    pub id: uuid::Uuid,
+    /// The message the error was reported for
+    pub message_id: uuid::Uuid,
+    /// The time the error was reported
+    pub reported_at: chrono::DateTime<chrono::Utc>,
+    /// The error text
+    pub error: String,
+    /// The category supplied with the error, see [`FailureReason`]
+    pub category: Option<String>,
+}
+
+/// The error of a failed attempt or dead message, with an optional category such as `timeout`, `5xx`, `validation`
+/// or `rate-limit` for aggregating what fails, see [`get_failure_categories`](crate::queries::get_failure_categories).
+///
+/// Plain error texts convert into reasons without a category.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FailureReason<'a> {
+    pub error: &'a str,
+    pub category: Option<&'a str>,
+}
+
+impl<'a> FailureReason<'a> {
+    pub fn new(error: &'a str) -> Self {
+        Self {
+            error,
+            category: None,
+        }
+    }
+
+    pub fn with_category(mut self, category: &'a str) -> Self {
+        self.category = Some(category);
+        self
+    }
+}
+
+impl<'a> From<&'a str> for FailureReason<'a> {
+    fn from(error: &'a str) -> Self {
+        Self::new(error)
+    }
+}
+
+impl<'a> From<&'a String> for FailureReason<'a> {
+    fn from(error: &'a String) -> Self {
+        Self::new(error)
+    }
+}
+
+/// Errors of a message name and category reported within a time bucket, see
+/// [`get_failure_categories`](crate::queries::get_failure_categories)
+#[derive(Debug, Clone, PartialEq)]
+pub struct FailureCategoryCount {
+    /// The start of the bucket
+    pub bucket: chrono::DateTime<chrono::Utc>,
+    /// The message name
+    pub name: String,
+    /// The category, None for errors reported without one
+    pub category: Option<String>,
+    /// Failed attempts that were to be retried
+    pub retries: i64,
+    /// Messages reported dead
+    pub deaths: i64,
+}
+
+/// A lease acquired on a message
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lease {
+    /// The time the lease expires
+    pub expires_at: chrono::DateTime<chrono::Utc>,
+    /// Monotonically increasing token identifying the lease, pass it to reports to reject stale reports
+    pub fencing_token: i64,
+}
+
+/// The current holder of the lease of a message
+#[derive(Debug, Clone, PartialEq)]
+pub struct LeaseHolder {
+    /// Id of the host holding the lease
+    pub host_id: uuid::Uuid,
+    /// Label of the host, None if the host never registered
+    pub label: Option<String>,
+    /// The time the lease was acquired
+    pub acquired_at: chrono::DateTime<chrono::Utc>,
+    /// The time the lease expires
+    pub expires_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// A message claimed by a host and not yet reported, see [`list_in_progress`](crate::queries::list_in_progress)
+#[derive(Debug, Clone, PartialEq)]
+pub struct InProgressMessage {
+    pub id: uuid::Uuid,
+    /// Event type name
+    pub name: String,
+    /// Sequence number assigned at publish, see [`RawMessage::seq`]
+    pub seq: i64,
+    /// The token of the lease the message was claimed with
+    pub fencing_token: i64,
+    /// The time the message was claimed
+    pub acquired_at: chrono::DateTime<chrono::Utc>,
+    /// The time the lease expires
+    pub expires_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Expired leases of a host that were taken over by a missing-claim
+#[derive(Debug, Clone, PartialEq)]
+pub struct LeaseLosses {
+    /// Id of the host that lost the leases
+    pub host_id: uuid::Uuid,
+    /// Label of the host, None if the host never registered
+    pub label: Option<String>,
+    /// Number of leases lost
+    pub lost: i64,
+    /// The time the most recent lost lease was taken over
+    pub last_taken_over_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Claimable messages that have not been claimed for longer than a threshold
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClaimStarvation {
+    /// Number of pending messages and retryable messages past their earliest retry time
+    pub claimable: i64,
+    /// The time the longest waiting claimable message became claimable
+    pub oldest_claimable_since: chrono::DateTime<chrono::Utc>,
+    /// The time of the most recent claim, None if nothing was ever claimed
+    pub last_claimed_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// Unprocessed messages of a single message name
+#[derive(Debug, Clone, PartialEq)]
+pub struct NameLag {
+    /// The message name
+    pub name: String,
+    /// Number of messages that are neither succeeded nor dead
+    pub unprocessed: i64,
+    /// Age of the oldest unprocessed message, measured from when it was published
+    pub max_age: std::time::Duration,
+}
+
+/// Distribution of the serialized payload sizes of a single message name, in bytes
+#[derive(Debug, Clone, PartialEq)]
+pub struct PayloadSizes {
+    /// The message name
+    pub name: String,
+    /// Number of messages measured
+    pub messages: i64,
+    /// Median payload size
+    pub p50: i64,
+    /// 95th percentile payload size
+    pub p95: i64,
+    /// Largest payload size
+    pub max: i64,
+}
+
+/// What a [`Quota`] limits the publishes of
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QuotaScope {
+    /// Messages of a single name
+    Name(String),
+    /// All messages of the schema
+    Schema,
+}
+
+impl QuotaScope {
+    /// The name the quota is stored under, `*` for the schema
+    pub fn key(&self) -> &str {
+        match self {
+            Self::Name(name) => name,
+            Self::Schema => "*",
+        }
+    }
+}
+
+impl std::fmt::Display for QuotaScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Name(name) => write!(f, "name {name}"),
+            Self::Schema => write!(f, "schema"),
+        }
+    }
+}
+
+/// Limits on the publishes of a message name or schema, enforced by
+/// [`Queries::with_quota_enforcement`](crate::queries::Queries::with_quota_enforcement)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Quota {
+    pub scope: QuotaScope,
+    /// The maximum number of messages published and not yet claimed, None if not limited
+    pub max_pending: Option<i64>,
+    /// The maximum number of messages published per UTC day, None if not limited
+    pub max_daily_publishes: Option<i64>,
+}
+
+/// Which limit of a [`Quota`] was exceeded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuotaLimit {
+    Pending,
+    DailyPublishes,
+}
+
+impl std::fmt::Display for QuotaLimit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Pending => write!(f, "pending messages"),
+            Self::DailyPublishes => write!(f, "daily publishes"),
+        }
+    }
+}
+
+/// Retry and lease settings of a single message name, stored in the database so that they can be changed at runtime
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueueSettings {
+    /// The message name
+    pub name: String,
+    /// Base of the exponential retry backoff
+    pub backoff_base: u32,
+    /// Delay before the first retry
+    pub backoff_base_delay: std::time::Duration,
+    /// Number of attempts after which a message should be reported dead
+    pub max_attempts: i32,
+    /// How long a claimed message is leased for
+    pub hold_for: std::time::Duration,
+}
+
+impl QueueSettings {
+    pub fn backoff(&self) -> crate::backoff::ExponentialBackoff {
+        crate::backoff::ExponentialBackoff::new(self.backoff_base, self.backoff_base_delay)
+    }
+}
+
+/// A filter on the payloads of claimed messages, such as `region = 'eu'` for data-residency-aware workers.
+///
+/// A payload matches if its top level field equals any of the values, compared as text with `payload ->> field` so
+/// that an index created with [`create_payload_index`](crate::migrator::create_payload_index) on the field serves the
+/// claim. The field and values are passed as query parameters, never interpolated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClaimPredicate {
+    field: String,
+    values: Vec<String>,
+}
+
+impl ClaimPredicate {
+    /// Matches payloads whose top level `field` equals `value`. Values are compared as text, so the number `42`
+    /// matches the value `"42"`.
+    pub fn field_equals(field: impl Into<String>, value: impl Into<String>) -> Self {
+        Self {
+            field: field.into(),
+            values: vec![value.into()],
+        }
+    }
+
+    /// Also matches payloads whose field equals `value`
+    pub fn or_equals(mut self, value: impl Into<String>) -> Self {
+        self.values.push(value.into());
+        self
+    }
+
+    pub fn field(&self) -> &str {
+        &self.field
+    }
+
+    pub fn values(&self) -> &[String] {
+        &self.values
+    }
+}
+
+/// How [`report_failure`](crate::queries::report_failure) reported a failed attempt
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureOutcome {
+    /// The message is retried from `at`
+    Retrying { at: chrono::DateTime<chrono::Utc> },
+    /// The retry window passed and the message was reported dead
+    Dead,
+}
+
+/// The state of a message at some instant, see [`get_message_status`](crate::queries::get_message_status)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageStatus {
+    /// Published but not claimed
+    Pending,
+    /// Claimed with a lease that had not expired
+    InProgress,
+    /// Claimed with a lease that had expired without a report
+    Missing,
+    /// Failed and waiting for a retry
+    Failed,
+    Succeeded,
+    Dead,
+}
+
+impl MessageStatus {
+    /// Parses the label status queries evaluate to
+    pub(crate) fn from_label(label: &str) -> Self {
+        match label {
+            "succeeded" => Self::Succeeded,
+            "dead" => Self::Dead,
+            "in_progress" => Self::InProgress,
+            "missing" => Self::Missing,
+            "failed" => Self::Failed,
+            _ => Self::Pending,
+        }
+    }
+}
+
+/// The overall status of a queue, ordered from healthy to critical
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum HealthStatus {
+    Ok,
+    Degraded,
+    Critical,
+}
+
+/// The values from which a metric of [`QueueHealth`] degrades and becomes critical, inclusive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Threshold<T> {
+    pub degraded: T,
+    pub critical: T,
+}
+
+impl<T: PartialOrd> Threshold<T> {
+    pub fn new(degraded: T, critical: T) -> Self {
+        Self { degraded, critical }
+    }
+
+    pub fn status(&self, value: &T) -> HealthStatus {
+        if *value >= self.critical {
+            HealthStatus::Critical
+        } else if *value >= self.degraded {
+            HealthStatus::Degraded
+        } else {
+            HealthStatus::Ok
+        }
+    }
+}
+
+/// The thresholds a queue is judged by in [`get_queue_health`](crate::queries::get_queue_health), to be tuned to
+/// the volume and latency expectations of the queue
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HealthThresholds {
+    pub depth: Threshold<i64>,
+    pub oldest_pending_age: Threshold<std::time::Duration>,
+    pub missing: Threshold<i64>,
+    pub dead_last_hour: Threshold<i64>,
+    pub retry_backlog: Threshold<i64>,
+    pub lease_anomalies: Threshold<i64>,
+}
+
+impl Default for HealthThresholds {
+    fn default() -> Self {
+        Self {
+            depth: Threshold::new(1_000, 10_000),
+            oldest_pending_age: Threshold::new(
+                std::time::Duration::from_mins(5),
+                std::time::Duration::from_mins(30),
+            ),
+            missing: Threshold::new(1, 10),
+            dead_last_hour: Threshold::new(1, 100),
+            retry_backlog: Threshold::new(100, 1_000),
+            // Reports clear leases, so any anomaly points at a bug or a manual change
+            lease_anomalies: Threshold::new(1, 1),
+        }
+    }
+}
+
+/// A metric of [`QueueHealth`] at or beyond its threshold
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HealthBreach {
+    pub metric: &'static str,
+    pub status: HealthStatus,
+}
+
+/// A summary of the health of a queue, see [`get_queue_health`](crate::queries::get_queue_health)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueueHealth {
+    /// Published messages that were never claimed
+    pub depth: i64,
+    /// How long the oldest of those messages has waited, None if there are none
+    pub oldest_pending_age: Option<std::time::Duration>,
+    /// Claimed messages whose lease expired without a report
+    pub missing: i64,
+    /// Messages reported dead within the hour before the evaluation
+    pub dead_last_hour: i64,
+    /// Failed messages past their earliest retry time that are not leased
+    pub retry_backlog: i64,
+    /// Live leases of finished messages and messages with more than one live lease
+    pub lease_anomalies: i64,
+    /// The worst status of any metric
+    pub status: HealthStatus,
+    /// The metrics at or beyond their thresholds, in the order of the fields above
+    pub breaches: Vec<HealthBreach>,
+}
+
+impl QueueHealth {
+    /// Judges the metrics by `thresholds`, setting the status and the breaches
+    pub fn evaluate(mut self, thresholds: &HealthThresholds) -> Self {
+        let oldest_pending_age = self.oldest_pending_age.unwrap_or_default();
+        let statuses = [
+            ("depth", thresholds.depth.status(&self.depth)),
+            (
+                "oldest_pending_age",
+                thresholds.oldest_pending_age.status(&oldest_pending_age),
+            ),
+            ("missing", thresholds.missing.status(&self.missing)),
+            (
+                "dead_last_hour",
+                thresholds.dead_last_hour.status(&self.dead_last_hour),
+            ),
+            (
+                "retry_backlog",
+                thresholds.retry_backlog.status(&self.retry_backlog),
+            ),
+            (
+                "lease_anomalies",
+                thresholds.lease_anomalies.status(&self.lease_anomalies),
+            ),
+        ];
+
+        self.breaches = statuses
+            .into_iter()
+            .filter(|(_, status)| *status != HealthStatus::Ok)
+            .map(|(metric, status)| HealthBreach { metric, status })
+            .collect();
+        self.status = self
+            .breaches
+            .iter()
+            .map(|breach| breach.status)
+            .max()
+            .unwrap_or(HealthStatus::Ok);
+        self
+    }
+}
+
+/// A message listed by [`list_messages`](crate::queries::list_messages)
+#[derive(Debug, Clone, PartialEq)]
+pub struct MessageSummary {
+    pub id: uuid::Uuid,
+    /// Event type name
+    pub name: String,
+    pub published_at: chrono::DateTime<chrono::Utc>,
+    /// Sequence number assigned at publish, see [`RawMessage::seq`]
+    pub seq: i64,
+    /// The status of the message when it was listed
+    pub status: MessageStatus,
+}
+
+/// Something that happened to a message, see [`get_message_timeline`](crate::queries::get_message_timeline)
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimelineEvent {
+    /// The time the event happened
+    pub at: chrono::DateTime<chrono::Utc>,
+    pub kind: TimelineEventKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TimelineEventKind {
+    Published,
+    /// A lease that is still held, or expired without a report, was acquired by `host_id`
+    Claimed {
+        host_id: uuid::Uuid,
+    },
+    /// The expired lease of `from` was taken over by `by`
+    TakenOver {
+        from: uuid::Uuid,
+        by: uuid::Uuid,
+    },
+    /// An attempt failed, `attempted` is its index
+    Failed {
+        attempted: i32,
+    },
+    /// An error was reported
+    Errored {
+        error: String,
+    },
+    Succeeded,
+    Dead,
+}
+
+/// A command operators issue to running workers, see [`OperatorControl`](crate::listener::OperatorControl)
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum OperatorCommand {
+    /// Stop claiming messages, while handling those already claimed
+    Pause,
+    /// Claim messages again after a pause or drain
+    Resume,
+    /// Claim the backlog without waiting for notifications or polling intervals
+    Drain,
+    /// Handle messages with the given number of concurrent workers
+    SetConcurrency { workers: u32 },
+    /// Reload the stored queue settings
+    ReloadSettings,
+}
+
+/// An [`OperatorCommand`] as issued with [`issue_command`](crate::queries::issue_command)
+#[derive(Debug, Clone, PartialEq)]
+pub struct IssuedCommand {
+    /// Increasing id, the position of the command among all issued commands
+    pub id: i64,
+    pub command: OperatorCommand,
+    /// The host the command is for, None for every host
+    pub target_host: Option<uuid::Uuid>,
+    pub issued_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// What [`publish_message_on_conflict`](crate::queries::publish_message_on_conflict) does when a message with the
+/// same id was already published, e.g. by an earlier attempt of a retried producer transaction
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PublishConflict {
+    /// Fail with [`PublishError::DuplicateId`](crate::queries::PublishError::DuplicateId)
+    #[default]
+    Error,
+    /// Keep the published message
+    Ignore,
+    /// Replace the payload of the published message if it is still pending, otherwise keep it
+    UpdatePayload,
+}
+
+/// The action [`publish_message_on_conflict`](crate::queries::publish_message_on_conflict) took
+#[derive(Debug, Clone)]
+pub enum PublishOutcome {
+    /// The message was published
+    Inserted(RawMessage),
+    /// The payload of the pending message with the same id was replaced
+    Updated(RawMessage),
+    /// A message with the same id was already published and kept as is
+    Ignored,
+}
+
+/// The order in which retryable messages are claimed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RetryOrder {
+    /// The message that failed the longest ago first
+    #[default]
+    FailedAt,
+    /// The message that has been retryable the longest first, which claims the most overdue retries first
+    /// during retry backlogs
+    RetryEarliestAt,
+}
+
+/// How an unattempted message is picked among the claimable ones
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ClaimStrategy {
+    /// The oldest message first
+    #[default]
+    Head,
+    /// A random message among the given number of oldest messages, spreading concurrent claims over several rows
+    /// to reduce lock contention at the head of the queue. Order is approximately FIFO within the window.
+    RandomWindow(u16),
+}
+
+/// The composition of a batch claimed with [`claim_batch`](crate::queries::claim_batch)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ClaimBatchSpec {
+    /// The maximum number of unattempted messages to claim
+    pub unattempted: i64,
+    /// The maximum number of retryable messages to claim
+    pub retryable: i64,
+}
+
+/// Which succeeded messages are appended to the event log, and the aggregate each belongs to, see
+/// [`Queries::with_event_log`](crate::queries::Queries::with_event_log)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EventLogSpec {
+    aggregate_path: Vec<String>,
+    names: Vec<String>,
+}
+
+impl EventLogSpec {
+    /// Appends messages to the aggregate identified by the top level `field` of their payload
+    pub fn aggregate_field(field: &str) -> Self {
+        Self::aggregate_path([field])
+    }
+
+    /// Appends messages to the aggregate identified by the value at `path` in their payload, e.g. `["order", "id"]`.
+    /// Messages without a value at `path` are not appended.
+    pub fn aggregate_path<I: IntoIterator<Item = impl Into<String>>>(path: I) -> Self {
+        Self {
+            aggregate_path: path.into_iter().map(Into::into).collect(),
+            names: Vec::new(),
+        }
+    }
+
+    /// Only appends messages named `name`, or any of the names added. Messages of all names are appended by default.
+    pub fn with_name(mut self, name: &str) -> Self {
+        self.names.push(name.to_string());
+        self
+    }
+
+    pub fn path(&self) -> &[String] {
+        &self.aggregate_path
+    }
+
+    /// Returns true if messages named `name` are appended
+    pub fn includes(&self, name: &str) -> bool {
+        self.names.is_empty() || self.names.iter().any(|n| n == name)
+    }
+}
+
+/// A succeeded message appended to the event log, see [`append_event`](crate::queries::append_event)
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoggedEvent {
+    pub aggregate_id: String,
+    /// The position of the event within its aggregate, gapless from 1
+    pub sequence: i64,
+    pub message_id: Uuid,
+    /// Event type name
+    pub name: String,
+    /// The payload, with an offloaded payload resolved
+    pub payload: serde_json::Value,
+    pub published_at: chrono::DateTime<chrono::Utc>,
+    pub appended_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// What is known of the attempts of a message, to forecast its retries with [`RetryState::forecast`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryState {
+    pub message_id: Uuid,
+    /// Event type name
+    pub name: String,
+    pub published_at: chrono::DateTime<chrono::Utc>,
+    /// The number of failed attempts
+    pub attempted: i32,
+    /// When the last failed attempt may be retried, None if no attempt failed
+    pub retry_earliest_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// Whether an attempt holds an active lease
+    pub in_progress: bool,
+    /// Whether the message succeeded or is dead
+    pub finished: bool,
+    /// The stored settings of the name, None if it has none
+    pub settings: Option<QueueSettings>,
+}
+
+impl RetryState {
+    /// Forecasts the attempts left of the message should every attempt fail, retrying as scheduled by `backoff`
+    /// until attempted `max_attempts` times
+    pub fn forecast<B: crate::backoff::Backoff + ?Sized>(
+        &self,
+        backoff: &B,
+        max_attempts: i32,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> RetryForecast {
+        let next = self.attempted + 1;
+        let schedule = if self.finished || next > max_attempts {
+            Vec::new()
+        } else if self.in_progress {
+            crate::backoff::retries_after(backoff, next, now, self.published_at, max_attempts)
+        } else {
+            let next_at = self.retry_earliest_at.map_or(now, |at| at.max(now));
+            let mut schedule = vec![next_at];
+            schedule.extend(crate::backoff::retries_after(
+                backoff,
+                next,
+                next_at,
+                self.published_at,
+                max_attempts,
+            ));
+            schedule
+        };
+
+        RetryForecast {
+            message_id: self.message_id,
+            attempted: self.attempted,
+            max_attempts,
+            in_progress: self.in_progress,
+            schedule,
+        }
+    }
+}
+
+/// The attempts a message has left should every attempt fail, see [`RetryState::forecast`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryForecast {
+    pub message_id: Uuid,
+    /// The number of failed attempts
+    pub attempted: i32,
+    pub max_attempts: i32,
+    /// Whether an attempt is in progress, which the schedule doesn't include
+    pub in_progress: bool,
+    /// The earliest time of each attempt left, a pending message may be attempted as soon as it is claimed
+    pub schedule: Vec<chrono::DateTime<chrono::Utc>>,
+}
+
+impl RetryForecast {
+    /// The earliest time of the next attempt, None if the message has no attempts left
+    pub fn next_attempt_at(&self) -> Option<chrono::DateTime<chrono::Utc>> {
+        self.schedule.first().copied()
+    }
+
+    /// The number of attempts left
+    pub fn remaining(&self) -> usize {
+        self.schedule.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn it_derives_the_same_id_from_the_same_payload() {
+        let namespace = Uuid::now_v7();
+        let message = RawMessage::with_derived_id(
+            "EmailRequested",
+            json!({ "to": "a@example.com", "template": { "id": 1, "locale": "en" } }),
+            namespace,
+        );
+        let retried = RawMessage::with_derived_id(
+            "EmailRequested",
+            json!({ "template": { "locale": "en", "id": 1 }, "to": "a@example.com" }),
+            namespace,
+        );
+
+        assert_eq!(message.id, retried.id);
+        assert_eq!(message.id.get_version_num(), 5);
+        assert_eq!(message.hash, fnv1a_hash_str_32("EmailRequested") as i32);
+
+        let derived = |name: &str, payload, namespace| {
+            RawMessage::with_derived_id(name, payload, namespace).id
+        };
+        let payload = json!({ "to": "a@example.com" });
+        assert_ne!(
+            derived(
+                "EmailRequested",
+                json!({ "to": "b@example.com" }),
+                namespace
+            ),
+            message.id
+        );
+        assert_ne!(
+            derived("EmailSent", payload.clone(), namespace),
+            derived("EmailRequested", payload.clone(), namespace)
+        );
+        assert_ne!(
+            derived("EmailRequested", payload.clone(), Uuid::now_v7()),
+            derived("EmailRequested", payload, namespace)
+        );
+    }
 }
@@ -0,0 +1,183 @@
+//! Paced purging of finished messages, deleting millions of messages without holding locks for long or starving
+//! the queue of I/O
+
+use crate::queries::Queries;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+/// Batching and pacing of a [`Purger`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PurgerConfig {
+    /// The maximum number of messages deleted in one transaction
+    pub batch_size: i64,
+    /// How long the purger sleeps between batches, leaving room for the queue and for replicas to catch up
+    pub pause: Duration,
+    /// Whether to `ANALYZE` the purged tables once done, so that the planner doesn't keep planning for the rows
+    /// deleted until autovacuum gets to them
+    pub analyze: bool,
+}
+
+impl Default for PurgerConfig {
+    fn default() -> Self {
+        Self {
+            batch_size: 1_000,
+            pause: Duration::from_millis(100),
+            analyze: false,
+        }
+    }
+}
+
+/// The progress of a [`Purger`], reported after every batch
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PurgeProgress {
+    /// The number of messages deleted so far
+    pub deleted: u64,
+    /// The number of batches committed so far
+    pub batches: u64,
+    /// The `seq` of the last message deleted, from which a purge may be resumed
+    pub last_seq: Option<i64>,
+}
+
+/// Deletes the messages that finished before a cutoff in batches, see
+/// [`purge_finished`](crate::queries::purge_finished).
+///
+/// Every batch is a transaction of its own, so a purge holds its locks for a single batch and may be cancelled at
+/// any time without losing the progress made. Batches walk the messages in publish order, resuming after the last
+/// message deleted rather than rescanning the messages kept. Deleted rows take up space until vacuumed, autovacuum
+/// reclaims it eventually, while running `VACUUM` on the purged tables afterwards reclaims it right away.
+#[derive(Debug, Clone, Default)]
+pub struct Purger {
+    config: PurgerConfig,
+}
+
+impl Purger {
+    pub fn new(config: PurgerConfig) -> Self {
+        Self { config }
+    }
+
+    /// Purges the messages of the schema of `queries` that finished before `finished_before`, calling `on_progress`
+    /// after every batch. Returns the progress made once nothing is left to purge or once cancelled, in which case
+    /// the purged tables are not analyzed.
+    pub async fn run<S>(
+        &self,
+        pool: &PgPool,
+        queries: &Queries<S>,
+        finished_before: DateTime<Utc>,
+        cancellation: CancellationToken,
+        mut on_progress: impl FnMut(&PurgeProgress),
+    ) -> Result<PurgeProgress, sqlx::Error> {
+        let batch_size = self.config.batch_size.max(1);
+        let mut progress = PurgeProgress::default();
+
+        loop {
+            let mut tx = pool.begin().await?;
+            let batch = queries
+                .purge_finished(&mut tx, finished_before, progress.last_seq, batch_size)
+                .await?;
+            tx.commit().await?;
+
+            if batch.deleted == 0 {
+                break;
+            }
+            progress.deleted += batch.deleted;
+            progress.batches += 1;
+            progress.last_seq = batch.last_seq;
+            on_progress(&progress);
+
+            if (batch.deleted as i64) < batch_size {
+                break;
+            }
+            tokio::select! {
+                _ = cancellation.cancelled() => return Ok(progress),
+                _ = tokio::time::sleep(self.config.pause) => {}
+            }
+        }
+
+        if self.config.analyze && progress.deleted > 0 {
+            let mut tx = pool.begin().await?;
+            queries.analyze_purged(&mut tx).await?;
+            tx.commit().await?;
+        }
+
+        Ok(progress)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queries::{get_next_unattempted, publish_message, report_success};
+    use crate::testing_tools::TestMessage;
+    use uuid::Uuid;
+
+    async fn publish_succeeded(pool: &PgPool, count: usize) -> anyhow::Result<()> {
+        let now = Utc::now();
+        for _ in 0..count {
+            publish_message(pool, &TestMessage::default().to_raw()?).await?;
+            let message = get_next_unattempted(pool, now, Uuid::now_v7(), Duration::from_mins(1))
+                .await?
+                .expect("Expected a message");
+            report_success(pool, message.id, message.fencing_token, now).await?;
+        }
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_purges_in_paced_batches(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        publish_succeeded(&pool, 5).await?;
+        let purger = Purger::new(PurgerConfig {
+            batch_size: 2,
+            pause: Duration::ZERO,
+            analyze: true,
+        });
+
+        let mut reported = Vec::new();
+        let progress = purger
+            .run(
+                &pool,
+                &Queries::new("public")?,
+                Utc::now() + Duration::from_secs(1),
+                CancellationToken::new(),
+                |progress| reported.push(progress.deleted),
+            )
+            .await?;
+
+        assert_eq!(progress.deleted, 5);
+        assert_eq!(progress.batches, 3);
+        assert_eq!(reported, [2, 4, 5]);
+        let remaining: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM messages_attempted")
+            .fetch_one(&pool)
+            .await?;
+        assert_eq!(remaining, 0);
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_stops_between_batches_once_cancelled(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        publish_succeeded(&pool, 5).await?;
+        let purger = Purger::new(PurgerConfig {
+            batch_size: 2,
+            ..PurgerConfig::default()
+        });
+        let cancellation = CancellationToken::new();
+        cancellation.cancel();
+
+        let progress = purger
+            .run(
+                &pool,
+                &Queries::new("public")?,
+                Utc::now() + Duration::from_secs(1),
+                cancellation,
+                |_| {},
+            )
+            .await?;
+
+        assert_eq!(progress.deleted, 2);
+        assert_eq!(progress.batches, 1);
+
+        Ok(())
+    }
+}
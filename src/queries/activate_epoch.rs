@@ -0,0 +1,140 @@
+use chrono::{DateTime, Utc};
+use sqlx::PgExecutor;
+
+/// Activates the deployment `epoch`, returning the epoch that was active before it if any.
+///
+/// Once activated, hosts restricted to another epoch, see
+/// [`Queries::with_deployment_epoch`](super::Queries::with_deployment_epoch), no longer claim. Claims of a
+/// restricted host lock the active epoch until they commit, so activation waits for claims in flight: once it
+/// returns no host of the previous epoch takes another message, and the previous epoch drains by finishing the
+/// messages it holds.
+pub async fn activate_epoch<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    epoch: i64,
+    now: DateTime<Utc>,
+) -> Result<Option<i64>, sqlx::Error> {
+    let previous = sqlx::query_scalar!(
+        r#"
+        WITH previous AS (
+            SELECT epoch FROM active_epoch FOR UPDATE
+        )
+        INSERT INTO active_epoch (epoch, activated_at)
+        -- Joins the previous epoch so it is locked and read before the upsert replaces it
+        SELECT $1, $2 FROM (VALUES (1)) v LEFT JOIN previous ON TRUE
+        ON CONFLICT (singleton) DO UPDATE
+        SET epoch = EXCLUDED.epoch,
+            activated_at = EXCLUDED.activated_at
+        RETURNING (SELECT epoch FROM previous)
+        "#,
+        epoch,
+        now
+    )
+    .fetch_one(tx)
+    .await?;
+
+    Ok(previous)
+}
+
+/// Returns the active deployment epoch, None if no epoch was activated
+pub async fn get_active_epoch<'tx, E: PgExecutor<'tx>>(tx: E) -> Result<Option<i64>, sqlx::Error> {
+    sqlx::query_scalar!("SELECT epoch FROM active_epoch")
+        .fetch_optional(tx)
+        .await
+}
+
+/// Returns whether hosts of `epoch` may claim, which they may while it is active or before any epoch was activated.
+///
+/// Locks the active epoch for the rest of the transaction, so that [`activate_epoch`] waits for the claim.
+pub async fn is_epoch_active<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    epoch: i64,
+) -> Result<bool, sqlx::Error> {
+    let active = sqlx::query_scalar!(
+        r#"
+        SELECT COALESCE(
+            (SELECT a.epoch = $1 FROM active_epoch a FOR SHARE),
+            TRUE
+        ) AS "active!"
+        "#,
+        epoch
+    )
+    .fetch_one(tx)
+    .await?;
+
+    Ok(active)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queries::{Queries, publish_message};
+    use crate::testing_tools::TestMessage;
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_activates_epochs(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        assert_eq!(get_active_epoch(&pool).await?, None);
+        assert!(is_epoch_active(&pool, 1).await?);
+
+        assert_eq!(activate_epoch(&pool, 1, Utc::now()).await?, None);
+        assert_eq!(activate_epoch(&pool, 2, Utc::now()).await?, Some(1));
+        assert_eq!(get_active_epoch(&pool).await?, Some(2));
+        assert!(!is_epoch_active(&pool, 1).await?);
+        assert!(is_epoch_active(&pool, 2).await?);
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_fences_claims_of_inactive_epochs(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let blue = Queries::new("public")?.with_deployment_epoch(1);
+        let green = Queries::new("public")?.with_deployment_epoch(2);
+        let hold_for = Duration::from_mins(1);
+        publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+        publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+        activate_epoch(&pool, 1, Utc::now()).await?;
+
+        // Green is on standby while blue is active
+        let mut tx = pool.begin().await?;
+        let claimed = green
+            .get_next_unattempted(&mut tx, Utc::now(), Uuid::now_v7(), hold_for)
+            .await?;
+        assert!(claimed.is_none());
+        let claimed = blue
+            .get_next_unattempted(&mut tx, Utc::now(), Uuid::now_v7(), hold_for)
+            .await?;
+        assert!(claimed.is_some());
+        tx.commit().await?;
+
+        // Activation waits for the claims of blue in flight
+        let mut claiming = pool.begin().await?;
+        assert!(
+            blue.get_next_unattempted(&mut claiming, Utc::now(), Uuid::now_v7(), hold_for)
+                .await?
+                .is_some()
+        );
+        let activation = tokio::spawn({
+            let pool = pool.clone();
+            async move { activate_epoch(&pool, 2, Utc::now()).await }
+        });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(!activation.is_finished());
+        claiming.rollback().await?;
+        assert_eq!(activation.await??, Some(1));
+
+        // Once green is active, blue no longer claims
+        let mut tx = pool.begin().await?;
+        let claimed = blue
+            .get_next_unattempted(&mut tx, Utc::now(), Uuid::now_v7(), hold_for)
+            .await?;
+        assert!(claimed.is_none());
+        let claimed = green
+            .get_next_unattempted(&mut tx, Utc::now(), Uuid::now_v7(), hold_for)
+            .await?;
+        assert!(claimed.is_some());
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
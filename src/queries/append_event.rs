@@ -0,0 +1,184 @@
+use crate::models::LoggedEvent;
+use chrono::{DateTime, Utc};
+use sqlx::{PgExecutor, PgTransaction};
+use uuid::Uuid;
+
+/// Appends the message `message_id` to the event log of the aggregate identified by the value at `aggregate_path`
+/// in its payload, returning the sequence number it was appended with. Returns None if the payload has no value at
+/// the path, or if the message was already appended.
+///
+/// Offloaded payloads are resolved, see [`attachments`](crate::attachments). Appends to the same aggregate are
+/// serialized by a transaction scoped advisory lock, so sequence numbers are gapless and follow the order in which
+/// the appending transactions took the lock.
+pub async fn append_event(
+    tx: &mut PgTransaction<'_>,
+    message_id: Uuid,
+    aggregate_path: &[String],
+    now: DateTime<Utc>,
+) -> Result<Option<i64>, sqlx::Error> {
+    let aggregate_id = sqlx::query_scalar!(
+        r#"
+        SELECT COALESCE(
+            (SELECT b.data FROM message_blobs b WHERE b.id = (ma.payload->>'$fx_mq_blob')::UUID),
+            ma.payload
+        ) #>> $2::TEXT[]
+        FROM messages_attempted ma
+        WHERE ma.id = $1
+        "#,
+        message_id,
+        aggregate_path
+    )
+    .fetch_optional(&mut **tx)
+    .await?
+    .flatten();
+
+    let Some(aggregate_id) = aggregate_id else {
+        return Ok(None);
+    };
+
+    // Locked in a statement of its own, so that the insert reads the sequence after the appends it waited for
+    sqlx::query("SELECT pg_advisory_xact_lock(hashtext('fx_mq_event_log'), hashtext(current_schema() || '.' || $1))")
+        .bind(&aggregate_id)
+        .execute(&mut **tx)
+        .await?;
+
+    let sequence = sqlx::query_scalar!(
+        r#"
+        INSERT INTO event_log (aggregate_id, sequence, message_id, name, payload, published_at, appended_at)
+        SELECT
+            $1,
+            COALESCE((SELECT MAX(e.sequence) FROM event_log e WHERE e.aggregate_id = $1), 0) + 1,
+            ma.id,
+            ma.name,
+            COALESCE(
+                (SELECT b.data FROM message_blobs b WHERE b.id = (ma.payload->>'$fx_mq_blob')::UUID),
+                ma.payload
+            ),
+            ma.published_at,
+            $3
+        FROM messages_attempted ma
+        WHERE ma.id = $2
+        ON CONFLICT (message_id) DO NOTHING
+        RETURNING sequence
+        "#,
+        aggregate_id,
+        message_id,
+        now
+    )
+    .fetch_optional(&mut **tx)
+    .await?;
+
+    Ok(sequence)
+}
+
+/// Returns up to `limit` events of the aggregate `aggregate_id` in sequence order, starting after the `sequence`
+/// passed as cursor
+pub async fn get_events<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    aggregate_id: &str,
+    after: Option<i64>,
+    limit: i64,
+) -> Result<Vec<LoggedEvent>, sqlx::Error> {
+    sqlx::query_as!(
+        LoggedEvent,
+        r#"
+        SELECT aggregate_id, sequence, message_id, name, payload, published_at, appended_at
+        FROM event_log
+        WHERE aggregate_id = $1
+          AND ($2::BIGINT IS NULL OR sequence > $2)
+        ORDER BY sequence ASC
+        LIMIT $3
+        "#,
+        aggregate_id,
+        after,
+        limit
+    )
+    .fetch_all(tx)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::EventLogSpec;
+    use crate::queries::{Queries, get_next_unattempted, publish_message};
+    use crate::testing_tools::TestMessage;
+    use serde_json::json;
+    use std::time::Duration;
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_appends_succeeded_messages_per_aggregate(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let queries =
+            Queries::new("public")?.with_event_log(EventLogSpec::aggregate_field("value"));
+        let hold_for = Duration::from_mins(1);
+
+        let mut published = Vec::new();
+        for value in [1, 2, 1] {
+            let message =
+                publish_message(&pool, &TestMessage::new("a".to_string(), value).to_raw()?).await?;
+            published.push(message.id);
+        }
+        for _ in 0..3 {
+            let mut tx = pool.begin().await?;
+            let message = queries
+                .get_next_unattempted(&mut tx, Utc::now(), Uuid::now_v7(), hold_for)
+                .await?
+                .expect("Expected a message");
+            queries
+                .report_success(&mut tx, &message, Utc::now())
+                .await?;
+            tx.commit().await?;
+        }
+
+        let events = get_events(&pool, "1", None, 10).await?;
+        assert_eq!(
+            events
+                .iter()
+                .map(|e| (e.sequence, e.message_id))
+                .collect::<Vec<_>>(),
+            [(1, published[0]), (2, published[2])]
+        );
+        assert_eq!(events[0].payload, json!({ "message": "a", "value": 1 }));
+        assert_eq!(get_events(&pool, "1", Some(1), 10).await?.len(), 1);
+        assert_eq!(get_events(&pool, "2", None, 10).await?.len(), 1);
+
+        // Appending again, or a message without an aggregate id, appends nothing
+        let mut tx = pool.begin().await?;
+        let path = |field: &str| [field.to_string()];
+        assert_eq!(
+            append_event(&mut tx, published[0], &path("value"), Utc::now()).await?,
+            None
+        );
+        assert_eq!(
+            append_event(&mut tx, published[1], &path("missing"), Utc::now()).await?,
+            None
+        );
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_skips_messages_of_other_names(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let queries = Queries::new("public")?
+            .with_event_log(EventLogSpec::aggregate_field("value").with_name("OrderPlaced"));
+        publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+
+        let mut tx = pool.begin().await?;
+        let message =
+            get_next_unattempted(&mut *tx, Utc::now(), Uuid::now_v7(), Duration::from_mins(1))
+                .await?
+                .expect("Expected a message");
+        queries
+            .report_success(&mut tx, &message, Utc::now())
+            .await?;
+        tx.commit().await?;
+
+        let logged: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM event_log")
+            .fetch_one(&pool)
+            .await?;
+        assert_eq!(logged, 0);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,184 @@
+use crate::models::{ClaimBatchSpec, ClaimPredicate, RawMessage};
+use chrono::{DateTime, Utc};
+use sqlx::PgExecutor;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Claims a batch of up to `spec.unattempted` unattempted and `spec.retryable` retryable messages in a single
+/// statement, so that a batch worker fills its batch in one round trip with a predictable composition.
+///
+/// Unattempted messages are returned first, in publish order, followed by retryable messages in the order they
+/// failed. Retry concurrency limits are checked against the retries in progress before the batch, so a batch may
+/// exceed a limit by its own retries, see [`set_retry_concurrency_limit`](super::set_retry_concurrency_limit).
+pub async fn claim_batch<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    now: DateTime<Utc>,
+    host_id: Uuid,
+    hold_for: Duration,
+    spec: ClaimBatchSpec,
+) -> Result<Vec<RawMessage>, sqlx::Error> {
+    claim_batch_inner(tx, now, host_id, hold_for, spec, None).await
+}
+
+/// Claims like [`claim_batch`].
+///
+/// With a `predicate`, only messages whose payload matches it are claimed.
+pub(super) async fn claim_batch_inner<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    now: DateTime<Utc>,
+    host_id: Uuid,
+    hold_for: Duration,
+    spec: ClaimBatchSpec,
+    predicate: Option<&ClaimPredicate>,
+) -> Result<Vec<RawMessage>, sqlx::Error> {
+    let expires_at = now + hold_for;
+
+    let messages = sqlx::query_as!(
+        RawMessage,
+        r#"
+        WITH next_messages AS (
+            SELECT id
+            FROM messages_unattempted
+            WHERE ($6::TEXT IS NULL OR payload ->> $6 = ANY($7::TEXT[]))
+              AND NOT EXISTS (
+                  SELECT 1 FROM leases l
+                  WHERE l.message_id = messages_unattempted.id AND l.expires_at > $1
+              )
+            ORDER BY seq ASC
+            FOR UPDATE SKIP LOCKED
+            LIMIT $4
+        ),
+        next_retryable AS (
+            SELECT
+                fa.message_id,
+                fa.attempted,
+                fa.failed_at
+            FROM attempts_failed fa
+            WHERE fa.retry_earliest_at <= $1
+              AND NOT EXISTS (
+                  SELECT 1 FROM leases l
+                  WHERE l.message_id = fa.message_id AND l.expires_at > $1
+              )
+              -- Finished messages are never claimed again, e.g. when a failure raced their success
+              AND NOT EXISTS (
+                  SELECT 1 FROM attempts_succeeded s
+                  WHERE s.message_id = fa.message_id
+              )
+              AND NOT EXISTS (
+                  SELECT 1 FROM attempts_dead d
+                  WHERE d.message_id = fa.message_id
+              )
+              AND fa.failed_at = (
+                  SELECT MAX(fa2.failed_at)
+                  FROM attempts_failed fa2
+                  WHERE fa2.message_id = fa.message_id
+              )
+              AND ($6::TEXT IS NULL OR EXISTS (
+                  SELECT 1 FROM messages_attempted ma
+                  WHERE ma.id = fa.message_id AND ma.payload ->> $6 = ANY($7::TEXT[])
+              ))
+              AND retry_slot_available(
+                  (SELECT ma.name FROM messages_attempted ma WHERE ma.id = fa.message_id),
+                  $1
+              )
+            ORDER BY fa.failed_at ASC, fa.message_id ASC
+            LIMIT $5
+            FOR UPDATE SKIP LOCKED
+        )
+        SELECT
+            id "id!",
+            name "name!",
+            hash "hash!",
+            payload "payload!",
+            attempted "attempted!",
+            fencing_token "fencing_token?",
+            seq "seq?",
+            max_attempts "max_attempts?"
+        FROM (
+            SELECT c.*, 0 AS source, NULL::TIMESTAMPTZ AS sort_key
+            FROM next_messages n, LATERAL claim_unattempted(n.id, $1, $2, $3) c
+
+            UNION ALL
+
+            SELECT c.*, 1 AS source, nr.failed_at AS sort_key
+            FROM next_retryable nr, LATERAL claim_attempted(nr.message_id, nr.attempted, $1, $2, $3) c
+        ) claimed
+        ORDER BY source ASC, sort_key ASC, seq ASC;
+        "#,
+        now,
+        host_id,
+        expires_at,
+        spec.unattempted,
+        spec.retryable,
+        predicate.map(ClaimPredicate::field),
+        predicate.map(ClaimPredicate::values) as Option<&[String]>
+    )
+    .fetch_all(tx)
+    .await?;
+
+    Ok(messages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queries::{get_next_unattempted, publish_message, report_retryable};
+    use crate::testing_tools::{TestMessage, is_in_progress, is_pending};
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_claims_the_requested_composition(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let now = Utc::now();
+        let host_id = Uuid::now_v7();
+        let hold_for = Duration::from_mins(1);
+
+        let mut failed = Vec::new();
+        for _ in 0..2 {
+            publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+            let message = get_next_unattempted(&pool, now, host_id, hold_for)
+                .await?
+                .expect("Expected a message");
+            report_retryable(&pool, message.id, None, now, 1, now, "err").await?;
+            failed.push(message.id);
+        }
+
+        let mut published = Vec::new();
+        for _ in 0..3 {
+            published.push(publish_message(&pool, &TestMessage::default().to_raw()?).await?);
+        }
+
+        let spec = ClaimBatchSpec {
+            unattempted: 2,
+            retryable: 1,
+        };
+        let batch = claim_batch(&pool, now, host_id, hold_for, spec).await?;
+
+        let ids: Vec<Uuid> = batch.iter().map(|m| m.id).collect();
+        assert_eq!(ids, vec![published[0].id, published[1].id, failed[0]]);
+        assert_eq!(batch[2].attempted, 1);
+        assert!(batch.iter().all(|m| m.fencing_token.is_some()));
+
+        for message in &batch {
+            assert!(is_in_progress(&pool, message.id, now).await?);
+        }
+        assert!(is_pending(&pool, published[2].id, now).await?);
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_returns_empty_batches(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+
+        let batch = claim_batch(
+            &pool,
+            Utc::now(),
+            Uuid::now_v7(),
+            Duration::from_mins(1),
+            ClaimBatchSpec::default(),
+        )
+        .await?;
+        assert!(batch.is_empty());
+
+        Ok(())
+    }
+}
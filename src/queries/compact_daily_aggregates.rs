@@ -0,0 +1,246 @@
+use chrono::{DateTime, Days, NaiveDate, Utc};
+use sqlx::PgExecutor;
+
+/// Recomputes the aggregates of a single UTC day and upserts them into `daily_aggregates`.
+///
+/// Intended to be called periodically by a compaction worker, typically for the current and the previous day, see
+/// [`Compactor`](crate::compactor::Compactor).
+/// Compaction is idempotent, running it repeatedly for the same day overwrites the previous result.
+///
+/// Attempts are the attempts recorded when each message succeeded or was reported dead, which keep counting when
+/// errors are capped.
+///
+/// Returns the number of (day, name) rows written.
+pub async fn compact_daily_aggregates<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    day: NaiveDate,
+    now: DateTime<Utc>,
+) -> Result<u64, sqlx::Error> {
+    let from = day.and_time(chrono::NaiveTime::MIN).and_utc();
+    let to = from + Days::new(1);
+
+    let result = sqlx::query!(
+        r#"
+        WITH published AS (
+            SELECT name, COUNT(*) AS n
+            FROM (
+                SELECT name FROM messages_unattempted
+                WHERE published_at >= $2 AND published_at < $3
+
+                UNION ALL
+
+                SELECT name FROM messages_attempted
+                WHERE published_at >= $2 AND published_at < $3
+            ) p
+            GROUP BY name
+        ),
+        succeeded AS (
+            SELECT
+                ma.name,
+                COUNT(*) AS n,
+                SUM(s.attempted) AS attempts
+            FROM attempts_succeeded s
+            JOIN messages_attempted ma ON ma.id = s.message_id
+            WHERE s.succeeded_at >= $2 AND s.succeeded_at < $3
+            GROUP BY ma.name
+        ),
+        dead AS (
+            SELECT
+                ma.name,
+                COUNT(*) AS n,
+                SUM(d.attempted) AS attempts
+            FROM attempts_dead d
+            JOIN messages_attempted ma ON ma.id = d.message_id
+            WHERE d.dead_at >= $2 AND d.dead_at < $3
+            GROUP BY ma.name
+        ),
+        names AS (
+            SELECT name FROM published
+            UNION
+            SELECT name FROM succeeded
+            UNION
+            SELECT name FROM dead
+        )
+        INSERT INTO daily_aggregates (
+            day,
+            name,
+            published,
+            succeeded,
+            dead,
+            attempts,
+            compacted_at
+        )
+        SELECT
+            $1,
+            n.name,
+            COALESCE(p.n, 0),
+            COALESCE(s.n, 0),
+            COALESCE(d.n, 0),
+            (COALESCE(s.attempts, 0) + COALESCE(d.attempts, 0))::BIGINT,
+            $4
+        FROM names n
+        LEFT JOIN published p ON p.name = n.name
+        LEFT JOIN succeeded s ON s.name = n.name
+        LEFT JOIN dead d ON d.name = n.name
+        ON CONFLICT (day, name) DO UPDATE
+        SET published = EXCLUDED.published,
+            succeeded = EXCLUDED.succeeded,
+            dead = EXCLUDED.dead,
+            attempts = EXCLUDED.attempts,
+            compacted_at = EXCLUDED.compacted_at
+        "#,
+        day,
+        from,
+        to,
+        now
+    )
+    .execute(tx)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        models::Message,
+        queries::{
+            get_daily_aggregates, get_next_unattempted, publish_message, report_dead,
+            report_retryable, report_retryable_capped, report_success,
+        },
+        testing_tools::TestMessage,
+    };
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_aggregates_published_succeeded_and_dead(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let now = Utc::now();
+        let today = now.date_naive();
+        let host_id = Uuid::now_v7();
+        let hold_for = Duration::from_mins(1);
+
+        // Pending
+        publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+
+        // Succeeded on the first attempt
+        publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+        let succeeded = get_next_unattempted(&pool, now, host_id, hold_for)
+            .await?
+            .unwrap();
+        report_success(&pool, succeeded.id, None, now).await?;
+
+        // Failed once, then dead on the second attempt
+        publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+        let dead = get_next_unattempted(&pool, now, host_id, hold_for)
+            .await?
+            .unwrap();
+        report_retryable(&pool, dead.id, None, now, 1, now, "err").await?;
+        report_dead(&pool, dead.id, None, now, "err").await?;
+
+        let written = compact_daily_aggregates(&pool, today, now).await?;
+        assert_eq!(written, 1);
+
+        let aggregates = get_daily_aggregates(&pool, today, today).await?;
+        assert_eq!(aggregates.len(), 1);
+
+        let aggregate = &aggregates[0];
+        assert_eq!(aggregate.day, today);
+        assert_eq!(aggregate.name, TestMessage::NAME);
+        assert_eq!(aggregate.published, 3);
+        assert_eq!(aggregate.succeeded, 1);
+        assert_eq!(aggregate.dead, 1);
+        assert_eq!(aggregate.avg_attempts, Some(1.5));
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_counts_attempts_beyond_the_error_cap(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let now = Utc::now();
+        let today = now.date_naive();
+        publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+        let message = get_next_unattempted(&pool, now, Uuid::now_v7(), Duration::from_mins(1))
+            .await?
+            .unwrap();
+
+        // Three failed attempts keep a single error
+        for attempted in 1..=3 {
+            report_retryable_capped(&pool, message.id, None, now, attempted, now, "err", 1).await?;
+        }
+        report_success(&pool, message.id, None, now).await?;
+
+        compact_daily_aggregates(&pool, today, now).await?;
+
+        let aggregates = get_daily_aggregates(&pool, today, today).await?;
+        assert_eq!(aggregates[0].avg_attempts, Some(4.0));
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_is_idempotent(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let now = Utc::now();
+        let today = now.date_naive();
+
+        publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+
+        compact_daily_aggregates(&pool, today, now).await?;
+        compact_daily_aggregates(&pool, today, now).await?;
+
+        let aggregates = get_daily_aggregates(&pool, today, today).await?;
+        assert_eq!(aggregates.len(), 1);
+        assert_eq!(aggregates[0].published, 1);
+        assert_eq!(aggregates[0].avg_attempts, None);
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_ignores_other_days(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let now = Utc::now();
+        let yesterday = now.date_naive() - Days::new(1);
+
+        publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+
+        let written = compact_daily_aggregates(&pool, yesterday, now).await?;
+        assert_eq!(written, 0);
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_scans_unattempted_messages_by_published_at(
+        pool: sqlx::PgPool,
+    ) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO messages_unattempted (id, name, hash, payload, published_at)
+            SELECT gen_random_uuid(), 'Unattempted', 0, '{}', now() - make_interval(mins => i)
+            FROM generate_series(1, 20000) i
+            "#,
+        )
+        .execute(&pool)
+        .await?;
+        sqlx::query("ANALYZE messages_unattempted")
+            .execute(&pool)
+            .await?;
+
+        let plan: Vec<String> = sqlx::query_scalar(
+            r#"
+            EXPLAIN SELECT name FROM messages_unattempted
+            WHERE published_at >= now() - INTERVAL '1 day' AND published_at < now()
+            "#,
+        )
+        .fetch_all(&pool)
+        .await?;
+        assert!(
+            plan.iter()
+                .any(|line| line.contains("idx_messages_unattempted_published_at")),
+            "{plan:#?}"
+        );
+
+        Ok(())
+    }
+}
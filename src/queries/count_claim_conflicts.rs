@@ -0,0 +1,50 @@
+use chrono::{DateTime, Utc};
+use sqlx::PgExecutor;
+
+/// Counts the claim conflicts recorded from `from` (inclusive) to `to` (exclusive)
+pub async fn count_claim_conflicts<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<i64, sqlx::Error> {
+    let count = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) "count!"
+        FROM claim_conflicts
+        WHERE reported_at >= $1 AND reported_at < $2
+        "#,
+        from,
+        to
+    )
+    .fetch_one(tx)
+    .await?;
+
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queries::record_claim_conflict;
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_counts_conflicts_within_the_range(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let now = Utc::now();
+
+        record_claim_conflict(&pool, Uuid::now_v7(), 1, now - Duration::from_mins(10)).await?;
+        record_claim_conflict(&pool, Uuid::now_v7(), 1, now).await?;
+        record_claim_conflict(&pool, Uuid::now_v7(), 2, now).await?;
+
+        let count = count_claim_conflicts(
+            &pool,
+            now - Duration::from_mins(1),
+            now + Duration::from_secs(1),
+        )
+        .await?;
+        assert_eq!(count, 2);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,36 @@
+use sqlx::PgExecutor;
+use uuid::Uuid;
+
+/// Deletes an offloaded payload from `message_blobs`, returning whether it existed
+pub async fn delete_blob<'tx, E: PgExecutor<'tx>>(tx: E, id: Uuid) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM message_blobs WHERE id = $1
+        "#,
+        id
+    )
+    .execute(tx)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queries::{get_blob, put_blob};
+    use chrono::Utc;
+    use serde_json::json;
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_deletes_blobs(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let id = Uuid::now_v7();
+        put_blob(&pool, id, &json!({ "large": true }), Utc::now()).await?;
+
+        assert!(delete_blob(&pool, id).await?);
+        assert_eq!(get_blob(&pool, id).await?, None);
+        assert!(!delete_blob(&pool, id).await?);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,195 @@
+use sqlx::PgExecutor;
+
+/// Hard-deletes up to `limit` messages named `name` whose payload contains `predicate`, in the sense of the jsonb
+/// `@>` operator, e.g. `{"user_id": 42}`. Returns the number of messages deleted.
+///
+/// Messages are deleted in any state, along with their leases, attempts, errors, lease history, claim conflicts and
+/// events, to satisfy data deletion requests. Events of matching messages purged before are deleted too. Messages whose payload was offloaded are matched by the offloaded payload,
+/// which is deleted too, see [`attachments`](crate::attachments). Daily aggregates only hold counts and are kept.
+///
+/// Call repeatedly until it returns 0, which also covers messages that moved from pending to attempted while
+/// being deleted.
+pub async fn delete_messages_matching<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    name: &str,
+    predicate: &serde_json::Value,
+    limit: i64,
+) -> Result<u64, sqlx::Error> {
+    let deleted = sqlx::query_scalar!(
+        r#"
+        WITH blobs AS (
+            SELECT id FROM message_blobs WHERE data @> $2
+        ),
+        matched AS (
+            SELECT id, payload FROM (
+                SELECT id, payload FROM messages_unattempted
+                WHERE name = $1
+                  AND (payload @> $2 OR (payload->>'$fx_mq_blob')::UUID IN (SELECT id FROM blobs))
+                UNION ALL
+                SELECT id, payload FROM messages_attempted
+                WHERE name = $1
+                  AND (payload @> $2 OR (payload->>'$fx_mq_blob')::UUID IN (SELECT id FROM blobs))
+            ) candidates
+            ORDER BY id
+            LIMIT $3
+        ),
+        deleted_errors AS (
+            DELETE FROM errors WHERE message_id IN (SELECT id FROM matched)
+        ),
+        deleted_failed AS (
+            DELETE FROM attempts_failed WHERE message_id IN (SELECT id FROM matched)
+        ),
+        deleted_retained AS (
+            DELETE FROM attempts_failed_retained WHERE message_id IN (SELECT id FROM matched)
+        ),
+        deleted_succeeded AS (
+            DELETE FROM attempts_succeeded WHERE message_id IN (SELECT id FROM matched)
+        ),
+        deleted_dead AS (
+            DELETE FROM attempts_dead WHERE message_id IN (SELECT id FROM matched)
+        ),
+        deleted_leases AS (
+            DELETE FROM leases WHERE message_id IN (SELECT id FROM matched)
+        ),
+        deleted_history AS (
+            DELETE FROM lease_history WHERE message_id IN (SELECT id FROM matched)
+        ),
+        deleted_conflicts AS (
+            DELETE FROM claim_conflicts WHERE message_id IN (SELECT id FROM matched)
+        ),
+        deleted_events AS (
+            DELETE FROM event_log
+            WHERE message_id IN (SELECT id FROM matched)
+               OR (name = $1 AND payload @> $2)
+        ),
+        deleted_blobs AS (
+            DELETE FROM message_blobs
+            WHERE id IN (SELECT (payload->>'$fx_mq_blob')::UUID FROM matched)
+        ),
+        deleted_unattempted AS (
+            DELETE FROM messages_unattempted WHERE id IN (SELECT id FROM matched)
+            RETURNING id
+        ),
+        deleted_attempted AS (
+            DELETE FROM messages_attempted WHERE id IN (SELECT id FROM matched)
+            RETURNING id
+        )
+        SELECT
+            (SELECT COUNT(*) FROM deleted_unattempted)
+            + (SELECT COUNT(*) FROM deleted_attempted) "deleted!"
+        "#,
+        name,
+        predicate,
+        limit
+    )
+    .fetch_one(tx)
+    .await?;
+
+    Ok(u64::try_from(deleted).unwrap_or(0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attachments::{Attachments, PgBlobStore};
+    use crate::models::Message;
+    use crate::queries::{append_event, get_next_unattempted, publish_message, report_retryable};
+    use crate::testing_tools::{TestMessage, get_all_messages};
+    use chrono::Utc;
+    use serde_json::json;
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    async fn count_errors(pool: &sqlx::PgPool) -> anyhow::Result<i64> {
+        Ok(sqlx::query_scalar("SELECT COUNT(*) FROM errors")
+            .fetch_one(pool)
+            .await?)
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_deletes_matching_messages_in_any_state(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let now = Utc::now();
+        let user = |value| TestMessage::new("user".to_string(), value).to_raw();
+
+        let failed = publish_message(&pool, &user(1)?).await?;
+        let claimed = get_next_unattempted(&pool, now, Uuid::now_v7(), Duration::from_mins(1))
+            .await?
+            .expect("Expected a message");
+        assert_eq!(claimed.id, failed.id);
+        report_retryable(&pool, claimed.id, claimed.fencing_token, now, 1, now, "err").await?;
+        let mut tx = pool.begin().await?;
+        let appended = append_event(&mut tx, claimed.id, &["value".to_string()], now).await?;
+        assert_eq!(appended, Some(1));
+        tx.commit().await?;
+
+        publish_message(&pool, &user(1)?).await?;
+        let kept = publish_message(&pool, &user(2)?).await?;
+
+        let deleted =
+            delete_messages_matching(&pool, TestMessage::NAME, &json!({ "value": 1 }), 10).await?;
+        assert_eq!(deleted, 2);
+
+        let remaining: Vec<Uuid> = get_all_messages(&pool)
+            .await?
+            .into_iter()
+            .map(|message| message.id)
+            .collect();
+        assert_eq!(remaining, [kept.id]);
+        assert_eq!(count_errors(&pool).await?, 0);
+
+        let events: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM event_log")
+            .fetch_one(&pool)
+            .await?;
+        assert_eq!(events, 0);
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_deletes_messages_with_offloaded_payloads(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let attachments = Attachments::new(PgBlobStore::new(pool.clone(), "public")?, 0);
+        let user = |value| TestMessage::new("user".to_string(), value).to_raw();
+        publish_message(&pool, &attachments.offload(user(1)?).await?).await?;
+        let kept = publish_message(&pool, &attachments.offload(user(2)?).await?).await?;
+
+        let deleted =
+            delete_messages_matching(&pool, TestMessage::NAME, &json!({ "value": 1 }), 10).await?;
+        assert_eq!(deleted, 1);
+
+        let remaining: Vec<Uuid> = get_all_messages(&pool)
+            .await?
+            .into_iter()
+            .map(|message| message.id)
+            .collect();
+        assert_eq!(remaining, [kept.id]);
+        let blobs: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM message_blobs")
+            .fetch_one(&pool)
+            .await?;
+        assert_eq!(blobs, 1);
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_deletes_in_batches(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        for _ in 0..3 {
+            publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+        }
+        let predicate = json!({ "value": 42 });
+
+        assert_eq!(
+            delete_messages_matching(&pool, TestMessage::NAME, &predicate, 2).await?,
+            2
+        );
+        assert_eq!(
+            delete_messages_matching(&pool, TestMessage::NAME, &predicate, 2).await?,
+            1
+        );
+        assert_eq!(
+            delete_messages_matching(&pool, TestMessage::NAME, &predicate, 2).await?,
+            0
+        );
+
+        Ok(())
+    }
+}
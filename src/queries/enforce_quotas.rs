@@ -0,0 +1,198 @@
+use crate::models::{QuotaLimit, QuotaScope};
+use crate::queries::{PublishError, QuotaExceeded};
+use chrono::{DateTime, Utc};
+use sqlx::PgTransaction;
+use std::collections::BTreeMap;
+
+/// Checks that publishing messages of `names` stays within the quotas of their names and of the schema, counting
+/// the publishes towards the daily quotas if so. Call it before inserting the messages.
+///
+/// The quotas involved are locked until the transaction ends, which serializes concurrent publishes of limited
+/// names so that none of them counts stale pending messages or publishes. Publishes of names without quotas are
+/// not serialized, unless the schema has a quota. Counted publishes are not returned when the transaction commits
+/// without inserting the messages.
+pub async fn enforce_quotas(
+    tx: &mut PgTransaction<'_>,
+    names: &[&str],
+    now: DateTime<Utc>,
+) -> Result<(), PublishError> {
+    let mut counts: BTreeMap<&str, i64> = BTreeMap::new();
+    for name in names {
+        *counts.entry(name).or_default() += 1;
+    }
+    let distinct: Vec<String> = counts.keys().map(|name| name.to_string()).collect();
+
+    let quotas = sqlx::query!(
+        r#"
+        SELECT name, max_pending, max_daily_publishes
+        FROM quotas
+        WHERE name = ANY($1) OR name = '*'
+        ORDER BY name
+        FOR UPDATE
+        "#,
+        &distinct
+    )
+    .fetch_all(&mut **tx)
+    .await?;
+
+    let day = now.date_naive();
+    let mut daily = Vec::new();
+
+    for quota in quotas {
+        let (scope, count) = match quota.name.as_str() {
+            "*" => (QuotaScope::Schema, names.len() as i64),
+            name => (QuotaScope::Name(name.to_string()), counts[name]),
+        };
+
+        if let Some(max) = quota.max_pending {
+            let pending = sqlx::query_scalar!(
+                r#"
+                SELECT COUNT(*) "pending!"
+                FROM messages_unattempted
+                WHERE $1 = '*' OR name = $1
+                "#,
+                quota.name
+            )
+            .fetch_one(&mut **tx)
+            .await?;
+
+            if pending + count > max {
+                return Err(QuotaExceeded {
+                    scope,
+                    limit: QuotaLimit::Pending,
+                    max,
+                    requested: pending + count,
+                }
+                .into());
+            }
+        }
+
+        if let Some(max) = quota.max_daily_publishes {
+            let published = sqlx::query_scalar!(
+                r#"
+                SELECT published
+                FROM publish_counts
+                WHERE day = $1 AND name = $2
+                "#,
+                day,
+                quota.name
+            )
+            .fetch_optional(&mut **tx)
+            .await?
+            .unwrap_or(0);
+
+            if published + count > max {
+                return Err(QuotaExceeded {
+                    scope,
+                    limit: QuotaLimit::DailyPublishes,
+                    max,
+                    requested: published + count,
+                }
+                .into());
+            }
+            daily.push((quota.name, count));
+        }
+    }
+
+    // Counted once every quota passed, so that a rejected publish doesn't count
+    for (name, count) in daily {
+        sqlx::query!(
+            r#"
+            INSERT INTO publish_counts (day, name, published)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (day, name) DO UPDATE
+            SET published = publish_counts.published + EXCLUDED.published
+            "#,
+            day,
+            name,
+            count
+        )
+        .execute(&mut **tx)
+        .await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Quota;
+    use crate::queries::{publish_message, put_quota};
+    use crate::testing_tools::TestMessage;
+
+    async fn publish(pool: &sqlx::PgPool, name: &str) -> anyhow::Result<Result<(), PublishError>> {
+        let mut tx = pool.begin().await?;
+        if let Err(error) = enforce_quotas(&mut tx, &[name], Utc::now()).await {
+            return Ok(Err(error));
+        }
+        let message = crate::models::RawMessage {
+            name: name.to_string(),
+            ..TestMessage::default().to_raw()?
+        };
+        publish_message(&mut *tx, &message).await?;
+        tx.commit().await?;
+        Ok(Ok(()))
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_limits_pending_messages_of_a_name(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let quota = Quota {
+            scope: QuotaScope::Name("limited".to_string()),
+            max_pending: Some(2),
+            max_daily_publishes: None,
+        };
+        put_quota(&pool, &quota, Utc::now()).await?;
+
+        publish(&pool, "limited").await??;
+        publish(&pool, "limited").await??;
+        let rejected = publish(&pool, "limited").await?;
+        publish(&pool, "unlimited").await??;
+
+        match rejected {
+            Err(PublishError::QuotaExceeded(exceeded)) => assert_eq!(
+                exceeded,
+                QuotaExceeded {
+                    scope: QuotaScope::Name("limited".to_string()),
+                    limit: QuotaLimit::Pending,
+                    max: 2,
+                    requested: 3,
+                }
+            ),
+            other => panic!("Expected the quota to be exceeded, got {other:?}"),
+        }
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_limits_daily_publishes_of_the_schema(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let quota = Quota {
+            scope: QuotaScope::Schema,
+            max_pending: None,
+            max_daily_publishes: Some(2),
+        };
+        put_quota(&pool, &quota, Utc::now()).await?;
+
+        publish(&pool, "a").await??;
+        // A rejected batch is not counted
+        let mut tx = pool.begin().await?;
+        assert!(
+            enforce_quotas(&mut tx, &["a", "b"], Utc::now())
+                .await
+                .is_err()
+        );
+        tx.commit().await?;
+        publish(&pool, "b").await??;
+
+        match publish(&pool, "c").await? {
+            Err(PublishError::QuotaExceeded(exceeded)) => {
+                assert_eq!(exceeded.scope, QuotaScope::Schema);
+                assert_eq!(exceeded.limit, QuotaLimit::DailyPublishes);
+            }
+            other => panic!("Expected the quota to be exceeded, got {other:?}"),
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,102 @@
+/// How a worker loop should react to a failed database operation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+    /// The connection or server was briefly unavailable, retry the operation
+    Transient,
+    /// The operation can't succeed as is, e.g. the schema is not migrated or a constraint is violated. Retrying
+    /// won't help, fail loudly
+    Permanent,
+    /// The server is out of connections or another resource, retry after backing off
+    RateLimited,
+    /// The operation lost against a concurrent one, e.g. a serialization failure or a stale fencing token. Retry
+    /// or give the message up, depending on the operation
+    Conflict,
+}
+
+impl ErrorClass {
+    /// Classifies an error by its kind and, for database errors, their SQLSTATE code
+    pub fn of(error: &sqlx::Error) -> Self {
+        match error {
+            sqlx::Error::Database(error) => error
+                .code()
+                .map_or(Self::Permanent, |code| Self::of_code(&code)),
+            sqlx::Error::Io(_)
+            | sqlx::Error::Tls(_)
+            | sqlx::Error::Protocol(_)
+            | sqlx::Error::WorkerCrashed => Self::Transient,
+            sqlx::Error::PoolTimedOut => Self::RateLimited,
+            _ => Self::Permanent,
+        }
+    }
+
+    fn of_code(code: &str) -> Self {
+        match code {
+            // serialization_failure, deadlock_detected, lock_not_available
+            "40001" | "40P01" | "55P03" => Self::Conflict,
+            // too_many_connections, configuration_limit_exceeded
+            "53300" | "53400" => Self::RateLimited,
+            // query_canceled, e.g. by a statement timeout, admin_shutdown, crash_shutdown, cannot_connect_now
+            "57014" | "57P01" | "57P02" | "57P03" => Self::Transient,
+            // connection exceptions and insufficient resources, e.g. out of memory
+            code if code.starts_with("08") || code.starts_with("53") => Self::Transient,
+            _ => Self::Permanent,
+        }
+    }
+
+    /// Returns true for classes that may succeed when retried
+    pub fn is_retryable(self) -> bool {
+        self != Self::Permanent
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queries::ReportError;
+    use uuid::Uuid;
+
+    #[test]
+    fn it_classifies_connection_errors() {
+        let reset = std::io::Error::from(std::io::ErrorKind::ConnectionReset);
+        assert_eq!(
+            ErrorClass::of(&sqlx::Error::Io(reset)),
+            ErrorClass::Transient
+        );
+        assert_eq!(
+            ErrorClass::of(&sqlx::Error::PoolTimedOut),
+            ErrorClass::RateLimited
+        );
+        assert_eq!(
+            ErrorClass::of(&sqlx::Error::RowNotFound),
+            ErrorClass::Permanent
+        );
+        assert_eq!(
+            ReportError::StaleFencingToken(Uuid::now_v7()).class(),
+            ErrorClass::Conflict
+        );
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_classifies_database_errors(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let missing = sqlx::query("SELECT * FROM no_such_table")
+            .execute(&pool)
+            .await
+            .expect_err("Expected an undefined table");
+        assert_eq!(ErrorClass::of(&missing), ErrorClass::Permanent);
+
+        let mut locker = pool.begin().await?;
+        sqlx::query("LOCK TABLE hosts IN ACCESS EXCLUSIVE MODE")
+            .execute(&mut *locker)
+            .await?;
+
+        let mut tx = pool.begin().await?;
+        let locked = sqlx::query("LOCK TABLE hosts IN ACCESS EXCLUSIVE MODE NOWAIT")
+            .execute(&mut *tx)
+            .await
+            .expect_err("Expected the lock to be unavailable");
+        assert_eq!(ErrorClass::of(&locked), ErrorClass::Conflict);
+        assert!(ReportError::from(locked).class().is_retryable());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,40 @@
+use sqlx::PgExecutor;
+use uuid::Uuid;
+
+/// Returns an offloaded payload from `message_blobs`, None if there is no such blob
+pub async fn get_blob<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    id: Uuid,
+) -> Result<Option<serde_json::Value>, sqlx::Error> {
+    let data = sqlx::query_scalar!(
+        r#"
+        SELECT data FROM message_blobs WHERE id = $1
+        "#,
+        id
+    )
+    .fetch_optional(tx)
+    .await?;
+
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queries::put_blob;
+    use chrono::Utc;
+    use serde_json::json;
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_stores_and_returns_blobs(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let id = Uuid::now_v7();
+        let data = json!({ "large": "payload" });
+
+        assert_eq!(get_blob(&pool, id).await?, None);
+
+        put_blob(&pool, id, &data, Utc::now()).await?;
+        assert_eq!(get_blob(&pool, id).await?, Some(data));
+
+        Ok(())
+    }
+}
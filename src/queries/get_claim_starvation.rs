@@ -0,0 +1,119 @@
+use crate::models::ClaimStarvation;
+use chrono::{DateTime, Utc};
+use sqlx::PgExecutor;
+use std::time::Duration;
+
+/// Detects claim starvation: messages that have been claimable for longer than `threshold`
+/// while no claim at all happened within `threshold`.
+///
+/// Claimable messages are pending messages and retryable messages past their earliest retry time
+/// that are not leased. Starvation usually points to workers that are down, misconfigured or
+/// paused, rather than to workers that are merely busy.
+///
+/// Returns None if there is no starvation.
+pub async fn get_claim_starvation<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    now: DateTime<Utc>,
+    threshold: Duration,
+) -> Result<Option<ClaimStarvation>, sqlx::Error> {
+    let since = now - threshold;
+
+    let row = sqlx::query!(
+        r#"
+        WITH claimable AS (
+            SELECT mu.published_at AS claimable_since
+            FROM messages_unattempted mu
+            WHERE mu.published_at <= $1
+
+            UNION ALL
+
+            SELECT fa.retry_earliest_at
+            FROM attempts_failed fa
+            WHERE fa.retry_earliest_at <= $1
+              AND NOT EXISTS (
+                  SELECT 1 FROM leases l
+                  WHERE l.message_id = fa.message_id AND l.expires_at > $1
+              )
+              AND fa.failed_at = (
+                  SELECT MAX(fa2.failed_at)
+                  FROM attempts_failed fa2
+                  WHERE fa2.message_id = fa.message_id
+              )
+        )
+        SELECT
+            (SELECT COUNT(*) FROM claimable) "claimable!",
+            (SELECT MIN(claimable_since) FROM claimable) "oldest_claimable_since",
+            (SELECT MAX(acquired_at) FROM leases) "last_claimed_at"
+        "#,
+        now
+    )
+    .fetch_one(tx)
+    .await?;
+
+    let Some(oldest_claimable_since) = row.oldest_claimable_since else {
+        return Ok(None);
+    };
+
+    let starving = oldest_claimable_since <= since
+        && row
+            .last_claimed_at
+            .is_none_or(|claimed_at| claimed_at <= since);
+
+    Ok(starving.then_some(ClaimStarvation {
+        claimable: row.claimable,
+        oldest_claimable_since,
+        last_claimed_at: row.last_claimed_at,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        queries::{get_next_unattempted, publish_message},
+        testing_tools::TestMessage,
+    };
+    use uuid::Uuid;
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_detects_unclaimed_messages(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let threshold = Duration::from_secs(30);
+
+        publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+        let now = Utc::now() + Duration::from_mins(1);
+
+        let starvation = get_claim_starvation(&pool, now, threshold)
+            .await?
+            .expect("expected starvation");
+        assert_eq!(starvation.claimable, 1);
+        assert_eq!(starvation.last_claimed_at, None);
+        assert!(starvation.oldest_claimable_since <= now - threshold);
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_ignores_recent_messages(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+
+        let starvation = get_claim_starvation(&pool, Utc::now(), Duration::from_secs(30)).await?;
+        assert_eq!(starvation, None);
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_ignores_queues_with_recent_claims(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let threshold = Duration::from_secs(30);
+        let now = Utc::now() + Duration::from_mins(1);
+
+        publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+        publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+        get_next_unattempted(&pool, now, Uuid::now_v7(), Duration::from_mins(1)).await?;
+
+        let starvation = get_claim_starvation(&pool, now, threshold).await?;
+        assert_eq!(starvation, None);
+
+        Ok(())
+    }
+}
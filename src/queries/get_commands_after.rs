@@ -0,0 +1,70 @@
+use crate::models::{IssuedCommand, OperatorCommand};
+use sqlx::PgExecutor;
+use uuid::Uuid;
+
+/// Returns the commands for `host_id` or every host issued after the command `after_id`, in the order issued.
+/// Commands that can't be decoded, e.g. those of a newer version, are skipped.
+pub async fn get_commands_after<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    after_id: i64,
+    host_id: Uuid,
+) -> Result<Vec<IssuedCommand>, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT id, command, target_host, issued_at
+        FROM operator_commands
+        WHERE id > $1
+          AND (target_host IS NULL OR target_host = $2)
+        ORDER BY id ASC
+        "#,
+        after_id,
+        host_id
+    )
+    .fetch_all(tx)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .filter_map(
+            |row| match serde_json::from_value::<OperatorCommand>(row.command) {
+                Ok(command) => Some(IssuedCommand {
+                    id: row.id,
+                    command,
+                    target_host: row.target_host,
+                    issued_at: row.issued_at,
+                }),
+                Err(error) => {
+                    tracing::warn!(id = row.id, %error, "Skipping an undecodable operator command");
+                    None
+                }
+            },
+        )
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queries::issue_command;
+    use chrono::Utc;
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_returns_the_commands_of_a_host(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let now = Utc::now();
+        let host_id = Uuid::now_v7();
+
+        let pause = issue_command(&pool, &OperatorCommand::Pause, None, now).await?;
+        issue_command(&pool, &OperatorCommand::Drain, Some(Uuid::now_v7()), now).await?;
+        let workers = OperatorCommand::SetConcurrency { workers: 4 };
+        let resize = issue_command(&pool, &workers, Some(host_id), now).await?;
+
+        let commands = get_commands_after(&pool, 0, host_id).await?;
+        let ids: Vec<i64> = commands.iter().map(|command| command.id).collect();
+        assert_eq!(ids, [pause, resize]);
+        assert_eq!(commands[1].command, workers);
+
+        assert!(get_commands_after(&pool, resize, host_id).await?.is_empty());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,38 @@
+use crate::models::DailyAggregate;
+use chrono::NaiveDate;
+use sqlx::PgExecutor;
+
+/// Returns the compacted aggregates of all days between `from` and `to` (both inclusive),
+/// ordered by day and name.
+///
+/// Only reflects what has been written by [`compact_daily_aggregates`](super::compact_daily_aggregates).
+pub async fn get_daily_aggregates<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Result<Vec<DailyAggregate>, sqlx::Error> {
+    let aggregates = sqlx::query_as!(
+        DailyAggregate,
+        r#"
+        SELECT
+            day,
+            name,
+            published,
+            succeeded,
+            dead,
+            CASE
+                WHEN succeeded + dead > 0 THEN attempts::FLOAT8 / (succeeded + dead)
+                ELSE NULL
+            END "avg_attempts"
+        FROM daily_aggregates
+        WHERE day >= $1 AND day <= $2
+        ORDER BY day ASC, name ASC
+        "#,
+        from,
+        to
+    )
+    .fetch_all(tx)
+    .await?;
+
+    Ok(aggregates)
+}
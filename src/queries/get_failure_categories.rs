@@ -0,0 +1,105 @@
+use crate::models::FailureCategoryCount;
+use chrono::{DateTime, Utc};
+use sqlx::PgExecutor;
+use std::time::Duration;
+
+/// Counts the errors reported within `[from, to)` by bucket of `bucket`, message name and category, ordered by
+/// bucket, name and category.
+///
+/// Buckets are aligned to the unix epoch. An error counts as a death when it was reported with the message dead,
+/// otherwise as a retry. Errors deleted by a cap on the error history, see
+/// [`report_retryable_capped`](super::report_retryable_capped), are not counted.
+pub async fn get_failure_categories<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    bucket: Duration,
+) -> Result<Vec<FailureCategoryCount>, sqlx::Error> {
+    let counts = sqlx::query_as!(
+        FailureCategoryCount,
+        r#"
+        SELECT
+            to_timestamp(floor(EXTRACT(EPOCH FROM e.reported_at)::FLOAT8 / $3) * $3) "bucket!",
+            ma.name,
+            e.category,
+            COUNT(*) FILTER (WHERE d.message_id IS NULL) "retries!",
+            COUNT(*) FILTER (WHERE d.message_id IS NOT NULL) "deaths!"
+        FROM errors e
+        JOIN messages_attempted ma ON ma.id = e.message_id
+        LEFT JOIN attempts_dead d ON d.message_id = e.message_id AND d.dead_at = e.reported_at
+        WHERE e.reported_at >= $1 AND e.reported_at < $2
+        GROUP BY 1, ma.name, e.category
+        ORDER BY 1 ASC, ma.name ASC, e.category ASC NULLS LAST
+        "#,
+        from,
+        to,
+        bucket.as_secs_f64().max(1.0)
+    )
+    .fetch_all(tx)
+    .await?;
+
+    Ok(counts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{FailureReason, RawMessage};
+    use crate::queries::{report_dead, report_retryable};
+    use crate::testing_tools::{TestMessage, publish_and_claim};
+    use chrono::TimeZone;
+    use uuid::Uuid;
+
+    async fn claim_named(pool: &sqlx::PgPool, name: &str) -> anyhow::Result<RawMessage> {
+        let message = RawMessage {
+            name: name.to_string(),
+            ..TestMessage::default().to_raw()?
+        };
+        publish_and_claim(pool, &message, Uuid::now_v7(), Duration::from_mins(1)).await
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_counts_retries_and_deaths_by_category(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let hour = Utc.with_ymd_and_hms(2026, 1, 1, 12, 0, 0).unwrap();
+        let timeout = FailureReason::new("timed out").with_category("timeout");
+
+        let message = claim_named(&pool, "fetch").await?;
+        report_retryable(&pool, message.id, None, hour, 1, hour, timeout).await?;
+        let message = claim_named(&pool, "fetch").await?;
+        report_dead(&pool, message.id, None, hour, timeout).await?;
+        let message = claim_named(&pool, "fetch").await?;
+        let later = hour + Duration::from_mins(90);
+        report_retryable(&pool, message.id, None, later, 1, later, "uncategorized").await?;
+        let message = claim_named(&pool, "parse").await?;
+        let validation = FailureReason::new("invalid").with_category("validation");
+        report_dead(&pool, message.id, None, hour, validation).await?;
+
+        let counts = get_failure_categories(
+            &pool,
+            hour,
+            hour + Duration::from_hours(2),
+            Duration::from_hours(1),
+        )
+        .await?;
+
+        let count =
+            |bucket, name: &str, category: Option<&str>, retries, deaths| FailureCategoryCount {
+                bucket,
+                name: name.to_string(),
+                category: category.map(str::to_string),
+                retries,
+                deaths,
+            };
+        let next_hour = hour + Duration::from_hours(1);
+        assert_eq!(
+            counts,
+            vec![
+                count(hour, "fetch", Some("timeout"), 1, 1),
+                count(hour, "parse", Some("validation"), 0, 1),
+                count(next_hour, "fetch", None, 1, 0),
+            ]
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,51 @@
+use crate::models::RawMessage;
+use sqlx::PgExecutor;
+
+/// Returns up to `limit` succeeded or dead messages in publish order, starting after the message with the `seq`
+/// passed as cursor.
+///
+/// `attempted` is the number of attempts recorded when the message finished. Messages are returned as-is, no leases
+/// are acquired.
+pub async fn get_finished_messages<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    after: Option<i64>,
+    limit: i64,
+) -> Result<Vec<RawMessage>, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT
+            ma.id,
+            ma.name,
+            ma.hash,
+            ma.payload,
+            ma.seq,
+            COALESCE(s.attempted, d.attempted) "attempted!",
+            (SELECT qs.max_attempts FROM queue_settings qs WHERE qs.name = ma.name) "max_attempts?"
+        FROM messages_attempted ma
+        LEFT JOIN attempts_succeeded s ON s.message_id = ma.id
+        LEFT JOIN attempts_dead d ON d.message_id = ma.id
+        WHERE (s.message_id IS NOT NULL OR d.message_id IS NOT NULL)
+          AND ($1::BIGINT IS NULL OR ma.seq > $1)
+        ORDER BY ma.seq ASC
+        LIMIT $2
+        "#,
+        after,
+        limit
+    )
+    .fetch_all(tx)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| RawMessage {
+            id: row.id,
+            name: row.name,
+            hash: row.hash,
+            payload: row.payload,
+            attempted: row.attempted,
+            fencing_token: None,
+            seq: Some(row.seq),
+            max_attempts: row.max_attempts,
+        })
+        .collect())
+}
@@ -0,0 +1,105 @@
+use crate::models::LeaseHolder;
+use chrono::{DateTime, Utc};
+use sqlx::PgExecutor;
+use uuid::Uuid;
+
+/// Returns the host currently holding an active lease on a message, with its label if registered
+pub async fn get_lease_holder<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    message_id: Uuid,
+    now: DateTime<Utc>,
+) -> Result<Option<LeaseHolder>, sqlx::Error> {
+    let holder = sqlx::query_as!(
+        LeaseHolder,
+        r#"
+        SELECT
+            l.acquired_by "host_id",
+            h.label "label?",
+            l.acquired_at,
+            l.expires_at
+        FROM leases l
+        LEFT JOIN hosts h ON h.id = l.acquired_by
+        WHERE l.message_id = $1 AND l.expires_at > $2
+        ORDER BY l.expires_at DESC
+        LIMIT 1
+        "#,
+        message_id,
+        now
+    )
+    .fetch_optional(tx)
+    .await?;
+
+    Ok(holder)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        queries::{get_next_unattempted, publish_message, register_host},
+        testing_tools::TestMessage,
+    };
+    use std::time::Duration;
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_returns_the_labelled_holder(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let now = Utc::now();
+        let host_id = Uuid::now_v7();
+
+        register_host(&pool, host_id, "worker-7f9c (v1.2.3)", now).await?;
+        publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+        let message = get_next_unattempted(&pool, now, host_id, Duration::from_mins(1))
+            .await?
+            .unwrap();
+
+        let holder = get_lease_holder(&pool, message.id, now)
+            .await?
+            .expect("Expected a lease holder");
+
+        assert_eq!(holder.host_id, host_id);
+        assert_eq!(holder.label.as_deref(), Some("worker-7f9c (v1.2.3)"));
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_returns_unregistered_holders_without_label(
+        pool: sqlx::PgPool,
+    ) -> anyhow::Result<()> {
+        let now = Utc::now();
+        let host_id = Uuid::now_v7();
+
+        publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+        let message = get_next_unattempted(&pool, now, host_id, Duration::from_mins(1))
+            .await?
+            .unwrap();
+
+        let holder = get_lease_holder(&pool, message.id, now)
+            .await?
+            .expect("Expected a lease holder");
+
+        assert_eq!(holder.host_id, host_id);
+        assert_eq!(holder.label, None);
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_replaces_labels_on_reregistration(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let now = Utc::now();
+        let host_id = Uuid::now_v7();
+
+        register_host(&pool, host_id, "old", now).await?;
+        register_host(&pool, host_id, "new", now).await?;
+        publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+        let message = get_next_unattempted(&pool, now, host_id, Duration::from_mins(1))
+            .await?
+            .unwrap();
+
+        let holder = get_lease_holder(&pool, message.id, now).await?.unwrap();
+
+        assert_eq!(holder.label.as_deref(), Some("new"));
+
+        Ok(())
+    }
+}
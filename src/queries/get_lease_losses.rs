@@ -0,0 +1,75 @@
+use crate::models::LeaseLosses;
+use chrono::{DateTime, Utc};
+use sqlx::PgExecutor;
+
+/// Returns the hosts whose expired leases were taken over from `from` (inclusive) to `to` (exclusive), the hosts
+/// losing the most leases first
+pub async fn get_lease_losses<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<Vec<LeaseLosses>, sqlx::Error> {
+    let losses = sqlx::query_as!(
+        LeaseLosses,
+        r#"
+        SELECT
+            lh.host_id,
+            h.label "label?",
+            COUNT(*) "lost!",
+            MAX(lh.taken_over_at) "last_taken_over_at!"
+        FROM lease_history lh
+        LEFT JOIN hosts h ON h.id = lh.host_id
+        WHERE lh.taken_over_at >= $1 AND lh.taken_over_at < $2
+        GROUP BY lh.host_id, h.label
+        ORDER BY COUNT(*) DESC, lh.host_id
+        "#,
+        from,
+        to
+    )
+    .fetch_all(tx)
+    .await?;
+
+    Ok(losses)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        queries::{get_next_missing, get_next_unattempted, publish_message, register_host},
+        testing_tools::TestMessage,
+    };
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_records_taken_over_leases(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let now = Utc::now();
+        let hold_for = Duration::from_secs(1);
+        let crashed = Uuid::now_v7();
+        let rescuer = Uuid::now_v7();
+        register_host(&pool, crashed, "hostname=worker-0", now).await?;
+
+        for _ in 0..2 {
+            publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+            get_next_unattempted(&pool, now, crashed, hold_for)
+                .await?
+                .expect("Expected a message");
+        }
+
+        let later = now + Duration::from_secs(2);
+        for _ in 0..2 {
+            get_next_missing(&pool, later, rescuer, hold_for)
+                .await?
+                .expect("Expected a missing message");
+        }
+
+        let losses = get_lease_losses(&pool, now, later + Duration::from_secs(1)).await?;
+        assert_eq!(losses.len(), 1);
+        assert_eq!(losses[0].host_id, crashed);
+        assert_eq!(losses[0].label.as_deref(), Some("hostname=worker-0"));
+        assert_eq!(losses[0].lost, 2);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,117 @@
+use crate::models::{ClaimPredicate, RawMessage};
+use chrono::{DateTime, Utc};
+use sqlx::PgExecutor;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Claims up to `limit` unattempted messages in a single statement, in publish order.
+///
+/// Behaves like `get_next_unattempted` for each claimed message. Useful to drain a backlog in batches.
+pub async fn get_many_unattempted<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    now: DateTime<Utc>,
+    host_id: Uuid,
+    hold_for: Duration,
+    limit: i64,
+) -> Result<Vec<RawMessage>, sqlx::Error> {
+    get_many_unattempted_inner(tx, now, host_id, hold_for, limit, None).await
+}
+
+/// Claims like [`get_many_unattempted`].
+///
+/// With a `predicate`, only messages whose payload matches it are claimed.
+pub(super) async fn get_many_unattempted_inner<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    now: DateTime<Utc>,
+    host_id: Uuid,
+    hold_for: Duration,
+    limit: i64,
+    predicate: Option<&ClaimPredicate>,
+) -> Result<Vec<RawMessage>, sqlx::Error> {
+    let expires_at = now + hold_for;
+
+    let messages = sqlx::query_as!(
+        RawMessage,
+        r#"
+        WITH next_messages AS (
+            SELECT id
+            FROM messages_unattempted
+            WHERE ($5::TEXT IS NULL OR payload ->> $5 = ANY($6::TEXT[]))
+              AND NOT EXISTS (
+                  SELECT 1 FROM leases l
+                  WHERE l.message_id = messages_unattempted.id AND l.expires_at > $1
+              )
+            ORDER BY seq ASC
+            FOR UPDATE SKIP LOCKED
+            LIMIT $4
+        )
+        SELECT
+            c.id "id!",
+            c.name "name!",
+            c.hash "hash!",
+            c.payload "payload!",
+            c.attempted "attempted!",
+            c.fencing_token "fencing_token?",
+            c.seq "seq?",
+            c.max_attempts "max_attempts?"
+        FROM next_messages n, LATERAL claim_unattempted(n.id, $1, $2, $3) c
+        ORDER BY c.seq ASC;
+        "#,
+        now,
+        host_id,
+        expires_at,
+        limit,
+        predicate.map(ClaimPredicate::field),
+        predicate.map(ClaimPredicate::values) as Option<&[String]>
+    )
+    .fetch_all(tx)
+    .await?;
+
+    Ok(messages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queries::publish_message;
+    use crate::testing_tools::{TestMessage, is_in_progress, is_pending};
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_claims_up_to_limit_in_publish_order(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let mut published = Vec::new();
+        for _ in 0..3 {
+            published.push(publish_message(&pool, &TestMessage::default().to_raw()?).await?);
+        }
+
+        let now = Utc::now();
+        let host_id = Uuid::now_v7();
+        let hold_for = Duration::from_mins(1);
+
+        let polled = get_many_unattempted(&pool, now, host_id, hold_for, 2).await?;
+
+        assert_eq!(polled.len(), 2);
+        assert_eq!(polled[0].id, published[0].id);
+        assert_eq!(polled[1].id, published[1].id);
+        assert!(is_in_progress(&pool, polled[0].id, now).await?);
+        assert!(is_in_progress(&pool, polled[1].id, now).await?);
+        assert!(is_pending(&pool, published[2].id, now).await?);
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_returns_empty_when_none_are_available(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let polled = get_many_unattempted(
+            &pool,
+            Utc::now(),
+            Uuid::now_v7(),
+            Duration::from_mins(1),
+            10,
+        )
+        .await?;
+
+        assert!(polled.is_empty());
+
+        Ok(())
+    }
+}
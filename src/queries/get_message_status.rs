@@ -0,0 +1,145 @@
+use crate::models::MessageStatus;
+use chrono::{DateTime, Utc};
+use sqlx::PgExecutor;
+use uuid::Uuid;
+
+/// Returns the status of a message as it was at `as_of`, or None if it did not exist or was not published yet.
+///
+/// Passing a past instant evaluates the recorded timestamps as of then, e.g. to investigate the state of a
+/// message when an incident happened. Leases are removed once a message is reported, so a message that was in
+/// progress at `as_of` but has been reported since appears as pending, or failed if it failed before.
+pub async fn get_message_status<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    message_id: Uuid,
+    as_of: DateTime<Utc>,
+) -> Result<Option<MessageStatus>, sqlx::Error> {
+    let status = sqlx::query_scalar!(
+        r#"
+        WITH message AS (
+            SELECT id, published_at FROM messages_unattempted WHERE id = $1
+            UNION ALL
+            SELECT id, published_at FROM messages_attempted WHERE id = $1
+            UNION ALL
+            -- Barriers waiting for the messages before them are pending
+            SELECT id, published_at FROM barriers WHERE id = $1
+        ),
+        -- Failed attempts are removed once a message succeeds, while their errors are kept
+        last_failed AS (
+            SELECT MAX(failed_at) failed_at
+            FROM (
+                SELECT failed_at FROM attempts_failed WHERE message_id = $1 AND failed_at <= $2
+                UNION ALL
+                SELECT reported_at FROM errors WHERE message_id = $1 AND reported_at <= $2
+            ) failures
+        ),
+        last_leased AS (
+            SELECT MAX(acquired_at) acquired_at
+            FROM leases
+            WHERE message_id = $1 AND acquired_at <= $2
+        )
+        SELECT
+            CASE
+                WHEN EXISTS (
+                    SELECT 1 FROM attempts_succeeded WHERE message_id = $1 AND succeeded_at <= $2
+                ) THEN 'succeeded'
+                WHEN EXISTS (
+                    SELECT 1 FROM attempts_dead WHERE message_id = $1 AND dead_at <= $2
+                ) THEN 'dead'
+                WHEN EXISTS (
+                    SELECT 1 FROM leases WHERE message_id = $1 AND acquired_at <= $2 AND expires_at > $2
+                ) THEN 'in_progress'
+                -- A lease acquired after the last failure is a retry, whose lease expired
+                WHEN (SELECT acquired_at FROM last_leased) IS NOT NULL
+                    AND (SELECT acquired_at FROM last_leased) > COALESCE((SELECT failed_at FROM last_failed), '-infinity')
+                    THEN 'missing'
+                WHEN (SELECT failed_at FROM last_failed) IS NOT NULL THEN 'failed'
+                ELSE 'pending'
+            END "status!"
+        FROM message
+        WHERE published_at <= $2
+        "#,
+        message_id,
+        as_of
+    )
+    .fetch_optional(tx)
+    .await?;
+
+    Ok(status.map(|status| MessageStatus::from_label(&status)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queries::{get_next_unattempted, publish_message, report_retryable, report_success};
+    use crate::testing_tools::TestMessage;
+    use std::time::Duration;
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_evaluates_the_status_as_of_an_instant(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let message = publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+        let published = Utc::now();
+        let host_id = Uuid::now_v7();
+        let hold_for = Duration::from_mins(1);
+
+        let claimed_at = published + Duration::from_secs(10);
+        let claimed = get_next_unattempted(&pool, claimed_at, host_id, hold_for)
+            .await?
+            .expect("Expected a message");
+
+        let failed_at = claimed_at + Duration::from_secs(10);
+        let retry_at = failed_at + Duration::from_mins(5);
+        report_retryable(
+            &pool,
+            claimed.id,
+            claimed.fencing_token,
+            failed_at,
+            1,
+            retry_at,
+            "err",
+        )
+        .await?;
+
+        let succeeded_at = retry_at + Duration::from_secs(10);
+        report_success(&pool, claimed.id, None, succeeded_at).await?;
+
+        let status = |as_of| get_message_status(&pool, message.id, as_of);
+        assert_eq!(status(published - Duration::from_mins(1)).await?, None);
+        assert_eq!(status(published).await?, Some(MessageStatus::Pending));
+        assert_eq!(status(failed_at).await?, Some(MessageStatus::Failed));
+        assert_eq!(status(succeeded_at).await?, Some(MessageStatus::Succeeded));
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_reports_in_progress_and_missing_leases(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let message = publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+        let claimed_at = Utc::now();
+        let hold_for = Duration::from_mins(1);
+
+        get_next_unattempted(&pool, claimed_at, Uuid::now_v7(), hold_for)
+            .await?
+            .expect("Expected a message");
+
+        let status = |as_of| get_message_status(&pool, message.id, as_of);
+        assert_eq!(
+            status(claimed_at + Duration::from_secs(30)).await?,
+            Some(MessageStatus::InProgress)
+        );
+        assert_eq!(
+            status(claimed_at + Duration::from_mins(2)).await?,
+            Some(MessageStatus::Missing)
+        );
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_returns_none_for_unknown_messages(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        assert_eq!(
+            get_message_status(&pool, Uuid::now_v7(), Utc::now()).await?,
+            None
+        );
+        Ok(())
+    }
+}
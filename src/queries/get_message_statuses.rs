@@ -0,0 +1,125 @@
+use crate::models::MessageStatus;
+use chrono::{DateTime, Utc};
+use sqlx::PgExecutor;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// Returns the status of each of `message_ids` as it was at `as_of` in one round trip, see
+/// [`get_message_status`](super::get_message_status). Messages that did not exist or were not published yet at
+/// `as_of` are left out.
+pub async fn get_message_statuses<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    message_ids: &[Uuid],
+    as_of: DateTime<Utc>,
+) -> Result<HashMap<Uuid, MessageStatus>, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"
+        WITH message AS (
+            SELECT id, published_at FROM messages_unattempted WHERE id = ANY($1)
+            UNION ALL
+            SELECT id, published_at FROM messages_attempted WHERE id = ANY($1)
+            UNION ALL
+            -- Barriers waiting for the messages before them are pending
+            SELECT id, published_at FROM barriers WHERE id = ANY($1)
+        )
+        SELECT
+            m.id "id!",
+            CASE
+                WHEN EXISTS (
+                    SELECT 1 FROM attempts_succeeded WHERE message_id = m.id AND succeeded_at <= $2
+                ) THEN 'succeeded'
+                WHEN EXISTS (
+                    SELECT 1 FROM attempts_dead WHERE message_id = m.id AND dead_at <= $2
+                ) THEN 'dead'
+                WHEN EXISTS (
+                    SELECT 1 FROM leases WHERE message_id = m.id AND acquired_at <= $2 AND expires_at > $2
+                ) THEN 'in_progress'
+                -- A lease acquired after the last failure is a retry, whose lease expired
+                WHEN last_leased.acquired_at IS NOT NULL
+                    AND last_leased.acquired_at > COALESCE(last_failed.failed_at, '-infinity')
+                    THEN 'missing'
+                WHEN last_failed.failed_at IS NOT NULL THEN 'failed'
+                ELSE 'pending'
+            END "status!"
+        FROM message m
+        -- Failed attempts are removed once a message succeeds, while their errors are kept
+        CROSS JOIN LATERAL (
+            SELECT MAX(failed_at) failed_at
+            FROM (
+                SELECT failed_at FROM attempts_failed WHERE message_id = m.id AND failed_at <= $2
+                UNION ALL
+                SELECT reported_at FROM errors WHERE message_id = m.id AND reported_at <= $2
+            ) failures
+        ) last_failed
+        CROSS JOIN LATERAL (
+            SELECT MAX(acquired_at) acquired_at
+            FROM leases
+            WHERE message_id = m.id AND acquired_at <= $2
+        ) last_leased
+        WHERE m.published_at <= $2
+        "#,
+        message_ids,
+        as_of
+    )
+    .fetch_all(tx)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| (row.id, MessageStatus::from_label(&row.status)))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queries::{
+        get_message_status, get_next_unattempted, publish_message, report_dead, report_success,
+    };
+    use crate::testing_tools::TestMessage;
+    use std::time::Duration;
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_returns_the_statuses_of_many_messages(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let host_id = Uuid::now_v7();
+        let hold_for = Duration::from_mins(1);
+
+        let mut ids = Vec::new();
+        for _ in 0..4 {
+            ids.push(
+                publish_message(&pool, &TestMessage::default().to_raw()?)
+                    .await?
+                    .id,
+            );
+        }
+        let now = Utc::now();
+        let succeeded = get_next_unattempted(&pool, now, host_id, hold_for)
+            .await?
+            .expect("Expected a message");
+        report_success(&pool, succeeded.id, succeeded.fencing_token, now).await?;
+        let dead = get_next_unattempted(&pool, now, host_id, hold_for)
+            .await?
+            .expect("Expected a message");
+        report_dead(&pool, dead.id, dead.fencing_token, now, "err").await?;
+        get_next_unattempted(&pool, now, host_id, hold_for).await?;
+
+        let unknown = Uuid::now_v7();
+        let statuses =
+            get_message_statuses(&pool, &[ids.clone(), vec![unknown]].concat(), now).await?;
+
+        assert_eq!(statuses.len(), 4);
+        assert!(!statuses.contains_key(&unknown));
+        for id in &ids {
+            assert_eq!(
+                statuses.get(id).copied(),
+                get_message_status(&pool, *id, now).await?
+            );
+        }
+        assert_eq!(statuses[&ids[0]], MessageStatus::Succeeded);
+        assert_eq!(statuses[&ids[1]], MessageStatus::Dead);
+        assert_eq!(statuses[&ids[2]], MessageStatus::InProgress);
+        assert_eq!(statuses[&ids[3]], MessageStatus::Pending);
+
+        Ok(())
+    }
+}
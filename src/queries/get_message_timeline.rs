@@ -0,0 +1,131 @@
+use crate::models::{TimelineEvent, TimelineEventKind};
+use sqlx::PgExecutor;
+use uuid::Uuid;
+
+/// Returns what happened to a message in the order it happened, empty if the message does not exist.
+///
+/// Leases are removed once a message is reported, so claims appear only while they are held or after they expired
+/// without a report. Attempts that were reported appear as their outcome, and failed attempts are removed once a
+/// message succeeds, leaving their errors.
+pub async fn get_message_timeline<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    message_id: Uuid,
+) -> Result<Vec<TimelineEvent>, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT
+            at "at!",
+            kind "kind!",
+            host_id,
+            by_host_id,
+            attempted,
+            error
+        FROM (
+            SELECT published_at at, 'published' kind, NULL::UUID host_id, NULL::UUID by_host_id, NULL::INTEGER attempted, NULL error, 0 seq
+            FROM messages_unattempted WHERE id = $1
+            UNION ALL
+            SELECT published_at, 'published', NULL, NULL, NULL, NULL, 0
+            FROM messages_attempted WHERE id = $1
+            UNION ALL
+            SELECT acquired_at, 'claimed', acquired_by, NULL, NULL, NULL, 1
+            FROM leases WHERE message_id = $1
+            UNION ALL
+            SELECT taken_over_at, 'taken_over', host_id, taken_over_by, NULL, NULL, 1
+            FROM lease_history WHERE message_id = $1
+            UNION ALL
+            SELECT reported_at, 'errored', NULL, NULL, NULL, error, 2
+            FROM errors WHERE message_id = $1
+            UNION ALL
+            SELECT failed_at, 'failed', NULL, NULL, attempted, NULL, 3
+            FROM attempts_failed WHERE message_id = $1
+            UNION ALL
+            SELECT succeeded_at, 'succeeded', NULL, NULL, NULL, NULL, 3
+            FROM attempts_succeeded WHERE message_id = $1
+            UNION ALL
+            SELECT dead_at, 'dead', NULL, NULL, NULL, NULL, 3
+            FROM attempts_dead WHERE message_id = $1
+        ) events
+        ORDER BY at ASC, seq ASC
+        "#,
+        message_id
+    )
+    .fetch_all(tx)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| TimelineEvent {
+            at: row.at,
+            kind: match row.kind.as_str() {
+                "claimed" => TimelineEventKind::Claimed {
+                    host_id: row.host_id.unwrap_or_default(),
+                },
+                "taken_over" => TimelineEventKind::TakenOver {
+                    from: row.host_id.unwrap_or_default(),
+                    by: row.by_host_id.unwrap_or_default(),
+                },
+                "errored" => TimelineEventKind::Errored {
+                    error: row.error.unwrap_or_default(),
+                },
+                "failed" => TimelineEventKind::Failed {
+                    attempted: row.attempted.unwrap_or_default(),
+                },
+                "succeeded" => TimelineEventKind::Succeeded,
+                "dead" => TimelineEventKind::Dead,
+                _ => TimelineEventKind::Published,
+            },
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queries::{get_next_unattempted, publish_message, report_retryable};
+    use crate::testing_tools::TestMessage;
+    use chrono::Utc;
+    use std::time::Duration;
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_returns_events_in_order(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let message = publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+        let host_id = Uuid::now_v7();
+        let now = Utc::now() + Duration::from_secs(1);
+
+        let claimed = get_next_unattempted(&pool, now, host_id, Duration::from_mins(1))
+            .await?
+            .expect("Expected a message");
+        let failed_at = now + Duration::from_secs(1);
+        report_retryable(
+            &pool,
+            claimed.id,
+            claimed.fencing_token,
+            failed_at,
+            1,
+            failed_at,
+            "timed out",
+        )
+        .await?;
+
+        let timeline = get_message_timeline(&pool, message.id).await?;
+        let kinds: Vec<_> = timeline.into_iter().map(|event| event.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                TimelineEventKind::Published,
+                TimelineEventKind::Errored {
+                    error: "timed out".to_string()
+                },
+                TimelineEventKind::Failed { attempted: 1 },
+            ]
+        );
+
+        assert!(
+            get_message_timeline(&pool, Uuid::now_v7())
+                .await?
+                .is_empty()
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,118 @@
+use crate::models::{ClaimPredicate, RawMessage};
+use chrono::{DateTime, Utc};
+use sqlx::PgExecutor;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Claims the earliest dead message still in review, for a remediation handler to attempt a last fix.
+///
+/// A dead message is in review until it is reviewed, see [`report_reviewed`](super::report_reviewed) and
+/// [`report_remediated`](super::report_remediated), or until `grace` has passed since it was reported dead. After
+/// that it is closed and never claimed for review again.
+pub async fn get_next_dead_for_review<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    now: DateTime<Utc>,
+    host_id: Uuid,
+    hold_for: Duration,
+    grace: Duration,
+) -> Result<Option<RawMessage>, sqlx::Error> {
+    get_next_dead_for_review_inner(tx, now, host_id, hold_for, grace, None).await
+}
+
+/// Claims like [`get_next_dead_for_review`].
+///
+/// With a `predicate`, only messages whose payload matches it are claimed.
+pub(super) async fn get_next_dead_for_review_inner<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    now: DateTime<Utc>,
+    host_id: Uuid,
+    hold_for: Duration,
+    grace: Duration,
+    predicate: Option<&ClaimPredicate>,
+) -> Result<Option<RawMessage>, sqlx::Error> {
+    let expires_at = now + hold_for;
+    let in_review_since = now - grace;
+
+    let message = sqlx::query_as!(
+        RawMessage,
+        r#"
+        WITH candidate AS (
+            SELECT d.message_id, d.attempted
+            FROM attempts_dead d
+            WHERE d.reviewed_at IS NULL
+              AND d.dead_at > $4
+              AND NOT EXISTS (
+                  SELECT 1 FROM leases l
+                  WHERE l.message_id = d.message_id AND l.expires_at > $1
+              )
+              AND ($5::TEXT IS NULL OR EXISTS (
+                  SELECT 1 FROM messages_attempted ma
+                  WHERE ma.id = d.message_id AND ma.payload ->> $5 = ANY($6::TEXT[])
+              ))
+            ORDER BY d.dead_at, d.message_id
+            LIMIT 1
+            FOR UPDATE SKIP LOCKED
+        )
+        SELECT
+            c.id "id!",
+            c.name "name!",
+            c.hash "hash!",
+            c.payload "payload!",
+            c.attempted "attempted!",
+            c.fencing_token "fencing_token?",
+            c.seq "seq?",
+            c.max_attempts "max_attempts?"
+        FROM candidate cd, LATERAL claim_attempted(cd.message_id, cd.attempted, $1, $2, $3) c;
+        "#,
+        now,
+        host_id,
+        expires_at,
+        in_review_since,
+        predicate.map(ClaimPredicate::field),
+        predicate.map(ClaimPredicate::values) as Option<&[String]>
+    )
+    .fetch_optional(tx)
+    .await?;
+
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queries::{get_next_unattempted, publish_message, report_dead};
+    use crate::testing_tools::TestMessage;
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_claims_dead_messages_within_the_grace_period(
+        pool: sqlx::PgPool,
+    ) -> anyhow::Result<()> {
+        let now = Utc::now();
+        let host_id = Uuid::now_v7();
+        let hold_for = Duration::from_mins(1);
+        let grace = Duration::from_mins(10);
+
+        publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+        let message = get_next_unattempted(&pool, now, host_id, hold_for)
+            .await?
+            .expect("Expected a message");
+        report_dead(&pool, message.id, None, now, "missing data").await?;
+
+        let claimed = get_next_dead_for_review(&pool, now, host_id, hold_for, grace)
+            .await?
+            .expect("Expected a dead message");
+        assert_eq!(claimed.id, message.id);
+        assert!(claimed.fencing_token.is_some());
+
+        // Leased for review
+        let again = get_next_dead_for_review(&pool, now, host_id, hold_for, grace).await?;
+        assert!(again.is_none());
+
+        // Closed once the grace period has passed
+        let closed = now + grace + hold_for;
+        let again = get_next_dead_for_review(&pool, closed, host_id, hold_for, grace).await?;
+        assert!(again.is_none());
+
+        Ok(())
+    }
+}
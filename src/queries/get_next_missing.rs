@@ -1,4 +1,4 @@
-use crate::models::RawMessage;
+use crate::models::{ClaimPredicate, RawMessage};
 use chrono::{DateTime, Utc};
 use sqlx::PgExecutor;
 use std::time::Duration;
@@ -8,23 +8,90 @@ use uuid::Uuid;
 /// A message is considered missing when it is attempted but not succeeded or dead and has an expired lease
 /// Failed, succeeded and dead messages have no-leases as reporting clears leases.
 /// As such attempted messages with expired leases indicate that a worker failed to report before the lease expiry, possibly due to a crash.
+/// The expired lease is recorded in `lease_history`, see [`get_lease_losses`](super::get_lease_losses).
 pub async fn get_next_missing<'tx, E: PgExecutor<'tx>>(
     tx: E,
     now: DateTime<Utc>,
     host_id: Uuid,
     hold_for: Duration,
+) -> Result<Option<RawMessage>, sqlx::Error> {
+    get_next_missing_inner(tx, now, host_id, hold_for, false, None, None, None).await
+}
+
+/// Claims the next missing message like [`get_next_missing`], preferring messages whose lease was lost by `host_id`,
+/// e.g. when a host restarts with the same id after a crash and may resume partial work.
+pub async fn get_next_missing_sticky<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    now: DateTime<Utc>,
+    host_id: Uuid,
+    hold_for: Duration,
+) -> Result<Option<RawMessage>, sqlx::Error> {
+    get_next_missing_inner(tx, now, host_id, hold_for, true, None, None, None).await
+}
+
+/// Claims the next missing message with one of the given names, see [`get_next_missing`].
+pub async fn get_next_missing_named<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    now: DateTime<Utc>,
+    host_id: Uuid,
+    hold_for: Duration,
+    names: &[String],
+) -> Result<Option<RawMessage>, sqlx::Error> {
+    get_next_missing_inner(tx, now, host_id, hold_for, false, Some(names), None, None).await
+}
+
+/// Claims the next missing message, see [`get_next_missing`].
+///
+/// With a `suppression_window`, messages whose lease expired within the window before `now` are skipped. This gives
+/// a late success report of the lost attempt the chance to land before the message is delivered again.
+///
+/// With a `predicate`, only messages whose payload matches it are claimed.
+#[allow(clippy::too_many_arguments)]
+pub(super) async fn get_next_missing_inner<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    now: DateTime<Utc>,
+    host_id: Uuid,
+    hold_for: Duration,
+    sticky: bool,
+    names: Option<&[String]>,
+    suppression_window: Option<Duration>,
+    predicate: Option<&ClaimPredicate>,
 ) -> Result<Option<RawMessage>, sqlx::Error> {
     let expires_at = now + hold_for;
+    let suppressed_since = suppression_window.map(|window| now - window);
 
     let message = sqlx::query_as!(
         RawMessage,
         r#"
         WITH candidate AS (
-            SELECT ma.*
+            SELECT ma.*,
+                -- The failed attempts, the lost attempt and the attempts lost since the last failure
+                COALESCE(failed.attempted, 0) + 1 + (
+                    SELECT COUNT(*) FROM lease_history h
+                    WHERE h.message_id = ma.id
+                      AND h.taken_over_at > COALESCE(failed.failed_at, '-infinity')
+                )::INTEGER AS attempted,
+                l.acquired_by AS lost_by,
+                l.acquired_at AS lost_acquired_at,
+                l.expires_at AS lost_expires_at
             FROM leases l
             JOIN messages_attempted ma
               ON ma.id = l.message_id
+            LEFT JOIN LATERAL (
+                SELECT fa.attempted, fa.failed_at
+                FROM attempts_failed fa
+                WHERE fa.message_id = ma.id
+                ORDER BY fa.failed_at DESC
+                LIMIT 1
+            ) failed ON TRUE
             WHERE l.expires_at < $1
+              AND ($6::TIMESTAMPTZ IS NULL OR l.expires_at <= $6)
+              AND ($5::TEXT[] IS NULL OR ma.name = ANY($5))
+              AND ($7::TEXT IS NULL OR ma.payload ->> $7 = ANY($8::TEXT[]))
+              AND NOT EXISTS (
+                  SELECT 1 FROM leases active
+                  WHERE active.message_id = ma.id AND active.expires_at >= $1
+              )
               AND NOT EXISTS (
                   SELECT 1 FROM attempts_succeeded s
                   WHERE s.message_id = ma.id
@@ -33,25 +100,61 @@ pub async fn get_next_missing<'tx, E: PgExecutor<'tx>>(
                 SELECT 1 FROM attempts_dead d
                 WHERE d.message_id = ma.id
               )
-            ORDER BY ma.published_at
+            ORDER BY
+                CASE WHEN $4 AND l.acquired_by = $2 THEN 0 ELSE 1 END ASC,
+                ma.seq ASC
             LIMIT 1
-            FOR UPDATE SKIP LOCKED
+            FOR UPDATE OF l, ma SKIP LOCKED
+        ),
+        taken AS (
+            UPDATE leases le
+            SET acquired_at = $1,
+                acquired_by = $2,
+                expires_at = $3,
+                fencing_token = nextval('lease_fencing_token_seq')
+            FROM candidate c
+            WHERE le.message_id = c.id AND le.expires_at = c.lost_expires_at
+            RETURNING c.id,
+                c.name,
+                c.hash,
+                c.payload,
+                c.seq,
+                c.attempted,
+                c.lost_by,
+                c.lost_acquired_at,
+                c.lost_expires_at,
+                le.fencing_token
+        ),
+        history AS (
+            INSERT INTO lease_history (
+                message_id,
+                host_id,
+                acquired_at,
+                expired_at,
+                taken_over_at,
+                taken_over_by
+            )
+            SELECT id, lost_by, lost_acquired_at, lost_expires_at, $1, $2
+            FROM taken
         )
-        UPDATE leases le
-        SET acquired_at = $1,
-            acquired_by = $2,
-            expires_at = $3
-        FROM candidate c
-        WHERE le.message_id = c.id
-        RETURNING c.id,
-            c.name,
-            c.hash,
-            c.payload,
-            0 "attempted!";
+        SELECT id,
+            name,
+            hash,
+            payload,
+            attempted "attempted!",
+            fencing_token "fencing_token?",
+            seq "seq?",
+            (SELECT qs.max_attempts FROM queue_settings qs WHERE qs.name = taken.name) "max_attempts?"
+        FROM taken;
         "#,
         now,
         host_id,
-        expires_at
+        expires_at,
+        sticky,
+        names,
+        suppressed_since,
+        predicate.map(ClaimPredicate::field),
+        predicate.map(ClaimPredicate::values) as Option<&[String]>
     )
     .fetch_optional(tx)
     .await?;
@@ -67,7 +170,15 @@ mod tests {
     use uuid::Uuid;
 
     use crate::{
-        queries::{get_next_missing::get_next_missing, get_next_unattempted, publish_message},
+        models::{Message, QueueSettings},
+        queries::{
+            get_next_missing::{
+                get_next_missing, get_next_missing_inner, get_next_missing_named,
+                get_next_missing_sticky,
+            },
+            get_next_retryable, get_next_unattempted, publish_message, put_queue_settings,
+            report_retryable, request_lease,
+        },
         testing_tools::{TestMessage, is_in_progress, is_missing},
     };
 
@@ -100,4 +211,174 @@ mod tests {
 
         Ok(())
     }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_suppresses_messages_that_recently_went_missing(
+        pool: sqlx::PgPool,
+    ) -> anyhow::Result<()> {
+        let now = Utc::now();
+        let host_id = Uuid::now_v7();
+        let hold_for = Duration::from_mins(1);
+        let window = Some(Duration::from_mins(5));
+
+        let published = publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+        get_next_unattempted(&pool, now, host_id, hold_for)
+            .await?
+            .expect("Expected a message");
+
+        let claim =
+            |at| get_next_missing_inner(&pool, at, host_id, hold_for, false, None, window, None);
+
+        // The lease expired a minute ago, within the window
+        let within = now + Duration::from_mins(2);
+        assert!(claim(within).await?.is_none());
+
+        let after = now + Duration::from_mins(7);
+        let claimed = claim(after).await?.expect("Expected a missing message");
+        assert_eq!(claimed.id, published.id);
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_recovers_messages_leased_before_their_claim(
+        pool: sqlx::PgPool,
+    ) -> anyhow::Result<()> {
+        let now = Utc::now();
+        let host_id = Uuid::now_v7();
+        let hold_for = Duration::from_secs(1);
+        let published = publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+
+        // A manual lease on the pending message expires before it is claimed
+        request_lease(&pool, published.id, now, host_id, hold_for)
+            .await?
+            .expect("Expected a lease");
+        let claimed_at = now + Duration::from_secs(2);
+        get_next_unattempted(&pool, claimed_at, host_id, hold_for)
+            .await?
+            .expect("Expected a message");
+
+        // The claim is lost, and the message recovered twice
+        let lost = claimed_at + Duration::from_secs(2);
+        let missing = get_next_missing(&pool, lost, host_id, hold_for)
+            .await?
+            .expect("Expected a missing message");
+        assert_eq!(missing.id, published.id);
+
+        let lost_again = lost + Duration::from_secs(2);
+        let missing = get_next_missing(&pool, lost_again, host_id, hold_for)
+            .await?
+            .expect("Expected a missing message");
+        assert_eq!(missing.id, published.id);
+
+        let leases: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM leases WHERE message_id = $1")
+            .bind(published.id)
+            .fetch_one(&pool)
+            .await?;
+        assert_eq!(leases, 1);
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_gets_missing_messages_with_the_given_names(
+        pool: sqlx::PgPool,
+    ) -> anyhow::Result<()> {
+        let now = Utc::now();
+        let host_id = Uuid::now_v7();
+        let hold_for = Duration::from_secs(1);
+        publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+        get_next_unattempted(&pool, now, host_id, hold_for)
+            .await?
+            .expect("Expected a message");
+
+        let lost = now + Duration::from_secs(2);
+        let other = vec!["other".to_string()];
+        assert!(
+            get_next_missing_named(&pool, lost, host_id, hold_for, &other)
+                .await?
+                .is_none()
+        );
+
+        let names = vec![TestMessage::NAME.to_string()];
+        let missing = get_next_missing_named(&pool, lost, host_id, hold_for, &names)
+            .await?
+            .expect("Expected a missing message");
+        assert_eq!(missing.name, TestMessage::NAME);
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_counts_lost_and_failed_attempts(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let now = Utc::now();
+        let host_id = Uuid::now_v7();
+        let hold_for = Duration::from_secs(1);
+        let settings = QueueSettings {
+            name: TestMessage::NAME.to_string(),
+            backoff_base: 2,
+            backoff_base_delay: Duration::ZERO,
+            max_attempts: 7,
+            hold_for,
+        };
+        put_queue_settings(&pool, &settings, now).await?;
+        publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+
+        let claimed = get_next_unattempted(&pool, now, host_id, hold_for)
+            .await?
+            .expect("Expected a message");
+        assert_eq!(claimed.attempted, 0);
+        assert_eq!(claimed.max_attempts, Some(7));
+
+        // The first attempt fails, the retry is lost twice
+        report_retryable(&pool, claimed.id, claimed.fencing_token, now, 1, now, "err").await?;
+        let retried = now + Duration::from_secs(1);
+        get_next_retryable(&pool, retried, host_id, hold_for)
+            .await?
+            .expect("Expected a retryable message");
+
+        let lost = retried + Duration::from_secs(2);
+        let missing = get_next_missing(&pool, lost, host_id, hold_for)
+            .await?
+            .expect("Expected a missing message");
+        assert_eq!(missing.attempted, 2);
+        assert_eq!(missing.max_attempts, Some(7));
+
+        let lost_again = lost + Duration::from_secs(2);
+        let missing = get_next_missing(&pool, lost_again, host_id, hold_for)
+            .await?
+            .expect("Expected a missing message");
+        assert_eq!(missing.attempted, 3);
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_prefers_messages_lost_by_the_same_host(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let now = Utc::now();
+        let hold_for = Duration::from_secs(10);
+        let hosts = [Uuid::now_v7(), Uuid::now_v7()];
+
+        let mut lost = Vec::new();
+        for host_id in hosts {
+            publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+            let message = get_next_unattempted(&pool, now, host_id, hold_for)
+                .await?
+                .expect("Expected a message");
+            lost.push(message.id);
+        }
+
+        let later = now + Duration::from_mins(1);
+        let claimed = get_next_missing_sticky(&pool, later, hosts[1], hold_for)
+            .await?
+            .expect("Expected a message");
+        assert_eq!(claimed.id, lost[1]);
+
+        let claimed = get_next_missing_sticky(&pool, later, hosts[1], hold_for)
+            .await?
+            .expect("Expected a message");
+        assert_eq!(claimed.id, lost[0]);
+
+        Ok(())
+    }
 }
@@ -1,16 +1,51 @@
-use crate::models::RawMessage;
+use crate::models::{ClaimPredicate, RawMessage, RetryOrder};
 use chrono::{DateTime, Utc};
 use sqlx::PgExecutor;
 use std::time::Duration;
 use uuid::Uuid;
 
+/// Claims the retryable message that failed the longest ago and is past its earliest retry time.
+///
+/// Succeeded and dead messages are skipped even with a failure recorded, e.g. one reported without a fencing token
+/// after another attempt of the message succeeded.
+///
+/// Messages whose name has reached its limit of in progress retries are skipped, see
+/// [`set_retry_concurrency_limit`](super::set_retry_concurrency_limit).
 pub async fn get_next_retryable<'tx, E: PgExecutor<'tx>>(
     tx: E,
     now: DateTime<Utc>,
     host_id: Uuid,
     hold_for: Duration,
+) -> Result<Option<RawMessage>, sqlx::Error> {
+    get_next_retryable_ordered(
+        tx,
+        now,
+        host_id,
+        hold_for,
+        RetryOrder::FailedAt,
+        false,
+        None,
+    )
+    .await
+}
+
+/// Claims the next retryable message past its earliest retry time, in the given order, see [`get_next_retryable`].
+///
+/// With `sticky`, messages whose last failed attempt was made by `host_id` are claimed first, so that a host
+/// retries its own failures while its caches are warm. Other messages are claimed when it has none.
+///
+/// With a `predicate`, only messages whose payload matches it are claimed.
+pub async fn get_next_retryable_ordered<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    now: DateTime<Utc>,
+    host_id: Uuid,
+    hold_for: Duration,
+    order: RetryOrder,
+    sticky: bool,
+    predicate: Option<&ClaimPredicate>,
 ) -> Result<Option<RawMessage>, sqlx::Error> {
     let expires_at = now + hold_for;
+    let by_retry_earliest_at = order == RetryOrder::RetryEarliestAt;
 
     let message = sqlx::query_as!(
         RawMessage,
@@ -25,42 +60,54 @@ pub async fn get_next_retryable<'tx, E: PgExecutor<'tx>>(
                   SELECT 1 FROM leases l
                   WHERE l.message_id = fa.message_id AND l.expires_at > $1
               )
+              -- Finished messages are never claimed again, e.g. when a failure raced their success
+              AND NOT EXISTS (
+                  SELECT 1 FROM attempts_succeeded s
+                  WHERE s.message_id = fa.message_id
+              )
+              AND NOT EXISTS (
+                  SELECT 1 FROM attempts_dead d
+                  WHERE d.message_id = fa.message_id
+              )
               AND fa.failed_at = (
                   SELECT MAX(fa2.failed_at)
                   FROM attempts_failed fa2
                   WHERE fa2.message_id = fa.message_id
               )
-            ORDER BY fa.failed_at ASC, fa.message_id ASC
+              AND EXISTS (
+                  SELECT 1 FROM messages_attempted ma
+                  WHERE ma.id = fa.message_id
+                    AND ($6::TEXT IS NULL OR ma.payload ->> $6 = ANY($7::TEXT[]))
+              )
+              AND retry_slot_available(
+                  (SELECT ma.name FROM messages_attempted ma WHERE ma.id = fa.message_id),
+                  $1
+              )
+            ORDER BY
+                CASE WHEN $5 AND fa.attempted_by = $2 THEN 0 ELSE 1 END ASC,
+                CASE WHEN $4 THEN fa.retry_earliest_at ELSE fa.failed_at END ASC,
+                fa.message_id ASC
             LIMIT 1
             FOR UPDATE SKIP LOCKED
-        ),
-        leased AS (
-            INSERT INTO leases (
-                message_id,
-                acquired_at,
-                acquired_by,
-                expires_at
-                )
-            SELECT
-                nr.message_id,
-                $1,
-                $2,
-                $3
-            FROM next_retryable nr
-            RETURNING message_id
         )
         SELECT
-            id,
-            name,
-            hash,
-            payload,
-            (select attempted from next_retryable) "attempted!:i32"
-        FROM messages_attempted
-        WHERE id = (SELECT message_id FROM leased);
+            c.id "id!",
+            c.name "name!",
+            c.hash "hash!",
+            c.payload "payload!",
+            c.attempted "attempted!",
+            c.fencing_token "fencing_token?",
+            c.seq "seq?",
+            c.max_attempts "max_attempts?"
+        FROM next_retryable nr, LATERAL claim_attempted(nr.message_id, nr.attempted, $1, $2, $3) c;
         "#,
         now,
         host_id,
-        expires_at
+        expires_at,
+        by_retry_earliest_at,
+        sticky,
+        predicate.map(ClaimPredicate::field),
+        predicate.map(ClaimPredicate::values) as Option<&[String]>
     )
     .fetch_optional(tx)
     .await?;
@@ -73,7 +120,9 @@ mod tests {
     use super::*;
     use crate::{
         backoff::ConstantBackoff,
-        queries::{get_next_unattempted, publish_message, report_retryable},
+        queries::{
+            get_next_unattempted, publish_message, report_dead, report_retryable, report_success,
+        },
         testing_tools::{TestMessage, is_in_progress},
     };
 
@@ -96,6 +145,7 @@ mod tests {
         report_retryable(
             &pool,
             published.id,
+            None,
             now,
             1,
             try_earliest_at,
@@ -132,6 +182,7 @@ mod tests {
         report_retryable(
             &pool,
             published.id,
+            None,
             now,
             1,
             try_earliest_at,
@@ -169,6 +220,7 @@ mod tests {
         report_retryable(
             &pool,
             published.id,
+            None,
             now,
             1,
             try_earliest_at,
@@ -220,4 +272,177 @@ mod tests {
         // We must test that we select the previous failure attempt, as we use attempted to incremented the count
         todo!()
     }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_orders_by_retry_earliest_at(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let now = Utc::now();
+        let host_id = Uuid::now_v7();
+        let hold_for = Duration::from_mins(1);
+
+        // The first failure is retryable last, the second one first
+        let mut failed = Vec::new();
+        for retry_in in [Duration::from_secs(2), Duration::from_secs(1)] {
+            publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+            let message = get_next_unattempted(&pool, now, host_id, hold_for)
+                .await?
+                .expect("Expected a message");
+            failed.push(message.id);
+            report_retryable(&pool, message.id, None, now, 1, now + retry_in, "err").await?;
+        }
+
+        let later = now + Duration::from_secs(3);
+        let claimed = get_next_retryable_ordered(
+            &pool,
+            later,
+            host_id,
+            hold_for,
+            RetryOrder::RetryEarliestAt,
+            false,
+            None,
+        )
+        .await?
+        .expect("Expected a message");
+        assert_eq!(claimed.id, failed[1]);
+
+        let claimed = get_next_retryable_ordered(
+            &pool,
+            later,
+            host_id,
+            hold_for,
+            RetryOrder::FailedAt,
+            false,
+            None,
+        )
+        .await?
+        .expect("Expected a message");
+        assert_eq!(claimed.id, failed[0]);
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_skips_finished_messages(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let now = Utc::now();
+        let host_id = Uuid::now_v7();
+        let hold_for = Duration::from_mins(1);
+
+        let mut finished = Vec::new();
+        for _ in 0..2 {
+            publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+            let message = get_next_unattempted(&pool, now, host_id, hold_for)
+                .await?
+                .expect("Expected a message");
+            finished.push(message.id);
+        }
+        report_success(&pool, finished[0], None, now).await?;
+        report_dead(&pool, finished[1], None, now, "err").await?;
+
+        // Failures recorded after the messages finished, e.g. by reports that raced them. Reports reject them as
+        // invalid transitions, so they are inserted directly
+        for message_id in &finished {
+            sqlx::query(
+                "INSERT INTO attempts_failed (id, message_id, failed_at, attempted, retry_earliest_at) \
+                 VALUES ($1, $2, $3, 1, $3)",
+            )
+            .bind(Uuid::now_v7())
+            .bind(message_id)
+            .bind(now)
+            .execute(&pool)
+            .await?;
+        }
+
+        let later = now + Duration::from_hours(1);
+        let claimed = get_next_retryable(&pool, later, host_id, hold_for).await?;
+        assert!(claimed.is_none());
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_prefers_failures_of_the_same_host(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let now = Utc::now();
+        let hold_for = Duration::from_mins(1);
+        let hosts = [Uuid::now_v7(), Uuid::now_v7()];
+
+        let mut failed = Vec::new();
+        for (i, host_id) in hosts.iter().enumerate() {
+            publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+            let message = get_next_unattempted(&pool, now, *host_id, hold_for)
+                .await?
+                .expect("Expected a message");
+            let failed_at = now + Duration::from_secs(i as u64);
+            report_retryable(
+                &pool,
+                message.id,
+                message.fencing_token,
+                failed_at,
+                1,
+                failed_at,
+                "err",
+            )
+            .await?;
+            failed.push(message.id);
+        }
+
+        let later = now + Duration::from_secs(5);
+        let claim = |sticky| {
+            get_next_retryable_ordered(
+                &pool,
+                later,
+                hosts[1],
+                hold_for,
+                RetryOrder::FailedAt,
+                sticky,
+                None,
+            )
+        };
+
+        // The failure of the other host is older, but the own failure is preferred
+        let claimed = claim(true).await?.expect("Expected a message");
+        assert_eq!(claimed.id, failed[1]);
+
+        // Falls back to any message
+        let claimed = claim(true).await?.expect("Expected a message");
+        assert_eq!(claimed.id, failed[0]);
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_claims_matching_retryable_payloads_only(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let now = Utc::now();
+        let host_id = Uuid::now_v7();
+        let hold_for = Duration::from_mins(1);
+
+        for region in ["us", "eu"] {
+            let raw = RawMessage {
+                payload: serde_json::json!({ "region": region }),
+                ..TestMessage::default().to_raw()?
+            };
+            publish_message(&pool, &raw).await?;
+            let claimed = get_next_unattempted(&pool, now, host_id, hold_for)
+                .await?
+                .expect("Expected a message");
+            report_retryable(&pool, claimed.id, None, now, 1, now, "err").await?;
+        }
+
+        let predicate = ClaimPredicate::field_equals("region", "eu");
+        let claim = || {
+            get_next_retryable_ordered(
+                &pool,
+                now,
+                host_id,
+                hold_for,
+                RetryOrder::FailedAt,
+                false,
+                Some(&predicate),
+            )
+        };
+
+        let claimed = claim().await?.expect("Expected a message");
+        assert_eq!(claimed.payload, serde_json::json!({ "region": "eu" }));
+        assert!(claim().await?.is_none());
+
+        Ok(())
+    }
 }
@@ -0,0 +1,138 @@
+use crate::models::{ClaimPredicate, RawMessage};
+use chrono::{DateTime, Utc};
+use sqlx::PgExecutor;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Claims the retryable message with one of the given names, see [`get_next_retryable`](super::get_next_retryable).
+pub async fn get_next_retryable_named<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    now: DateTime<Utc>,
+    host_id: Uuid,
+    hold_for: Duration,
+    names: &[String],
+) -> Result<Option<RawMessage>, sqlx::Error> {
+    get_next_retryable_named_inner(tx, now, host_id, hold_for, names, None).await
+}
+
+/// Claims like [`get_next_retryable_named`].
+///
+/// With a `predicate`, only messages whose payload matches it are claimed.
+pub(super) async fn get_next_retryable_named_inner<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    now: DateTime<Utc>,
+    host_id: Uuid,
+    hold_for: Duration,
+    names: &[String],
+    predicate: Option<&ClaimPredicate>,
+) -> Result<Option<RawMessage>, sqlx::Error> {
+    let expires_at = now + hold_for;
+
+    let message = sqlx::query_as!(
+        RawMessage,
+        r#"
+        WITH next_retryable AS (
+            SELECT
+                fa.message_id,
+                fa.attempted
+            FROM attempts_failed fa
+            WHERE fa.retry_earliest_at <= $1
+              AND NOT EXISTS (
+                  SELECT 1 FROM leases l
+                  WHERE l.message_id = fa.message_id AND l.expires_at > $1
+              )
+              -- Finished messages are never claimed again, e.g. when a failure raced their success
+              AND NOT EXISTS (
+                  SELECT 1 FROM attempts_succeeded s
+                  WHERE s.message_id = fa.message_id
+              )
+              AND NOT EXISTS (
+                  SELECT 1 FROM attempts_dead d
+                  WHERE d.message_id = fa.message_id
+              )
+              AND fa.failed_at = (
+                  SELECT MAX(fa2.failed_at)
+                  FROM attempts_failed fa2
+                  WHERE fa2.message_id = fa.message_id
+              )
+              AND EXISTS (
+                  SELECT 1 FROM messages_attempted ma
+                  WHERE ma.id = fa.message_id AND ma.name = ANY($4)
+                    AND ($5::TEXT IS NULL OR ma.payload ->> $5 = ANY($6::TEXT[]))
+              )
+              AND retry_slot_available(
+                  (SELECT ma.name FROM messages_attempted ma WHERE ma.id = fa.message_id),
+                  $1
+              )
+            ORDER BY fa.failed_at ASC, fa.message_id ASC
+            LIMIT 1
+            FOR UPDATE SKIP LOCKED
+        )
+        SELECT
+            c.id "id!",
+            c.name "name!",
+            c.hash "hash!",
+            c.payload "payload!",
+            c.attempted "attempted!",
+            c.fencing_token "fencing_token?",
+            c.seq "seq?",
+            c.max_attempts "max_attempts?"
+        FROM next_retryable nr, LATERAL claim_attempted(nr.message_id, nr.attempted, $1, $2, $3) c;
+        "#,
+        now,
+        host_id,
+        expires_at,
+        names,
+        predicate.map(ClaimPredicate::field),
+        predicate.map(ClaimPredicate::values) as Option<&[String]>
+    )
+    .fetch_optional(tx)
+    .await?;
+
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        queries::{get_next_unattempted, publish_message, report_retryable},
+        testing_tools::TestMessage,
+    };
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_claims_retryable_messages_with_the_given_names(
+        pool: sqlx::PgPool,
+    ) -> anyhow::Result<()> {
+        let now = Utc::now();
+        let host_id = Uuid::now_v7();
+        let hold_for = Duration::from_mins(1);
+
+        for name in ["invoice", "receipt"] {
+            let raw = RawMessage {
+                name: name.to_string(),
+                ..TestMessage::default().to_raw()?
+            };
+            publish_message(&pool, &raw).await?;
+            let claimed = get_next_unattempted(&pool, now, host_id, hold_for)
+                .await?
+                .expect("Expected a message");
+            report_retryable(&pool, claimed.id, None, now, 1, now, "err").await?;
+        }
+
+        let names = vec!["receipt".to_string()];
+        let claimed = get_next_retryable_named(&pool, now, host_id, hold_for, &names)
+            .await?
+            .expect("Expected a message");
+        assert_eq!(claimed.name, "receipt");
+        assert_eq!(claimed.attempted, 1);
+
+        assert!(
+            get_next_retryable_named(&pool, now, host_id, hold_for, &names)
+                .await?
+                .is_none()
+        );
+
+        Ok(())
+    }
+}
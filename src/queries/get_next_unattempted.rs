@@ -1,4 +1,4 @@
-use crate::models::RawMessage;
+use crate::models::{ClaimPredicate, RawMessage};
 use chrono::{DateTime, Utc};
 use sqlx::PgExecutor;
 use std::time::Duration;
@@ -9,6 +9,19 @@ pub async fn get_next_unattempted<'tx, E: PgExecutor<'tx>>(
     now: DateTime<Utc>,
     host_id: Uuid,
     hold_for: Duration,
+) -> Result<Option<RawMessage>, sqlx::Error> {
+    get_next_unattempted_inner(tx, now, host_id, hold_for, None).await
+}
+
+/// Claims the oldest unattempted message, see [`get_next_unattempted`].
+///
+/// With a `predicate`, only messages whose payload matches it are claimed.
+pub(super) async fn get_next_unattempted_inner<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    now: DateTime<Utc>,
+    host_id: Uuid,
+    hold_for: Duration,
+    predicate: Option<&ClaimPredicate>,
 ) -> Result<Option<RawMessage>, sqlx::Error> {
     let expires_at = now + hold_for;
 
@@ -16,60 +29,114 @@ pub async fn get_next_unattempted<'tx, E: PgExecutor<'tx>>(
         RawMessage,
         r#"
         WITH next_message AS (
-            DELETE FROM messages_unattempted
-            WHERE id = (
+            SELECT id
+            FROM messages_unattempted
+            WHERE ($4::TEXT IS NULL OR payload ->> $4 = ANY($5::TEXT[]))
+              AND NOT EXISTS (
+                  SELECT 1 FROM leases l
+                  WHERE l.message_id = messages_unattempted.id AND l.expires_at > $1
+              )
+            ORDER BY seq ASC
+            FOR UPDATE SKIP LOCKED
+            LIMIT 1
+        )
+        SELECT
+            c.id "id!",
+            c.name "name!",
+            c.hash "hash!",
+            c.payload "payload!",
+            c.attempted "attempted!",
+            c.fencing_token "fencing_token?",
+            c.seq "seq?",
+            c.max_attempts "max_attempts?"
+        FROM next_message n, LATERAL claim_unattempted(n.id, $1, $2, $3) c;
+        "#,
+        now,
+        host_id,
+        expires_at,
+        predicate.map(ClaimPredicate::field),
+        predicate.map(ClaimPredicate::values) as Option<&[String]>
+    )
+    .fetch_optional(tx)
+    .await?;
+
+    Ok(message)
+}
+
+/// Claims a random message among the `window` oldest unattempted messages.
+///
+/// With many workers claiming concurrently, claims of the oldest message contend for the same row lock. Spreading
+/// claims over a small window at the head reduces that contention while keeping the order approximately FIFO.
+/// If every message in the window is locked by other claims, the oldest message past them is claimed.
+///
+/// With a `predicate`, only messages whose payload matches it are claimed.
+pub async fn get_next_unattempted_windowed<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    now: DateTime<Utc>,
+    host_id: Uuid,
+    hold_for: Duration,
+    window: u16,
+    predicate: Option<&ClaimPredicate>,
+) -> Result<Option<RawMessage>, sqlx::Error> {
+    let expires_at = now + hold_for;
+
+    let message = sqlx::query_as!(
+        RawMessage,
+        r#"
+        WITH windowed AS (
+            SELECT mu.id
+            FROM messages_unattempted mu
+            WHERE mu.id IN (
                 SELECT id
                 FROM messages_unattempted
-                ORDER BY published_at ASC, id ASC
-                FOR UPDATE SKIP LOCKED
-                LIMIT 1
+                WHERE ($5::TEXT IS NULL OR payload ->> $5 = ANY($6::TEXT[]))
+                  AND NOT EXISTS (
+                      SELECT 1 FROM leases l
+                      WHERE l.message_id = messages_unattempted.id AND l.expires_at > $1
+                  )
+                ORDER BY seq ASC
+                LIMIT $4
             )
-            RETURNING *
+            ORDER BY random()
+            FOR UPDATE SKIP LOCKED
+            LIMIT 1
         ),
-        leased AS (
-            INSERT INTO leases (
-                message_id,
-                acquired_at,
-                acquired_by,
-                expires_at
+        -- Only scans when every message in the window is locked, claiming past it
+        fallback AS (
+            SELECT id
+            FROM messages_unattempted
+            WHERE NOT EXISTS (SELECT 1 FROM windowed)
+            AND ($5::TEXT IS NULL OR payload ->> $5 = ANY($6::TEXT[]))
+            AND NOT EXISTS (
+                SELECT 1 FROM leases l
+                WHERE l.message_id = messages_unattempted.id AND l.expires_at > $1
             )
-            SELECT id, $1, $2, $3
-            FROM next_message
-            RETURNING message_id
+            ORDER BY seq ASC
+            FOR UPDATE SKIP LOCKED
+            LIMIT 1
         ),
-        attempted AS (
-            INSERT INTO messages_attempted (
-                id,
-                name,
-                hash,
-                payload,
-                published_at
-            )
-            SELECT
-                id,
-                name,
-                hash,
-                payload,
-                published_at
-            FROM next_message
-            RETURNING
-                id,
-                name,
-                hash,
-                payload,
-                published_at
+        next_message AS (
+            SELECT id FROM windowed
+            UNION ALL
+            SELECT id FROM fallback
         )
         SELECT
-            id,
-            name,
-            hash,
-            payload,
-            0 "attempted!:i32"
-        FROM attempted;
+            c.id "id!",
+            c.name "name!",
+            c.hash "hash!",
+            c.payload "payload!",
+            c.attempted "attempted!",
+            c.fencing_token "fencing_token?",
+            c.seq "seq?",
+            c.max_attempts "max_attempts?"
+        FROM next_message n, LATERAL claim_unattempted(n.id, $1, $2, $3) c;
         "#,
         now,
         host_id,
-        expires_at
+        expires_at,
+        i64::from(window.max(1)),
+        predicate.map(ClaimPredicate::field),
+        predicate.map(ClaimPredicate::values) as Option<&[String]>
     )
     .fetch_optional(tx)
     .await?;
@@ -81,7 +148,7 @@ pub async fn get_next_unattempted<'tx, E: PgExecutor<'tx>>(
 mod tests {
     use super::*;
     use crate::models::Message;
-    use crate::queries::publish_message;
+    use crate::queries::{publish_many_messages_with_notify, publish_message};
     use crate::testing_tools::{TestMessage, is_in_progress};
     use serde_json::json;
 
@@ -170,4 +237,131 @@ mod tests {
 
         Ok(())
     }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_claims_within_the_window(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let now = Utc::now();
+        let host_id = Uuid::now_v7();
+        let hold_for = Duration::from_mins(1);
+
+        let mut published = Vec::new();
+        for _ in 0..8 {
+            published.push(
+                publish_message(&pool, &TestMessage::default().to_raw()?)
+                    .await?
+                    .id,
+            );
+        }
+
+        let mut remaining = published;
+        while !remaining.is_empty() {
+            let message = get_next_unattempted_windowed(&pool, now, host_id, hold_for, 4, None)
+                .await?
+                .expect("Expected a message");
+
+            // Each claim is among the 4 oldest remaining messages
+            let position = remaining
+                .iter()
+                .position(|id| *id == message.id)
+                .expect("Expected a remaining message");
+            assert!(position < 4);
+            remaining.remove(position);
+        }
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_claims_past_a_locked_window(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let now = Utc::now();
+        let hold_for = Duration::from_mins(1);
+
+        let mut published = Vec::new();
+        for _ in 0..3 {
+            published.push(
+                publish_message(&pool, &TestMessage::default().to_raw()?)
+                    .await?
+                    .id,
+            );
+        }
+
+        let mut locking = pool.begin().await?;
+        sqlx::query("SELECT id FROM messages_unattempted WHERE id = ANY($1) FOR UPDATE")
+            .bind(&published[..2])
+            .execute(&mut *locking)
+            .await?;
+
+        let claimed = get_next_unattempted_windowed(&pool, now, Uuid::now_v7(), hold_for, 2, None)
+            .await?
+            .expect("Expected a message");
+        assert_eq!(claimed.id, published[2]);
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_claims_in_publish_sequence(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        // Published at the same instant, with ids sorting opposite to the order they were published in
+        let messages = [
+            RawMessage {
+                id: Uuid::max(),
+                ..TestMessage::default().to_raw()?
+            },
+            RawMessage {
+                id: Uuid::nil(),
+                ..TestMessage::default().to_raw()?
+            },
+        ];
+        let mut tx = pool.begin().await?;
+        let published = publish_many_messages_with_notify(&mut tx, &messages, "test").await?;
+        tx.commit().await?;
+        assert!(published[0].seq < published[1].seq);
+
+        let now = Utc::now();
+        let host_id = Uuid::now_v7();
+        for expected in &published {
+            let claimed = get_next_unattempted(&pool, now, host_id, Duration::from_mins(1))
+                .await?
+                .expect("Expected a message");
+            assert_eq!(claimed.id, expected.id);
+            assert_eq!(claimed.seq, expected.seq);
+        }
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_claims_matching_payloads_only(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let host_id = Uuid::now_v7();
+        let hold_for = Duration::from_mins(1);
+
+        let mut published = Vec::new();
+        for region in ["us", "eu"] {
+            let raw = RawMessage {
+                payload: json!({ "region": region, "body": "hello" }),
+                ..TestMessage::default().to_raw()?
+            };
+            published.push(publish_message(&pool, &raw).await?);
+        }
+
+        let now = Utc::now();
+        let predicate = ClaimPredicate::field_equals("region", "eu");
+        let claimed = get_next_unattempted_inner(&pool, now, host_id, hold_for, Some(&predicate))
+            .await?
+            .expect("Expected a message");
+        assert_eq!(claimed.id, published[1].id);
+        assert!(
+            get_next_unattempted_inner(&pool, now, host_id, hold_for, Some(&predicate))
+                .await?
+                .is_none()
+        );
+
+        let predicate = predicate.or_equals("us");
+        let claimed = get_next_unattempted_inner(&pool, now, host_id, hold_for, Some(&predicate))
+            .await?
+            .expect("Expected a message");
+        assert_eq!(claimed.id, published[0].id);
+
+        Ok(())
+    }
 }
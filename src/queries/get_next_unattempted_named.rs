@@ -0,0 +1,106 @@
+use crate::models::{ClaimPredicate, RawMessage};
+use chrono::{DateTime, Utc};
+use sqlx::PgExecutor;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Claims the oldest unattempted message with one of the given names, see [`get_next_unattempted`](super::get_next_unattempted).
+///
+/// Useful for workers that only handle some messages, such as bridges to other messaging systems.
+pub async fn get_next_unattempted_named<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    now: DateTime<Utc>,
+    host_id: Uuid,
+    hold_for: Duration,
+    names: &[String],
+) -> Result<Option<RawMessage>, sqlx::Error> {
+    get_next_unattempted_named_inner(tx, now, host_id, hold_for, names, None).await
+}
+
+/// Claims like [`get_next_unattempted_named`].
+///
+/// With a `predicate`, only messages whose payload matches it are claimed.
+pub(super) async fn get_next_unattempted_named_inner<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    now: DateTime<Utc>,
+    host_id: Uuid,
+    hold_for: Duration,
+    names: &[String],
+    predicate: Option<&ClaimPredicate>,
+) -> Result<Option<RawMessage>, sqlx::Error> {
+    let expires_at = now + hold_for;
+
+    let message = sqlx::query_as!(
+        RawMessage,
+        r#"
+        WITH next_message AS (
+            SELECT id
+            FROM messages_unattempted
+            WHERE name = ANY($4)
+              AND ($5::TEXT IS NULL OR payload ->> $5 = ANY($6::TEXT[]))
+              AND NOT EXISTS (
+                  SELECT 1 FROM leases l
+                  WHERE l.message_id = messages_unattempted.id AND l.expires_at > $1
+              )
+            ORDER BY seq ASC
+            FOR UPDATE SKIP LOCKED
+            LIMIT 1
+        )
+        SELECT
+            c.id "id!",
+            c.name "name!",
+            c.hash "hash!",
+            c.payload "payload!",
+            c.attempted "attempted!",
+            c.fencing_token "fencing_token?",
+            c.seq "seq?",
+            c.max_attempts "max_attempts?"
+        FROM next_message n, LATERAL claim_unattempted(n.id, $1, $2, $3) c;
+        "#,
+        now,
+        host_id,
+        expires_at,
+        names,
+        predicate.map(ClaimPredicate::field),
+        predicate.map(ClaimPredicate::values) as Option<&[String]>
+    )
+    .fetch_optional(tx)
+    .await?;
+
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Message;
+    use crate::queries::publish_message;
+    use crate::testing_tools::TestMessage;
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_only_claims_messages_with_the_given_names(
+        pool: sqlx::PgPool,
+    ) -> anyhow::Result<()> {
+        let other = RawMessage {
+            name: "Other".to_string(),
+            ..TestMessage::default().to_raw()?
+        };
+        publish_message(&pool, &other).await?;
+        let published = publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+
+        let now = Utc::now();
+        let host_id = Uuid::now_v7();
+        let hold_for = Duration::from_mins(1);
+        let names = [TestMessage::NAME.to_string()];
+
+        let claimed = get_next_unattempted_named(&pool, now, host_id, hold_for, &names)
+            .await?
+            .expect("Expected a message to be returned");
+        assert_eq!(claimed.id, published.id);
+
+        let claimed = get_next_unattempted_named(&pool, now, host_id, hold_for, &names).await?;
+        assert!(claimed.is_none());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,112 @@
+use crate::models::{ClaimPredicate, RawMessage};
+use chrono::{DateTime, Utc};
+use sqlx::PgExecutor;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Claims the next unattempted message like [`get_next_unattempted`](super::get_next_unattempted), but returns only
+/// the top-level payload `fields` that are present, keeping large payloads off the wire.
+///
+/// Useful for workers that route or filter on a few fields. The full payload is retained and can be fetched with
+/// [`get_payload`](super::get_payload) when it is needed.
+pub async fn get_next_unattempted_projected<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    now: DateTime<Utc>,
+    host_id: Uuid,
+    hold_for: Duration,
+    fields: &[String],
+) -> Result<Option<RawMessage>, sqlx::Error> {
+    get_next_unattempted_projected_inner(tx, now, host_id, hold_for, fields, None).await
+}
+
+/// Claims like [`get_next_unattempted_projected`].
+///
+/// With a `predicate`, only messages whose payload matches it are claimed.
+pub(super) async fn get_next_unattempted_projected_inner<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    now: DateTime<Utc>,
+    host_id: Uuid,
+    hold_for: Duration,
+    fields: &[String],
+    predicate: Option<&ClaimPredicate>,
+) -> Result<Option<RawMessage>, sqlx::Error> {
+    let expires_at = now + hold_for;
+
+    let message = sqlx::query_as!(
+        RawMessage,
+        r#"
+        WITH next_message AS (
+            SELECT id
+            FROM messages_unattempted
+            WHERE ($5::TEXT IS NULL OR payload ->> $5 = ANY($6::TEXT[]))
+              AND NOT EXISTS (
+                  SELECT 1 FROM leases l
+                  WHERE l.message_id = messages_unattempted.id AND l.expires_at > $1
+              )
+            ORDER BY seq ASC
+            FOR UPDATE SKIP LOCKED
+            LIMIT 1
+        )
+        SELECT
+            c.id "id!",
+            c.name "name!",
+            c.hash "hash!",
+            COALESCE(
+                (SELECT jsonb_object_agg(key, value) FROM jsonb_each(c.payload) WHERE key = ANY($4)),
+                '{}'::JSONB
+            ) "payload!",
+            c.attempted "attempted!",
+            c.fencing_token "fencing_token?",
+            c.seq "seq?",
+            c.max_attempts "max_attempts?"
+        FROM next_message n, LATERAL claim_unattempted(n.id, $1, $2, $3) c;
+        "#,
+        now,
+        host_id,
+        expires_at,
+        fields,
+        predicate.map(ClaimPredicate::field),
+        predicate.map(ClaimPredicate::values) as Option<&[String]>
+    )
+    .fetch_optional(tx)
+    .await?;
+
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queries::{get_payload, publish_message};
+    use crate::testing_tools::TestMessage;
+    use serde_json::json;
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_returns_only_the_selected_fields(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let message = TestMessage {
+            message: "a large body".to_string(),
+            value: 42,
+        };
+        let published = publish_message(&pool, &message.to_raw()?).await?;
+
+        let now = Utc::now();
+        let fields = ["value".to_string(), "missing".to_string()];
+        let claimed = get_next_unattempted_projected(
+            &pool,
+            now,
+            Uuid::now_v7(),
+            Duration::from_mins(1),
+            &fields,
+        )
+        .await?
+        .expect("Expected a message to be returned");
+
+        assert_eq!(claimed.id, published.id);
+        assert_eq!(claimed.payload, json!({ "value": 42 }));
+
+        let payload = get_payload(&pool, claimed.id).await?;
+        assert_eq!(payload, Some(published.payload));
+
+        Ok(())
+    }
+}
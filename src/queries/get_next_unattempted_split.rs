@@ -0,0 +1,151 @@
+use crate::models::{ClaimPredicate, RawMessage};
+use chrono::{DateTime, Utc};
+use sqlx::PgTransaction;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Claims the oldest unattempted message like [`get_next_unattempted`](super::get_next_unattempted), in two
+/// statements of the transaction rather than one.
+///
+/// The single statement claim selects the message and claims it with the `claim_unattempted` SQL function in one
+/// statement, relying on the planner to lock the selected message before calling the function for it. This claim
+/// locks the message in one statement and claims it in the next. It takes an extra round trip, and is meant as a
+/// fallback should a planner change break the single statement claim.
+///
+/// With a `predicate`, only messages whose payload matches it are claimed.
+pub async fn get_next_unattempted_split(
+    tx: &mut PgTransaction<'_>,
+    now: DateTime<Utc>,
+    host_id: Uuid,
+    hold_for: Duration,
+    predicate: Option<&ClaimPredicate>,
+) -> Result<Option<RawMessage>, sqlx::Error> {
+    let expires_at = now + hold_for;
+
+    let taken = sqlx::query_scalar!(
+        r#"
+        SELECT id
+        FROM messages_unattempted
+        WHERE ($2::TEXT IS NULL OR payload ->> $2 = ANY($3::TEXT[]))
+          AND NOT EXISTS (
+              SELECT 1 FROM leases l
+              WHERE l.message_id = messages_unattempted.id AND l.expires_at > $1
+          )
+        ORDER BY seq ASC
+        FOR UPDATE SKIP LOCKED
+        LIMIT 1
+        "#,
+        now,
+        predicate.map(ClaimPredicate::field),
+        predicate.map(ClaimPredicate::values) as Option<&[String]>
+    )
+    .fetch_optional(&mut **tx)
+    .await?;
+
+    let Some(message_id) = taken else {
+        return Ok(None);
+    };
+
+    let message = sqlx::query_as!(
+        RawMessage,
+        r#"
+        SELECT
+            id "id!",
+            name "name!",
+            hash "hash!",
+            payload "payload!",
+            attempted "attempted!",
+            fencing_token "fencing_token?",
+            seq "seq?",
+            max_attempts "max_attempts?"
+        FROM claim_unattempted($1, $2, $3, $4);
+        "#,
+        message_id,
+        now,
+        host_id,
+        expires_at
+    )
+    .fetch_optional(&mut **tx)
+    .await?;
+
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queries::{get_next_unattempted, publish_message};
+    use crate::testing_tools::{TestMessage, is_in_progress};
+    use std::collections::HashSet;
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_claims_like_the_single_statement_claim(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let first = publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+        publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+
+        let now = Utc::now();
+        let mut tx = pool.begin().await?;
+        let claimed =
+            get_next_unattempted_split(&mut tx, now, Uuid::now_v7(), Duration::from_mins(1), None)
+                .await?
+                .expect("Expected a message");
+        tx.commit().await?;
+
+        assert_eq!(claimed.id, first.id);
+        assert_eq!(claimed.seq, first.seq);
+        assert!(claimed.fencing_token.is_some());
+        assert!(is_in_progress(&pool, claimed.id, now).await?);
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_claims_each_message_once_alongside_single_statement_claims(
+        pool: sqlx::PgPool,
+    ) -> anyhow::Result<()> {
+        const MESSAGES: usize = 100;
+        for _ in 0..MESSAGES {
+            publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+        }
+
+        let mut claimers = tokio::task::JoinSet::new();
+        for i in 0..8 {
+            let pool = pool.clone();
+            claimers.spawn(async move {
+                let host_id = Uuid::now_v7();
+                let hold_for = Duration::from_mins(1);
+                let mut claimed = Vec::new();
+                loop {
+                    let mut tx = pool.begin().await?;
+                    let message = if i % 2 == 0 {
+                        get_next_unattempted_split(&mut tx, Utc::now(), host_id, hold_for, None)
+                            .await?
+                    } else {
+                        get_next_unattempted(&mut *tx, Utc::now(), host_id, hold_for).await?
+                    };
+                    tx.commit().await?;
+                    match message {
+                        Some(message) => claimed.push(message.id),
+                        None => return Ok::<_, sqlx::Error>(claimed),
+                    }
+                }
+            });
+        }
+
+        let mut claimed = Vec::new();
+        while let Some(result) = claimers.join_next().await {
+            claimed.extend(result??);
+        }
+
+        let unique: HashSet<_> = claimed.iter().collect();
+        assert_eq!(claimed.len(), MESSAGES);
+        assert_eq!(unique.len(), MESSAGES);
+
+        let leases: i64 = sqlx::query_scalar("SELECT COUNT(DISTINCT message_id) FROM leases")
+            .fetch_one(&pool)
+            .await?;
+        assert_eq!(leases, MESSAGES as i64);
+
+        Ok(())
+    }
+}
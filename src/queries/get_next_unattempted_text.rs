@@ -0,0 +1,108 @@
+use crate::models::{ClaimPredicate, RawTextMessage};
+use chrono::{DateTime, Utc};
+use sqlx::PgExecutor;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Claims the next unattempted message like [`get_next_unattempted`](super::get_next_unattempted), but returns the
+/// payload as JSON text to be decoded with [`RawTextMessage::decode_borrowed`].
+pub async fn get_next_unattempted_text<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    now: DateTime<Utc>,
+    host_id: Uuid,
+    hold_for: Duration,
+) -> Result<Option<RawTextMessage>, sqlx::Error> {
+    get_next_unattempted_text_inner(tx, now, host_id, hold_for, None).await
+}
+
+/// Claims like [`get_next_unattempted_text`].
+///
+/// With a `predicate`, only messages whose payload matches it are claimed.
+pub(super) async fn get_next_unattempted_text_inner<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    now: DateTime<Utc>,
+    host_id: Uuid,
+    hold_for: Duration,
+    predicate: Option<&ClaimPredicate>,
+) -> Result<Option<RawTextMessage>, sqlx::Error> {
+    let expires_at = now + hold_for;
+
+    let message = sqlx::query_as!(
+        RawTextMessage,
+        r#"
+        WITH next_message AS (
+            SELECT id
+            FROM messages_unattempted
+            WHERE ($4::TEXT IS NULL OR payload ->> $4 = ANY($5::TEXT[]))
+              AND NOT EXISTS (
+                  SELECT 1 FROM leases l
+                  WHERE l.message_id = messages_unattempted.id AND l.expires_at > $1
+              )
+            ORDER BY seq ASC
+            FOR UPDATE SKIP LOCKED
+            LIMIT 1
+        )
+        SELECT
+            c.id "id!",
+            c.name "name!",
+            c.hash "hash!",
+            c.payload::TEXT "payload!",
+            c.attempted "attempted!",
+            c.fencing_token "fencing_token?",
+            c.seq "seq?",
+            c.max_attempts "max_attempts?"
+        FROM next_message n, LATERAL claim_unattempted(n.id, $1, $2, $3) c;
+        "#,
+        now,
+        host_id,
+        expires_at,
+        predicate.map(ClaimPredicate::field),
+        predicate.map(ClaimPredicate::values) as Option<&[String]>
+    )
+    .fetch_optional(tx)
+    .await?;
+
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queries::publish_message;
+    use crate::testing_tools::{TestMessage, is_in_progress};
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Borrowed<'a> {
+        message: &'a str,
+        value: i32,
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_claims_payloads_as_text(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let message = TestMessage {
+            message: "testing".to_string(),
+            value: 42,
+        };
+        let published = publish_message(&pool, &message.to_raw()?).await?;
+
+        let now = Utc::now();
+        let claimed = get_next_unattempted_text(&pool, now, Uuid::now_v7(), Duration::from_mins(1))
+            .await?
+            .expect("Expected a message");
+
+        assert_eq!(claimed.id, published.id);
+        assert!(claimed.fencing_token.is_some());
+        assert_eq!(
+            claimed.decode_borrowed::<Borrowed>()?,
+            Borrowed {
+                message: "testing",
+                value: 42
+            }
+        );
+        assert_eq!(claimed.into_raw()?.payload, published.payload);
+        assert!(is_in_progress(&pool, published.id, now).await?);
+
+        Ok(())
+    }
+}
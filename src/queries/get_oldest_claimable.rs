@@ -0,0 +1,70 @@
+use chrono::{DateTime, Utc};
+use sqlx::PgExecutor;
+
+/// Returns the time the longest waiting claimable message became claimable, None if nothing is claimable.
+///
+/// Claimable messages are pending messages and retryable messages past their earliest retry time that are not
+/// leased. The difference to now is the lag of the queue.
+pub async fn get_oldest_claimable<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    now: DateTime<Utc>,
+) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+    let oldest = sqlx::query_scalar!(
+        r#"
+        SELECT MIN(claimable_since) "oldest"
+        FROM (
+            SELECT MIN(mu.published_at) AS claimable_since
+            FROM messages_unattempted mu
+            WHERE mu.published_at <= $1
+
+            UNION ALL
+
+            SELECT MIN(fa.retry_earliest_at)
+            FROM attempts_failed fa
+            WHERE fa.retry_earliest_at <= $1
+              AND NOT EXISTS (
+                  SELECT 1 FROM leases l
+                  WHERE l.message_id = fa.message_id AND l.expires_at > $1
+              )
+              AND fa.failed_at = (
+                  SELECT MAX(fa2.failed_at)
+                  FROM attempts_failed fa2
+                  WHERE fa2.message_id = fa.message_id
+              )
+        ) c
+        "#,
+        now
+    )
+    .fetch_one(tx)
+    .await?;
+
+    Ok(oldest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        queries::{get_next_unattempted, publish_message},
+        testing_tools::TestMessage,
+    };
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_returns_the_oldest_claimable_message(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        assert_eq!(get_oldest_claimable(&pool, Utc::now()).await?, None);
+
+        publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+        publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+        let now = Utc::now();
+        let oldest = get_oldest_claimable(&pool, now).await?;
+        assert!(oldest.is_some_and(|oldest| oldest <= now));
+
+        get_next_unattempted(&pool, now, Uuid::now_v7(), Duration::from_mins(1)).await?;
+        get_next_unattempted(&pool, now, Uuid::now_v7(), Duration::from_mins(1)).await?;
+        assert_eq!(get_oldest_claimable(&pool, now).await?, None);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,21 @@
+use sqlx::PgExecutor;
+use uuid::Uuid;
+
+/// Returns the full payload of a message, None if there is no such message
+pub async fn get_payload<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    message_id: Uuid,
+) -> Result<Option<serde_json::Value>, sqlx::Error> {
+    let payload = sqlx::query_scalar!(
+        r#"
+        SELECT payload "payload!" FROM messages_unattempted WHERE id = $1
+        UNION ALL
+        SELECT payload FROM messages_attempted WHERE id = $1
+        "#,
+        message_id
+    )
+    .fetch_optional(tx)
+    .await?;
+
+    Ok(payload)
+}
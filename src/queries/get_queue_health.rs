@@ -0,0 +1,148 @@
+use crate::models::{HealthStatus, HealthThresholds, QueueHealth};
+use chrono::{DateTime, Utc};
+use sqlx::PgExecutor;
+
+/// Summarizes the health of the queue at `now` in one query, judged by `thresholds`.
+///
+/// Combines the depth and the age of the oldest pending message, the missing messages, the messages reported dead
+/// within the last hour, the retry backlog and lease anomalies, so that dashboards and alerts need a single call.
+pub async fn get_queue_health<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    now: DateTime<Utc>,
+    thresholds: &HealthThresholds,
+) -> Result<QueueHealth, sqlx::Error> {
+    let dead_since = now - std::time::Duration::from_hours(1);
+
+    let row = sqlx::query!(
+        r#"
+        WITH finished AS (
+            SELECT message_id FROM attempts_succeeded
+            UNION ALL
+            SELECT message_id FROM attempts_dead
+        )
+        SELECT
+            (SELECT COUNT(*) FROM messages_unattempted WHERE published_at <= $1) "depth!",
+            (SELECT MIN(published_at) FROM messages_unattempted WHERE published_at <= $1) "oldest_pending_at",
+            (
+                SELECT COUNT(DISTINCT l.message_id)
+                FROM leases l
+                WHERE l.expires_at < $1
+                  AND NOT EXISTS (
+                      SELECT 1 FROM leases active
+                      WHERE active.message_id = l.message_id AND active.expires_at >= $1
+                  )
+                  AND NOT EXISTS (SELECT 1 FROM finished f WHERE f.message_id = l.message_id)
+            ) "missing!",
+            (SELECT COUNT(*) FROM attempts_dead WHERE dead_at > $2 AND dead_at <= $1) "dead_last_hour!",
+            (
+                SELECT COUNT(*)
+                FROM attempts_failed fa
+                WHERE fa.retry_earliest_at <= $1
+                  AND fa.failed_at = (
+                      SELECT MAX(fa2.failed_at)
+                      FROM attempts_failed fa2
+                      WHERE fa2.message_id = fa.message_id
+                  )
+                  AND NOT EXISTS (
+                      SELECT 1 FROM leases l
+                      WHERE l.message_id = fa.message_id AND l.expires_at > $1
+                  )
+            ) "retry_backlog!",
+            -- Reports clear leases, and a message is only leased by one claim at a time
+            (
+                SELECT COUNT(*)
+                FROM (
+                    SELECT l.message_id
+                    FROM leases l
+                    WHERE l.expires_at > $1
+                    GROUP BY l.message_id
+                    HAVING COUNT(*) > 1
+                        OR bool_or(EXISTS (SELECT 1 FROM finished f WHERE f.message_id = l.message_id))
+                ) anomalies
+            ) "lease_anomalies!"
+        "#,
+        now,
+        dead_since
+    )
+    .fetch_one(tx)
+    .await?;
+
+    let health = QueueHealth {
+        depth: row.depth,
+        oldest_pending_age: row
+            .oldest_pending_at
+            .map(|at| (now - at).to_std().unwrap_or_default()),
+        missing: row.missing,
+        dead_last_hour: row.dead_last_hour,
+        retry_backlog: row.retry_backlog,
+        lease_anomalies: row.lease_anomalies,
+        status: HealthStatus::Ok,
+        breaches: Vec::new(),
+    };
+
+    Ok(health.evaluate(thresholds))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Threshold;
+    use crate::queries::{get_next_unattempted, publish_message, report_dead};
+    use crate::testing_tools::TestMessage;
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_is_ok_when_empty(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let health = get_queue_health(&pool, Utc::now(), &HealthThresholds::default()).await?;
+
+        assert_eq!(health.depth, 0);
+        assert_eq!(health.oldest_pending_age, None);
+        assert_eq!(health.status, HealthStatus::Ok);
+        assert!(health.breaches.is_empty());
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_summarizes_the_queue(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        for _ in 0..3 {
+            publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+        }
+        let now = Utc::now();
+        let host_id = Uuid::now_v7();
+
+        let dead = get_next_unattempted(&pool, now, host_id, Duration::from_mins(1))
+            .await?
+            .expect("Expected a message");
+        report_dead(&pool, dead.id, dead.fencing_token, now, "broken").await?;
+        get_next_unattempted(&pool, now, host_id, Duration::from_secs(1))
+            .await?
+            .expect("Expected a message");
+
+        let thresholds = HealthThresholds {
+            depth: Threshold::new(1, 10),
+            ..HealthThresholds::default()
+        };
+        let later = now + Duration::from_secs(2);
+        let health = get_queue_health(&pool, later, &thresholds).await?;
+
+        assert_eq!(health.depth, 1);
+        assert!(health.oldest_pending_age >= Some(Duration::from_secs(2)));
+        assert_eq!(health.missing, 1);
+        assert_eq!(health.dead_last_hour, 1);
+        assert_eq!(health.retry_backlog, 0);
+        assert_eq!(health.lease_anomalies, 0);
+        assert_eq!(health.status, HealthStatus::Degraded);
+        assert_eq!(
+            health
+                .breaches
+                .iter()
+                .map(|breach| breach.metric)
+                .collect::<Vec<_>>(),
+            vec!["depth", "missing", "dead_last_hour"]
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,62 @@
+use crate::models::QueueSettings;
+use sqlx::PgExecutor;
+use std::time::Duration;
+
+/// Returns the settings of all message names that have settings
+pub async fn get_queue_settings<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+) -> Result<Vec<QueueSettings>, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT name, backoff_base, backoff_base_delay_ms, max_attempts, hold_for_ms
+        FROM queue_settings
+        ORDER BY name
+        "#
+    )
+    .fetch_all(tx)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| QueueSettings {
+            name: row.name,
+            backoff_base: row.backoff_base as u32,
+            backoff_base_delay: Duration::from_millis(row.backoff_base_delay_ms as u64),
+            max_attempts: row.max_attempts,
+            hold_for: Duration::from_millis(row.hold_for_ms as u64),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queries::{delete_queue_settings, put_queue_settings};
+    use chrono::Utc;
+
+    fn settings(name: &str, max_attempts: i32) -> QueueSettings {
+        QueueSettings {
+            name: name.to_string(),
+            backoff_base: 2,
+            backoff_base_delay: Duration::from_secs(30),
+            max_attempts,
+            hold_for: Duration::from_mins(5),
+        }
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_stores_and_replaces_settings(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        put_queue_settings(&pool, &settings("b", 3), Utc::now()).await?;
+        put_queue_settings(&pool, &settings("a", 3), Utc::now()).await?;
+        put_queue_settings(&pool, &settings("a", 10), Utc::now()).await?;
+
+        let stored = get_queue_settings(&pool).await?;
+        assert_eq!(stored, vec![settings("a", 10), settings("b", 3)]);
+
+        assert!(delete_queue_settings(&pool, "a").await?);
+        assert!(!delete_queue_settings(&pool, "a").await?);
+        assert_eq!(get_queue_settings(&pool).await?, vec![settings("b", 3)]);
+
+        Ok(())
+    }
+}
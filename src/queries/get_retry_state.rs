@@ -0,0 +1,155 @@
+use crate::models::{QueueSettings, RetryState};
+use chrono::{DateTime, Utc};
+use sqlx::PgExecutor;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Returns the attempts of the message `message_id` and the stored settings of its name, None if it does not exist
+/// or is a barrier not yet released
+pub async fn get_retry_state<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    message_id: Uuid,
+    now: DateTime<Utc>,
+) -> Result<Option<RetryState>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        WITH message AS (
+            SELECT id, name, published_at FROM messages_unattempted WHERE id = $1
+            UNION ALL
+            SELECT id, name, published_at FROM messages_attempted WHERE id = $1
+        )
+        SELECT
+            m.id "id!",
+            m.name "name!",
+            m.published_at "published_at!",
+            COALESCE(
+                (SELECT MAX(f.attempted) FROM attempts_failed f WHERE f.message_id = m.id),
+                0
+            ) "attempted!",
+            (
+                SELECT f.retry_earliest_at FROM attempts_failed f
+                WHERE f.message_id = m.id
+                ORDER BY f.failed_at DESC
+                LIMIT 1
+            ) "retry_earliest_at?",
+            EXISTS (
+                SELECT 1 FROM leases l WHERE l.message_id = m.id AND l.expires_at > $2
+            ) "in_progress!",
+            (
+                EXISTS (SELECT 1 FROM attempts_succeeded s WHERE s.message_id = m.id)
+                OR EXISTS (SELECT 1 FROM attempts_dead d WHERE d.message_id = m.id)
+            ) "finished!",
+            qs.backoff_base "backoff_base?",
+            qs.backoff_base_delay_ms "backoff_base_delay_ms?",
+            qs.max_attempts "max_attempts?",
+            qs.hold_for_ms "hold_for_ms?"
+        FROM message m
+        LEFT JOIN queue_settings qs ON qs.name = m.name
+        "#,
+        message_id,
+        now
+    )
+    .fetch_optional(tx)
+    .await?;
+
+    Ok(row.map(|row| {
+        let settings = match (
+            row.backoff_base,
+            row.backoff_base_delay_ms,
+            row.max_attempts,
+            row.hold_for_ms,
+        ) {
+            (Some(base), Some(delay_ms), Some(max_attempts), Some(hold_for_ms)) => {
+                Some(QueueSettings {
+                    name: row.name.clone(),
+                    backoff_base: base as u32,
+                    backoff_base_delay: Duration::from_millis(delay_ms as u64),
+                    max_attempts,
+                    hold_for: Duration::from_millis(hold_for_ms as u64),
+                })
+            }
+            _ => None,
+        };
+        RetryState {
+            message_id: row.id,
+            name: row.name,
+            published_at: row.published_at,
+            attempted: row.attempted,
+            retry_earliest_at: row.retry_earliest_at,
+            in_progress: row.in_progress,
+            finished: row.finished,
+            settings,
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backoff::ExponentialBackoff;
+    use crate::models::Message;
+    use crate::queries::{
+        get_next_unattempted, publish_message, put_queue_settings, report_retryable,
+    };
+    use crate::testing_tools::TestMessage;
+    use chrono::SubsecRound;
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_forecasts_the_retries_of_a_message(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let now = Utc::now();
+        let message = publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+        let backoff = ExponentialBackoff::new(2, Duration::from_mins(1));
+        let minutes = |at: DateTime<Utc>, m| at + chrono::TimeDelta::minutes(m);
+
+        let state = get_retry_state(&pool, message.id, now)
+            .await?
+            .expect("Expected a message");
+        assert_eq!(state.attempted, 0);
+        assert_eq!(state.settings, None);
+        let forecast = state.forecast(&backoff, 3, now);
+        assert_eq!(forecast.schedule, [now, minutes(now, 1), minutes(now, 3)]);
+
+        let claimed = get_next_unattempted(&pool, now, Uuid::now_v7(), Duration::from_mins(1))
+            .await?
+            .expect("Expected a message");
+        let forecast = get_retry_state(&pool, message.id, now)
+            .await?
+            .expect("Expected a message")
+            .forecast(&backoff, 3, now);
+        assert!(forecast.in_progress);
+        assert_eq!(forecast.schedule, [minutes(now, 1), minutes(now, 3)]);
+
+        let retry_at = minutes(now, 1);
+        report_retryable(
+            &pool,
+            claimed.id,
+            claimed.fencing_token,
+            now,
+            1,
+            retry_at,
+            "err",
+        )
+        .await?;
+        let settings = QueueSettings {
+            name: TestMessage::NAME.to_string(),
+            backoff_base: 2,
+            backoff_base_delay: Duration::from_mins(1),
+            max_attempts: 2,
+            hold_for: Duration::from_mins(1),
+        };
+        put_queue_settings(&pool, &settings, now).await?;
+
+        let state = get_retry_state(&pool, message.id, now)
+            .await?
+            .expect("Expected a message");
+        assert_eq!(state.settings.as_ref(), Some(&settings));
+        let forecast = state.forecast(&settings.backoff(), settings.max_attempts, now);
+        assert_eq!(forecast.attempted, 1);
+        assert_eq!(forecast.next_attempt_at(), Some(retry_at.trunc_subsecs(6)));
+        assert_eq!(forecast.remaining(), 1);
+
+        assert_eq!(get_retry_state(&pool, Uuid::now_v7(), now).await?, None);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,142 @@
+use crate::models::ErrorRecord;
+use sqlx::PgExecutor;
+
+/// Inserts errors reported separately from their attempts, returning the number inserted.
+///
+/// Errors already inserted are skipped by their id, so a batch may be inserted again after a failure. Errors of
+/// messages that no longer exist are dropped rather than failing the batch.
+pub async fn insert_errors<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    errors: &[ErrorRecord],
+) -> Result<u64, sqlx::Error> {
+    insert_errors_inner(tx, errors, None).await
+}
+
+/// Inserts errors like [`insert_errors`], keeping only the `max_errors` most recent errors of each message when
+/// given, like [`report_retryable_capped`](super::report_retryable_capped). Errors of the batch that are older than
+/// the kept ones are not inserted.
+pub(crate) async fn insert_errors_inner<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    errors: &[ErrorRecord],
+    max_errors: Option<i64>,
+) -> Result<u64, sqlx::Error> {
+    let ids: Vec<_> = errors.iter().map(|e| e.id).collect();
+    let message_ids: Vec<_> = errors.iter().map(|e| e.message_id).collect();
+    let reported_at: Vec<_> = errors.iter().map(|e| e.reported_at).collect();
+    let texts: Vec<_> = errors.iter().map(|e| e.error.clone()).collect();
+    let categories: Vec<_> = errors.iter().map(|e| e.category.clone()).collect();
+
+    let inserted = sqlx::query_scalar!(
+        r#"
+        WITH batch AS (
+            SELECT e.id, e.message_id, e.reported_at, e.error, e.category
+            FROM UNNEST($1::UUID[], $2::UUID[], $3::TIMESTAMPTZ[], $4::TEXT[], $5::TEXT[])
+                AS e(id, message_id, reported_at, error, category)
+            WHERE EXISTS (SELECT 1 FROM messages_attempted ma WHERE ma.id = e.message_id)
+        ),
+        -- The errors of the batch are not visible to the delete, so the stored and new errors are ranked together
+        ranked AS (
+            SELECT id, ROW_NUMBER() OVER (PARTITION BY message_id ORDER BY reported_at DESC, id DESC) AS rank
+            FROM (
+                SELECT id, message_id, reported_at
+                FROM errors
+                WHERE message_id IN (SELECT message_id FROM batch)
+                UNION
+                SELECT id, message_id, reported_at
+                FROM batch
+            ) candidates
+        ),
+        del_errors AS (
+            DELETE FROM errors
+            WHERE $6::BIGINT IS NOT NULL
+              AND id IN (SELECT id FROM ranked WHERE rank > GREATEST($6, 1))
+        ),
+        ins_errors AS (
+            INSERT INTO errors (id, message_id, reported_at, error, category)
+            SELECT id, message_id, reported_at, error, category
+            FROM batch
+            WHERE $6::BIGINT IS NULL
+               OR id IN (SELECT id FROM ranked WHERE rank <= GREATEST($6, 1))
+            ON CONFLICT (id) DO NOTHING
+            RETURNING id
+        )
+        SELECT COUNT(*) "inserted!"
+        FROM ins_errors
+        "#,
+        &ids,
+        &message_ids,
+        &reported_at,
+        &texts,
+        &categories as &[Option<String>],
+        max_errors
+    )
+    .fetch_one(tx)
+    .await?;
+
+    Ok(inserted as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queries::{get_next_unattempted, publish_message};
+    use crate::testing_tools::TestMessage;
+    use chrono::Utc;
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_inserts_errors_once(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+        let message =
+            get_next_unattempted(&pool, Utc::now(), Uuid::now_v7(), Duration::from_mins(1))
+                .await?
+                .expect("Expected a message");
+
+        let record = |message_id| ErrorRecord {
+            id: Uuid::now_v7(),
+            message_id,
+            reported_at: Utc::now(),
+            error: "timeout".to_string(),
+            category: Some("timeout".to_string()),
+        };
+        let errors = [record(message.id), record(Uuid::now_v7())];
+
+        assert_eq!(insert_errors(&pool, &errors).await?, 1);
+        assert_eq!(insert_errors(&pool, &errors).await?, 0);
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_keeps_the_most_recent_errors(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+        let message =
+            get_next_unattempted(&pool, Utc::now(), Uuid::now_v7(), Duration::from_mins(1))
+                .await?
+                .expect("Expected a message");
+
+        let now = Utc::now();
+        let record = |secs| ErrorRecord {
+            id: Uuid::now_v7(),
+            message_id: message.id,
+            reported_at: now + Duration::from_secs(secs),
+            error: format!("error {secs}"),
+            category: None,
+        };
+
+        insert_errors_inner(&pool, &[record(0), record(2)], Some(2)).await?;
+        // The oldest error of the batch is not inserted and the oldest stored one is deleted
+        let inserted =
+            insert_errors_inner(&pool, &[record(1), record(3), record(4)], Some(2)).await?;
+        assert_eq!(inserted, 2);
+
+        let errors: Vec<String> =
+            sqlx::query_scalar("SELECT error FROM errors ORDER BY reported_at ASC")
+                .fetch_all(&pool)
+                .await?;
+        assert_eq!(errors, ["error 3", "error 4"]);
+
+        Ok(())
+    }
+}
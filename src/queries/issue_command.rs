@@ -0,0 +1,33 @@
+use crate::models::OperatorCommand;
+use chrono::{DateTime, Utc};
+use sqlx::PgExecutor;
+use uuid::Uuid;
+
+/// Records an operator command for the host `target_host`, or every host when None, returning its id.
+///
+/// Workers only learn about the command when notified, see [`Queries::issue_command`](super::Queries::issue_command),
+/// or when they next check for commands.
+pub async fn issue_command<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    command: &OperatorCommand,
+    target_host: Option<Uuid>,
+    now: DateTime<Utc>,
+) -> Result<i64, sqlx::Error> {
+    let command =
+        serde_json::to_value(command).map_err(|error| sqlx::Error::Encode(error.into()))?;
+
+    let id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO operator_commands (command, target_host, issued_at)
+        VALUES ($1, $2, $3)
+        RETURNING id
+        "#,
+        command,
+        target_host,
+        now
+    )
+    .fetch_one(tx)
+    .await?;
+
+    Ok(id)
+}
@@ -0,0 +1,92 @@
+use crate::models::NameLag;
+use chrono::{DateTime, Utc};
+use sqlx::PgExecutor;
+
+/// Returns, for each message name with unprocessed messages, their count and the age of the oldest one.
+///
+/// Unprocessed messages are pending, in progress or failed, but neither succeeded nor dead.
+pub async fn lag_by_name<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    now: DateTime<Utc>,
+) -> Result<Vec<NameLag>, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT
+            u.name "name!",
+            COUNT(*) "unprocessed!",
+            MIN(u.published_at) "oldest_published_at!"
+        FROM (
+            SELECT name, published_at
+            FROM messages_unattempted
+
+            UNION ALL
+
+            SELECT ma.name, ma.published_at
+            FROM messages_attempted ma
+            WHERE NOT EXISTS (SELECT 1 FROM attempts_succeeded s WHERE s.message_id = ma.id)
+              AND NOT EXISTS (SELECT 1 FROM attempts_dead d WHERE d.message_id = ma.id)
+        ) u
+        GROUP BY u.name
+        ORDER BY u.name
+        "#
+    )
+    .fetch_all(tx)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| NameLag {
+            name: row.name,
+            unprocessed: row.unprocessed,
+            max_age: (now - row.oldest_published_at).to_std().unwrap_or_default(),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        models::{Message, RawMessage},
+        queries::{get_next_unattempted, publish_message, report_success},
+        testing_tools::TestMessage,
+    };
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_returns_lag_per_name(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let host_id = Uuid::now_v7();
+        let hold_for = Duration::from_mins(1);
+
+        // Succeeded, not lagging
+        publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+        let claimed = get_next_unattempted(&pool, Utc::now(), host_id, hold_for)
+            .await?
+            .unwrap();
+        report_success(&pool, claimed.id, None, Utc::now()).await?;
+
+        // In progress and pending
+        publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+        get_next_unattempted(&pool, Utc::now(), host_id, hold_for).await?;
+        publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+
+        let other = RawMessage {
+            name: "Other".to_string(),
+            ..TestMessage::default().to_raw()?
+        };
+        publish_message(&pool, &other).await?;
+
+        let now = Utc::now() + Duration::from_secs(10);
+        let lag = lag_by_name(&pool, now).await?;
+
+        assert_eq!(lag.len(), 2);
+        assert_eq!(lag[0].name, "Other");
+        assert_eq!(lag[0].unprocessed, 1);
+        assert_eq!(lag[1].name, TestMessage::NAME);
+        assert_eq!(lag[1].unprocessed, 2);
+        assert!(lag[1].max_age >= Duration::from_secs(10));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,71 @@
+use crate::models::InProgressMessage;
+use chrono::{DateTime, Utc};
+use sqlx::PgExecutor;
+use uuid::Uuid;
+
+/// Lists the messages claimed by `host_id` whose leases are active at `now`, oldest claim first.
+///
+/// Meant for a worker to log what it is working on, e.g. while shutting down. Manual leases on pending messages,
+/// see [`request_lease`](super::request_lease), are not claims and are not listed.
+pub async fn list_in_progress<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    host_id: Uuid,
+    now: DateTime<Utc>,
+) -> Result<Vec<InProgressMessage>, sqlx::Error> {
+    sqlx::query_as!(
+        InProgressMessage,
+        r#"
+        SELECT
+            ma.id,
+            ma.name,
+            ma.seq,
+            l.fencing_token,
+            l.acquired_at,
+            l.expires_at
+        FROM leases l
+        JOIN messages_attempted ma ON ma.id = l.message_id
+        WHERE l.acquired_by = $1 AND l.expires_at > $2
+        ORDER BY l.acquired_at ASC, ma.seq ASC
+        "#,
+        host_id,
+        now
+    )
+    .fetch_all(tx)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queries::{get_next_unattempted, publish_message, report_success};
+    use crate::testing_tools::TestMessage;
+    use std::time::Duration;
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_lists_the_active_claims_of_a_host(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let now = Utc::now();
+        let host_id = Uuid::now_v7();
+        let other_host_id = Uuid::now_v7();
+        for _ in 0..4 {
+            publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+        }
+
+        let claim = |host_id, hold_for| get_next_unattempted(&pool, now, host_id, hold_for);
+        let first = claim(host_id, Duration::from_mins(1)).await?.unwrap();
+        let reported = claim(host_id, Duration::from_mins(1)).await?.unwrap();
+        claim(host_id, Duration::from_secs(1)).await?.unwrap();
+        claim(other_host_id, Duration::from_mins(1)).await?.unwrap();
+
+        report_success(&pool, reported.id, reported.fencing_token, now).await?;
+
+        let in_progress = list_in_progress(&pool, host_id, now + Duration::from_secs(2)).await?;
+
+        assert_eq!(in_progress.len(), 1);
+        assert_eq!(in_progress[0].id, first.id);
+        assert_eq!(in_progress[0].seq, first.seq.unwrap());
+        assert_eq!(Some(in_progress[0].fencing_token), first.fencing_token);
+        assert!(in_progress[0].expires_at > now + Duration::from_secs(2));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,130 @@
+use crate::models::{MessageStatus, MessageSummary};
+use chrono::{DateTime, Utc};
+use sqlx::PgExecutor;
+
+/// Returns up to `limit` messages published before the message with the `seq` passed as `before`, most recent
+/// first, optionally of a single name, with their status at `now`.
+///
+/// Pass None as `before` to get the first page, and the `seq` of the last message of a page to get the next page. Statuses are
+/// evaluated like [`get_message_status`](super::get_message_status).
+pub async fn list_messages<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    name: Option<&str>,
+    before: Option<i64>,
+    limit: i64,
+    now: DateTime<Utc>,
+) -> Result<Vec<MessageSummary>, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"
+        WITH listed AS (
+            SELECT id, name, published_at, seq
+            FROM (
+                SELECT id, name, published_at, seq FROM messages_unattempted
+                UNION ALL
+                SELECT id, name, published_at, seq FROM messages_attempted
+            ) m
+            WHERE ($2::BIGINT IS NULL OR seq < $2)
+              AND published_at <= $4
+              AND ($1::TEXT IS NULL OR name = $1)
+            ORDER BY seq DESC
+            LIMIT $3
+        )
+        SELECT
+            m.id "id!",
+            m.name "name!",
+            m.published_at "published_at!",
+            m.seq "seq!",
+            CASE
+                WHEN EXISTS (
+                    SELECT 1 FROM attempts_succeeded WHERE message_id = m.id AND succeeded_at <= $4
+                ) THEN 'succeeded'
+                WHEN EXISTS (
+                    SELECT 1 FROM attempts_dead WHERE message_id = m.id AND dead_at <= $4
+                ) THEN 'dead'
+                WHEN EXISTS (
+                    SELECT 1 FROM leases WHERE message_id = m.id AND acquired_at <= $4 AND expires_at > $4
+                ) THEN 'in_progress'
+                -- A lease acquired after the last failure is a retry, whose lease expired
+                WHEN l.acquired_at > COALESCE(f.failed_at, '-infinity') THEN 'missing'
+                WHEN f.failed_at IS NOT NULL THEN 'failed'
+                ELSE 'pending'
+            END "status!"
+        FROM listed m
+        CROSS JOIN LATERAL (
+            SELECT MAX(failed_at) failed_at
+            FROM (
+                SELECT failed_at FROM attempts_failed WHERE message_id = m.id AND failed_at <= $4
+                UNION ALL
+                SELECT reported_at FROM errors WHERE message_id = m.id AND reported_at <= $4
+            ) failures
+        ) f
+        CROSS JOIN LATERAL (
+            SELECT MAX(acquired_at) acquired_at
+            FROM leases
+            WHERE message_id = m.id AND acquired_at <= $4
+        ) l
+        ORDER BY m.seq DESC
+        "#,
+        name,
+        before,
+        limit,
+        now
+    )
+    .fetch_all(tx)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| MessageSummary {
+            id: row.id,
+            name: row.name,
+            published_at: row.published_at,
+            seq: row.seq,
+            status: MessageStatus::from_label(&row.status),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Message, RawMessage};
+    use crate::queries::{get_next_unattempted, publish_message, report_success};
+    use crate::testing_tools::TestMessage;
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_lists_messages_by_page(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let host_id = Uuid::now_v7();
+        let first = publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+        let claimed = get_next_unattempted(&pool, Utc::now(), host_id, Duration::from_mins(1))
+            .await?
+            .expect("Expected a message");
+        report_success(&pool, claimed.id, claimed.fencing_token, Utc::now()).await?;
+
+        let other = RawMessage {
+            name: "Other".to_string(),
+            ..TestMessage::default().to_raw()?
+        };
+        publish_message(&pool, &other).await?;
+        let last = publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+
+        let now = Utc::now();
+        let page = list_messages(&pool, Some(TestMessage::NAME), None, 1, now).await?;
+        assert_eq!(page.len(), 1);
+        assert_eq!(page[0].id, last.id);
+        assert_eq!(page[0].status, MessageStatus::Pending);
+
+        let next =
+            list_messages(&pool, Some(TestMessage::NAME), Some(page[0].seq), 10, now).await?;
+        assert_eq!(next.len(), 1);
+        assert_eq!(next[0].id, first.id);
+        assert_eq!(next[0].status, MessageStatus::Succeeded);
+
+        let all = list_messages(&pool, None, None, 10, now).await?;
+        assert_eq!(all.len(), 3);
+
+        Ok(())
+    }
+}
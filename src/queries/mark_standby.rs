@@ -0,0 +1,84 @@
+use chrono::{DateTime, Utc};
+use sqlx::PgExecutor;
+
+/// Marks the schema as a warm standby, on which queries with
+/// [`with_standby_fencing`](super::Queries::with_standby_fencing) don't claim until [promoted](promote_standby).
+/// Marking a standby again keeps the time it became one.
+pub async fn mark_standby<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    now: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO standby (since)
+        VALUES ($1)
+        ON CONFLICT (singleton) DO NOTHING
+        "#,
+        now
+    )
+    .execute(tx)
+    .await?;
+
+    Ok(())
+}
+
+/// Promotes the standby, allowing claims. Returns false if the schema was not a standby.
+///
+/// Fenced claims lock the standby mark until they commit, so promotion waits for the claims that found the schema
+/// a standby, which return no messages.
+pub async fn promote_standby<'tx, E: PgExecutor<'tx>>(tx: E) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!("DELETE FROM standby").execute(tx).await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Returns whether the schema is a standby, locking the standby mark for the rest of the transaction
+pub async fn is_standby<'tx, E: PgExecutor<'tx>>(tx: E) -> Result<bool, sqlx::Error> {
+    let standby = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS (SELECT 1 FROM standby FOR SHARE) "standby!"
+        "#
+    )
+    .fetch_one(tx)
+    .await?;
+
+    Ok(standby)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queries::{Queries, publish_message};
+    use crate::testing_tools::TestMessage;
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_fences_claims_on_a_standby_until_promoted(
+        pool: sqlx::PgPool,
+    ) -> anyhow::Result<()> {
+        let queries = Queries::new("public")?.with_standby_fencing(true);
+        let hold_for = Duration::from_mins(1);
+        publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+        mark_standby(&pool, Utc::now()).await?;
+        assert!(is_standby(&pool).await?);
+
+        let mut tx = pool.begin().await?;
+        let claimed = queries
+            .get_next_unattempted(&mut tx, Utc::now(), Uuid::now_v7(), hold_for)
+            .await?;
+        tx.commit().await?;
+        assert!(claimed.is_none());
+
+        assert!(promote_standby(&pool).await?);
+        assert!(!promote_standby(&pool).await?);
+
+        let mut tx = pool.begin().await?;
+        let claimed = queries
+            .get_next_unattempted(&mut tx, Utc::now(), Uuid::now_v7(), hold_for)
+            .await?;
+        tx.commit().await?;
+        assert!(claimed.is_some());
+
+        Ok(())
+    }
+}
@@ -1,20 +1,144 @@
+mod activate_epoch;
+mod append_event;
+mod claim_batch;
+mod compact_daily_aggregates;
+mod count_claim_conflicts;
+mod delete_blob;
+mod delete_messages_matching;
+mod enforce_quotas;
+mod error_class;
+mod get_blob;
+mod get_claim_starvation;
+mod get_commands_after;
+mod get_daily_aggregates;
+mod get_failure_categories;
+mod get_finished_messages;
+mod get_lease_holder;
+mod get_lease_losses;
+mod get_many_unattempted;
+mod get_message_status;
+mod get_message_statuses;
+mod get_message_timeline;
+mod get_next_dead_for_review;
 mod get_next_missing;
 mod get_next_retryable;
+mod get_next_retryable_named;
 mod get_next_unattempted;
+mod get_next_unattempted_named;
+mod get_next_unattempted_projected;
+mod get_next_unattempted_split;
+mod get_next_unattempted_text;
+mod get_oldest_claimable;
+mod get_payload;
+mod get_queue_health;
+mod get_queue_settings;
+mod get_retry_state;
+mod insert_errors;
+mod issue_command;
+mod lag_by_name;
+mod list_in_progress;
+mod list_messages;
+mod mark_standby;
+mod notify;
+mod payload_sizes_by_name;
+mod publish_barrier;
+mod publish_error;
 mod publish_message;
+mod publish_succeeded;
+mod purge_finished;
+mod put_blob;
+mod put_queue_settings;
+mod put_quota;
+mod record_claim_conflict;
+mod register_host;
+mod release_barriers;
+mod release_lease;
+mod renew_lease;
 mod report_dead;
+mod report_deferred;
+mod report_error;
+mod report_failure;
 mod report_retryable;
+mod report_review;
 mod report_success;
 mod request_lease;
+mod retry_dead_by_name;
+mod rewrite_payloads;
+mod search_errors;
 mod search_scheduled;
+mod set_retry_concurrency_limit;
 mod with_schema;
+mod with_tx;
 
-pub use get_next_missing::get_next_missing;
-pub use get_next_retryable::get_next_retryable;
-pub use get_next_unattempted::get_next_unattempted;
-pub use publish_message::{publish_many_messages_with_notify, publish_message};
+pub use activate_epoch::{activate_epoch, get_active_epoch, is_epoch_active};
+pub use append_event::{append_event, get_events};
+pub use claim_batch::claim_batch;
+pub use compact_daily_aggregates::compact_daily_aggregates;
+pub use count_claim_conflicts::count_claim_conflicts;
+pub use delete_blob::delete_blob;
+pub use delete_messages_matching::delete_messages_matching;
+pub use enforce_quotas::enforce_quotas;
+pub use error_class::ErrorClass;
+pub use get_blob::get_blob;
+pub use get_claim_starvation::get_claim_starvation;
+pub use get_commands_after::get_commands_after;
+pub use get_daily_aggregates::get_daily_aggregates;
+pub use get_failure_categories::get_failure_categories;
+pub use get_finished_messages::get_finished_messages;
+pub use get_lease_holder::get_lease_holder;
+pub use get_lease_losses::get_lease_losses;
+pub use get_many_unattempted::get_many_unattempted;
+pub use get_message_status::get_message_status;
+pub use get_message_statuses::get_message_statuses;
+pub use get_message_timeline::get_message_timeline;
+pub use get_next_dead_for_review::get_next_dead_for_review;
+pub use get_next_missing::{get_next_missing, get_next_missing_named, get_next_missing_sticky};
+pub use get_next_retryable::{get_next_retryable, get_next_retryable_ordered};
+pub use get_next_retryable_named::get_next_retryable_named;
+pub use get_next_unattempted::{get_next_unattempted, get_next_unattempted_windowed};
+pub use get_next_unattempted_named::get_next_unattempted_named;
+pub use get_next_unattempted_projected::get_next_unattempted_projected;
+pub use get_next_unattempted_split::get_next_unattempted_split;
+pub use get_next_unattempted_text::get_next_unattempted_text;
+pub use get_oldest_claimable::get_oldest_claimable;
+pub use get_payload::get_payload;
+pub use get_queue_health::get_queue_health;
+pub use get_queue_settings::get_queue_settings;
+pub use get_retry_state::get_retry_state;
+pub use insert_errors::insert_errors;
+pub use issue_command::issue_command;
+pub use lag_by_name::lag_by_name;
+pub use list_in_progress::list_in_progress;
+pub use list_messages::list_messages;
+pub use mark_standby::{is_standby, mark_standby, promote_standby};
+pub use notify::{notify, notify_payload};
+pub use payload_sizes_by_name::payload_sizes_by_name;
+pub use publish_barrier::publish_barrier;
+pub use publish_error::{PublishError, QuotaExceeded};
+pub use publish_message::{
+    publish_many_messages_with_notify, publish_message, publish_message_on_conflict,
+};
+pub use publish_succeeded::publish_succeeded;
+pub use purge_finished::{PurgedBatch, analyze_purged, purge_finished};
+pub use put_blob::put_blob;
+pub use put_queue_settings::{delete_queue_settings, put_queue_settings};
+pub use put_quota::{delete_quota, put_quota};
+pub use record_claim_conflict::record_claim_conflict;
+pub use register_host::{register_host, register_host_in_epoch};
+pub use release_barriers::release_barriers;
+pub use release_lease::release_lease;
+pub use renew_lease::renew_lease;
 pub use report_dead::report_dead;
-pub use report_retryable::report_retryable;
+pub use report_deferred::report_deferred;
+pub use report_error::ReportError;
+pub use report_failure::report_failure;
+pub use report_retryable::{report_retryable, report_retryable_capped};
+pub use report_review::{report_remediated, report_reviewed};
 pub use report_success::report_success;
 pub use request_lease::request_lease;
-pub use with_schema::{Queries, set_schema_for_transaction};
+pub use retry_dead_by_name::retry_dead_by_name;
+pub use rewrite_payloads::rewrite_payloads;
+pub use search_errors::search_errors;
+pub use set_retry_concurrency_limit::set_retry_concurrency_limit;
+pub use with_schema::{Queries, SchemaTag, Untagged, set_schema_for_transaction};
+pub use with_tx::{TransactionRetry, is_retryable_transaction_error, with_tx};
@@ -0,0 +1,30 @@
+use sqlx::PgExecutor;
+
+/// Sends a `pg_notify` on `channel` with `count` as payload, announcing that `count` messages became claimable.
+///
+/// The notification is delivered when the transaction commits.
+pub async fn notify<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    channel: &str,
+    count: i64,
+) -> Result<(), sqlx::Error> {
+    notify_payload(tx, channel, &count.to_string()).await
+}
+
+/// Sends a `pg_notify` on `channel` with `payload`, e.g. a [`Notification`](crate::listener::Notification) carrying
+/// the schema and name of the messages that became claimable.
+///
+/// The notification is delivered when the transaction commits.
+pub async fn notify_payload<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    channel: &str,
+    payload: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("SELECT pg_notify($1, $2)")
+        .bind(channel)
+        .bind(payload)
+        .execute(tx)
+        .await?;
+
+    Ok(())
+}
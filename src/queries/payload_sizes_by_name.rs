@@ -0,0 +1,87 @@
+use crate::models::PayloadSizes;
+use chrono::{DateTime, Utc};
+use sqlx::PgExecutor;
+
+/// Returns, for each message name, the distribution of payload sizes of messages published since `since`.
+///
+/// Sizes are the length of the payload serialized as JSON text, i.e. roughly the size a handler deserializes,
+/// rather than the compressed size stored on disk. Measuring reads every payload in range, so keep the range short
+/// on large queues.
+pub async fn payload_sizes_by_name<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    since: DateTime<Utc>,
+) -> Result<Vec<PayloadSizes>, sqlx::Error> {
+    let sizes = sqlx::query_as!(
+        PayloadSizes,
+        r#"
+        SELECT
+            m.name "name!",
+            COUNT(*) "messages!",
+            PERCENTILE_DISC(0.5) WITHIN GROUP (ORDER BY m.size)::BIGINT "p50!",
+            PERCENTILE_DISC(0.95) WITHIN GROUP (ORDER BY m.size)::BIGINT "p95!",
+            MAX(m.size)::BIGINT "max!"
+        FROM (
+            SELECT name, octet_length(payload::TEXT) size
+            FROM messages_unattempted
+            WHERE published_at >= $1
+
+            UNION ALL
+
+            SELECT name, octet_length(payload::TEXT) size
+            FROM messages_attempted
+            WHERE published_at >= $1
+        ) m
+        GROUP BY m.name
+        ORDER BY m.name
+        "#,
+        since
+    )
+    .fetch_all(tx)
+    .await?;
+
+    Ok(sizes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::RawMessage;
+    use crate::queries::{get_next_unattempted, publish_message};
+    use crate::testing_tools::TestMessage;
+    use serde_json::json;
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    fn sized(name: &str, size: usize) -> anyhow::Result<RawMessage> {
+        Ok(RawMessage {
+            name: name.to_string(),
+            payload: json!("x".repeat(size)),
+            ..TestMessage::default().to_raw()?
+        })
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_reports_sizes_per_name(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let since = Utc::now() - Duration::from_mins(1);
+
+        for size in 1..=20 {
+            publish_message(&pool, &sized("a", size * 10)?).await?;
+        }
+        publish_message(&pool, &sized("b", 1000)?).await?;
+        // Attempted messages are measured too
+        get_next_unattempted(&pool, Utc::now(), Uuid::now_v7(), Duration::from_mins(1)).await?;
+
+        let sizes = payload_sizes_by_name(&pool, since).await?;
+        assert_eq!(sizes.len(), 2);
+
+        // JSON strings are quoted, adding 2 bytes
+        let a = &sizes[0];
+        assert_eq!((a.name.as_str(), a.messages), ("a", 20));
+        assert_eq!((a.p50, a.p95, a.max), (102, 192, 202));
+
+        let b = &sizes[1];
+        assert_eq!((b.name.as_str(), b.messages, b.max), ("b", 1, 1002));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,43 @@
+use crate::models::RawMessage;
+use chrono::Utc;
+use sqlx::PgExecutor;
+
+/// Publishes a barrier message, which is only claimable once every message published before it has succeeded or
+/// is dead, e.g. to run a schema cutover after the messages of the old schema are handled.
+///
+/// The barrier is held back until [`release_barriers`](super::release_barriers) moves it to the unattempted
+/// messages, which happens whenever a message is reported succeeded or dead. Call it after publishing to release
+/// a barrier that has nothing to wait for. Barriers are released in the order they were published, so a barrier
+/// also waits for the barriers before it.
+pub async fn publish_barrier<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    message: &RawMessage,
+) -> Result<RawMessage, sqlx::Error> {
+    let now = Utc::now();
+
+    let message = sqlx::query_as!(
+        RawMessage,
+        r#"
+        INSERT INTO barriers (id, name, hash, payload, published_at)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING
+            id,
+            name,
+            hash,
+            payload,
+            0 "attempted!:i32",
+            NULL::BIGINT "fencing_token",
+            seq "seq?",
+            (SELECT qs.max_attempts FROM queue_settings qs WHERE qs.name = barriers.name) "max_attempts?"
+        "#,
+        message.id,
+        message.name,
+        message.hash,
+        message.payload,
+        now,
+    )
+    .fetch_one(tx)
+    .await?;
+
+    Ok(message)
+}
@@ -0,0 +1,34 @@
+use crate::models::{QuotaLimit, QuotaScope};
+use crate::queries::ErrorClass;
+use uuid::Uuid;
+
+/// A publish rejected because it would exceed a [`Quota`](crate::models::Quota)
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("the {limit} quota of the {scope} is {max}, publishing would make it {requested}")]
+pub struct QuotaExceeded {
+    pub scope: QuotaScope,
+    pub limit: QuotaLimit,
+    pub max: i64,
+    /// The pending messages or daily publishes the publish would have resulted in
+    pub requested: i64,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum PublishError {
+    #[error("DuplicateId: a message with id {0} was already published")]
+    DuplicateId(Uuid),
+    #[error("QuotaExceededError: {0}")]
+    QuotaExceeded(#[from] QuotaExceeded),
+    #[error("DatabaseError: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+impl PublishError {
+    pub fn class(&self) -> ErrorClass {
+        match self {
+            Self::DuplicateId(_) => ErrorClass::Conflict,
+            Self::QuotaExceeded(_) => ErrorClass::RateLimited,
+            Self::Database(error) => ErrorClass::of(error),
+        }
+    }
+}
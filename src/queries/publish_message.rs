@@ -1,4 +1,5 @@
-use crate::models::RawMessage;
+use crate::models::{PublishConflict, PublishOutcome, RawMessage};
+use crate::queries::{PublishError, notify};
 use chrono::Utc;
 use sqlx::{PgExecutor, PgTransaction, QueryBuilder};
 
@@ -18,7 +19,10 @@ pub async fn publish_message<'tx, E: PgExecutor<'tx>>(
             name,
             hash,
             payload,
-            0 "attempted!:i32"
+            0 "attempted!:i32",
+            NULL::BIGINT "fencing_token",
+            seq "seq?",
+            (SELECT qs.max_attempts FROM queue_settings qs WHERE qs.name = messages_unattempted.name) "max_attempts?"
         "#,
         message.id,
         message.name,
@@ -32,6 +36,71 @@ pub async fn publish_message<'tx, E: PgExecutor<'tx>>(
     Ok(message)
 }
 
+/// Publishes a message like [`publish_message`], handling a message with the same id published before as
+/// configured by `on_conflict` rather than failing with a unique violation.
+///
+/// Messages that were already claimed are never updated, so [`PublishConflict::UpdatePayload`] ignores them.
+pub async fn publish_message_on_conflict<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    message: &RawMessage,
+    on_conflict: PublishConflict,
+) -> Result<PublishOutcome, PublishError> {
+    let now = Utc::now();
+    let update = on_conflict == PublishConflict::UpdatePayload;
+
+    let row = sqlx::query!(
+        r#"
+        INSERT INTO messages_unattempted (id, name, hash, payload, published_at)
+        SELECT $1, $2, $3, $4, $5
+        WHERE NOT EXISTS (SELECT 1 FROM messages_attempted WHERE id = $1)
+        ON CONFLICT (id) DO UPDATE
+        SET payload = EXCLUDED.payload
+        WHERE $6
+        RETURNING
+            id,
+            name,
+            hash,
+            payload,
+            seq,
+            (SELECT qs.max_attempts FROM queue_settings qs WHERE qs.name = messages_unattempted.name) "max_attempts?",
+            -- The row version of an inserted row has no deleting transaction
+            xmax::TEXT = '0' "inserted!"
+        "#,
+        message.id,
+        message.name,
+        message.hash,
+        message.payload,
+        now,
+        update
+    )
+    .fetch_optional(tx)
+    .await?;
+
+    let Some(row) = row else {
+        return match on_conflict {
+            PublishConflict::Error => Err(PublishError::DuplicateId(message.id)),
+            PublishConflict::Ignore | PublishConflict::UpdatePayload => Ok(PublishOutcome::Ignored),
+        };
+    };
+
+    let published = RawMessage {
+        id: row.id,
+        name: row.name,
+        hash: row.hash,
+        payload: row.payload,
+        attempted: 0,
+        fencing_token: None,
+        seq: Some(row.seq),
+        max_attempts: row.max_attempts,
+    };
+
+    Ok(if row.inserted {
+        PublishOutcome::Inserted(published)
+    } else {
+        PublishOutcome::Updated(published)
+    })
+}
+
 /// Inserts one or more messages into `messages_unattempted` in a single batch
 /// and sends a **single** `pg_notify` on the given channel with the total
 /// count as payload (e.g. `"1"` for 1 message, `"5"` for 5 messages).
@@ -44,6 +113,20 @@ pub async fn publish_many_messages_with_notify(
     tx: &mut PgTransaction<'_>,
     messages: &[RawMessage],
     channel: &str,
+) -> Result<Vec<RawMessage>, sqlx::Error> {
+    let published = publish_many_messages_inner(tx, messages).await?;
+
+    if !published.is_empty() {
+        notify(&mut **tx, channel, published.len() as i64).await?;
+    }
+
+    Ok(published)
+}
+
+/// Inserts the messages like [`publish_many_messages_with_notify`] without notifying
+pub(crate) async fn publish_many_messages_inner(
+    tx: &mut PgTransaction<'_>,
+    messages: &[RawMessage],
 ) -> Result<Vec<RawMessage>, sqlx::Error> {
     if messages.is_empty() {
         return Ok(Vec::new());
@@ -70,12 +153,12 @@ pub async fn publish_many_messages_with_notify(
             .push(", ")
             .push_bind(&msg.payload)
             .push(", ")
-            .push_bind(&now)
+            .push_bind(now)
             .push(")");
     }
 
     let published: Vec<RawMessage> = query_builder
-        .push(" RETURNING id, name, hash, payload")
+        .push(" RETURNING id, name, hash, payload, seq")
         .build()
         .fetch_all(&mut **tx)
         .await?
@@ -88,19 +171,13 @@ pub async fn publish_many_messages_with_notify(
                 hash: row.get("hash"),
                 payload: row.get("payload"),
                 attempted: 0,
+                fencing_token: None,
+                seq: Some(row.get("seq")),
+                max_attempts: None,
             }
         })
         .collect();
 
-    if !published.is_empty() {
-        let count = published.len() as i64;
-        sqlx::query("SELECT pg_notify($1, $2::text)")
-            .bind(channel)
-            .bind(count)
-            .execute(&mut **tx)
-            .await?;
-    }
-
     Ok(published)
 }
 
@@ -206,4 +283,51 @@ mod tests {
 
         Ok(())
     }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_handles_duplicate_ids_as_configured(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let message = TestMessage::default().to_raw()?;
+        let outcome = publish_message_on_conflict(&pool, &message, PublishConflict::Error).await?;
+        assert!(matches!(outcome, PublishOutcome::Inserted(_)));
+
+        let duplicate = publish_message_on_conflict(&pool, &message, PublishConflict::Error).await;
+        assert!(matches!(duplicate, Err(PublishError::DuplicateId(id)) if id == message.id));
+
+        let outcome = publish_message_on_conflict(&pool, &message, PublishConflict::Ignore).await?;
+        assert!(matches!(outcome, PublishOutcome::Ignored));
+
+        let changed = RawMessage {
+            payload: json!({ "message": "changed", "value": 1 }),
+            ..message.clone()
+        };
+        let outcome =
+            publish_message_on_conflict(&pool, &changed, PublishConflict::UpdatePayload).await?;
+        assert!(matches!(outcome, PublishOutcome::Updated(m) if m.payload == changed.payload));
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_never_republishes_claimed_messages(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let message = TestMessage::default().to_raw()?;
+        publish_message(&pool, &message).await?;
+        crate::queries::get_next_unattempted(
+            &pool,
+            Utc::now(),
+            uuid::Uuid::now_v7(),
+            Duration::from_mins(1),
+        )
+        .await?
+        .expect("Expected a message");
+
+        let outcome =
+            publish_message_on_conflict(&pool, &message, PublishConflict::UpdatePayload).await?;
+        assert!(matches!(outcome, PublishOutcome::Ignored));
+        assert!(!is_pending(&pool, message.id, Utc::now()).await?);
+
+        let duplicate = publish_message_on_conflict(&pool, &message, PublishConflict::Error).await;
+        assert!(matches!(duplicate, Err(PublishError::DuplicateId(_))));
+
+        Ok(())
+    }
 }
@@ -0,0 +1,49 @@
+use crate::models::RawMessage;
+use chrono::{DateTime, Utc};
+use sqlx::PgExecutor;
+
+/// Records a message handled without passing through the queue as published and succeeded at `now`, keeping it
+/// in the history of the queue
+pub async fn publish_succeeded<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    message: &RawMessage,
+    now: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        WITH attempted AS (
+            INSERT INTO messages_attempted (id, name, hash, payload, published_at)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING id
+        )
+        INSERT INTO attempts_succeeded (message_id, succeeded_at)
+        SELECT id, $5 FROM attempted
+        "#,
+        message.id,
+        message.name,
+        message.hash,
+        message.payload,
+        now,
+    )
+    .execute(tx)
+    .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing_tools::{TestMessage, is_succeeded};
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_records_the_message_as_succeeded(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let now = Utc::now();
+        let message = TestMessage::default().to_raw()?;
+
+        publish_succeeded(&pool, &message, now).await?;
+        assert!(is_succeeded(&pool, message.id, now).await?);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,212 @@
+use chrono::{DateTime, Utc};
+use sqlx::PgExecutor;
+
+/// The outcome of a single [`purge_finished`] batch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PurgedBatch {
+    /// The number of messages deleted
+    pub deleted: u64,
+    /// The `seq` of the last message deleted, to pass as cursor to the next batch. None once nothing is left.
+    pub last_seq: Option<i64>,
+}
+
+/// Hard-deletes up to `limit` messages that succeeded or died before `finished_before`, in publish order, starting
+/// after the message with the `seq` passed as cursor.
+///
+/// Messages are deleted along with their leases, attempts, errors, lease history, claim conflicts and offloaded
+/// payloads. Dead messages are deleted whether or not they were reviewed. Daily aggregates only hold counts and are
+/// kept. Each batch locks the messages it deletes until committed, so keep batches small and call repeatedly,
+/// passing the returned cursor, until nothing is deleted, see [`Purger`](crate::purger::Purger).
+pub async fn purge_finished<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    finished_before: DateTime<Utc>,
+    after: Option<i64>,
+    limit: i64,
+) -> Result<PurgedBatch, sqlx::Error> {
+    let purged = sqlx::query!(
+        r#"
+        WITH purged AS (
+            SELECT ma.id, ma.payload
+            FROM messages_attempted ma
+            WHERE ($2::BIGINT IS NULL OR ma.seq > $2)
+              AND (
+                  EXISTS (
+                      SELECT 1 FROM attempts_succeeded s
+                      WHERE s.message_id = ma.id AND s.succeeded_at < $1
+                  )
+                  OR EXISTS (
+                      SELECT 1 FROM attempts_dead d
+                      WHERE d.message_id = ma.id AND d.dead_at < $1
+                  )
+              )
+            ORDER BY ma.seq ASC
+            LIMIT $3
+            FOR UPDATE
+        ),
+        deleted_errors AS (
+            DELETE FROM errors WHERE message_id IN (SELECT id FROM purged)
+        ),
+        deleted_failed AS (
+            DELETE FROM attempts_failed WHERE message_id IN (SELECT id FROM purged)
+        ),
+        deleted_retained AS (
+            DELETE FROM attempts_failed_retained WHERE message_id IN (SELECT id FROM purged)
+        ),
+        deleted_succeeded AS (
+            DELETE FROM attempts_succeeded WHERE message_id IN (SELECT id FROM purged)
+        ),
+        deleted_dead AS (
+            DELETE FROM attempts_dead WHERE message_id IN (SELECT id FROM purged)
+        ),
+        deleted_leases AS (
+            DELETE FROM leases WHERE message_id IN (SELECT id FROM purged)
+        ),
+        deleted_history AS (
+            DELETE FROM lease_history WHERE message_id IN (SELECT id FROM purged)
+        ),
+        deleted_conflicts AS (
+            DELETE FROM claim_conflicts WHERE message_id IN (SELECT id FROM purged)
+        ),
+        deleted_blobs AS (
+            DELETE FROM message_blobs
+            WHERE id IN (SELECT (payload->>'$fx_mq_blob')::UUID FROM purged)
+        ),
+        deleted AS (
+            DELETE FROM messages_attempted WHERE id IN (SELECT id FROM purged)
+            RETURNING seq
+        )
+        SELECT COUNT(*) "deleted!", MAX(seq) "last_seq"
+        FROM deleted
+        "#,
+        finished_before,
+        after,
+        limit
+    )
+    .fetch_one(tx)
+    .await?;
+
+    Ok(PurgedBatch {
+        deleted: u64::try_from(purged.deleted).unwrap_or(0),
+        last_seq: purged.last_seq,
+    })
+}
+
+/// Updates the planner statistics of the tables [`purge_finished`] deletes from, which are off after a large purge
+/// until autovacuum catches up
+pub async fn analyze_purged<'tx, E: PgExecutor<'tx>>(tx: E) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        ANALYZE messages_attempted, attempts_succeeded, attempts_dead, attempts_failed, errors, leases,
+            lease_history, claim_conflicts, message_blobs
+        "#,
+    )
+    .execute(tx)
+    .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attachments::{Attachments, PgBlobStore};
+    use crate::queries::{
+        get_next_unattempted, publish_message, report_dead, report_retryable, report_success,
+    };
+    use crate::testing_tools::{TestMessage, get_all_messages};
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_purges_finished_messages_in_batches(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let now = Utc::now();
+        let host_id = Uuid::now_v7();
+        let hold_for = Duration::from_mins(1);
+
+        let mut finished = Vec::new();
+        for i in 0..3 {
+            publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+            let message = get_next_unattempted(&pool, now, host_id, hold_for)
+                .await?
+                .expect("Expected a message");
+            if i == 1 {
+                report_dead(&pool, message.id, message.fencing_token, now, "err").await?;
+            } else {
+                report_success(&pool, message.id, message.fencing_token, now).await?;
+            }
+            finished.push(message.seq);
+        }
+
+        // Failed, in progress and pending messages are kept
+        publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+        let failed = get_next_unattempted(&pool, now, host_id, hold_for)
+            .await?
+            .expect("Expected a message");
+        report_retryable(&pool, failed.id, failed.fencing_token, now, 1, now, "err").await?;
+        publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+        get_next_unattempted(&pool, now, host_id, hold_for).await?;
+        let pending = publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+
+        // Messages finished after the cutoff are kept
+        assert_eq!(
+            purge_finished(&pool, now, None, 10).await?,
+            PurgedBatch {
+                deleted: 0,
+                last_seq: None
+            }
+        );
+
+        let cutoff = now + Duration::from_secs(1);
+        let first = purge_finished(&pool, cutoff, None, 2).await?;
+        assert_eq!(first.deleted, 2);
+        assert_eq!(first.last_seq, finished[1]);
+        let second = purge_finished(&pool, cutoff, first.last_seq, 2).await?;
+        assert_eq!(second.deleted, 1);
+        assert_eq!(second.last_seq, finished[2]);
+        assert_eq!(
+            purge_finished(&pool, cutoff, second.last_seq, 2)
+                .await?
+                .deleted,
+            0
+        );
+        analyze_purged(&pool).await?;
+
+        let remaining: Vec<Uuid> = get_all_messages(&pool)
+            .await?
+            .into_iter()
+            .map(|message| message.id)
+            .collect();
+        assert_eq!(remaining.len(), 3);
+        assert!(remaining.contains(&failed.id));
+        assert!(remaining.contains(&pending.id));
+        let errors: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM errors")
+            .fetch_one(&pool)
+            .await?;
+        assert_eq!(errors, 1);
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_purges_the_blobs_of_finished_messages(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let now = Utc::now();
+        let attachments = Attachments::new(PgBlobStore::new(pool.clone(), "public")?, 0);
+        let message = attachments
+            .offload(TestMessage::default().to_raw()?)
+            .await?;
+        publish_message(&pool, &message).await?;
+        let message = get_next_unattempted(&pool, now, Uuid::now_v7(), Duration::from_mins(1))
+            .await?
+            .expect("Expected a message");
+        report_success(&pool, message.id, message.fencing_token, now).await?;
+
+        let purged = purge_finished(&pool, now + Duration::from_secs(1), None, 10).await?;
+        assert_eq!(purged.deleted, 1);
+        let blobs: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM message_blobs")
+            .fetch_one(&pool)
+            .await?;
+        assert_eq!(blobs, 0);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,25 @@
+use chrono::{DateTime, Utc};
+use sqlx::PgExecutor;
+use uuid::Uuid;
+
+/// Stores an offloaded payload in `message_blobs`
+pub async fn put_blob<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    id: Uuid,
+    data: &serde_json::Value,
+    now: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO message_blobs (id, data, stored_at)
+        VALUES ($1, $2, $3)
+        "#,
+        id,
+        data,
+        now
+    )
+    .execute(tx)
+    .await?;
+
+    Ok(())
+}
@@ -0,0 +1,52 @@
+use crate::models::QueueSettings;
+use chrono::{DateTime, Utc};
+use sqlx::PgExecutor;
+
+/// Stores the settings of a message name, replacing any previous settings of that name
+pub async fn put_queue_settings<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    settings: &QueueSettings,
+    now: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO queue_settings (name, backoff_base, backoff_base_delay_ms, max_attempts, hold_for_ms, updated_at)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        ON CONFLICT (name) DO UPDATE
+        SET backoff_base = EXCLUDED.backoff_base,
+            backoff_base_delay_ms = EXCLUDED.backoff_base_delay_ms,
+            max_attempts = EXCLUDED.max_attempts,
+            hold_for_ms = EXCLUDED.hold_for_ms,
+            updated_at = EXCLUDED.updated_at
+        "#,
+        settings.name,
+        i32::try_from(settings.backoff_base).unwrap_or(i32::MAX),
+        i64::try_from(settings.backoff_base_delay.as_millis()).unwrap_or(i64::MAX),
+        settings.max_attempts,
+        i64::try_from(settings.hold_for.as_millis()).unwrap_or(i64::MAX),
+        now
+    )
+    .execute(tx)
+    .await?;
+
+    Ok(())
+}
+
+/// Removes the settings of a message name, so that workers fall back to their defaults.
+/// Returns false if the name had no settings.
+pub async fn delete_queue_settings<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    name: &str,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM queue_settings
+        WHERE name = $1
+        "#,
+        name
+    )
+    .execute(tx)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
@@ -0,0 +1,47 @@
+use crate::models::{Quota, QuotaScope};
+use chrono::{DateTime, Utc};
+use sqlx::PgExecutor;
+
+/// Stores a quota, replacing any previous quota of its scope
+pub async fn put_quota<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    quota: &Quota,
+    now: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO quotas (name, max_pending, max_daily_publishes, updated_at)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (name) DO UPDATE
+        SET max_pending = EXCLUDED.max_pending,
+            max_daily_publishes = EXCLUDED.max_daily_publishes,
+            updated_at = EXCLUDED.updated_at
+        "#,
+        quota.scope.key(),
+        quota.max_pending,
+        quota.max_daily_publishes,
+        now
+    )
+    .execute(tx)
+    .await?;
+
+    Ok(())
+}
+
+/// Removes the quota of a scope. Returns false if the scope had no quota.
+pub async fn delete_quota<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    scope: &QuotaScope,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM quotas
+        WHERE name = $1
+        "#,
+        scope.key()
+    )
+    .execute(tx)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
@@ -0,0 +1,27 @@
+use chrono::{DateTime, Utc};
+use sqlx::PgExecutor;
+use uuid::Uuid;
+
+/// Records that a report of `message_id` with `fencing_token` was rejected because the message had been
+/// reclaimed, meaning that it was, or may have been, processed twice.
+pub async fn record_claim_conflict<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    message_id: Uuid,
+    fencing_token: i64,
+    reported_at: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO claim_conflicts (id, message_id, fencing_token, reported_at)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        Uuid::now_v7(),
+        message_id,
+        fencing_token,
+        reported_at
+    )
+    .execute(tx)
+    .await?;
+
+    Ok(())
+}
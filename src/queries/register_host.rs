@@ -0,0 +1,59 @@
+use chrono::{DateTime, Utc};
+use sqlx::PgExecutor;
+use uuid::Uuid;
+
+/// Registers a human-readable label for a host, such as its hostname, pod name and version.
+///
+/// Registering an already registered host replaces its label.
+pub async fn register_host<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    host_id: Uuid,
+    label: &str,
+    now: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO hosts (id, label, registered_at)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (id) DO UPDATE
+        SET label = EXCLUDED.label,
+            registered_at = EXCLUDED.registered_at
+        "#,
+        host_id,
+        label,
+        now
+    )
+    .execute(tx)
+    .await?;
+
+    Ok(())
+}
+
+/// Registers a host like [`register_host`], along with the deployment `epoch` it runs in, see
+/// [`activate_epoch`](super::activate_epoch)
+pub async fn register_host_in_epoch<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    host_id: Uuid,
+    label: &str,
+    epoch: i64,
+    now: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO hosts (id, label, epoch, registered_at)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (id) DO UPDATE
+        SET label = EXCLUDED.label,
+            epoch = EXCLUDED.epoch,
+            registered_at = EXCLUDED.registered_at
+        "#,
+        host_id,
+        label,
+        epoch,
+        now
+    )
+    .execute(tx)
+    .await?;
+
+    Ok(())
+}
@@ -0,0 +1,84 @@
+use sqlx::PgExecutor;
+
+/// Releases the oldest barrier if every message published before it has succeeded or is dead, returning the
+/// number of barriers released. Released barriers are claimable like unattempted messages.
+///
+/// Reporting a message succeeded or dead releases barriers as part of the report, so this only needs to be called
+/// after publishing a barrier or deleting messages a barrier waits for.
+pub async fn release_barriers<'tx, E: PgExecutor<'tx>>(tx: E) -> Result<i32, sqlx::Error> {
+    let released = sqlx::query_scalar!(r#"SELECT release_barriers() "released!""#)
+        .fetch_one(tx)
+        .await?;
+
+    Ok(released)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queries::{get_next_unattempted, publish_barrier, publish_message, report_success};
+    use crate::testing_tools::TestMessage;
+    use chrono::Utc;
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_releases_barriers_once_prior_messages_are_terminal(
+        pool: sqlx::PgPool,
+    ) -> anyhow::Result<()> {
+        let host_id = Uuid::now_v7();
+        let hold_for = Duration::from_mins(1);
+
+        let before = publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+        let barrier = publish_barrier(&pool, &TestMessage::default().to_raw()?).await?;
+        let after = publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+        assert_eq!(release_barriers(&pool).await?, 0);
+
+        // The barrier is held back while the message before it is in progress
+        let claimed = get_next_unattempted(&pool, Utc::now(), host_id, hold_for)
+            .await?
+            .expect("Expected a message");
+        assert_eq!(claimed.id, before.id);
+        let claimed = get_next_unattempted(&pool, Utc::now(), host_id, hold_for)
+            .await?
+            .expect("Expected a message");
+        assert_eq!(claimed.id, after.id);
+        assert!(
+            get_next_unattempted(&pool, Utc::now(), host_id, hold_for)
+                .await?
+                .is_none()
+        );
+
+        // Reporting the message before it released the barrier
+        report_success(&pool, before.id, None, Utc::now()).await?;
+        let claimed = get_next_unattempted(&pool, Utc::now(), host_id, hold_for)
+            .await?
+            .expect("Expected a message");
+        assert_eq!(claimed.id, barrier.id);
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_releases_barriers_in_publish_order(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let host_id = Uuid::now_v7();
+
+        let first = publish_barrier(&pool, &TestMessage::default().to_raw()?).await?;
+        let second = publish_barrier(&pool, &TestMessage::default().to_raw()?).await?;
+        assert_eq!(release_barriers(&pool).await?, 1);
+        assert_eq!(release_barriers(&pool).await?, 0);
+
+        let claimed = get_next_unattempted(&pool, Utc::now(), host_id, Duration::from_mins(1))
+            .await?
+            .expect("Expected a message");
+        assert_eq!(claimed.id, first.id);
+        report_success(&pool, first.id, None, Utc::now()).await?;
+
+        let claimed = get_next_unattempted(&pool, Utc::now(), host_id, Duration::from_mins(1))
+            .await?
+            .expect("Expected a message");
+        assert_eq!(claimed.id, second.id);
+
+        Ok(())
+    }
+}
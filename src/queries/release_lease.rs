@@ -0,0 +1,107 @@
+use chrono::{DateTime, Utc};
+use sqlx::PgExecutor;
+use uuid::Uuid;
+
+/// Releases the active lease held by `host_id` on a message by expiring it at `now`.
+///
+/// The message is left attempted with an expired lease, i.e. missing, so that it may be claimed again by any host
+/// through `get_next_missing`. When a `fencing_token` is given, only the lease of the claim that returned it is
+/// released, so that a host can't release a later claim of its own with a stale message. Returns true if a lease was
+/// released.
+pub async fn release_lease<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    message_id: Uuid,
+    fencing_token: Option<i64>,
+    now: DateTime<Utc>,
+    host_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE leases
+        SET expires_at = $2
+        WHERE message_id = $1
+          AND acquired_by = $3
+          AND expires_at > $2
+          AND ($4::BIGINT IS NULL OR fencing_token = $4)
+        "#,
+        message_id,
+        now,
+        host_id,
+        fencing_token
+    )
+    .execute(tx)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        queries::{get_next_missing, get_next_unattempted, publish_message},
+        testing_tools::{TestMessage, is_missing},
+    };
+    use std::time::Duration;
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_releases_a_held_lease(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let now = Utc::now();
+        let host_id = Uuid::now_v7();
+        let hold_for = Duration::from_mins(1);
+
+        let published = publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+        get_next_unattempted(&pool, now, host_id, hold_for)
+            .await?
+            .expect("Expected a message");
+
+        assert!(release_lease(&pool, published.id, None, now, host_id).await?);
+
+        let later = now + Duration::from_millis(1);
+        assert!(is_missing(&pool, published.id, later).await?);
+
+        let reclaimed = get_next_missing(&pool, later, Uuid::now_v7(), hold_for)
+            .await?
+            .expect("Expected the released message to be claimable");
+        assert_eq!(reclaimed.id, published.id);
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_does_not_release_leases_of_other_hosts(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let now = Utc::now();
+        let host_id = Uuid::now_v7();
+        let hold_for = Duration::from_mins(1);
+
+        let published = publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+        get_next_unattempted(&pool, now, host_id, hold_for)
+            .await?
+            .expect("Expected a message");
+
+        assert!(!release_lease(&pool, published.id, None, now, Uuid::now_v7()).await?);
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_does_not_release_later_claims(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let now = Utc::now();
+        let host_id = Uuid::now_v7();
+        let hold_for = Duration::from_mins(1);
+
+        publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+        let stale = get_next_unattempted(&pool, now, host_id, hold_for)
+            .await?
+            .expect("Expected a message");
+        let later = now + hold_for * 2;
+        let current = get_next_missing(&pool, later, host_id, hold_for)
+            .await?
+            .expect("Expected the expired message to be reclaimed");
+
+        assert!(!release_lease(&pool, stale.id, stale.fencing_token, later, host_id).await?);
+        assert!(release_lease(&pool, current.id, current.fencing_token, later, host_id).await?);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,71 @@
+use chrono::{DateTime, Utc};
+use sqlx::PgExecutor;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Extends the lease with `fencing_token` on a message to expire `hold_for` after `now`, returning the new expiry.
+///
+/// Returns None if the lease has expired or was replaced, in which case the message may have been claimed by
+/// another host and its handling should be abandoned.
+pub async fn renew_lease<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    message_id: Uuid,
+    fencing_token: i64,
+    now: DateTime<Utc>,
+    hold_for: Duration,
+) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+    let expires_at = now + hold_for;
+
+    sqlx::query_scalar!(
+        r#"
+        UPDATE leases
+        SET expires_at = $4
+        WHERE message_id = $1 AND fencing_token = $2 AND expires_at > $3
+        RETURNING expires_at
+        "#,
+        message_id,
+        fencing_token,
+        now,
+        expires_at
+    )
+    .fetch_optional(tx)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queries::{get_next_unattempted, publish_message};
+    use crate::testing_tools::{TestMessage, is_in_progress};
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_renews_active_leases(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+        let now = Utc::now();
+        let hold_for = Duration::from_mins(1);
+        let claimed = get_next_unattempted(&pool, now, Uuid::now_v7(), hold_for)
+            .await?
+            .expect("Expected a message");
+        let token = claimed.fencing_token.expect("Expected a fencing token");
+
+        let renewed_at = now + Duration::from_secs(50);
+        let expires_at = renew_lease(&pool, claimed.id, token, renewed_at, hold_for).await?;
+        assert!(expires_at.is_some());
+        assert!(is_in_progress(&pool, claimed.id, now + Duration::from_secs(90)).await?);
+
+        let stale = renew_lease(&pool, claimed.id, token + 1, renewed_at, hold_for).await?;
+        assert_eq!(stale, None);
+
+        let expired = renew_lease(
+            &pool,
+            claimed.id,
+            token,
+            now + Duration::from_mins(5),
+            hold_for,
+        )
+        .await?;
+        assert_eq!(expired, None);
+
+        Ok(())
+    }
+}
@@ -1,40 +1,119 @@
+use crate::models::{FailureReason, MessageStatus};
+use crate::queries::ReportError;
 use chrono::{DateTime, Utc};
 use sqlx::PgExecutor;
 use uuid::Uuid;
 
-pub async fn report_dead<'tx, E: PgExecutor<'tx>>(
+/// Reports a message as dead, it will not be attempted again.
+///
+/// When a `fencing_token` is given the report is rejected with [`ReportError::StaleFencingToken`] unless the message
+/// is still leased with that token. Messages that were not claimed or are already finished are rejected with
+/// [`ReportError::InvalidTransition`].
+pub async fn report_dead<'tx, 'a, E: PgExecutor<'tx>>(
     tx: E,
     message_id: Uuid,
+    fencing_token: Option<i64>,
     now: DateTime<Utc>,
-    error: &str,
-) -> Result<(), sqlx::Error> {
+    error: impl Into<FailureReason<'a>>,
+) -> Result<(), ReportError> {
+    report_dead_inner(tx, message_id, fencing_token, now, Some(error.into()), None).await
+}
+
+/// Reports a message as dead, inserting `error` unless it is None, e.g. when errors are written by an
+/// [`ErrorWriter`](crate::listener::ErrorWriter), and copying its `retain_failed` most recent failed attempts to
+/// `attempts_failed_retained` unless it is None
+pub(crate) async fn report_dead_inner<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    message_id: Uuid,
+    fencing_token: Option<i64>,
+    now: DateTime<Utc>,
+    error: Option<FailureReason<'_>>,
+    retain_failed: Option<i64>,
+) -> Result<(), ReportError> {
     let dead_id = Uuid::now_v7();
 
-    sqlx::query!(
+    let row = sqlx::query!(
         r#"
-        WITH del_leases AS (
+        WITH fence AS (
+            SELECT 1
+            FROM leases
+            WHERE message_id = $2 AND fencing_token = $5
+            FOR UPDATE
+        ),
+        -- Only attempted messages that are not finished yet may be reported
+        state AS (
+            SELECT CASE
+                WHEN EXISTS (SELECT 1 FROM attempts_succeeded WHERE message_id = $2) THEN 'succeeded'
+                WHEN EXISTS (SELECT 1 FROM attempts_dead WHERE message_id = $2) THEN 'dead'
+                WHEN EXISTS (SELECT 1 FROM messages_attempted WHERE id = $2) THEN 'attempted'
+                WHEN EXISTS (SELECT 1 FROM messages_unattempted WHERE id = $2) THEN 'pending'
+            END AS label
+        ),
+        valid AS (
+            SELECT COALESCE((SELECT label FROM state) = 'attempted', FALSE)
+                AND ($5::BIGINT IS NULL OR EXISTS (SELECT 1 FROM fence)) AS ok
+        ),
+        del_leases AS (
             DELETE FROM leases
-            WHERE message_id = $2
+            WHERE message_id = $2 AND (SELECT ok FROM valid)
+        ),
+        -- Kept for postmortems, the deleted rows are visible to this insert
+        ins_retained AS (
+            INSERT INTO attempts_failed_retained (
+                id,
+                message_id,
+                failed_at,
+                attempted,
+                retry_earliest_at,
+                attempted_by
+            )
+            SELECT fa.id, fa.message_id, fa.failed_at, fa.attempted, fa.retry_earliest_at, fa.attempted_by
+            FROM attempts_failed fa
+            WHERE fa.message_id = $2 AND $7::BIGINT IS NOT NULL AND (SELECT ok FROM valid)
+            ORDER BY fa.failed_at DESC, fa.id DESC
+            LIMIT $7
+            ON CONFLICT (id) DO NOTHING
         ),
         del_failed AS (
             DELETE FROM attempts_failed
-            WHERE message_id = $2
+            WHERE message_id = $2 AND (SELECT ok FROM valid)
         ),
         ins_dead AS (
-            INSERT INTO attempts_dead (message_id, dead_at)
-            VALUES ($2, $3)
+            INSERT INTO attempts_dead (message_id, dead_at, attempted)
+            SELECT
+                $2,
+                $3,
+                -- The failed attempts and this one, read before del_failed deletes them
+                COALESCE((SELECT MAX(fa.attempted) FROM attempts_failed fa WHERE fa.message_id = $2), 0) + 1
+            WHERE (SELECT ok FROM valid)
+        ),
+        ins_error AS (
+            INSERT INTO errors (id, message_id, reported_at, error, category)
+            SELECT $1, $2, $3, $4, $6
+            WHERE (SELECT ok FROM valid) AND $4::TEXT IS NOT NULL
         )
-        INSERT INTO errors (id, message_id, reported_at, error)
-        VALUES ($1, $2, $3, $4)
+        SELECT v.ok "ok!", s.label
+        FROM valid v, state s
         "#,
         dead_id,
         message_id,
         now,
-        error
+        error.map(|e| e.error),
+        fencing_token,
+        error.and_then(|e| e.category),
+        retain_failed
     )
-    .execute(tx)
+    .fetch_one(tx)
     .await?;
 
+    if !row.ok {
+        return Err(ReportError::rejected(
+            message_id,
+            row.label.as_deref(),
+            MessageStatus::Dead,
+        ));
+    }
+
     Ok(())
 }
 
@@ -58,7 +137,7 @@ mod tests {
 
         get_next_unattempted(&pool, now, host_id, hold_for).await?;
 
-        report_dead(&pool, published.id, now, "some error happend").await?;
+        report_dead(&pool, published.id, None, now, "some error happend").await?;
 
         assert!(is_dead(&pool, published.id, now).await?);
 
@@ -72,7 +151,7 @@ mod tests {
 
         let published = publish_message(&pool, &message.to_raw()?).await?;
 
-        let result = report_dead(&pool, published.id, now, "some error happend").await;
+        let result = report_dead(&pool, published.id, None, now, "some error happend").await;
 
         assert!(result.is_err());
 
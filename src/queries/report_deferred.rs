@@ -0,0 +1,133 @@
+use crate::models::MessageStatus;
+use crate::queries::ReportError;
+use chrono::{DateTime, Utc};
+use sqlx::PgExecutor;
+use uuid::Uuid;
+
+/// Releases a claimed message without counting the attempt, it may be claimed again from `until`.
+///
+/// Unlike [`report_retryable`](super::report_retryable) no error is recorded, pass the attempt count of the
+/// claimed message unchanged.
+///
+/// When a `fencing_token` is given the report is rejected with [`ReportError::StaleFencingToken`] unless the message
+/// is still leased with that token. Messages that were not claimed or are already finished are rejected with
+/// [`ReportError::InvalidTransition`].
+pub async fn report_deferred<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    message_id: Uuid,
+    fencing_token: Option<i64>,
+    deferred_at: DateTime<Utc>,
+    attempted: i32,
+    until: DateTime<Utc>,
+) -> Result<(), ReportError> {
+    let failed_id = Uuid::now_v7();
+
+    let row = sqlx::query!(
+        r#"
+        WITH fence AS (
+            SELECT 1
+            FROM leases
+            WHERE message_id = $1 AND fencing_token = $6
+            FOR UPDATE
+        ),
+        -- Only attempted messages that are not finished yet may be reported
+        state AS (
+            SELECT CASE
+                WHEN EXISTS (SELECT 1 FROM attempts_succeeded WHERE message_id = $1) THEN 'succeeded'
+                WHEN EXISTS (SELECT 1 FROM attempts_dead WHERE message_id = $1) THEN 'dead'
+                WHEN EXISTS (SELECT 1 FROM messages_attempted WHERE id = $1) THEN 'attempted'
+                WHEN EXISTS (SELECT 1 FROM messages_unattempted WHERE id = $1) THEN 'pending'
+            END AS label
+        ),
+        valid AS (
+            SELECT COALESCE((SELECT label FROM state) = 'attempted', FALSE)
+                AND ($6::BIGINT IS NULL OR EXISTS (SELECT 1 FROM fence)) AS ok
+        ),
+        del_leases AS (
+            DELETE FROM leases
+            WHERE message_id = $1 AND (SELECT ok FROM valid)
+            RETURNING acquired_by, expires_at
+        ),
+        ins_failed AS (
+            INSERT INTO attempts_failed (
+                id,
+                message_id,
+                failed_at,
+                attempted,
+                retry_earliest_at,
+                attempted_by
+            )
+            SELECT $2, $1, $3, $4, $5, (SELECT acquired_by FROM del_leases ORDER BY expires_at DESC LIMIT 1)
+            WHERE (SELECT ok FROM valid)
+        )
+        SELECT v.ok "ok!", s.label
+        FROM valid v, state s
+        "#,
+        message_id,
+        failed_id,
+        deferred_at,
+        attempted,
+        until,
+        fencing_token
+    )
+    .fetch_one(tx)
+    .await?;
+
+    if !row.ok {
+        return Err(ReportError::rejected(
+            message_id,
+            row.label.as_deref(),
+            MessageStatus::Failed,
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        queries::{get_next_retryable, get_next_unattempted, publish_message},
+        testing_tools::{TestMessage, is_failed},
+    };
+    use std::time::Duration;
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_defers_without_counting_the_attempt(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let now = Utc::now();
+        let host_id = Uuid::now_v7();
+        let hold_for = Duration::from_mins(1);
+        let until = now + Duration::from_mins(5);
+
+        publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+        let claimed = get_next_unattempted(&pool, now, host_id, hold_for)
+            .await?
+            .unwrap();
+
+        report_deferred(
+            &pool,
+            claimed.id,
+            claimed.fencing_token,
+            now,
+            claimed.attempted,
+            until,
+        )
+        .await?;
+
+        assert!(is_failed(&pool, claimed.id, now).await?);
+        assert!(
+            get_next_retryable(&pool, now, host_id, hold_for)
+                .await?
+                .is_none()
+        );
+
+        let reclaimed = get_next_retryable(&pool, until, host_id, hold_for)
+            .await?
+            .unwrap();
+        assert_eq!(reclaimed.id, claimed.id);
+        assert_eq!(reclaimed.attempted, 0);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,47 @@
+use crate::models::MessageStatus;
+use crate::queries::ErrorClass;
+use uuid::Uuid;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ReportError {
+    #[error("StaleFencingToken: message {0} is no longer leased with the given fencing token")]
+    StaleFencingToken(Uuid),
+    #[error("InvalidTransition: message {message_id} can't be reported {to:?} when it is {from:?}")]
+    InvalidTransition {
+        message_id: Uuid,
+        /// The state the message was in when it was reported
+        from: MessageStatus,
+        /// The state the report would have moved the message to
+        to: MessageStatus,
+    },
+    #[error("NotFound: message {0} does not exist")]
+    NotFound(Uuid),
+    #[error("DatabaseError: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+impl ReportError {
+    pub fn class(&self) -> ErrorClass {
+        match self {
+            Self::StaleFencingToken(_) => ErrorClass::Conflict,
+            Self::InvalidTransition { .. } | Self::NotFound(_) => ErrorClass::Permanent,
+            Self::Database(error) => ErrorClass::of(error),
+        }
+    }
+}
+
+impl ReportError {
+    /// The error of a report rejected with the message in `state`, the label the report queries evaluate to
+    pub(crate) fn rejected(message_id: Uuid, state: Option<&str>, to: MessageStatus) -> Self {
+        match state {
+            None => Self::NotFound(message_id),
+            // Reportable, so the fencing token didn't match
+            Some("attempted") => Self::StaleFencingToken(message_id),
+            Some(label) => Self::InvalidTransition {
+                message_id,
+                from: MessageStatus::from_label(label),
+                to,
+            },
+        }
+    }
+}
@@ -0,0 +1,109 @@
+use crate::backoff::Backoff;
+use crate::models::{FailureOutcome, FailureReason, RawMessage};
+use crate::queries::{ReportError, report_dead, report_retryable};
+use chrono::{DateTime, Utc};
+use sqlx::{PgExecutor, PgTransaction};
+
+/// Reports a failed attempt of `message`, retrying it as scheduled by `backoff`, or reporting it dead if the retry
+/// would fall outside the [`give_up_after`](Backoff::give_up_after) window of the backoff.
+pub async fn report_failure<'a>(
+    tx: &mut PgTransaction<'_>,
+    message: &RawMessage,
+    now: DateTime<Utc>,
+    backoff: &impl Backoff,
+    error: impl Into<FailureReason<'a>>,
+) -> Result<FailureOutcome, ReportError> {
+    let error = error.into();
+    match retry_at(&mut **tx, message, now, backoff).await? {
+        Some(at) => {
+            report_retryable(
+                &mut **tx,
+                message.id,
+                message.fencing_token,
+                now,
+                message.attempted + 1,
+                at,
+                error,
+            )
+            .await?;
+            Ok(FailureOutcome::Retrying { at })
+        }
+        None => {
+            report_dead(&mut **tx, message.id, message.fencing_token, now, error).await?;
+            Ok(FailureOutcome::Dead)
+        }
+    }
+}
+
+/// Returns the time of the next attempt of `message`, or None if it should be given up on
+pub(crate) async fn retry_at<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    message: &RawMessage,
+    now: DateTime<Utc>,
+    backoff: &impl Backoff,
+) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+    let attempted = message.attempted + 1;
+    if backoff.give_up_after().is_none() {
+        return Ok(Some(backoff.try_at(attempted, now)));
+    }
+
+    let published_at = sqlx::query_scalar!(
+        r#"
+        SELECT published_at FROM messages_attempted WHERE id = $1
+        "#,
+        message.id
+    )
+    .fetch_one(tx)
+    .await?;
+
+    Ok(backoff.retry_at(attempted, now, published_at))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backoff::{ConstantBackoff, ExponentialBackoff};
+    use crate::testing_tools::{claim, is_dead, is_failed};
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_retries_within_the_window(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let message = claim(&pool, Uuid::now_v7()).await?;
+        let now = Utc::now();
+        let backoff = ExponentialBackoff::new(2, Duration::from_mins(1))
+            .with_max_delay(Duration::from_mins(10))
+            .with_give_up_after(Duration::from_hours(1));
+
+        let mut tx = pool.begin().await?;
+        let outcome = report_failure(&mut tx, &message, now, &backoff, "timed out").await?;
+        tx.commit().await?;
+
+        assert_eq!(
+            outcome,
+            FailureOutcome::Retrying {
+                at: now + Duration::from_mins(1)
+            }
+        );
+        assert!(is_failed(&pool, message.id, now).await?);
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_gives_up_after_the_window(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let message = claim(&pool, Uuid::now_v7()).await?;
+        let now = Utc::now();
+        let backoff = ConstantBackoff::new(Duration::from_mins(1))
+            .with_give_up_after(Duration::from_secs(30));
+
+        let mut tx = pool.begin().await?;
+        let outcome = report_failure(&mut tx, &message, now, &backoff, "timed out").await?;
+        tx.commit().await?;
+
+        assert_eq!(outcome, FailureOutcome::Dead);
+        assert!(is_dead(&pool, message.id, now).await?);
+
+        Ok(())
+    }
+}
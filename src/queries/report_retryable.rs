@@ -1,23 +1,108 @@
+use crate::models::{FailureReason, MessageStatus};
+use crate::queries::ReportError;
 use chrono::{DateTime, Utc};
 use sqlx::PgExecutor;
 use uuid::Uuid;
 
-pub async fn report_retryable<'tx, E: PgExecutor<'tx>>(
+/// Reports a failed attempt of a message, it may be retried from `retry_earliest_at`.
+///
+/// When a `fencing_token` is given the report is rejected with [`ReportError::StaleFencingToken`] unless the message
+/// is still leased with that token. Messages that were not claimed or are already finished are rejected with
+/// [`ReportError::InvalidTransition`].
+pub async fn report_retryable<'tx, 'a, E: PgExecutor<'tx>>(
     tx: E,
     message_id: Uuid,
+    fencing_token: Option<i64>,
     attempted_at: DateTime<Utc>,
     attempted: i32, // increment this before passing to the query!
     retry_earliest_at: DateTime<Utc>,
-    error: &str,
-) -> Result<(), sqlx::Error> {
+    error: impl Into<FailureReason<'a>>,
+) -> Result<(), ReportError> {
+    report_retryable_inner(
+        tx,
+        message_id,
+        fencing_token,
+        attempted_at,
+        attempted,
+        retry_earliest_at,
+        Some(error.into()),
+        None,
+        None,
+    )
+    .await
+}
+
+/// Reports a failed attempt of a message like [`report_retryable`], keeping only the `max_errors` most recent errors
+/// of the message. Older errors are deleted in the same statement that inserts the new one, so that messages retried
+/// many times don't bloat the `errors` table. The new error is always kept, even with a cap of zero.
+#[allow(clippy::too_many_arguments)]
+pub async fn report_retryable_capped<'tx, 'a, E: PgExecutor<'tx>>(
+    tx: E,
+    message_id: Uuid,
+    fencing_token: Option<i64>,
+    attempted_at: DateTime<Utc>,
+    attempted: i32, // increment this before passing to the query!
+    retry_earliest_at: DateTime<Utc>,
+    error: impl Into<FailureReason<'a>>,
+    max_errors: u32,
+) -> Result<(), ReportError> {
+    report_retryable_inner(
+        tx,
+        message_id,
+        fencing_token,
+        attempted_at,
+        attempted,
+        retry_earliest_at,
+        Some(error.into()),
+        Some(i64::from(max_errors)),
+        None,
+    )
+    .await
+}
+
+/// Reports a failed attempt, inserting `error` unless it is None, e.g. when errors are written by an
+/// [`ErrorWriter`](crate::listener::ErrorWriter). With `max_failed`, only that many of the most recent failed attempts of
+/// the message are kept, including the new one.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn report_retryable_inner<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    message_id: Uuid,
+    fencing_token: Option<i64>,
+    attempted_at: DateTime<Utc>,
+    attempted: i32,
+    retry_earliest_at: DateTime<Utc>,
+    error: Option<FailureReason<'_>>,
+    max_errors: Option<i64>,
+    max_failed: Option<i64>,
+) -> Result<(), ReportError> {
     let failed_id = Uuid::now_v7();
     let error_id = Uuid::now_v7();
 
-    sqlx::query!(
+    let row = sqlx::query!(
         r#"
-        WITH del_leases AS (
+        WITH fence AS (
+            SELECT 1
+            FROM leases
+            WHERE message_id = $1 AND fencing_token = $8
+            FOR UPDATE
+        ),
+        -- Only attempted messages that are not finished yet may be reported
+        state AS (
+            SELECT CASE
+                WHEN EXISTS (SELECT 1 FROM attempts_succeeded WHERE message_id = $1) THEN 'succeeded'
+                WHEN EXISTS (SELECT 1 FROM attempts_dead WHERE message_id = $1) THEN 'dead'
+                WHEN EXISTS (SELECT 1 FROM messages_attempted WHERE id = $1) THEN 'attempted'
+                WHEN EXISTS (SELECT 1 FROM messages_unattempted WHERE id = $1) THEN 'pending'
+            END AS label
+        ),
+        valid AS (
+            SELECT COALESCE((SELECT label FROM state) = 'attempted', FALSE)
+                AND ($8::BIGINT IS NULL OR EXISTS (SELECT 1 FROM fence)) AS ok
+        ),
+        del_leases AS (
             DELETE FROM leases
-            WHERE message_id = $1
+            WHERE message_id = $1 AND (SELECT ok FROM valid)
+            RETURNING acquired_by, expires_at
         ),
         ins_failed AS (
             INSERT INTO attempts_failed (
@@ -25,17 +110,53 @@ pub async fn report_retryable<'tx, E: PgExecutor<'tx>>(
                 message_id,
                 failed_at,
                 attempted,
-                retry_earliest_at
+                retry_earliest_at,
+                attempted_by
             )
-            VALUES ($2, $1, $3, $4, $5)
-        )
-        INSERT INTO errors (
-            id,
-            message_id,
-            reported_at,
-            error
+            SELECT $2, $1, $3, $4, $5, (SELECT acquired_by FROM del_leases ORDER BY expires_at DESC LIMIT 1)
+            WHERE (SELECT ok FROM valid)
+        ),
+        -- The new attempt is not visible to this delete, so keep one less than the cap
+        del_failed AS (
+            DELETE FROM attempts_failed
+            WHERE message_id = $1
+              AND $11::BIGINT IS NOT NULL
+              AND (SELECT ok FROM valid)
+              AND id NOT IN (
+                  SELECT fa.id
+                  FROM attempts_failed fa
+                  WHERE fa.message_id = $1
+                  ORDER BY fa.failed_at DESC, fa.id DESC
+                  LIMIT GREATEST($11::BIGINT - 1, 0)
+              )
+        ),
+        -- The new error is not visible to this delete, so keep one less than the cap
+        del_errors AS (
+            DELETE FROM errors
+            WHERE message_id = $1
+              AND $9::BIGINT IS NOT NULL
+              AND (SELECT ok FROM valid)
+              AND id NOT IN (
+                  SELECT e.id
+                  FROM errors e
+                  WHERE e.message_id = $1
+                  ORDER BY e.reported_at DESC, e.id DESC
+                  LIMIT GREATEST($9::BIGINT - 1, 0)
+              )
+        ),
+        ins_error AS (
+            INSERT INTO errors (
+                id,
+                message_id,
+                reported_at,
+                error,
+                category
+            )
+            SELECT $6, $1, $3, $7, $10
+            WHERE (SELECT ok FROM valid) AND $7::TEXT IS NOT NULL
         )
-        VALUES ($6, $1, $3, $7)
+        SELECT v.ok "ok!", s.label
+        FROM valid v, state s
         "#,
         message_id,        // $1 → message_id
         failed_id,         // $2 → new failed row ID
@@ -43,11 +164,23 @@ pub async fn report_retryable<'tx, E: PgExecutor<'tx>>(
         attempted,         // $4 → attempted
         retry_earliest_at, // $5 → retry_earliest_at
         error_id,          // $6 → error row ID
-        error              // $7 → error text
+        error.map(|e| e.error), // $7 → error text, None when written separately
+        fencing_token,     // $8 → fencing token of the lease
+        max_errors,        // $9 → number of errors to keep, None keeps all
+        error.and_then(|e| e.category), // $10 → error category
+        max_failed         // $11 → number of failed attempts to keep, None keeps all
     )
-    .execute(tx)
+    .fetch_one(tx)
     .await?;
 
+    if !row.ok {
+        return Err(ReportError::rejected(
+            message_id,
+            row.label.as_deref(),
+            MessageStatus::Failed,
+        ));
+    }
+
     Ok(())
 }
 
@@ -79,6 +212,7 @@ mod tests {
         report_retryable(
             &pool,
             published.id,
+            None,
             now,
             1,
             try_earliest_at,
@@ -103,6 +237,7 @@ mod tests {
         let result = report_retryable(
             &pool,
             published.id,
+            None,
             now,
             1,
             try_earliest_at,
@@ -114,4 +249,36 @@ mod tests {
 
         Ok(())
     }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_keeps_the_most_recent_errors(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let now = Utc::now();
+        let published = publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+        get_next_unattempted(&pool, now, Uuid::now_v7(), Duration::from_mins(1)).await?;
+
+        for attempted in 1..=5 {
+            let failed_at = now + Duration::from_secs(attempted as u64);
+            report_retryable_capped(
+                &pool,
+                published.id,
+                None,
+                failed_at,
+                attempted,
+                failed_at,
+                &format!("error {attempted}"),
+                3,
+            )
+            .await?;
+        }
+
+        let errors: Vec<String> = sqlx::query_scalar(
+            "SELECT error FROM errors WHERE message_id = $1 ORDER BY reported_at",
+        )
+        .bind(published.id)
+        .fetch_all(&pool)
+        .await?;
+        assert_eq!(errors, vec!["error 3", "error 4", "error 5"]);
+
+        Ok(())
+    }
 }
@@ -0,0 +1,267 @@
+use crate::models::MessageStatus;
+use crate::queries::ReportError;
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use sqlx::PgExecutor;
+use uuid::Uuid;
+
+/// Reports the review of a dead message as done without a fix, closing it.
+///
+/// Only dead messages still in review may be reported, others are rejected with [`ReportError::NotFound`] or
+/// [`ReportError::InvalidTransition`]. When a `fencing_token` is given the report is rejected with
+/// [`ReportError::StaleFencingToken`] unless the message is still leased with that token.
+pub async fn report_reviewed<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    message_id: Uuid,
+    fencing_token: Option<i64>,
+    now: DateTime<Utc>,
+) -> Result<(), ReportError> {
+    let row = sqlx::query!(
+        r#"
+        WITH fence AS (
+            SELECT 1
+            FROM leases
+            WHERE message_id = $1 AND fencing_token = $3
+            FOR UPDATE
+        ),
+        -- Only dead messages still in review may be reported
+        state AS (
+            SELECT CASE
+                WHEN EXISTS (SELECT 1 FROM attempts_dead WHERE message_id = $1 AND reviewed_at IS NULL) THEN 'in_review'
+                WHEN EXISTS (SELECT 1 FROM attempts_dead WHERE message_id = $1) THEN 'dead'
+                WHEN EXISTS (SELECT 1 FROM attempts_succeeded WHERE message_id = $1) THEN 'succeeded'
+                WHEN EXISTS (SELECT 1 FROM attempts_failed WHERE message_id = $1) THEN 'failed'
+                WHEN EXISTS (SELECT 1 FROM messages_attempted WHERE id = $1) THEN 'in_progress'
+                WHEN EXISTS (SELECT 1 FROM messages_unattempted WHERE id = $1) THEN 'pending'
+            END AS label
+        ),
+        valid AS (
+            SELECT COALESCE((SELECT label FROM state) = 'in_review', FALSE)
+                AND ($3::BIGINT IS NULL OR EXISTS (SELECT 1 FROM fence)) AS ok
+        ),
+        del_leases AS (
+            DELETE FROM leases
+            WHERE message_id = $1 AND (SELECT ok FROM valid)
+        ),
+        upd_dead AS (
+            UPDATE attempts_dead
+            SET reviewed_at = $2
+            WHERE message_id = $1 AND (SELECT ok FROM valid)
+        )
+        SELECT v.ok "ok!", s.label
+        FROM valid v, state s;
+        "#,
+        message_id,
+        now,
+        fencing_token,
+    )
+    .fetch_one(tx)
+    .await?;
+
+    if !row.ok {
+        return Err(rejected(
+            message_id,
+            row.label.as_deref(),
+            MessageStatus::Dead,
+        ));
+    }
+
+    Ok(())
+}
+
+/// Reports a dead message as fixed by its review, requeuing it as retryable at `retry_earliest_at` with a fresh
+/// attempt count. A given `payload` replaces the payload of the message, e.g. with enriched data.
+///
+/// Rejected like [`report_reviewed`] unless the message is dead and still in review.
+pub async fn report_remediated<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    message_id: Uuid,
+    fencing_token: Option<i64>,
+    now: DateTime<Utc>,
+    payload: Option<&Value>,
+    retry_earliest_at: DateTime<Utc>,
+) -> Result<(), ReportError> {
+    let row = sqlx::query!(
+        r#"
+        WITH fence AS (
+            SELECT 1
+            FROM leases
+            WHERE message_id = $1 AND fencing_token = $3
+            FOR UPDATE
+        ),
+        -- Only dead messages still in review may be reported
+        state AS (
+            SELECT CASE
+                WHEN EXISTS (SELECT 1 FROM attempts_dead WHERE message_id = $1 AND reviewed_at IS NULL) THEN 'in_review'
+                WHEN EXISTS (SELECT 1 FROM attempts_dead WHERE message_id = $1) THEN 'dead'
+                WHEN EXISTS (SELECT 1 FROM attempts_succeeded WHERE message_id = $1) THEN 'succeeded'
+                WHEN EXISTS (SELECT 1 FROM attempts_failed WHERE message_id = $1) THEN 'failed'
+                WHEN EXISTS (SELECT 1 FROM messages_attempted WHERE id = $1) THEN 'in_progress'
+                WHEN EXISTS (SELECT 1 FROM messages_unattempted WHERE id = $1) THEN 'pending'
+            END AS label
+        ),
+        valid AS (
+            SELECT COALESCE((SELECT label FROM state) = 'in_review', FALSE)
+                AND ($3::BIGINT IS NULL OR EXISTS (SELECT 1 FROM fence)) AS ok
+        ),
+        del_leases AS (
+            DELETE FROM leases
+            WHERE message_id = $1 AND (SELECT ok FROM valid)
+        ),
+        upd_payload AS (
+            UPDATE messages_attempted
+            SET payload = $4
+            WHERE id = $1 AND $4::JSONB IS NOT NULL AND (SELECT ok FROM valid)
+        ),
+        revived AS (
+            DELETE FROM attempts_dead
+            WHERE message_id = $1 AND (SELECT ok FROM valid)
+            RETURNING message_id
+        ),
+        ins_failed AS (
+            INSERT INTO attempts_failed (id, message_id, failed_at, attempted, retry_earliest_at)
+            SELECT $6, message_id, $2, 0, $5
+            FROM revived
+        )
+        SELECT v.ok "ok!", s.label
+        FROM valid v, state s;
+        "#,
+        message_id,
+        now,
+        fencing_token,
+        payload,
+        retry_earliest_at,
+        Uuid::now_v7(),
+    )
+    .fetch_one(tx)
+    .await?;
+
+    if !row.ok {
+        return Err(rejected(
+            message_id,
+            row.label.as_deref(),
+            MessageStatus::Failed,
+        ));
+    }
+
+    Ok(())
+}
+
+/// The error of a review report rejected with the message in `state`, see [`ReportError::rejected`]
+fn rejected(message_id: Uuid, state: Option<&str>, to: MessageStatus) -> ReportError {
+    match state {
+        None => ReportError::NotFound(message_id),
+        // Reportable, so the fencing token didn't match
+        Some("in_review") => ReportError::StaleFencingToken(message_id),
+        Some(label) => ReportError::InvalidTransition {
+            message_id,
+            from: MessageStatus::from_label(label),
+            to,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queries::{
+        get_next_dead_for_review, get_next_retryable, get_next_unattempted, publish_message,
+        report_dead,
+    };
+    use crate::testing_tools::{TestMessage, is_dead};
+    use serde_json::json;
+    use std::time::Duration;
+
+    async fn dead_for_review(
+        pool: &sqlx::PgPool,
+        now: DateTime<Utc>,
+        host_id: Uuid,
+    ) -> anyhow::Result<crate::models::RawMessage> {
+        let hold_for = Duration::from_mins(1);
+        publish_message(pool, &TestMessage::default().to_raw()?).await?;
+        let message = get_next_unattempted(pool, now, host_id, hold_for)
+            .await?
+            .expect("Expected a message");
+        report_dead(pool, message.id, None, now, "missing data").await?;
+
+        Ok(
+            get_next_dead_for_review(pool, now, host_id, hold_for, Duration::from_mins(10))
+                .await?
+                .expect("Expected a dead message"),
+        )
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_closes_reviewed_messages(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let now = Utc::now();
+        let host_id = Uuid::now_v7();
+        let claimed = dead_for_review(&pool, now, host_id).await?;
+
+        report_reviewed(&pool, claimed.id, claimed.fencing_token, now).await?;
+        assert!(is_dead(&pool, claimed.id, now).await?);
+
+        let later = now + Duration::from_mins(2);
+        let again = get_next_dead_for_review(
+            &pool,
+            later,
+            host_id,
+            Duration::from_mins(1),
+            Duration::from_mins(10),
+        )
+        .await?;
+        assert!(again.is_none());
+
+        // Closed reviews can't be remediated
+        let closed = report_remediated(&pool, claimed.id, None, later, None, later).await;
+        assert!(matches!(
+            closed,
+            Err(ReportError::InvalidTransition {
+                from: MessageStatus::Dead,
+                to: MessageStatus::Failed,
+                ..
+            })
+        ));
+        let unknown = report_reviewed(&pool, Uuid::now_v7(), None, later).await;
+        assert!(matches!(unknown, Err(ReportError::NotFound(_))));
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_requeues_remediated_messages(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let now = Utc::now();
+        let host_id = Uuid::now_v7();
+        let claimed = dead_for_review(&pool, now, host_id).await?;
+
+        let enriched = json!({ "enriched": true });
+        report_remediated(
+            &pool,
+            claimed.id,
+            claimed.fencing_token,
+            now,
+            Some(&enriched),
+            now,
+        )
+        .await?;
+        assert!(!is_dead(&pool, claimed.id, now).await?);
+
+        let retried = get_next_retryable(&pool, now, host_id, Duration::from_mins(1))
+            .await?
+            .expect("Expected a retryable message");
+        assert_eq!(retried.id, claimed.id);
+        assert_eq!(retried.attempted, 0);
+        assert_eq!(retried.payload, enriched);
+
+        // The message is no longer in review
+        let stale = report_reviewed(&pool, claimed.id, claimed.fencing_token, now).await;
+        assert!(matches!(
+            stale,
+            Err(ReportError::InvalidTransition {
+                from: MessageStatus::Failed,
+                ..
+            })
+        ));
+
+        Ok(())
+    }
+}
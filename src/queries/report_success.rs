@@ -1,31 +1,106 @@
+use crate::models::MessageStatus;
+use crate::queries::ReportError;
 use chrono::{DateTime, Utc};
 use sqlx::PgExecutor;
 use uuid::Uuid;
 
+/// Reports a message as succeeded.
+///
+/// When a `fencing_token` is given the report is rejected with [`ReportError::StaleFencingToken`] unless the message
+/// is still leased with that token. Messages that were not claimed or are already finished are rejected with
+/// [`ReportError::InvalidTransition`].
 pub async fn report_success<'tx, E: PgExecutor<'tx>>(
     tx: E,
     message_id: Uuid,
+    fencing_token: Option<i64>,
     now: DateTime<Utc>,
-) -> Result<(), sqlx::Error> {
-    sqlx::query!(
+) -> Result<(), ReportError> {
+    report_success_inner(tx, message_id, fencing_token, now, None).await
+}
+
+/// Reports a message as succeeded, copying its `retain_failed` most recent failed attempts to
+/// `attempts_failed_retained` before they are deleted, unless it is None
+pub(crate) async fn report_success_inner<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    message_id: Uuid,
+    fencing_token: Option<i64>,
+    now: DateTime<Utc>,
+    retain_failed: Option<i64>,
+) -> Result<(), ReportError> {
+    let row = sqlx::query!(
         r#"
-        WITH del_leases AS (
+        WITH fence AS (
+            SELECT 1
+            FROM leases
+            WHERE message_id = $1 AND fencing_token = $3
+            FOR UPDATE
+        ),
+        -- Only attempted messages that are not finished yet may be reported
+        state AS (
+            SELECT CASE
+                WHEN EXISTS (SELECT 1 FROM attempts_succeeded WHERE message_id = $1) THEN 'succeeded'
+                WHEN EXISTS (SELECT 1 FROM attempts_dead WHERE message_id = $1) THEN 'dead'
+                WHEN EXISTS (SELECT 1 FROM messages_attempted WHERE id = $1) THEN 'attempted'
+                WHEN EXISTS (SELECT 1 FROM messages_unattempted WHERE id = $1) THEN 'pending'
+            END AS label
+        ),
+        valid AS (
+            SELECT COALESCE((SELECT label FROM state) = 'attempted', FALSE)
+                AND ($3::BIGINT IS NULL OR EXISTS (SELECT 1 FROM fence)) AS ok
+        ),
+        del_leases AS (
             DELETE FROM leases
-            WHERE message_id = $1
+            WHERE message_id = $1 AND (SELECT ok FROM valid)
+        ),
+        -- Kept for postmortems, the deleted rows are visible to this insert
+        ins_retained AS (
+            INSERT INTO attempts_failed_retained (
+                id,
+                message_id,
+                failed_at,
+                attempted,
+                retry_earliest_at,
+                attempted_by
+            )
+            SELECT fa.id, fa.message_id, fa.failed_at, fa.attempted, fa.retry_earliest_at, fa.attempted_by
+            FROM attempts_failed fa
+            WHERE fa.message_id = $1 AND $4::BIGINT IS NOT NULL AND (SELECT ok FROM valid)
+            ORDER BY fa.failed_at DESC, fa.id DESC
+            LIMIT $4
+            ON CONFLICT (id) DO NOTHING
         ),
         del_failed AS (
             DELETE FROM attempts_failed
-            WHERE message_id = $1
+            WHERE message_id = $1 AND (SELECT ok FROM valid)
+        ),
+        ins_succeeded AS (
+            INSERT INTO attempts_succeeded (message_id, succeeded_at, attempted)
+            SELECT
+                $1,
+                $2,
+                -- The failed attempts and this one, read before del_failed deletes them
+                COALESCE((SELECT MAX(fa.attempted) FROM attempts_failed fa WHERE fa.message_id = $1), 0) + 1
+            WHERE (SELECT ok FROM valid)
         )
-        INSERT INTO attempts_succeeded (message_id, succeeded_at)
-        VALUES ($1, $2);
+        SELECT v.ok "ok!", s.label
+        FROM valid v, state s;
         "#,
         message_id,
         now,
+        fencing_token,
+        retain_failed,
     )
-    .execute(tx)
+    .fetch_one(tx)
     .await?;
 
+    if !row.ok {
+        return Err(ReportError::rejected(
+            message_id,
+            row.label.as_deref(),
+            MessageStatus::Succeeded,
+        ));
+    }
+
     Ok(())
 }
 
@@ -34,7 +109,8 @@ mod tests {
     use super::*;
     use crate::backoff::ConstantBackoff;
     use crate::queries::{
-        get_next_retryable, get_next_unattempted, publish_message, report_retryable,
+        get_next_missing, get_next_retryable, get_next_unattempted, publish_message, report_dead,
+        report_retryable,
     };
     use crate::testing_tools::{TestMessage, is_succeeded};
     use std::time::Duration;
@@ -54,7 +130,7 @@ mod tests {
 
         assert!(published.id == polled.id);
 
-        report_success(&pool, polled.id, now).await?;
+        report_success(&pool, polled.id, None, now).await?;
 
         assert!(is_succeeded(&pool, published.id, now).await?);
 
@@ -68,7 +144,7 @@ mod tests {
 
         let published = publish_message(&pool, &message.to_raw()?).await?;
 
-        let result = report_success(&pool, published.id, now).await;
+        let result = report_success(&pool, published.id, None, now).await;
 
         assert!(result.is_err());
 
@@ -91,16 +167,81 @@ mod tests {
 
         let try_earliest_at = backoff.try_at(1, now);
 
-        report_retryable(&pool, published.id, now, 1, try_earliest_at, "error").await?;
+        report_retryable(&pool, published.id, None, now, 1, try_earliest_at, "error").await?;
 
         get_next_retryable(&pool, now, host_id, hold_for)
             .await?
             .expect("Expected a message");
 
-        report_success(&pool, published.id, now).await?;
+        report_success(&pool, published.id, None, now).await?;
 
         assert!(is_succeeded(&pool, published.id, now).await?);
 
         Ok(())
     }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_rejects_stale_fencing_tokens(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let now = Utc::now();
+        let hold_for = Duration::from_millis(1);
+
+        publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+
+        let zombie = get_next_unattempted(&pool, now, Uuid::now_v7(), hold_for)
+            .await?
+            .expect("Expected a message");
+
+        // The lease of the first host expires and the message is taken over by another host
+        let later = now + hold_for * 2;
+        let current = get_next_missing(&pool, later, Uuid::now_v7(), Duration::from_mins(1))
+            .await?
+            .expect("Expected a missing message");
+
+        assert!(current.fencing_token > zombie.fencing_token);
+
+        let result = report_success(&pool, zombie.id, zombie.fencing_token, later).await;
+        assert!(matches!(result, Err(ReportError::StaleFencingToken(id)) if id == zombie.id));
+
+        report_success(&pool, current.id, current.fencing_token, later).await?;
+        assert!(is_succeeded(&pool, current.id, later).await?);
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_rejects_invalid_transitions(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let now = Utc::now();
+        let published = publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+
+        let result = report_success(&pool, published.id, None, now).await;
+        assert!(matches!(
+            result,
+            Err(ReportError::InvalidTransition {
+                from: MessageStatus::Pending,
+                to: MessageStatus::Succeeded,
+                ..
+            })
+        ));
+
+        let claimed = get_next_unattempted(&pool, now, Uuid::now_v7(), Duration::from_mins(1))
+            .await?
+            .expect("Expected a message");
+        report_dead(&pool, claimed.id, None, now, "invalid").await?;
+
+        let result = report_success(&pool, claimed.id, None, now).await;
+        assert!(matches!(
+            result,
+            Err(ReportError::InvalidTransition {
+                from: MessageStatus::Dead,
+                to: MessageStatus::Succeeded,
+                ..
+            })
+        ));
+
+        let unknown = Uuid::now_v7();
+        let result = report_success(&pool, unknown, None, now).await;
+        assert!(matches!(result, Err(ReportError::NotFound(id)) if id == unknown));
+
+        Ok(())
+    }
 }
@@ -1,20 +1,34 @@
+use crate::models::Lease;
 use chrono::{DateTime, Utc};
 use sqlx::PgExecutor;
 use std::time::Duration;
 use uuid::Uuid;
 
-/// Requests a lease on a message
-/// Will only acquire if no other host holds currently hold a lease for the requested message
-/// Returns None if no lease could be acquired, otherwise Some(expires_at)
+/// Requests a lease on a message, returning None if another host holds an active lease on it.
+///
+/// Otherwise the acquired lease with its fencing token is returned. An active manual lease protects the message
+/// from all claims until it expires, including claims of the requesting host, while the message stays where it is:
+/// a pending message is still pending, it is not moved to the attempted messages and can't be reported on.
+///
+/// The lease is acquired after locking the message if it is pending, so a request racing a claim of the message
+/// either returns None or prevents the claim. Requests of different hosts for the same message are not serialized
+/// against each other.
 pub async fn request_lease<'tx, E: PgExecutor<'tx>>(
     tx: E,
     message_id: Uuid,
     now: DateTime<Utc>,
     host_id: Uuid,
     hold_for: Duration,
-) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
-    let expires_at = sqlx::query_scalar!(
+) -> Result<Option<Lease>, sqlx::Error> {
+    let lease = sqlx::query_as!(
+        Lease,
         r#"
+        WITH pending AS (
+            SELECT id
+            FROM messages_unattempted
+            WHERE id = $1
+            FOR UPDATE
+        )
         INSERT INTO leases (
             message_id,
             acquired_at,
@@ -23,12 +37,17 @@ pub async fn request_lease<'tx, E: PgExecutor<'tx>>(
         )
         SELECT
             $1, $2, $3, $4
-        WHERE not exists (
-            SELECT *
+        WHERE NOT EXISTS (
+            SELECT 1
             FROM leases
-            WHERE acquired_by != $3 AND expires_at > $2
+            WHERE message_id = $1 AND acquired_by != $3 AND expires_at > $2
+        )
+        -- A pending message that was claimed while waiting for its lock is no longer pending
+        AND (
+            NOT EXISTS (SELECT 1 FROM messages_unattempted WHERE id = $1)
+            OR EXISTS (SELECT 1 FROM pending)
         )
-        RETURNING expires_at;
+        RETURNING expires_at, fencing_token;
         "#,
         message_id,
         now,
@@ -38,13 +57,16 @@ pub async fn request_lease<'tx, E: PgExecutor<'tx>>(
     .fetch_optional(tx)
     .await?;
 
-    Ok(expires_at)
+    Ok(lease)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{queries::request_lease, testing_tools::has_active_lease};
+    use crate::{
+        queries::{get_next_unattempted, publish_message, request_lease},
+        testing_tools::{TestMessage, has_active_lease},
+    };
     use chrono::SubsecRound;
     use std::time::Duration;
     use uuid::Uuid;
@@ -56,7 +78,9 @@ mod tests {
         let now = Utc::now();
         let hold_for = Duration::from_mins(1);
 
-        let actual = request_lease(&pool, message_id, now, host_id, hold_for).await?;
+        let actual = request_lease(&pool, message_id, now, host_id, hold_for)
+            .await?
+            .map(|lease| lease.expires_at);
 
         assert_eq!(Some((now + hold_for).trunc_subsecs(6)), actual);
         assert!(has_active_lease(&pool, message_id, now).await?);
@@ -73,7 +97,9 @@ mod tests {
         let now = Utc::now();
         let hold_for = Duration::from_mins(1);
 
-        let actual = request_lease(&pool, message_id, now, host_id, hold_for).await?;
+        let actual = request_lease(&pool, message_id, now, host_id, hold_for)
+            .await?
+            .map(|lease| lease.expires_at);
 
         assert_eq!(Some((now + hold_for).trunc_subsecs(6)), actual);
         assert!(has_active_lease(&pool, message_id, now).await?);
@@ -83,7 +109,9 @@ mod tests {
 
         let now = Utc::now();
 
-        let actual = request_lease(&pool, message_id, now, host_id, hold_for).await?;
+        let actual = request_lease(&pool, message_id, now, host_id, hold_for)
+            .await?
+            .map(|lease| lease.expires_at);
 
         assert_eq!(Some((now + hold_for).trunc_subsecs(6)), actual);
         assert!(has_active_lease(&pool, message_id, now).await?);
@@ -100,7 +128,9 @@ mod tests {
         let now = Utc::now();
         let hold_for = Duration::from_millis(10);
 
-        let actual = request_lease(&pool, message_id, now, host_id, hold_for).await?;
+        let actual = request_lease(&pool, message_id, now, host_id, hold_for)
+            .await?
+            .map(|lease| lease.expires_at);
 
         assert_eq!(Some((now + hold_for).trunc_subsecs(6)), actual);
         assert!(has_active_lease(&pool, message_id, now).await?);
@@ -111,7 +141,9 @@ mod tests {
         let host_id = Uuid::now_v7();
         let now = Utc::now();
 
-        let actual = request_lease(&pool, message_id, now, host_id, hold_for).await?;
+        let actual = request_lease(&pool, message_id, now, host_id, hold_for)
+            .await?
+            .map(|lease| lease.expires_at);
 
         assert_eq!(Some((now + hold_for).trunc_subsecs(6)), actual);
         assert!(has_active_lease(&pool, message_id, now).await?);
@@ -128,7 +160,9 @@ mod tests {
         let now = Utc::now();
         let hold_for = Duration::from_mins(1);
 
-        let actual = request_lease(&pool, message_id, now, host_id, hold_for).await?;
+        let actual = request_lease(&pool, message_id, now, host_id, hold_for)
+            .await?
+            .map(|lease| lease.expires_at);
 
         assert_eq!(Some((now + hold_for).trunc_subsecs(6)), actual);
         assert!(has_active_lease(&pool, message_id, now).await?);
@@ -136,10 +170,107 @@ mod tests {
         let host_id = Uuid::now_v7();
         let now = Utc::now();
 
-        let actual = request_lease(&pool, message_id, now, host_id, hold_for).await?;
+        let actual = request_lease(&pool, message_id, now, host_id, hold_for)
+            .await?
+            .map(|lease| lease.expires_at);
 
         assert_eq!(None, actual);
 
         Ok(())
     }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_returns_increasing_fencing_tokens(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let message_id = Uuid::now_v7();
+        let host_id = Uuid::now_v7();
+        let hold_for = Duration::from_mins(1);
+
+        let first = request_lease(&pool, message_id, Utc::now(), host_id, hold_for)
+            .await?
+            .expect("Expected a lease");
+
+        tokio::time::sleep(Duration::from_micros(1)).await;
+
+        let second = request_lease(&pool, message_id, Utc::now(), host_id, hold_for)
+            .await?
+            .expect("Expected a lease");
+
+        assert!(second.fencing_token > first.fencing_token);
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_only_considers_leases_of_the_requested_message(
+        pool: sqlx::PgPool,
+    ) -> anyhow::Result<()> {
+        let now = Utc::now();
+        let hold_for = Duration::from_mins(1);
+
+        request_lease(&pool, Uuid::now_v7(), now, Uuid::now_v7(), hold_for)
+            .await?
+            .expect("Expected a lease");
+
+        let other = request_lease(&pool, Uuid::now_v7(), now, Uuid::now_v7(), hold_for).await?;
+        assert!(other.is_some());
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_protects_messages_from_claims(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let now = Utc::now();
+        let host_id = Uuid::now_v7();
+        let hold_for = Duration::from_mins(1);
+
+        let leased = publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+        let next = publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+        request_lease(&pool, leased.id, now, Uuid::now_v7(), hold_for)
+            .await?
+            .expect("Expected a lease");
+
+        let claimed = get_next_unattempted(&pool, now, host_id, hold_for)
+            .await?
+            .expect("Expected a message");
+        assert_eq!(claimed.id, next.id);
+        assert!(
+            get_next_unattempted(&pool, now, host_id, hold_for)
+                .await?
+                .is_none()
+        );
+
+        // Claimable again once the manual lease expired
+        let later = now + hold_for * 2;
+        let claimed = get_next_unattempted(&pool, later, host_id, hold_for)
+            .await?
+            .expect("Expected a message");
+        assert_eq!(claimed.id, leased.id);
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_does_not_lease_messages_claimed_concurrently(
+        pool: sqlx::PgPool,
+    ) -> anyhow::Result<()> {
+        let now = Utc::now();
+        let hold_for = Duration::from_mins(1);
+        let published = publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+
+        let mut claim = pool.begin().await?;
+        get_next_unattempted(&mut *claim, now, Uuid::now_v7(), hold_for)
+            .await?
+            .expect("Expected a message");
+
+        let request = tokio::spawn({
+            let pool = pool.clone();
+            async move { request_lease(&pool, published.id, now, Uuid::now_v7(), hold_for).await }
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        claim.commit().await?;
+
+        assert!(request.await??.is_none());
+
+        Ok(())
+    }
 }
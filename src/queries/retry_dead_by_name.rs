@@ -0,0 +1,114 @@
+use crate::backoff::Backoff;
+use chrono::{DateTime, Utc};
+use sqlx::PgExecutor;
+
+/// Requeues all dead messages named `name` as retryable, returning the number of requeued messages.
+///
+/// Requeued messages start over with a fresh attempt count and become retryable at `backoff.try_at(0, now)`.
+/// Previously reported errors are kept.
+pub async fn retry_dead_by_name<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    name: &str,
+    now: DateTime<Utc>,
+    backoff: &impl Backoff,
+) -> Result<u64, sqlx::Error> {
+    let retry_earliest_at = backoff.try_at(0, now);
+
+    let result = sqlx::query!(
+        r#"
+        WITH revived AS (
+            DELETE FROM attempts_dead d
+            USING messages_attempted ma
+            WHERE ma.id = d.message_id
+              AND ma.name = $1
+            RETURNING d.message_id
+        )
+        INSERT INTO attempts_failed (
+            id,
+            message_id,
+            failed_at,
+            attempted,
+            retry_earliest_at
+        )
+        SELECT
+            gen_random_uuid(),
+            message_id,
+            $2,
+            0,
+            $3
+        FROM revived
+        "#,
+        name,
+        now,
+        retry_earliest_at
+    )
+    .execute(tx)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        backoff::ConstantBackoff,
+        models::Message,
+        queries::{get_next_retryable, get_next_unattempted, publish_message, report_dead},
+        testing_tools::{TestMessage, is_dead, is_failed},
+    };
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_requeues_dead_messages_by_name(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let now = Utc::now();
+        let host_id = Uuid::now_v7();
+        let hold_for = Duration::from_mins(1);
+        let backoff = ConstantBackoff::new(Duration::ZERO);
+
+        let mut dead = Vec::new();
+        for _ in 0..2 {
+            publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+            let message = get_next_unattempted(&pool, now, host_id, hold_for)
+                .await?
+                .unwrap();
+            report_dead(&pool, message.id, None, now, "bug").await?;
+            dead.push(message.id);
+        }
+
+        let requeued = retry_dead_by_name(&pool, TestMessage::NAME, now, &backoff).await?;
+
+        assert_eq!(requeued, 2);
+        for id in &dead {
+            assert!(is_failed(&pool, *id, now).await?);
+        }
+
+        let retried = get_next_retryable(&pool, now, host_id, hold_for)
+            .await?
+            .expect("Expected a requeued message to be retryable");
+        assert!(dead.contains(&retried.id));
+        assert_eq!(retried.attempted, 0);
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_ignores_other_names(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let now = Utc::now();
+        let backoff = ConstantBackoff::new(Duration::ZERO);
+
+        publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+        let message = get_next_unattempted(&pool, now, Uuid::now_v7(), Duration::from_mins(1))
+            .await?
+            .unwrap();
+        report_dead(&pool, message.id, None, now, "bug").await?;
+
+        let requeued = retry_dead_by_name(&pool, "OtherMessage", now, &backoff).await?;
+
+        assert_eq!(requeued, 0);
+        assert!(is_dead(&pool, message.id, now).await?);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,175 @@
+use crate::models::PayloadRewrite;
+use sqlx::PgTransaction;
+use uuid::Uuid;
+
+/// Applies `rewrite` to the stored payloads of up to `limit` messages named `name`, ordered by id and
+/// starting after the `after` cursor.
+///
+/// Only messages that are not yet terminal are rewritten, i.e. pending, in-progress, retrying and missing messages.
+/// Pending messages are locked while rewritten so that they can not be claimed concurrently, note that an in-progress
+/// message may already be handled with its previous payload.
+///
+/// Call repeatedly with the returned cursor until it is `None` to rewrite all messages in bounded batches.
+pub async fn rewrite_payloads<F>(
+    tx: &mut PgTransaction<'_>,
+    name: &str,
+    after: Option<Uuid>,
+    limit: i64,
+    mut rewrite: F,
+) -> Result<PayloadRewrite, sqlx::Error>
+where
+    F: FnMut(serde_json::Value) -> serde_json::Value,
+{
+    let rows = sqlx::query!(
+        r#"
+        WITH pending AS (
+            SELECT id, payload
+            FROM messages_unattempted
+            WHERE name = $1 AND ($2::UUID IS NULL OR id > $2)
+            ORDER BY id ASC
+            LIMIT $3
+            FOR UPDATE
+        ),
+        unfinished AS (
+            SELECT ma.id, ma.payload
+            FROM messages_attempted ma
+            WHERE ma.name = $1
+              AND ($2::UUID IS NULL OR ma.id > $2)
+              AND NOT EXISTS (SELECT 1 FROM attempts_succeeded s WHERE s.message_id = ma.id)
+              AND NOT EXISTS (SELECT 1 FROM attempts_dead d WHERE d.message_id = ma.id)
+            ORDER BY ma.id ASC
+            LIMIT $3
+        )
+        SELECT id "id!", payload "payload!"
+        FROM (
+            SELECT * FROM pending
+            UNION ALL
+            SELECT * FROM unfinished
+        ) candidates
+        ORDER BY id ASC
+        LIMIT $3
+        "#,
+        name,
+        after,
+        limit
+    )
+    .fetch_all(&mut **tx)
+    .await?;
+
+    let cursor = rows.last().map(|row| row.id);
+
+    let mut ids = Vec::new();
+    let mut payloads = Vec::new();
+    for row in rows {
+        let rewritten = rewrite(row.payload.clone());
+        if rewritten != row.payload {
+            ids.push(row.id);
+            payloads.push(rewritten);
+        }
+    }
+
+    if !ids.is_empty() {
+        sqlx::query!(
+            r#"
+            WITH rewrites AS (
+                SELECT * FROM UNNEST($1::UUID[], $2::JSONB[]) AS r(id, payload)
+            ),
+            unattempted AS (
+                UPDATE messages_unattempted mu
+                SET payload = r.payload
+                FROM rewrites r
+                WHERE mu.id = r.id
+            )
+            UPDATE messages_attempted ma
+            SET payload = r.payload
+            FROM rewrites r
+            WHERE ma.id = r.id
+            "#,
+            &ids,
+            &payloads
+        )
+        .execute(&mut **tx)
+        .await?;
+    }
+
+    Ok(PayloadRewrite {
+        rewritten: ids.len() as u64,
+        cursor,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        queries::{get_next_unattempted, publish_message, report_success},
+        testing_tools::{TestMessage, get_all_messages},
+    };
+    use chrono::Utc;
+    use std::time::Duration;
+
+    fn set_value(mut payload: serde_json::Value) -> serde_json::Value {
+        payload["value"] = serde_json::json!(7);
+        payload
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_rewrites_unfinished_payloads_in_batches(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let now = Utc::now();
+        let host_id = Uuid::now_v7();
+        let hold_for = Duration::from_mins(1);
+
+        // Succeeded messages are left untouched
+        publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+        let succeeded = get_next_unattempted(&pool, now, host_id, hold_for)
+            .await?
+            .unwrap();
+        report_success(&pool, succeeded.id, None, now).await?;
+
+        // In progress
+        publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+        get_next_unattempted(&pool, now, host_id, hold_for)
+            .await?
+            .unwrap();
+
+        // Pending
+        publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+
+        let mut tx = pool.begin().await?;
+        let first = rewrite_payloads(&mut tx, "TestMessage", None, 1, set_value).await?;
+        let second = rewrite_payloads(&mut tx, "TestMessage", first.cursor, 1, set_value).await?;
+        let third = rewrite_payloads(&mut tx, "TestMessage", second.cursor, 1, set_value).await?;
+        tx.commit().await?;
+
+        assert_eq!(first.rewritten, 1);
+        assert_eq!(second.rewritten, 1);
+        assert_eq!(
+            third,
+            PayloadRewrite {
+                rewritten: 0,
+                cursor: None
+            }
+        );
+
+        for message in get_all_messages(&pool).await? {
+            let expected = if message.id == succeeded.id { 42 } else { 7 };
+            assert_eq!(message.payload["value"], expected);
+        }
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_skips_unchanged_payloads(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+
+        let mut tx = pool.begin().await?;
+        let result = rewrite_payloads(&mut tx, "TestMessage", None, 10, |payload| payload).await?;
+        tx.commit().await?;
+
+        assert_eq!(result.rewritten, 0);
+        assert!(result.cursor.is_some());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,131 @@
+use crate::models::ErrorRecord;
+use chrono::{DateTime, Utc};
+use sqlx::PgExecutor;
+
+/// Returns up to `limit` errors reported within `[from, to)` whose text contains `query` (case-insensitive),
+/// most recent first.
+///
+/// `query` is matched literally, `%` and `_` have no special meaning.
+pub async fn search_errors<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    query: &str,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    limit: i64,
+) -> Result<Vec<ErrorRecord>, sqlx::Error> {
+    let pattern = format!(
+        "%{}%",
+        query
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_")
+    );
+
+    let errors = sqlx::query_as!(
+        ErrorRecord,
+        r#"
+        SELECT
+            id,
+            message_id,
+            reported_at,
+            error,
+            category
+        FROM errors
+        WHERE error ILIKE $1
+          AND reported_at >= $2
+          AND reported_at < $3
+        ORDER BY reported_at DESC, id DESC
+        LIMIT $4
+        "#,
+        pattern,
+        from,
+        to,
+        limit
+    )
+    .fetch_all(tx)
+    .await?;
+
+    Ok(errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        queries::{get_next_unattempted, publish_message, report_retryable},
+        testing_tools::TestMessage,
+    };
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    async fn fail(pool: &sqlx::PgPool, now: DateTime<Utc>, error: &str) -> anyhow::Result<Uuid> {
+        publish_message(pool, &TestMessage::default().to_raw()?).await?;
+        let message = get_next_unattempted(pool, now, Uuid::now_v7(), Duration::from_mins(1))
+            .await?
+            .unwrap();
+        report_retryable(pool, message.id, None, now, 1, now, error).await?;
+        Ok(message.id)
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_finds_errors_by_text(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let now = Utc::now();
+        let timeout = fail(&pool, now, "Request TIMED OUT after 30s").await?;
+        fail(&pool, now, "connection refused").await?;
+
+        let found = search_errors(
+            &pool,
+            "timed out",
+            now - Duration::from_mins(1),
+            now + Duration::from_mins(1),
+            10,
+        )
+        .await?;
+
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].message_id, timeout);
+        assert_eq!(found[0].error, "Request TIMED OUT after 30s");
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_filters_by_range(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let now = Utc::now();
+        fail(&pool, now - Duration::from_mins(10), "timed out").await?;
+
+        let found = search_errors(
+            &pool,
+            "timed out",
+            now - Duration::from_mins(1),
+            now + Duration::from_mins(1),
+            10,
+        )
+        .await?;
+
+        assert!(found.is_empty());
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_matches_wildcards_literally(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let now = Utc::now();
+        fail(&pool, now, "disk 100% full").await?;
+        fail(&pool, now, "disk 100 full").await?;
+
+        let found = search_errors(
+            &pool,
+            "100%",
+            now - Duration::from_mins(1),
+            now + Duration::from_mins(1),
+            10,
+        )
+        .await?;
+
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].error, "disk 100% full");
+
+        Ok(())
+    }
+}
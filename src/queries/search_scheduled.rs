@@ -1,33 +1,37 @@
+use chrono::{DateTime, Utc};
 use sqlx::PgExecutor;
 
-/// Returns the number of messages matching `name` and `payload` that are not yet terminal
-/// (i.e. not succeeded or dead). This includes pending, in-progress, and retrying messages.
+/// Returns the number of messages matching `name` and `payload` that were published but not yet terminal
+/// (i.e. not succeeded or dead) at `now`. This includes pending, in-progress, and retrying messages.
 ///
 /// `payload` is matched with the JSONB containment operator (`@>`), so partial matches are supported.
 pub async fn search_scheduled<'tx, E: PgExecutor<'tx>>(
     tx: E,
     name: &str,
     payload: &serde_json::Value,
+    now: DateTime<Utc>,
 ) -> Result<i64, sqlx::Error> {
     let count = sqlx::query_scalar!(
         r#"
         SELECT COUNT(*) FROM (
             SELECT id FROM messages_unattempted
-            WHERE name = $1 AND payload @> $2
+            WHERE name = $1 AND payload @> $2 AND published_at <= $3
 
             UNION ALL
 
             SELECT ma.id FROM messages_attempted ma
-            LEFT JOIN attempts_succeeded s ON s.message_id = ma.id
-            LEFT JOIN attempts_dead d ON d.message_id = ma.id
+            LEFT JOIN attempts_succeeded s ON s.message_id = ma.id AND s.succeeded_at <= $3
+            LEFT JOIN attempts_dead d ON d.message_id = ma.id AND d.dead_at <= $3
             WHERE ma.name = $1
               AND ma.payload @> $2
+              AND ma.published_at <= $3
               AND s.message_id IS NULL
               AND d.message_id IS NULL
         ) matches
         "#,
         name,
-        payload
+        payload,
+        now
     )
     .fetch_one(tx)
     .await?;
@@ -46,7 +50,6 @@ mod tests {
         },
         testing_tools::TestMessage,
     };
-    use chrono::Utc;
     use std::time::Duration;
     use uuid::Uuid;
 
@@ -73,21 +76,30 @@ mod tests {
         let retrying = get_next_unattempted(pool, now, host_id, hold_for)
             .await?
             .unwrap();
-        report_retryable(pool, retrying.id, now, 1, backoff.try_at(1, now), "err").await?;
+        report_retryable(
+            pool,
+            retrying.id,
+            None,
+            now,
+            1,
+            backoff.try_at(1, now),
+            "err",
+        )
+        .await?;
 
         // Succeeded: polled then reported success
         publish_message(pool, &new_msg()?).await?;
         let succeeded = get_next_unattempted(pool, now, host_id, hold_for)
             .await?
             .unwrap();
-        report_success(pool, succeeded.id, now).await?;
+        report_success(pool, succeeded.id, None, now).await?;
 
         // Dead: polled then reported dead
         publish_message(pool, &new_msg()?).await?;
         let dead = get_next_unattempted(pool, now, host_id, hold_for)
             .await?
             .unwrap();
-        report_dead(pool, dead.id, now, "err").await?;
+        report_dead(pool, dead.id, None, now, "err").await?;
 
         Ok(())
     }
@@ -96,7 +108,7 @@ mod tests {
     async fn it_returns_zero_when_empty(pool: sqlx::PgPool) -> anyhow::Result<()> {
         let payload = serde_json::to_value(TestMessage::default())?;
 
-        let count = search_scheduled(&pool, TestMessage::NAME, &payload).await?;
+        let count = search_scheduled(&pool, TestMessage::NAME, &payload, Utc::now()).await?;
 
         assert_eq!(count, 0);
         Ok(())
@@ -107,7 +119,7 @@ mod tests {
         let payload = serde_json::to_value(TestMessage::default())?;
         publish_message(&pool, &TestMessage::default().to_raw()?).await?;
 
-        let count = search_scheduled(&pool, TestMessage::NAME, &payload).await?;
+        let count = search_scheduled(&pool, TestMessage::NAME, &payload, Utc::now()).await?;
 
         assert_eq!(count, 1);
         Ok(())
@@ -125,7 +137,7 @@ mod tests {
             .await?
             .unwrap();
 
-        let count = search_scheduled(&pool, TestMessage::NAME, &payload).await?;
+        let count = search_scheduled(&pool, TestMessage::NAME, &payload, Utc::now()).await?;
 
         assert_eq!(count, 1);
         Ok(())
@@ -143,9 +155,9 @@ mod tests {
         let msg = get_next_unattempted(&pool, now, host_id, hold_for)
             .await?
             .unwrap();
-        report_retryable(&pool, msg.id, now, 1, backoff.try_at(1, now), "err").await?;
+        report_retryable(&pool, msg.id, None, now, 1, backoff.try_at(1, now), "err").await?;
 
-        let count = search_scheduled(&pool, TestMessage::NAME, &payload).await?;
+        let count = search_scheduled(&pool, TestMessage::NAME, &payload, Utc::now()).await?;
 
         assert_eq!(count, 1);
         Ok(())
@@ -156,7 +168,7 @@ mod tests {
         seed(&pool).await?;
         let payload = serde_json::to_value(TestMessage::default())?;
 
-        let count = search_scheduled(&pool, TestMessage::NAME, &payload).await?;
+        let count = search_scheduled(&pool, TestMessage::NAME, &payload, Utc::now()).await?;
 
         assert_eq!(count, 3, "succeeded message should not be counted");
         Ok(())
@@ -167,7 +179,7 @@ mod tests {
         seed(&pool).await?;
         let payload = serde_json::to_value(TestMessage::default())?;
 
-        let count = search_scheduled(&pool, TestMessage::NAME, &payload).await?;
+        let count = search_scheduled(&pool, TestMessage::NAME, &payload, Utc::now()).await?;
 
         assert_eq!(count, 3, "dead message should not be counted");
         Ok(())
@@ -178,7 +190,7 @@ mod tests {
         seed(&pool).await?;
         let payload = serde_json::to_value(TestMessage::default())?;
 
-        let count = search_scheduled(&pool, "NonExistentMessage", &payload).await?;
+        let count = search_scheduled(&pool, "NonExistentMessage", &payload, Utc::now()).await?;
 
         assert_eq!(count, 0);
         Ok(())
@@ -189,7 +201,7 @@ mod tests {
         seed(&pool).await?;
         let payload = serde_json::json!({ "value": 9999 });
 
-        let count = search_scheduled(&pool, TestMessage::NAME, &payload).await?;
+        let count = search_scheduled(&pool, TestMessage::NAME, &payload, Utc::now()).await?;
 
         assert_eq!(count, 0);
         Ok(())
@@ -201,7 +213,7 @@ mod tests {
         // Only supply one field — @> containment should still match
         let payload = serde_json::json!({ "value": 42 });
 
-        let count = search_scheduled(&pool, TestMessage::NAME, &payload).await?;
+        let count = search_scheduled(&pool, TestMessage::NAME, &payload, Utc::now()).await?;
 
         assert_eq!(count, 3);
         Ok(())
@@ -0,0 +1,130 @@
+use sqlx::PgExecutor;
+
+/// Sets the maximum number of retries of messages named `name` that may be in progress at the same time,
+/// or removes the limit when `max_in_progress` is None.
+///
+/// The limit is enforced when claiming with [`get_next_retryable`](super::get_next_retryable), retries beyond
+/// it stay pending until a slot frees up. First attempts are not limited.
+pub async fn set_retry_concurrency_limit<'tx, E: PgExecutor<'tx>>(
+    tx: E,
+    name: &str,
+    max_in_progress: Option<i32>,
+) -> Result<(), sqlx::Error> {
+    match max_in_progress {
+        Some(max_in_progress) => {
+            sqlx::query!(
+                r#"
+                INSERT INTO retry_concurrency_limits (name, max_in_progress)
+                VALUES ($1, $2)
+                ON CONFLICT (name) DO UPDATE
+                SET max_in_progress = EXCLUDED.max_in_progress
+                "#,
+                name,
+                max_in_progress
+            )
+            .execute(tx)
+            .await?;
+        }
+        None => {
+            sqlx::query!(
+                r#"
+                DELETE FROM retry_concurrency_limits
+                WHERE name = $1
+                "#,
+                name
+            )
+            .execute(tx)
+            .await?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        models::Message,
+        queries::{get_next_retryable, get_next_unattempted, publish_message, report_retryable},
+        testing_tools::TestMessage,
+    };
+    use chrono::Utc;
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    async fn fail_twice(pool: &sqlx::PgPool) -> anyhow::Result<()> {
+        let now = Utc::now();
+        let host_id = Uuid::now_v7();
+
+        for _ in 0..2 {
+            publish_message(pool, &TestMessage::default().to_raw()?).await?;
+            let message = get_next_unattempted(pool, now, host_id, Duration::from_mins(1))
+                .await?
+                .unwrap();
+            report_retryable(pool, message.id, None, now, 1, now, "err").await?;
+        }
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_limits_retries_in_progress(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let host_id = Uuid::now_v7();
+        let hold_for = Duration::from_mins(1);
+
+        fail_twice(&pool).await?;
+        set_retry_concurrency_limit(&pool, TestMessage::NAME, Some(1)).await?;
+
+        let now = Utc::now();
+        assert!(
+            get_next_retryable(&pool, now, host_id, hold_for)
+                .await?
+                .is_some()
+        );
+        assert!(
+            get_next_retryable(&pool, now, host_id, hold_for)
+                .await?
+                .is_none()
+        );
+
+        set_retry_concurrency_limit(&pool, TestMessage::NAME, None).await?;
+        assert!(
+            get_next_retryable(&pool, now, host_id, hold_for)
+                .await?
+                .is_some()
+        );
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_limits_concurrent_claims(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let host_id = Uuid::now_v7();
+        let hold_for = Duration::from_mins(1);
+
+        fail_twice(&pool).await?;
+        set_retry_concurrency_limit(&pool, TestMessage::NAME, Some(1)).await?;
+
+        let now = Utc::now();
+        let mut first = pool.begin().await?;
+        let mut second = pool.begin().await?;
+
+        assert!(
+            get_next_retryable(&mut *first, now, host_id, hold_for)
+                .await?
+                .is_some()
+        );
+        // The first claim is not committed yet, the second claimer must not exceed the limit
+        assert!(
+            get_next_retryable(&mut *second, now, host_id, hold_for)
+                .await?
+                .is_none()
+        );
+
+        first.commit().await?;
+        second.commit().await?;
+
+        Ok(())
+    }
+}
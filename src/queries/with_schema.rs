@@ -1,23 +1,69 @@
-use crate::constants::FX_MQ_MESSAGE_NOTIFICATION_CHANNEL;
-use crate::models::RawMessage;
+use crate::backoff::Backoff;
+use crate::compatibility::ServerCapabilities;
+use crate::constants::{
+    FX_MQ_MESSAGE_NOTIFICATION_CHANNEL, command_channel_for_schema, notification_channel_for_schema,
+};
+use crate::listener::{ErrorWriter, Notification, NotifiedQueue};
+use crate::migrator::{PgIdentifier, PgIdentifierParsingError};
+use crate::models::{
+    ClaimBatchSpec, ClaimPredicate, ClaimStarvation, ClaimStrategy, DailyAggregate, ErrorRecord,
+    EventLogSpec, FailureCategoryCount, FailureOutcome, FailureReason, HealthThresholds,
+    InProgressMessage, IssuedCommand, Lease, LeaseHolder, LeaseLosses, LoggedEvent, MessageStatus,
+    MessageSummary, NameLag, OperatorCommand, PayloadRewrite, PayloadSizes, PublishConflict,
+    PublishOutcome, QueueHealth, QueueSettings, Quota, QuotaScope, RawMessage, RawTextMessage,
+    RetryOrder, RetryState, TimelineEvent,
+};
+use crate::queries::claim_batch::claim_batch_inner;
+use crate::queries::get_many_unattempted::get_many_unattempted_inner;
+use crate::queries::get_next_dead_for_review::get_next_dead_for_review_inner;
+use crate::queries::get_next_missing::get_next_missing_inner;
+use crate::queries::get_next_retryable_named::get_next_retryable_named_inner;
+use crate::queries::get_next_unattempted::get_next_unattempted_inner;
+use crate::queries::get_next_unattempted_named::get_next_unattempted_named_inner;
+use crate::queries::get_next_unattempted_projected::get_next_unattempted_projected_inner;
+use crate::queries::get_next_unattempted_text::get_next_unattempted_text_inner;
+use crate::queries::insert_errors::insert_errors_inner;
+use crate::queries::publish_message::publish_many_messages_inner;
+use crate::queries::report_dead::report_dead_inner;
+use crate::queries::report_failure::retry_at;
+use crate::queries::report_retryable::report_retryable_inner;
+use crate::queries::report_success::report_success_inner;
 use crate::queries::search_scheduled::search_scheduled;
 use crate::queries::{
-    get_next_missing, get_next_retryable, get_next_unattempted, publish_many_messages_with_notify,
-    report_dead, report_retryable, report_success, request_lease,
+    PublishError, PurgedBatch, ReportError, TransactionRetry, activate_epoch, analyze_purged,
+    append_event, compact_daily_aggregates, count_claim_conflicts, delete_blob,
+    delete_messages_matching, delete_queue_settings, delete_quota, enforce_quotas,
+    get_active_epoch, get_blob, get_claim_starvation, get_commands_after, get_daily_aggregates,
+    get_events, get_failure_categories, get_lease_holder, get_lease_losses, get_message_status,
+    get_message_statuses, get_message_timeline, get_next_retryable_ordered,
+    get_next_unattempted_split, get_next_unattempted_windowed, get_oldest_claimable, get_payload,
+    get_queue_health, get_queue_settings, get_retry_state, is_epoch_active, is_standby,
+    issue_command, lag_by_name, list_in_progress, list_messages, mark_standby, notify,
+    notify_payload, payload_sizes_by_name, promote_standby, publish_barrier,
+    publish_message_on_conflict, publish_succeeded, purge_finished, put_blob, put_queue_settings,
+    put_quota, record_claim_conflict, register_host, register_host_in_epoch, release_barriers,
+    release_lease, renew_lease, report_deferred, report_remediated, report_reviewed, request_lease,
+    retry_dead_by_name, rewrite_payloads, search_errors, set_retry_concurrency_limit, with_tx,
 };
 use crate::testing_tools::{
     is_dead, is_failed, is_in_progress, is_missing, is_pending, is_succeeded,
 };
-use chrono::{DateTime, Utc};
-use sqlx::PgTransaction;
+use chrono::{DateTime, NaiveDate, Utc};
+use futures::future::BoxFuture;
+use sqlx::{PgPool, PgTransaction};
+use std::collections::HashMap;
+use std::marker::PhantomData;
 use std::time::Duration;
 use uuid::Uuid;
 
 /// Sets the schema for the given transaction.
 /// This should be called before running any queries that need to operate on a specific schema.
+///
+/// The schema is a parsed [`PgIdentifier`], so only validated and quoted identifiers reach the search path. Being
+/// quoted, the schema name is case sensitive.
 pub async fn set_schema_for_transaction(
     tx: &mut PgTransaction<'_>,
-    schema: &str,
+    schema: &PgIdentifier,
 ) -> Result<(), sqlx::Error> {
     sqlx::query(&format!("SET LOCAL search_path TO {}", schema))
         .execute(&mut **tx)
@@ -25,16 +71,400 @@ pub async fn set_schema_for_transaction(
     Ok(())
 }
 
+/// A type naming a schema, for [`Queries`] and [`Queue`](crate::queue::Queue) handles that are bound to it.
+///
+/// Handles of different tags are distinct types, so a process serving several tenants can't pass the handle of
+/// one tenant where another's is expected:
+///
+/// ```compile_fail
+/// use fx_mq_building_blocks::queries::{Queries, SchemaTag};
+///
+/// #[derive(Debug)]
+/// struct TenantA;
+/// impl SchemaTag for TenantA {
+///     const SCHEMA: &str = "tenant_a";
+/// }
+///
+/// #[derive(Debug)]
+/// struct TenantB;
+/// impl SchemaTag for TenantB {
+///     const SCHEMA: &str = "tenant_b";
+/// }
+///
+/// fn bill_tenant_a(queries: &Queries<TenantA>) {}
+///
+/// let queries = Queries::<TenantB>::tagged().unwrap();
+/// bill_tenant_a(&queries);
+/// ```
+pub trait SchemaTag {
+    const SCHEMA: &str;
+}
+
+/// The tag of handles created for a schema given at runtime, see [`Queries::new`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Untagged;
+
 #[derive(Debug)]
-pub struct Queries {
-    schema: String,
+pub struct Queries<S = Untagged> {
+    schema: PgIdentifier,
+    channel: String,
+    notify_on_release: bool,
+    conflict_recorder: Option<PgPool>,
+    max_errors: Option<u32>,
+    max_failed_attempts: Option<u32>,
+    retained_failed_attempts: Option<u32>,
+    retry_order: RetryOrder,
+    suppression_window: Option<Duration>,
+    claim_strategy: ClaimStrategy,
+    claim_predicate: Option<ClaimPredicate>,
+    sticky_claims: bool,
+    split_claims: bool,
+    claim_timeout: Option<Duration>,
+    notification_metadata: bool,
+    quota_enforcement: bool,
+    deployment_epoch: Option<i64>,
+    event_log: Option<EventLogSpec>,
+    standby_fencing: bool,
+    error_writer: Option<ErrorWriter>,
+    _tag: PhantomData<fn() -> S>,
 }
 
 impl Queries {
-    pub fn new(schema: &str) -> Self {
-        Self {
-            schema: schema.to_string(),
+    /// Creates the queries of `schema`, failing if it is not a valid identifier. The name is case sensitive, see
+    /// [`PgIdentifier::parse`]
+    pub fn new(schema: &str) -> Result<Self, PgIdentifierParsingError> {
+        Self::for_schema(schema)
+    }
+}
+
+impl<S: SchemaTag> Queries<S> {
+    /// Creates the queries of the schema of the tag `S`, failing if it is not a valid identifier
+    pub fn tagged() -> Result<Self, PgIdentifierParsingError> {
+        Self::for_schema(S::SCHEMA)
+    }
+}
+
+impl<S> Queries<S> {
+    fn for_schema(schema: &str) -> Result<Self, PgIdentifierParsingError> {
+        Ok(Self {
+            schema: PgIdentifier::parse(schema)?,
+            channel: FX_MQ_MESSAGE_NOTIFICATION_CHANNEL.to_string(),
+            notify_on_release: false,
+            conflict_recorder: None,
+            max_errors: None,
+            max_failed_attempts: None,
+            retained_failed_attempts: None,
+            retry_order: RetryOrder::FailedAt,
+            suppression_window: None,
+            claim_strategy: ClaimStrategy::Head,
+            claim_predicate: None,
+            sticky_claims: false,
+            split_claims: false,
+            claim_timeout: None,
+            notification_metadata: false,
+            quota_enforcement: false,
+            deployment_epoch: None,
+            event_log: None,
+            standby_fencing: false,
+            error_writer: None,
+            _tag: PhantomData,
+        })
+    }
+
+    /// The schema the queries run on
+    pub fn schema(&self) -> &PgIdentifier {
+        &self.schema
+    }
+
+    /// Sends notifications on the channel of this schema, see [`notification_channel_for_schema`],
+    /// rather than the shared [`FX_MQ_MESSAGE_NOTIFICATION_CHANNEL`].
+    pub fn with_schema_channel(mut self) -> Self {
+        self.channel = notification_channel_for_schema(self.schema.unquoted());
+        self
+    }
+
+    /// The channel notifications are sent on
+    pub fn channel(&self) -> &str {
+        &self.channel
+    }
+
+    /// The channel operator commands are announced on, see [`issue_command`](Self::issue_command)
+    pub fn command_channel(&self) -> String {
+        command_channel_for_schema(self.schema.unquoted())
+    }
+
+    /// Sends a `pg_notify` on the [`channel`](Self::channel) whenever a message becomes claimable again
+    /// through [`release_lease`](Self::release_lease) or [`report_retryable`](Self::report_retryable) with a
+    /// retry time that has already passed, waking idle workers of other hosts immediately. Disabled by default.
+    pub fn with_release_notifications(mut self, enabled: bool) -> Self {
+        self.notify_on_release = enabled;
+        self
+    }
+
+    /// Records a claim conflict, see [`record_claim_conflict`], whenever a report with a fencing token is rejected
+    /// as stale. The conflict is committed in a transaction of its own on `pool`, so it is kept when the transaction
+    /// of the rejected report is rolled back. None, the default, records no conflicts.
+    pub fn with_conflict_recording(mut self, pool: Option<PgPool>) -> Self {
+        self.conflict_recorder = pool;
+        self
+    }
+
+    /// Keeps at most `max_errors` errors per message when reporting with [`report_retryable`](Self::report_retryable),
+    /// see [`report_retryable_capped`](super::report_retryable_capped), and when inserting errors with
+    /// [`insert_errors`](Self::insert_errors), e.g. those of an [`ErrorWriter`]. None, the default, keeps all errors.
+    pub fn with_error_history_cap(mut self, max_errors: Option<u32>) -> Self {
+        self.max_errors = max_errors;
+        self
+    }
+
+    /// Keeps at most `max_failed` failed attempts per message when reporting with
+    /// [`report_retryable`](Self::report_retryable), deleting older ones in the same statement so that endlessly
+    /// retried messages don't grow `attempts_failed` without bound. None, the default, keeps all attempts.
+    pub fn with_failed_attempt_cap(mut self, max_failed: Option<u32>) -> Self {
+        self.max_failed_attempts = max_failed;
+        self
+    }
+
+    /// Copies the `retain` most recent failed attempts of a message to `attempts_failed_retained` when it is reported
+    /// succeeded or dead, for postmortems, rather than only deleting them. None, the default, retains none.
+    pub fn with_failed_attempt_retention(mut self, retain: Option<u32>) -> Self {
+        self.retained_failed_attempts = retain;
+        self
+    }
+
+    /// Buffers the errors of [`report_retryable`](Self::report_retryable) and [`report_dead`](Self::report_dead)
+    /// with `writer` rather than inserting them with the report, see [`ErrorWriter`]
+    pub fn with_error_writer(mut self, writer: ErrorWriter) -> Self {
+        self.error_writer = Some(writer);
+        self
+    }
+
+    /// Claims retryable messages in the given order with [`get_next_retryable`](Self::get_next_retryable),
+    /// [`RetryOrder::FailedAt`] by default.
+    pub fn with_retry_order(mut self, order: RetryOrder) -> Self {
+        self.retry_order = order;
+        self
+    }
+
+    /// Skips messages whose lease expired within `window` when claiming with
+    /// [`get_next_missing`](Self::get_next_missing), so that a late success report of the lost attempt lands before
+    /// the message is delivered again. None, the default, disables suppression.
+    pub fn with_duplicate_suppression(mut self, window: Option<Duration>) -> Self {
+        self.suppression_window = window;
+        self
+    }
+
+    /// Picks unattempted messages with the given strategy when claiming with
+    /// [`get_next_unattempted`](Self::get_next_unattempted), [`ClaimStrategy::Head`] by default.
+    pub fn with_claim_strategy(mut self, strategy: ClaimStrategy) -> Self {
+        self.claim_strategy = strategy;
+        self
+    }
+
+    /// Only claims messages whose payload matches `predicate`, with every claiming method of these queries, e.g. for
+    /// workers that may only process the messages of their region. None, the default, claims any message.
+    pub fn with_claim_predicate(mut self, predicate: Option<ClaimPredicate>) -> Self {
+        self.claim_predicate = predicate;
+        self
+    }
+
+    /// Bounds how long the server works on a claim, setting `lock_timeout` and `statement_timeout` for the rest of
+    /// the claim transaction. A claim that exceeds it fails and frees its connection, whereas a timeout on the client
+    /// only stops waiting while the backend keeps waiting for the lock. The timeouts also apply to statements run
+    /// after the claim in the same transaction. None, the default, leaves the timeouts of the connection.
+    pub fn with_claim_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.claim_timeout = timeout;
+        self
+    }
+
+    /// Prefers messages previously attempted by the claiming host when claiming with
+    /// [`get_next_retryable`](Self::get_next_retryable) and [`get_next_missing`](Self::get_next_missing), falling
+    /// back to any message. Disabled by default.
+    pub fn with_sticky_claims(mut self, enabled: bool) -> Self {
+        self.sticky_claims = enabled;
+        self
+    }
+
+    /// Claims the oldest unattempted message in two statements when claiming with
+    /// [`get_next_unattempted`](Self::get_next_unattempted) and [`ClaimStrategy::Head`], see
+    /// [`get_next_unattempted_split`]. Disabled by default.
+    pub fn with_split_claims(mut self, enabled: bool) -> Self {
+        self.split_claims = enabled;
+        self
+    }
+
+    /// Selects the query implementations suited to the server, detected with
+    /// [`detect`](crate::compatibility::detect). Claims split in two statements on servers the single statement
+    /// claim is not verified against, see [`ServerCapabilities::single_statement_claims`], and otherwise keeps
+    /// what was configured.
+    pub fn with_server_capabilities(mut self, capabilities: &ServerCapabilities) -> Self {
+        self.split_claims |= !capabilities.single_statement_claims();
+        self
+    }
+
+    /// Sends notifications carrying the schema, queue and name of the messages that became claimable as a
+    /// [`Notification`], rather than only their count, so that listeners can filter notifications and choose what
+    /// to claim. Listeners parsing bare counts only must be updated first. Disabled by default.
+    pub fn with_notification_metadata(mut self, enabled: bool) -> Self {
+        self.notification_metadata = enabled;
+        self
+    }
+
+    /// Rejects publishes exceeding the [`Quota`]s of their names or of the schema with
+    /// [`PublishError::QuotaExceeded`], see [`enforce_quotas`]. Disabled by default, as enforcing quotas takes
+    /// extra queries on every publish.
+    pub fn with_quota_enforcement(mut self, enabled: bool) -> Self {
+        self.quota_enforcement = enabled;
+        self
+    }
+
+    /// Restricts claims to while `epoch` is the active deployment epoch, see [`activate_epoch`]. Claims made while
+    /// another epoch is active return no messages, so that the hosts of a blue/green deployment on standby wait for
+    /// their cutover, and the hosts of the previous epoch drain deterministically once it happened. Hosts
+    /// [registered](Self::register_host) by these queries are registered with the epoch. Unrestricted by default.
+    pub fn with_deployment_epoch(mut self, epoch: i64) -> Self {
+        self.deployment_epoch = Some(epoch);
+        self
+    }
+
+    /// Appends messages to the event log as they succeed with [`report_success`](Self::report_success), in the
+    /// same transaction, see [`append_event`]. Consumers rebuild read models from the log with [`get_events`],
+    /// which outlives purges of the messages. Disabled by default.
+    pub fn with_event_log(mut self, spec: EventLogSpec) -> Self {
+        self.event_log = Some(spec);
+        self
+    }
+
+    /// Doesn't claim while the schema is marked as a warm standby, see [`mark_standby`], so that workers may run
+    /// against the mirror of a queue in another region and take over once it is [promoted](promote_standby).
+    /// Disabled by default, as it takes an extra query on every claim.
+    pub fn with_standby_fencing(mut self, enabled: bool) -> Self {
+        self.standby_fencing = enabled;
+        self
+    }
+
+    /// Returns whether the queries may claim, locking the standby mark if fenced and the active epoch if restricted
+    async fn may_claim(&self, tx: &mut PgTransaction<'_>) -> Result<bool, sqlx::Error> {
+        if let Some(timeout) = self.claim_timeout {
+            let millis = timeout.as_millis().max(1);
+            sqlx::query(&format!(
+                "SET LOCAL lock_timeout = {millis}; SET LOCAL statement_timeout = {millis}"
+            ))
+            .execute(&mut **tx)
+            .await?;
+        }
+        if self.standby_fencing && is_standby(&mut **tx).await? {
+            return Ok(false);
+        }
+        match self.deployment_epoch {
+            Some(epoch) => is_epoch_active(&mut **tx, epoch).await,
+            None => Ok(true),
+        }
+    }
+
+    /// Enforces the quotas of the names of `messages` if enabled
+    async fn enforce_quotas(
+        &self,
+        tx: &mut PgTransaction<'_>,
+        messages: &[&RawMessage],
+    ) -> Result<(), PublishError> {
+        if !self.quota_enforcement || messages.is_empty() {
+            return Ok(());
+        }
+        let names: Vec<&str> = messages.iter().map(|m| m.name.as_str()).collect();
+        enforce_quotas(tx, &names, Utc::now()).await
+    }
+
+    /// Notifies listeners that `count` messages became claimable, with metadata if enabled
+    async fn notify_claimable(
+        &self,
+        tx: &mut PgTransaction<'_>,
+        count: i64,
+        queue: Option<NotifiedQueue>,
+        name: Option<&str>,
+    ) -> Result<(), sqlx::Error> {
+        if !self.notification_metadata {
+            return notify(&mut **tx, &self.channel, count).await;
+        }
+
+        let notification = Notification {
+            schema: Some(self.schema.unquoted().to_string()),
+            queue,
+            name: name.map(str::to_string),
+            count: Some(count),
+        };
+        notify_payload(&mut **tx, &self.channel, &notification.payload()).await
+    }
+
+    /// Notifies listeners of published messages, naming them if they share a name
+    async fn notify_published(
+        &self,
+        tx: &mut PgTransaction<'_>,
+        published: &[RawMessage],
+    ) -> Result<(), sqlx::Error> {
+        let Some(first) = published.first() else {
+            return Ok(());
+        };
+        let name = published
+            .iter()
+            .all(|message| message.name == first.name)
+            .then_some(first.name.as_str());
+        self.notify_claimable(
+            tx,
+            published.len() as i64,
+            Some(NotifiedQueue::Unattempted),
+            name,
+        )
+        .await
+    }
+
+    /// Records a claim conflict if `result` is a stale fencing token rejection and recording is enabled
+    async fn record_conflict(
+        &self,
+        result: Result<(), ReportError>,
+        fencing_token: Option<i64>,
+        now: DateTime<Utc>,
+    ) -> Result<(), ReportError> {
+        if let (Some(pool), Err(ReportError::StaleFencingToken(message_id)), Some(fencing_token)) =
+            (&self.conflict_recorder, &result, fencing_token)
+        {
+            tracing::warn!(
+                message_id = %message_id,
+                fencing_token,
+                "Report rejected, the message was reclaimed while it was being handled"
+            );
+            // Rejected reports are typically rolled back, the conflict is kept regardless
+            let mut tx = pool.begin().await?;
+            set_schema_for_transaction(&mut tx, &self.schema).await?;
+            record_claim_conflict(&mut *tx, *message_id, fencing_token, now).await?;
+            tx.commit().await?;
         }
+
+        result
+    }
+
+    pub async fn count_claim_conflicts<'tx>(
+        &self,
+        tx: &mut PgTransaction<'tx>,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<i64, sqlx::Error> {
+        set_schema_for_transaction(tx, &self.schema).await?;
+        count_claim_conflicts(&mut **tx, from, to).await
+    }
+
+    /// Runs `f` in a transaction on this schema and commits it, retrying on serialization failures and
+    /// deadlocks. See [`with_tx`].
+    pub async fn transaction<T, F>(
+        &self,
+        pool: &PgPool,
+        retry: &TransactionRetry,
+        f: F,
+    ) -> Result<T, sqlx::Error>
+    where
+        F: for<'c> FnMut(&'c mut PgTransaction<'static>) -> BoxFuture<'c, Result<T, sqlx::Error>>,
+    {
+        with_tx(pool, &self.schema, retry, f).await
     }
 
     pub async fn get_next_retryable(
@@ -45,183 +475,1825 @@ impl Queries {
         hold_for: Duration,
     ) -> Result<Option<RawMessage>, sqlx::Error> {
         set_schema_for_transaction(tx, &self.schema).await?;
-        get_next_retryable(&mut **tx, now, host_id, hold_for).await
+        if !self.may_claim(tx).await? {
+            return Ok(None);
+        }
+        get_next_retryable_ordered(
+            &mut **tx,
+            now,
+            host_id,
+            hold_for,
+            self.retry_order,
+            self.sticky_claims,
+            self.claim_predicate.as_ref(),
+        )
+        .await
     }
 
-    pub async fn get_next_missing<'tx>(
+    pub async fn claim_batch<'tx>(
         &self,
         tx: &mut PgTransaction<'tx>,
         now: DateTime<Utc>,
         host_id: Uuid,
         hold_for: Duration,
-    ) -> Result<Option<RawMessage>, sqlx::Error> {
+        spec: ClaimBatchSpec,
+    ) -> Result<Vec<RawMessage>, sqlx::Error> {
+        set_schema_for_transaction(tx, &self.schema).await?;
+        if !self.may_claim(tx).await? {
+            return Ok(Vec::new());
+        }
+        claim_batch_inner(
+            &mut **tx,
+            now,
+            host_id,
+            hold_for,
+            spec,
+            self.claim_predicate.as_ref(),
+        )
+        .await
+    }
+
+    pub async fn get_lease_losses<'tx>(
+        &self,
+        tx: &mut PgTransaction<'tx>,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<LeaseLosses>, sqlx::Error> {
         set_schema_for_transaction(tx, &self.schema).await?;
-        get_next_missing(&mut **tx, now, host_id, hold_for).await
+        get_lease_losses(&mut **tx, from, to).await
     }
 
-    pub async fn get_next_unattempted<'tx>(
+    pub async fn get_next_dead_for_review<'tx>(
         &self,
         tx: &mut PgTransaction<'tx>,
         now: DateTime<Utc>,
         host_id: Uuid,
         hold_for: Duration,
+        grace: Duration,
     ) -> Result<Option<RawMessage>, sqlx::Error> {
         set_schema_for_transaction(tx, &self.schema).await?;
-        get_next_unattempted(&mut **tx, now, host_id, hold_for).await
+        if !self.may_claim(tx).await? {
+            return Ok(None);
+        }
+        get_next_dead_for_review_inner(
+            &mut **tx,
+            now,
+            host_id,
+            hold_for,
+            grace,
+            self.claim_predicate.as_ref(),
+        )
+        .await
     }
 
-    /// Inserts a single message into `messages_unattempted` and sends a single
-    /// `pg_notify` on [`FX_MQ_MESSAGE_NOTIFICATION_CHANNEL`] with payload `"1"`.
-    ///
-    /// Only one NOTIFY is sent per call, regardless of the number of messages
-    /// (which is always 1 for this method).
-    pub async fn publish_message(
+    pub async fn get_next_missing<'tx>(
         &self,
-        tx: &mut PgTransaction<'_>,
-        message: RawMessage,
-    ) -> Result<RawMessage, sqlx::Error> {
+        tx: &mut PgTransaction<'tx>,
+        now: DateTime<Utc>,
+        host_id: Uuid,
+        hold_for: Duration,
+    ) -> Result<Option<RawMessage>, sqlx::Error> {
         set_schema_for_transaction(tx, &self.schema).await?;
-        publish_many_messages_with_notify(tx, &[message], FX_MQ_MESSAGE_NOTIFICATION_CHANNEL)
-            .await
-            .map(|mut v| v.remove(0))
+        if !self.may_claim(tx).await? {
+            return Ok(None);
+        }
+        get_next_missing_inner(
+            &mut **tx,
+            now,
+            host_id,
+            hold_for,
+            self.sticky_claims,
+            None,
+            self.suppression_window,
+            self.claim_predicate.as_ref(),
+        )
+        .await
     }
 
-    /// Inserts multiple messages into `messages_unattempted` in a single batch
-    /// and sends a **single** `pg_notify` on [`FX_MQ_MESSAGE_NOTIFICATION_CHANNEL`]
-    /// with the total count as payload (e.g. `"5"` for 5 messages).
-    ///
-    /// As with [`publish_message`](Self::publish_message), there is exactly one
-    /// NOTIFY per call, regardless of batch size.
-    pub async fn publish_many_messages(
+    pub async fn get_next_unattempted<'tx>(
         &self,
-        tx: &mut PgTransaction<'_>,
-        messages: &[RawMessage],
-    ) -> Result<Vec<RawMessage>, sqlx::Error> {
+        tx: &mut PgTransaction<'tx>,
+        now: DateTime<Utc>,
+        host_id: Uuid,
+        hold_for: Duration,
+    ) -> Result<Option<RawMessage>, sqlx::Error> {
         set_schema_for_transaction(tx, &self.schema).await?;
-        publish_many_messages_with_notify(tx, messages, FX_MQ_MESSAGE_NOTIFICATION_CHANNEL).await
+        if !self.may_claim(tx).await? {
+            return Ok(None);
+        }
+        let predicate = self.claim_predicate.as_ref();
+        match self.claim_strategy {
+            ClaimStrategy::Head if self.split_claims => {
+                get_next_unattempted_split(tx, now, host_id, hold_for, predicate).await
+            }
+            ClaimStrategy::Head => {
+                get_next_unattempted_inner(&mut **tx, now, host_id, hold_for, predicate).await
+            }
+            ClaimStrategy::RandomWindow(window) => {
+                get_next_unattempted_windowed(&mut **tx, now, host_id, hold_for, window, predicate)
+                    .await
+            }
+        }
     }
 
-    pub async fn report_dead<'tx>(
+    /// Claims the next unattempted message with its payload as JSON text, see
+    /// [`get_next_unattempted_text`](crate::queries::get_next_unattempted_text)
+    pub async fn get_next_unattempted_text<'tx>(
         &self,
         tx: &mut PgTransaction<'tx>,
-        message_id: Uuid,
         now: DateTime<Utc>,
-        error_str: &str,
-    ) -> Result<(), sqlx::Error> {
+        host_id: Uuid,
+        hold_for: Duration,
+    ) -> Result<Option<RawTextMessage>, sqlx::Error> {
         set_schema_for_transaction(tx, &self.schema).await?;
-        report_dead(&mut **tx, message_id, now, error_str).await
+        if !self.may_claim(tx).await? {
+            return Ok(None);
+        }
+        get_next_unattempted_text_inner(
+            &mut **tx,
+            now,
+            host_id,
+            hold_for,
+            self.claim_predicate.as_ref(),
+        )
+        .await
     }
 
-    pub async fn report_retryable<'tx>(
+    pub async fn get_next_unattempted_projected<'tx>(
         &self,
         tx: &mut PgTransaction<'tx>,
-        message_id: Uuid,
-        failed_at: DateTime<Utc>,
-        attempted: i32, // increment this before passing to the query!
-        try_earliest_at: DateTime<Utc>,
-        error_str: &str,
-    ) -> Result<(), sqlx::Error> {
+        now: DateTime<Utc>,
+        host_id: Uuid,
+        hold_for: Duration,
+        fields: &[String],
+    ) -> Result<Option<RawMessage>, sqlx::Error> {
         set_schema_for_transaction(tx, &self.schema).await?;
-        report_retryable(
+        if !self.may_claim(tx).await? {
+            return Ok(None);
+        }
+        get_next_unattempted_projected_inner(
             &mut **tx,
-            message_id,
-            failed_at,
-            attempted,
-            try_earliest_at,
-            error_str,
+            now,
+            host_id,
+            hold_for,
+            fields,
+            self.claim_predicate.as_ref(),
         )
         .await
     }
 
-    pub async fn report_success<'tx>(
+    pub async fn get_payload<'tx>(
         &self,
         tx: &mut PgTransaction<'tx>,
         message_id: Uuid,
-        now: DateTime<Utc>,
-    ) -> Result<(), sqlx::Error> {
+    ) -> Result<Option<serde_json::Value>, sqlx::Error> {
         set_schema_for_transaction(tx, &self.schema).await?;
-        report_success(&mut **tx, message_id, now).await
+        get_payload(&mut **tx, message_id).await
     }
 
-    pub async fn request_lease<'tx>(
+    pub async fn get_next_unattempted_named<'tx>(
         &self,
         tx: &mut PgTransaction<'tx>,
-        message_id: Uuid,
         now: DateTime<Utc>,
         host_id: Uuid,
         hold_for: Duration,
-    ) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+        names: &[String],
+    ) -> Result<Option<RawMessage>, sqlx::Error> {
         set_schema_for_transaction(tx, &self.schema).await?;
-        request_lease(&mut **tx, message_id, now, host_id, hold_for).await
+        if !self.may_claim(tx).await? {
+            return Ok(None);
+        }
+        get_next_unattempted_named_inner(
+            &mut **tx,
+            now,
+            host_id,
+            hold_for,
+            names,
+            self.claim_predicate.as_ref(),
+        )
+        .await
     }
 
-    pub async fn is_pending<'tx>(
+    pub async fn get_next_retryable_named<'tx>(
         &self,
         tx: &mut PgTransaction<'tx>,
-        message_id: Uuid,
         now: DateTime<Utc>,
-    ) -> Result<bool, sqlx::Error> {
+        host_id: Uuid,
+        hold_for: Duration,
+        names: &[String],
+    ) -> Result<Option<RawMessage>, sqlx::Error> {
         set_schema_for_transaction(tx, &self.schema).await?;
-        is_pending(&mut **tx, message_id, now).await
+        if !self.may_claim(tx).await? {
+            return Ok(None);
+        }
+        get_next_retryable_named_inner(
+            &mut **tx,
+            now,
+            host_id,
+            hold_for,
+            names,
+            self.claim_predicate.as_ref(),
+        )
+        .await
     }
 
-    pub async fn is_in_progress<'tx>(
+    pub async fn get_next_missing_named<'tx>(
         &self,
         tx: &mut PgTransaction<'tx>,
-        message_id: Uuid,
         now: DateTime<Utc>,
-    ) -> Result<bool, sqlx::Error> {
+        host_id: Uuid,
+        hold_for: Duration,
+        names: &[String],
+    ) -> Result<Option<RawMessage>, sqlx::Error> {
         set_schema_for_transaction(tx, &self.schema).await?;
-        is_in_progress(&mut **tx, message_id, now).await
+        if !self.may_claim(tx).await? {
+            return Ok(None);
+        }
+        get_next_missing_inner(
+            &mut **tx,
+            now,
+            host_id,
+            hold_for,
+            false,
+            Some(names),
+            self.suppression_window,
+            self.claim_predicate.as_ref(),
+        )
+        .await
     }
 
-    pub async fn is_missing<'tx>(
+    pub async fn get_many_unattempted<'tx>(
         &self,
         tx: &mut PgTransaction<'tx>,
-        message_id: Uuid,
         now: DateTime<Utc>,
-    ) -> Result<bool, sqlx::Error> {
+        host_id: Uuid,
+        hold_for: Duration,
+        limit: i64,
+    ) -> Result<Vec<RawMessage>, sqlx::Error> {
         set_schema_for_transaction(tx, &self.schema).await?;
-        is_missing(&mut **tx, message_id, now).await
+        if !self.may_claim(tx).await? {
+            return Ok(Vec::new());
+        }
+        get_many_unattempted_inner(
+            &mut **tx,
+            now,
+            host_id,
+            hold_for,
+            limit,
+            self.claim_predicate.as_ref(),
+        )
+        .await
     }
 
-    pub async fn is_failed<'tx>(
+    /// Returns the distribution of payload sizes per message name since `since`, see [`payload_sizes_by_name`]
+    pub async fn payload_sizes_by_name(
         &self,
-        tx: &mut PgTransaction<'tx>,
-        message_id: Uuid,
-        now: DateTime<Utc>,
-    ) -> Result<bool, sqlx::Error> {
+        tx: &mut PgTransaction<'_>,
+        since: DateTime<Utc>,
+    ) -> Result<Vec<PayloadSizes>, sqlx::Error> {
         set_schema_for_transaction(tx, &self.schema).await?;
-        is_failed(&mut **tx, message_id, now).await
+        payload_sizes_by_name(&mut **tx, since).await
     }
 
-    pub async fn is_succeeded<'tx>(
+    /// Inserts a single message into `messages_unattempted` and sends a single
+    /// `pg_notify` on the [`channel`](Self::channel) with payload `"1"`.
+    ///
+    /// Only one NOTIFY is sent per call, regardless of the number of messages
+    /// (which is always 1 for this method).
+    pub async fn publish_message(
         &self,
-        tx: &mut PgTransaction<'tx>,
-        message_id: Uuid,
-        now: DateTime<Utc>,
-    ) -> Result<bool, sqlx::Error> {
+        tx: &mut PgTransaction<'_>,
+        message: RawMessage,
+    ) -> Result<RawMessage, PublishError> {
         set_schema_for_transaction(tx, &self.schema).await?;
-        is_succeeded(&mut **tx, message_id, now).await
+        self.enforce_quotas(tx, &[&message]).await?;
+        let mut published = publish_many_messages_inner(tx, &[message]).await?;
+        self.notify_published(tx, &published).await?;
+        Ok(published.remove(0))
     }
 
-    pub async fn is_dead<'tx>(
+    /// Publishes a message handling duplicate ids as configured by `on_conflict`, see
+    /// [`publish_message_on_conflict`]. Listeners are notified when the message was inserted.
+    pub async fn publish_message_on_conflict(
         &self,
-        tx: &mut PgTransaction<'tx>,
-        message_id: Uuid,
-        now: DateTime<Utc>,
-    ) -> Result<bool, sqlx::Error> {
+        tx: &mut PgTransaction<'_>,
+        message: &RawMessage,
+        on_conflict: PublishConflict,
+    ) -> Result<PublishOutcome, PublishError> {
         set_schema_for_transaction(tx, &self.schema).await?;
-        is_dead(&mut **tx, message_id, now).await
+        self.enforce_quotas(tx, &[message]).await?;
+        let outcome = publish_message_on_conflict(&mut **tx, message, on_conflict).await?;
+        if matches!(outcome, PublishOutcome::Inserted(_)) {
+            self.notify_claimable(tx, 1, Some(NotifiedQueue::Unattempted), Some(&message.name))
+                .await?;
+        }
+        Ok(outcome)
     }
 
-    pub async fn search_pending<'tx>(
+    /// Publishes a barrier message, claimable once every message published before it has succeeded or is dead,
+    /// see [`publish_barrier`]. Listeners are notified when the barrier is released right away.
+    pub async fn publish_barrier(
         &self,
-        tx: &mut PgTransaction<'tx>,
-        name: &str,
-        payload: &serde_json::Value,
-    ) -> Result<i64, sqlx::Error> {
+        tx: &mut PgTransaction<'_>,
+        message: &RawMessage,
+    ) -> Result<RawMessage, sqlx::Error> {
         set_schema_for_transaction(tx, &self.schema).await?;
-        search_scheduled(&mut **tx, name, payload).await
+        let message = publish_barrier(&mut **tx, message).await?;
+        self.release_barriers(tx).await?;
+        Ok(message)
+    }
+
+    /// Releases the oldest barrier if every message before it is terminal, see [`release_barriers`]. Listeners
+    /// are notified of released barriers.
+    pub async fn release_barriers(&self, tx: &mut PgTransaction<'_>) -> Result<i32, sqlx::Error> {
+        set_schema_for_transaction(tx, &self.schema).await?;
+        let released = release_barriers(&mut **tx).await?;
+        if released > 0 {
+            self.notify_claimable(tx, released.into(), Some(NotifiedQueue::Unattempted), None)
+                .await?;
+        }
+        Ok(released)
+    }
+
+    pub async fn publish_succeeded(
+        &self,
+        tx: &mut PgTransaction<'_>,
+        message: &RawMessage,
+        now: DateTime<Utc>,
+    ) -> Result<(), sqlx::Error> {
+        set_schema_for_transaction(tx, &self.schema).await?;
+        publish_succeeded(&mut **tx, message, now).await
+    }
+
+    /// Inserts multiple messages into `messages_unattempted` in a single batch
+    /// and sends a **single** `pg_notify` on the [`channel`](Self::channel)
+    /// with the total count as payload (e.g. `"5"` for 5 messages).
+    ///
+    /// As with [`publish_message`](Self::publish_message), there is exactly one
+    /// NOTIFY per call, regardless of batch size.
+    pub async fn publish_many_messages(
+        &self,
+        tx: &mut PgTransaction<'_>,
+        messages: &[RawMessage],
+    ) -> Result<Vec<RawMessage>, PublishError> {
+        set_schema_for_transaction(tx, &self.schema).await?;
+        self.enforce_quotas(tx, &messages.iter().collect::<Vec<_>>())
+            .await?;
+        let published = publish_many_messages_inner(tx, messages).await?;
+        self.notify_published(tx, &published).await?;
+        Ok(published)
+    }
+
+    /// Releases a claimed message without counting the attempt, see [`report_deferred`]
+    pub async fn report_deferred<'tx>(
+        &self,
+        tx: &mut PgTransaction<'tx>,
+        message: &RawMessage,
+        deferred_at: DateTime<Utc>,
+        until: DateTime<Utc>,
+    ) -> Result<(), ReportError> {
+        set_schema_for_transaction(tx, &self.schema).await?;
+        let result = report_deferred(
+            &mut **tx,
+            message.id,
+            message.fencing_token,
+            deferred_at,
+            message.attempted,
+            until,
+        )
+        .await;
+        self.record_conflict(result, message.fencing_token, deferred_at)
+            .await
+    }
+
+    /// Reports a claimed message as dead, see [`report_dead`](super::report_dead)
+    pub async fn report_dead<'tx, 'a>(
+        &self,
+        tx: &mut PgTransaction<'tx>,
+        message: &RawMessage,
+        now: DateTime<Utc>,
+        error: impl Into<FailureReason<'a>>,
+    ) -> Result<(), ReportError> {
+        set_schema_for_transaction(tx, &self.schema).await?;
+        let error = error.into();
+        let fencing_token = message.fencing_token;
+        let Some(writer) = &self.error_writer else {
+            let result = report_dead_inner(
+                &mut **tx,
+                message.id,
+                fencing_token,
+                now,
+                Some(error),
+                self.retained_failed_attempts.map(i64::from),
+            )
+            .await;
+            return self.record_conflict(result, fencing_token, now).await;
+        };
+
+        let result = report_dead_inner(
+            &mut **tx,
+            message.id,
+            fencing_token,
+            now,
+            None,
+            self.retained_failed_attempts.map(i64::from),
+        )
+        .await;
+        self.record_conflict(result, fencing_token, now).await?;
+        self.write_error(writer, tx, message.id, now, error).await?;
+        Ok(())
+    }
+
+    /// Buffers an error with `writer`, inserting it in `tx` if the buffer is full or the flusher of the writer has
+    /// stopped, rather than waiting for room while holding the report transaction
+    async fn write_error(
+        &self,
+        writer: &ErrorWriter,
+        tx: &mut PgTransaction<'_>,
+        message_id: Uuid,
+        reported_at: DateTime<Utc>,
+        error: FailureReason<'_>,
+    ) -> Result<(), sqlx::Error> {
+        let record = ErrorRecord {
+            id: Uuid::now_v7(),
+            message_id,
+            reported_at,
+            error: error.error.to_string(),
+            category: error.category.map(str::to_string),
+        };
+        if let Err(record) = writer.try_write(record) {
+            insert_errors_inner(&mut **tx, &[record], self.max_errors.map(i64::from)).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn report_reviewed<'tx>(
+        &self,
+        tx: &mut PgTransaction<'tx>,
+        message_id: Uuid,
+        fencing_token: Option<i64>,
+        now: DateTime<Utc>,
+    ) -> Result<(), ReportError> {
+        set_schema_for_transaction(tx, &self.schema).await?;
+        let result = report_reviewed(&mut **tx, message_id, fencing_token, now).await;
+        self.record_conflict(result, fencing_token, now).await
+    }
+
+    pub async fn report_remediated<'tx>(
+        &self,
+        tx: &mut PgTransaction<'tx>,
+        message_id: Uuid,
+        fencing_token: Option<i64>,
+        now: DateTime<Utc>,
+        payload: Option<&serde_json::Value>,
+        retry_earliest_at: DateTime<Utc>,
+    ) -> Result<(), ReportError> {
+        set_schema_for_transaction(tx, &self.schema).await?;
+        let result = report_remediated(
+            &mut **tx,
+            message_id,
+            fencing_token,
+            now,
+            payload,
+            retry_earliest_at,
+        )
+        .await;
+        self.record_conflict(result, fencing_token, now).await
+    }
+
+    /// Reports a failed attempt of a claimed message, counting the attempt, see
+    /// [`report_retryable`](super::report_retryable)
+    pub async fn report_retryable<'tx, 'a>(
+        &self,
+        tx: &mut PgTransaction<'tx>,
+        message: &RawMessage,
+        failed_at: DateTime<Utc>,
+        try_earliest_at: DateTime<Utc>,
+        error: impl Into<FailureReason<'a>>,
+    ) -> Result<(), ReportError> {
+        let (message_id, fencing_token) = (message.id, message.fencing_token);
+        set_schema_for_transaction(tx, &self.schema).await?;
+        let reason = error.into();
+        let error = match self.error_writer {
+            Some(_) => None,
+            None => Some(reason),
+        };
+        let result = report_retryable_inner(
+            &mut **tx,
+            message_id,
+            fencing_token,
+            failed_at,
+            message.attempted + 1,
+            try_earliest_at,
+            error,
+            self.max_errors.map(i64::from),
+            self.max_failed_attempts.map(i64::from),
+        )
+        .await;
+        self.record_conflict(result, fencing_token, failed_at)
+            .await?;
+
+        if let Some(writer) = &self.error_writer {
+            self.write_error(writer, tx, message_id, failed_at, reason)
+                .await?;
+        }
+
+        if self.notify_on_release && try_earliest_at <= failed_at {
+            self.notify_claimable(tx, 1, Some(NotifiedQueue::Retryable), Some(&message.name))
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Reports a failed attempt like [`report_retryable`](Self::report_retryable), or like
+    /// [`report_dead`](Self::report_dead) once the retry window of `backoff` has passed, see
+    /// [`report_failure`](super::report_failure)
+    pub async fn report_failure<'tx, 'a>(
+        &self,
+        tx: &mut PgTransaction<'tx>,
+        message: &RawMessage,
+        now: DateTime<Utc>,
+        backoff: &impl Backoff,
+        error: impl Into<FailureReason<'a>>,
+    ) -> Result<FailureOutcome, ReportError> {
+        set_schema_for_transaction(tx, &self.schema).await?;
+        let error = error.into();
+        match retry_at(&mut **tx, message, now, backoff).await? {
+            Some(at) => {
+                self.report_retryable(tx, message, now, at, error).await?;
+                Ok(FailureOutcome::Retrying { at })
+            }
+            None => {
+                self.report_dead(tx, message, now, error).await?;
+                Ok(FailureOutcome::Dead)
+            }
+        }
+    }
+
+    /// Reports a claimed message as succeeded, see [`report_success`](super::report_success)
+    pub async fn report_success<'tx>(
+        &self,
+        tx: &mut PgTransaction<'tx>,
+        message: &RawMessage,
+        now: DateTime<Utc>,
+    ) -> Result<(), ReportError> {
+        set_schema_for_transaction(tx, &self.schema).await?;
+        let result = report_success_inner(
+            &mut **tx,
+            message.id,
+            message.fencing_token,
+            now,
+            self.retained_failed_attempts.map(i64::from),
+        )
+        .await;
+        self.record_conflict(result, message.fencing_token, now)
+            .await?;
+        if let Some(spec) = self
+            .event_log
+            .as_ref()
+            .filter(|s| s.includes(&message.name))
+        {
+            append_event(tx, message.id, spec.path(), now).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn append_event<'tx>(
+        &self,
+        tx: &mut PgTransaction<'tx>,
+        message_id: Uuid,
+        aggregate_path: &[String],
+        now: DateTime<Utc>,
+    ) -> Result<Option<i64>, sqlx::Error> {
+        set_schema_for_transaction(tx, &self.schema).await?;
+        append_event(tx, message_id, aggregate_path, now).await
+    }
+
+    pub async fn get_events<'tx>(
+        &self,
+        tx: &mut PgTransaction<'tx>,
+        aggregate_id: &str,
+        after: Option<i64>,
+        limit: i64,
+    ) -> Result<Vec<LoggedEvent>, sqlx::Error> {
+        set_schema_for_transaction(tx, &self.schema).await?;
+        get_events(&mut **tx, aggregate_id, after, limit).await
+    }
+
+    pub async fn request_lease<'tx>(
+        &self,
+        tx: &mut PgTransaction<'tx>,
+        message_id: Uuid,
+        now: DateTime<Utc>,
+        host_id: Uuid,
+        hold_for: Duration,
+    ) -> Result<Option<Lease>, sqlx::Error> {
+        set_schema_for_transaction(tx, &self.schema).await?;
+        if !self.may_claim(tx).await? {
+            return Ok(None);
+        }
+        request_lease(&mut **tx, message_id, now, host_id, hold_for).await
+    }
+
+    pub async fn mark_standby<'tx>(
+        &self,
+        tx: &mut PgTransaction<'tx>,
+        now: DateTime<Utc>,
+    ) -> Result<(), sqlx::Error> {
+        set_schema_for_transaction(tx, &self.schema).await?;
+        mark_standby(&mut **tx, now).await
+    }
+
+    pub async fn promote_standby<'tx>(
+        &self,
+        tx: &mut PgTransaction<'tx>,
+    ) -> Result<bool, sqlx::Error> {
+        set_schema_for_transaction(tx, &self.schema).await?;
+        promote_standby(&mut **tx).await
+    }
+
+    pub async fn activate_epoch<'tx>(
+        &self,
+        tx: &mut PgTransaction<'tx>,
+        epoch: i64,
+        now: DateTime<Utc>,
+    ) -> Result<Option<i64>, sqlx::Error> {
+        set_schema_for_transaction(tx, &self.schema).await?;
+        activate_epoch(&mut **tx, epoch, now).await
+    }
+
+    pub async fn get_active_epoch<'tx>(
+        &self,
+        tx: &mut PgTransaction<'tx>,
+    ) -> Result<Option<i64>, sqlx::Error> {
+        set_schema_for_transaction(tx, &self.schema).await?;
+        get_active_epoch(&mut **tx).await
+    }
+
+    pub async fn register_host<'tx>(
+        &self,
+        tx: &mut PgTransaction<'tx>,
+        host_id: Uuid,
+        label: &str,
+        now: DateTime<Utc>,
+    ) -> Result<(), sqlx::Error> {
+        set_schema_for_transaction(tx, &self.schema).await?;
+        match self.deployment_epoch {
+            Some(epoch) => register_host_in_epoch(&mut **tx, host_id, label, epoch, now).await,
+            None => register_host(&mut **tx, host_id, label, now).await,
+        }
+    }
+
+    /// Detects claim starvation, see [`get_claim_starvation`], and logs a warning when detected
+    pub async fn get_claim_starvation<'tx>(
+        &self,
+        tx: &mut PgTransaction<'tx>,
+        now: DateTime<Utc>,
+        threshold: Duration,
+    ) -> Result<Option<ClaimStarvation>, sqlx::Error> {
+        set_schema_for_transaction(tx, &self.schema).await?;
+        let starvation = get_claim_starvation(&mut **tx, now, threshold).await?;
+
+        if let Some(starvation) = &starvation {
+            tracing::warn!(
+                schema = self.schema.unquoted(),
+                claimable = starvation.claimable,
+                oldest_claimable_since = %starvation.oldest_claimable_since,
+                last_claimed_at = ?starvation.last_claimed_at,
+                "Claimable messages are not being claimed, check that workers are running and not paused"
+            );
+        }
+
+        Ok(starvation)
+    }
+
+    pub async fn set_retry_concurrency_limit<'tx>(
+        &self,
+        tx: &mut PgTransaction<'tx>,
+        name: &str,
+        max_in_progress: Option<i32>,
+    ) -> Result<(), sqlx::Error> {
+        set_schema_for_transaction(tx, &self.schema).await?;
+        set_retry_concurrency_limit(&mut **tx, name, max_in_progress).await
+    }
+
+    pub async fn get_oldest_claimable<'tx>(
+        &self,
+        tx: &mut PgTransaction<'tx>,
+        now: DateTime<Utc>,
+    ) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+        set_schema_for_transaction(tx, &self.schema).await?;
+        get_oldest_claimable(&mut **tx, now).await
+    }
+
+    pub async fn lag_by_name<'tx>(
+        &self,
+        tx: &mut PgTransaction<'tx>,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<NameLag>, sqlx::Error> {
+        set_schema_for_transaction(tx, &self.schema).await?;
+        lag_by_name(&mut **tx, now).await
+    }
+
+    pub async fn get_queue_settings<'tx>(
+        &self,
+        tx: &mut PgTransaction<'tx>,
+    ) -> Result<Vec<QueueSettings>, sqlx::Error> {
+        set_schema_for_transaction(tx, &self.schema).await?;
+        get_queue_settings(&mut **tx).await
+    }
+
+    pub async fn put_queue_settings<'tx>(
+        &self,
+        tx: &mut PgTransaction<'tx>,
+        settings: &QueueSettings,
+        now: DateTime<Utc>,
+    ) -> Result<(), sqlx::Error> {
+        set_schema_for_transaction(tx, &self.schema).await?;
+        put_queue_settings(&mut **tx, settings, now).await
+    }
+
+    pub async fn delete_queue_settings<'tx>(
+        &self,
+        tx: &mut PgTransaction<'tx>,
+        name: &str,
+    ) -> Result<bool, sqlx::Error> {
+        set_schema_for_transaction(tx, &self.schema).await?;
+        delete_queue_settings(&mut **tx, name).await
+    }
+
+    pub async fn put_quota<'tx>(
+        &self,
+        tx: &mut PgTransaction<'tx>,
+        quota: &Quota,
+        now: DateTime<Utc>,
+    ) -> Result<(), sqlx::Error> {
+        set_schema_for_transaction(tx, &self.schema).await?;
+        put_quota(&mut **tx, quota, now).await
+    }
+
+    pub async fn delete_quota<'tx>(
+        &self,
+        tx: &mut PgTransaction<'tx>,
+        scope: &QuotaScope,
+    ) -> Result<bool, sqlx::Error> {
+        set_schema_for_transaction(tx, &self.schema).await?;
+        delete_quota(&mut **tx, scope).await
+    }
+
+    pub async fn put_blob<'tx>(
+        &self,
+        tx: &mut PgTransaction<'tx>,
+        id: Uuid,
+        data: &serde_json::Value,
+        now: DateTime<Utc>,
+    ) -> Result<(), sqlx::Error> {
+        set_schema_for_transaction(tx, &self.schema).await?;
+        put_blob(&mut **tx, id, data, now).await
+    }
+
+    pub async fn get_blob<'tx>(
+        &self,
+        tx: &mut PgTransaction<'tx>,
+        id: Uuid,
+    ) -> Result<Option<serde_json::Value>, sqlx::Error> {
+        set_schema_for_transaction(tx, &self.schema).await?;
+        get_blob(&mut **tx, id).await
+    }
+
+    pub async fn delete_blob<'tx>(
+        &self,
+        tx: &mut PgTransaction<'tx>,
+        id: Uuid,
+    ) -> Result<bool, sqlx::Error> {
+        set_schema_for_transaction(tx, &self.schema).await?;
+        delete_blob(&mut **tx, id).await
+    }
+
+    pub async fn get_lease_holder<'tx>(
+        &self,
+        tx: &mut PgTransaction<'tx>,
+        message_id: Uuid,
+        now: DateTime<Utc>,
+    ) -> Result<Option<LeaseHolder>, sqlx::Error> {
+        set_schema_for_transaction(tx, &self.schema).await?;
+        get_lease_holder(&mut **tx, message_id, now).await
+    }
+
+    pub async fn list_in_progress<'tx>(
+        &self,
+        tx: &mut PgTransaction<'tx>,
+        host_id: Uuid,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<InProgressMessage>, sqlx::Error> {
+        set_schema_for_transaction(tx, &self.schema).await?;
+        list_in_progress(&mut **tx, host_id, now).await
+    }
+
+    /// Records an operator command and notifies the workers listening on the
+    /// [`command_channel`](Self::command_channel) when the transaction commits
+    pub async fn issue_command<'tx>(
+        &self,
+        tx: &mut PgTransaction<'tx>,
+        command: &OperatorCommand,
+        target_host: Option<Uuid>,
+        now: DateTime<Utc>,
+    ) -> Result<i64, sqlx::Error> {
+        set_schema_for_transaction(tx, &self.schema).await?;
+        let id = issue_command(&mut **tx, command, target_host, now).await?;
+        notify(&mut **tx, &self.command_channel(), 1).await?;
+        Ok(id)
+    }
+
+    pub async fn get_commands_after<'tx>(
+        &self,
+        tx: &mut PgTransaction<'tx>,
+        after_id: i64,
+        host_id: Uuid,
+    ) -> Result<Vec<IssuedCommand>, sqlx::Error> {
+        set_schema_for_transaction(tx, &self.schema).await?;
+        get_commands_after(&mut **tx, after_id, host_id).await
+    }
+
+    pub async fn get_message_status<'tx>(
+        &self,
+        tx: &mut PgTransaction<'tx>,
+        message_id: Uuid,
+        as_of: DateTime<Utc>,
+    ) -> Result<Option<MessageStatus>, sqlx::Error> {
+        set_schema_for_transaction(tx, &self.schema).await?;
+        get_message_status(&mut **tx, message_id, as_of).await
+    }
+
+    pub async fn get_message_statuses<'tx>(
+        &self,
+        tx: &mut PgTransaction<'tx>,
+        message_ids: &[Uuid],
+        as_of: DateTime<Utc>,
+    ) -> Result<HashMap<Uuid, MessageStatus>, sqlx::Error> {
+        set_schema_for_transaction(tx, &self.schema).await?;
+        get_message_statuses(&mut **tx, message_ids, as_of).await
+    }
+
+    pub async fn get_message_timeline<'tx>(
+        &self,
+        tx: &mut PgTransaction<'tx>,
+        message_id: Uuid,
+    ) -> Result<Vec<TimelineEvent>, sqlx::Error> {
+        set_schema_for_transaction(tx, &self.schema).await?;
+        get_message_timeline(&mut **tx, message_id).await
+    }
+
+    pub async fn list_messages<'tx>(
+        &self,
+        tx: &mut PgTransaction<'tx>,
+        name: Option<&str>,
+        before: Option<i64>,
+        limit: i64,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<MessageSummary>, sqlx::Error> {
+        set_schema_for_transaction(tx, &self.schema).await?;
+        list_messages(&mut **tx, name, before, limit, now).await
+    }
+
+    pub async fn release_lease<'tx>(
+        &self,
+        tx: &mut PgTransaction<'tx>,
+        message_id: Uuid,
+        fencing_token: Option<i64>,
+        now: DateTime<Utc>,
+        host_id: Uuid,
+    ) -> Result<bool, sqlx::Error> {
+        set_schema_for_transaction(tx, &self.schema).await?;
+        let released = release_lease(&mut **tx, message_id, fencing_token, now, host_id).await?;
+
+        if self.notify_on_release && released {
+            self.notify_claimable(tx, 1, None, None).await?;
+        }
+
+        Ok(released)
+    }
+
+    pub async fn renew_lease<'tx>(
+        &self,
+        tx: &mut PgTransaction<'tx>,
+        message_id: Uuid,
+        fencing_token: i64,
+        now: DateTime<Utc>,
+        hold_for: Duration,
+    ) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+        set_schema_for_transaction(tx, &self.schema).await?;
+        renew_lease(&mut **tx, message_id, fencing_token, now, hold_for).await
+    }
+
+    pub async fn retry_dead_by_name<'tx>(
+        &self,
+        tx: &mut PgTransaction<'tx>,
+        name: &str,
+        now: DateTime<Utc>,
+        backoff: &impl Backoff,
+    ) -> Result<u64, sqlx::Error> {
+        set_schema_for_transaction(tx, &self.schema).await?;
+        retry_dead_by_name(&mut **tx, name, now, backoff).await
+    }
+
+    pub async fn rewrite_payloads<'tx, F>(
+        &self,
+        tx: &mut PgTransaction<'tx>,
+        name: &str,
+        after: Option<Uuid>,
+        limit: i64,
+        rewrite: F,
+    ) -> Result<PayloadRewrite, sqlx::Error>
+    where
+        F: FnMut(serde_json::Value) -> serde_json::Value,
+    {
+        set_schema_for_transaction(tx, &self.schema).await?;
+        rewrite_payloads(tx, name, after, limit, rewrite).await
+    }
+
+    pub async fn is_pending<'tx>(
+        &self,
+        tx: &mut PgTransaction<'tx>,
+        message_id: Uuid,
+        now: DateTime<Utc>,
+    ) -> Result<bool, sqlx::Error> {
+        set_schema_for_transaction(tx, &self.schema).await?;
+        is_pending(&mut **tx, message_id, now).await
+    }
+
+    pub async fn is_in_progress<'tx>(
+        &self,
+        tx: &mut PgTransaction<'tx>,
+        message_id: Uuid,
+        now: DateTime<Utc>,
+    ) -> Result<bool, sqlx::Error> {
+        set_schema_for_transaction(tx, &self.schema).await?;
+        is_in_progress(&mut **tx, message_id, now).await
+    }
+
+    pub async fn is_missing<'tx>(
+        &self,
+        tx: &mut PgTransaction<'tx>,
+        message_id: Uuid,
+        now: DateTime<Utc>,
+    ) -> Result<bool, sqlx::Error> {
+        set_schema_for_transaction(tx, &self.schema).await?;
+        is_missing(&mut **tx, message_id, now).await
+    }
+
+    pub async fn is_failed<'tx>(
+        &self,
+        tx: &mut PgTransaction<'tx>,
+        message_id: Uuid,
+        now: DateTime<Utc>,
+    ) -> Result<bool, sqlx::Error> {
+        set_schema_for_transaction(tx, &self.schema).await?;
+        is_failed(&mut **tx, message_id, now).await
+    }
+
+    pub async fn is_succeeded<'tx>(
+        &self,
+        tx: &mut PgTransaction<'tx>,
+        message_id: Uuid,
+        now: DateTime<Utc>,
+    ) -> Result<bool, sqlx::Error> {
+        set_schema_for_transaction(tx, &self.schema).await?;
+        is_succeeded(&mut **tx, message_id, now).await
+    }
+
+    pub async fn is_dead<'tx>(
+        &self,
+        tx: &mut PgTransaction<'tx>,
+        message_id: Uuid,
+        now: DateTime<Utc>,
+    ) -> Result<bool, sqlx::Error> {
+        set_schema_for_transaction(tx, &self.schema).await?;
+        is_dead(&mut **tx, message_id, now).await
+    }
+
+    pub async fn delete_messages_matching<'tx>(
+        &self,
+        tx: &mut PgTransaction<'tx>,
+        name: &str,
+        predicate: &serde_json::Value,
+        limit: i64,
+    ) -> Result<u64, sqlx::Error> {
+        set_schema_for_transaction(tx, &self.schema).await?;
+        delete_messages_matching(&mut **tx, name, predicate, limit).await
+    }
+
+    pub async fn get_queue_health<'tx>(
+        &self,
+        tx: &mut PgTransaction<'tx>,
+        now: DateTime<Utc>,
+        thresholds: &HealthThresholds,
+    ) -> Result<QueueHealth, sqlx::Error> {
+        set_schema_for_transaction(tx, &self.schema).await?;
+        get_queue_health(&mut **tx, now, thresholds).await
+    }
+
+    pub async fn get_retry_state<'tx>(
+        &self,
+        tx: &mut PgTransaction<'tx>,
+        message_id: Uuid,
+        now: DateTime<Utc>,
+    ) -> Result<Option<RetryState>, sqlx::Error> {
+        set_schema_for_transaction(tx, &self.schema).await?;
+        get_retry_state(&mut **tx, message_id, now).await
+    }
+
+    pub async fn purge_finished<'tx>(
+        &self,
+        tx: &mut PgTransaction<'tx>,
+        finished_before: DateTime<Utc>,
+        after: Option<i64>,
+        limit: i64,
+    ) -> Result<PurgedBatch, sqlx::Error> {
+        set_schema_for_transaction(tx, &self.schema).await?;
+        purge_finished(&mut **tx, finished_before, after, limit).await
+    }
+
+    pub async fn analyze_purged<'tx>(
+        &self,
+        tx: &mut PgTransaction<'tx>,
+    ) -> Result<(), sqlx::Error> {
+        set_schema_for_transaction(tx, &self.schema).await?;
+        analyze_purged(&mut **tx).await
+    }
+
+    pub async fn search_pending<'tx>(
+        &self,
+        tx: &mut PgTransaction<'tx>,
+        name: &str,
+        payload: &serde_json::Value,
+        now: DateTime<Utc>,
+    ) -> Result<i64, sqlx::Error> {
+        set_schema_for_transaction(tx, &self.schema).await?;
+        search_scheduled(&mut **tx, name, payload, now).await
+    }
+
+    pub async fn compact_daily_aggregates<'tx>(
+        &self,
+        tx: &mut PgTransaction<'tx>,
+        day: NaiveDate,
+        now: DateTime<Utc>,
+    ) -> Result<u64, sqlx::Error> {
+        set_schema_for_transaction(tx, &self.schema).await?;
+        compact_daily_aggregates(&mut **tx, day, now).await
+    }
+
+    pub async fn get_failure_categories<'tx>(
+        &self,
+        tx: &mut PgTransaction<'tx>,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        bucket: Duration,
+    ) -> Result<Vec<FailureCategoryCount>, sqlx::Error> {
+        set_schema_for_transaction(tx, &self.schema).await?;
+        get_failure_categories(&mut **tx, from, to, bucket).await
+    }
+
+    pub async fn get_daily_aggregates<'tx>(
+        &self,
+        tx: &mut PgTransaction<'tx>,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<Vec<DailyAggregate>, sqlx::Error> {
+        set_schema_for_transaction(tx, &self.schema).await?;
+        get_daily_aggregates(&mut **tx, from, to).await
+    }
+
+    pub async fn insert_errors<'tx>(
+        &self,
+        tx: &mut PgTransaction<'tx>,
+        errors: &[ErrorRecord],
+    ) -> Result<u64, sqlx::Error> {
+        set_schema_for_transaction(tx, &self.schema).await?;
+        insert_errors_inner(&mut **tx, errors, self.max_errors.map(i64::from)).await
+    }
+
+    pub async fn search_errors<'tx>(
+        &self,
+        tx: &mut PgTransaction<'tx>,
+        query: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        limit: i64,
+    ) -> Result<Vec<ErrorRecord>, sqlx::Error> {
+        set_schema_for_transaction(tx, &self.schema).await?;
+        search_errors(&mut **tx, query, from, to, limit).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing_tools::{TestMessage, claim_with};
+    use futures::StreamExt;
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_quotes_the_schema(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let queries = Queries::new("public; DROP TABLE messages_unattempted")?;
+        let mut tx = pool.begin().await?;
+        set_schema_for_transaction(&mut tx, queries.schema()).await?;
+        tx.rollback().await?;
+
+        assert!(matches!(
+            Queries::new("1public"),
+            Err(PgIdentifierParsingError::InvalidFirstChar)
+        ));
+
+        sqlx::query("SELECT 1 FROM messages_unattempted")
+            .execute(&pool)
+            .await?;
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_claims_with_the_claim_predicate(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let queries = Queries::new("public")?
+            .with_claim_predicate(Some(ClaimPredicate::field_equals("region", "eu")));
+        let host_id = Uuid::now_v7();
+
+        let mut tx = pool.begin().await?;
+        for region in ["us", "eu"] {
+            let raw = RawMessage {
+                payload: serde_json::json!({ "region": region }),
+                ..TestMessage::default().to_raw()?
+            };
+            queries.publish_message(&mut tx, raw).await?;
+        }
+
+        let claimed = queries
+            .get_next_unattempted(&mut tx, Utc::now(), host_id, Duration::from_mins(1))
+            .await?
+            .expect("Expected a message");
+        assert_eq!(claimed.payload, serde_json::json!({ "region": "eu" }));
+        assert!(
+            queries
+                .get_next_unattempted(&mut tx, Utc::now(), host_id, Duration::from_mins(1))
+                .await?
+                .is_none()
+        );
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Publishes one message for the `"us"` and one for the `"eu"` region, returning the one of `"eu"`
+    async fn publish_regions(pool: &sqlx::PgPool) -> anyhow::Result<RawMessage> {
+        let queries = Queries::new("public")?;
+        let mut tx = pool.begin().await?;
+        let mut published = Vec::new();
+        for region in ["us", "eu"] {
+            let raw = RawMessage {
+                payload: serde_json::json!({ "region": region }),
+                ..TestMessage::default().to_raw()?
+            };
+            published.push(queries.publish_message(&mut tx, raw).await?);
+        }
+        tx.commit().await?;
+        Ok(published.pop().expect("Expected the eu message"))
+    }
+
+    /// Claims both published messages regardless of region, so that they can be reported
+    async fn claim_regions(
+        pool: &sqlx::PgPool,
+        now: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<RawMessage>> {
+        let mut tx = pool.begin().await?;
+        let claimed = Queries::new("public")?
+            .get_many_unattempted(&mut tx, now, Uuid::now_v7(), Duration::from_mins(1), 2)
+            .await?;
+        tx.commit().await?;
+        assert_eq!(claimed.len(), 2);
+        Ok(claimed)
+    }
+
+    fn eu_only() -> anyhow::Result<Queries> {
+        Ok(Queries::new("public")?
+            .with_claim_predicate(Some(ClaimPredicate::field_equals("region", "eu"))))
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_claims_batches_with_the_claim_predicate(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let eu = publish_regions(&pool).await?;
+        let spec = ClaimBatchSpec {
+            unattempted: 2,
+            retryable: 2,
+        };
+
+        let mut tx = pool.begin().await?;
+        let claimed = eu_only()?
+            .claim_batch(
+                &mut tx,
+                Utc::now(),
+                Uuid::now_v7(),
+                Duration::from_mins(1),
+                spec,
+            )
+            .await?;
+        tx.commit().await?;
+
+        assert_eq!(
+            claimed.iter().map(|m| m.id).collect::<Vec<_>>(),
+            vec![eu.id]
+        );
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_claims_retryable_batches_with_the_claim_predicate(
+        pool: sqlx::PgPool,
+    ) -> anyhow::Result<()> {
+        let eu = publish_regions(&pool).await?;
+        let now = Utc::now();
+        let unfiltered = Queries::new("public")?;
+        let mut tx = pool.begin().await?;
+        for message in claim_regions(&pool, now).await? {
+            unfiltered
+                .report_retryable(&mut tx, &message, now, now, "retry")
+                .await?;
+        }
+        tx.commit().await?;
+        let spec = ClaimBatchSpec {
+            unattempted: 0,
+            retryable: 2,
+        };
+
+        let mut tx = pool.begin().await?;
+        let claimed = eu_only()?
+            .claim_batch(&mut tx, now, Uuid::now_v7(), Duration::from_mins(1), spec)
+            .await?;
+        tx.commit().await?;
+
+        assert_eq!(
+            claimed.iter().map(|m| m.id).collect::<Vec<_>>(),
+            vec![eu.id]
+        );
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_claims_many_with_the_claim_predicate(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let eu = publish_regions(&pool).await?;
+
+        let mut tx = pool.begin().await?;
+        let claimed = eu_only()?
+            .get_many_unattempted(
+                &mut tx,
+                Utc::now(),
+                Uuid::now_v7(),
+                Duration::from_mins(1),
+                2,
+            )
+            .await?;
+        tx.commit().await?;
+
+        assert_eq!(
+            claimed.iter().map(|m| m.id).collect::<Vec<_>>(),
+            vec![eu.id]
+        );
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_claims_named_with_the_claim_predicate(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let eu = publish_regions(&pool).await?;
+        let queries = eu_only()?;
+        let names = [eu.name.clone()];
+        let host_id = Uuid::now_v7();
+
+        let mut tx = pool.begin().await?;
+        let claimed = queries
+            .get_next_unattempted_named(
+                &mut tx,
+                Utc::now(),
+                host_id,
+                Duration::from_mins(1),
+                &names,
+            )
+            .await?
+            .expect("Expected a message");
+        assert_eq!(claimed.id, eu.id);
+        let again = queries
+            .get_next_unattempted_named(
+                &mut tx,
+                Utc::now(),
+                host_id,
+                Duration::from_mins(1),
+                &names,
+            )
+            .await?;
+        assert!(again.is_none());
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_claims_retryable_named_with_the_claim_predicate(
+        pool: sqlx::PgPool,
+    ) -> anyhow::Result<()> {
+        let eu = publish_regions(&pool).await?;
+        let now = Utc::now();
+        let unfiltered = Queries::new("public")?;
+        let mut tx = pool.begin().await?;
+        for message in claim_regions(&pool, now).await? {
+            unfiltered
+                .report_retryable(&mut tx, &message, now, now, "retry")
+                .await?;
+        }
+        tx.commit().await?;
+        let queries = eu_only()?;
+        let names = [eu.name.clone()];
+        let host_id = Uuid::now_v7();
+
+        let mut tx = pool.begin().await?;
+        let claimed = queries
+            .get_next_retryable_named(&mut tx, now, host_id, Duration::from_mins(1), &names)
+            .await?
+            .expect("Expected a message");
+        assert_eq!(claimed.id, eu.id);
+        let again = queries
+            .get_next_retryable_named(&mut tx, now, host_id, Duration::from_mins(1), &names)
+            .await?;
+        assert!(again.is_none());
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_claims_text_with_the_claim_predicate(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let eu = publish_regions(&pool).await?;
+        let queries = eu_only()?;
+        let host_id = Uuid::now_v7();
+
+        let mut tx = pool.begin().await?;
+        let claimed = queries
+            .get_next_unattempted_text(&mut tx, Utc::now(), host_id, Duration::from_mins(1))
+            .await?
+            .expect("Expected a message");
+        assert_eq!(claimed.id, eu.id);
+        let again = queries
+            .get_next_unattempted_text(&mut tx, Utc::now(), host_id, Duration::from_mins(1))
+            .await?;
+        assert!(again.is_none());
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_claims_projected_with_the_claim_predicate(
+        pool: sqlx::PgPool,
+    ) -> anyhow::Result<()> {
+        let eu = publish_regions(&pool).await?;
+        let queries = eu_only()?;
+        let fields = ["region".to_string()];
+        let host_id = Uuid::now_v7();
+
+        let mut tx = pool.begin().await?;
+        let claimed = queries
+            .get_next_unattempted_projected(
+                &mut tx,
+                Utc::now(),
+                host_id,
+                Duration::from_mins(1),
+                &fields,
+            )
+            .await?
+            .expect("Expected a message");
+        assert_eq!(claimed.id, eu.id);
+        let again = queries
+            .get_next_unattempted_projected(
+                &mut tx,
+                Utc::now(),
+                host_id,
+                Duration::from_mins(1),
+                &fields,
+            )
+            .await?;
+        assert!(again.is_none());
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_claims_dead_for_review_with_the_claim_predicate(
+        pool: sqlx::PgPool,
+    ) -> anyhow::Result<()> {
+        let eu = publish_regions(&pool).await?;
+        let now = Utc::now();
+        let unfiltered = Queries::new("public")?;
+        let mut tx = pool.begin().await?;
+        for message in claim_regions(&pool, now).await? {
+            unfiltered
+                .report_dead(&mut tx, &message, now, "missing data")
+                .await?;
+        }
+        tx.commit().await?;
+        let queries = eu_only()?;
+        let (host_id, grace) = (Uuid::now_v7(), Duration::from_mins(10));
+
+        let mut tx = pool.begin().await?;
+        let claimed = queries
+            .get_next_dead_for_review(&mut tx, now, host_id, Duration::from_mins(1), grace)
+            .await?
+            .expect("Expected a dead message");
+        assert_eq!(claimed.id, eu.id);
+        let again = queries
+            .get_next_dead_for_review(&mut tx, now, host_id, Duration::from_mins(1), grace)
+            .await?;
+        assert!(again.is_none());
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_notifies_when_releasing_leases(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let queries = Queries::new("public")?.with_release_notifications(true);
+        let host_id = Uuid::now_v7();
+        let message = claim_with(&pool, &queries, host_id).await?;
+
+        let mut listener = sqlx::postgres::PgListener::connect_with(&pool).await?;
+        listener.listen(FX_MQ_MESSAGE_NOTIFICATION_CHANNEL).await?;
+        let mut notifications = listener.into_stream();
+
+        let mut tx = pool.begin().await?;
+        let released = queries
+            .release_lease(
+                &mut tx,
+                message.id,
+                message.fencing_token,
+                Utc::now(),
+                host_id,
+            )
+            .await?;
+        tx.commit().await?;
+
+        assert!(released);
+        let notification = notifications
+            .next()
+            .await
+            .expect("expected a pg_notify to be received")?;
+        assert_eq!(notification.payload(), "1");
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_notifies_on_the_schema_channel(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let queries = Queries::new("public")?.with_schema_channel();
+        assert_eq!(queries.channel(), "fx_mq_public");
+
+        let mut listener = sqlx::postgres::PgListener::connect_with(&pool).await?;
+        listener.listen(queries.channel()).await?;
+        let mut notifications = listener.into_stream();
+
+        let mut tx = pool.begin().await?;
+        queries
+            .publish_message(&mut tx, TestMessage::default().to_raw()?)
+            .await?;
+        tx.commit().await?;
+
+        let notification = notifications
+            .next()
+            .await
+            .expect("expected a pg_notify to be received")?;
+        assert_eq!(notification.payload(), "1");
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_notifies_with_metadata(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let queries = Queries::new("public")?
+            .with_notification_metadata(true)
+            .with_release_notifications(true);
+
+        let mut listener = sqlx::postgres::PgListener::connect_with(&pool).await?;
+        listener.listen(queries.channel()).await?;
+        let mut notifications = listener.into_stream();
+        let mut next = async || -> anyhow::Result<Notification> {
+            let notification = notifications
+                .next()
+                .await
+                .expect("expected a pg_notify to be received")?;
+            Ok(Notification::parse(notification.payload())?)
+        };
+
+        let mut tx = pool.begin().await?;
+        let message = TestMessage::default().to_raw()?;
+        let name = message.name.clone();
+        queries
+            .publish_many_messages(&mut tx, &[message, TestMessage::default().to_raw()?])
+            .await?;
+        tx.commit().await?;
+
+        assert_eq!(
+            next().await?,
+            Notification {
+                schema: Some("public".to_string()),
+                queue: Some(NotifiedQueue::Unattempted),
+                name: Some(name.clone()),
+                count: Some(2),
+            }
+        );
+
+        let mut tx = pool.begin().await?;
+        let claimed = queries
+            .get_next_unattempted(&mut tx, Utc::now(), Uuid::now_v7(), Duration::from_mins(1))
+            .await?
+            .expect("Expected a message");
+        let now = Utc::now();
+        queries
+            .report_retryable(&mut tx, &claimed, now, now, "timeout")
+            .await?;
+        tx.commit().await?;
+
+        let notification = next().await?;
+        assert_eq!(notification.queue, Some(NotifiedQueue::Retryable));
+        assert_eq!(notification.name, Some(name));
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_enforces_quotas_when_enabled(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let queries = Queries::new("public")?;
+        let quota = Quota {
+            scope: QuotaScope::Schema,
+            max_pending: Some(1),
+            max_daily_publishes: None,
+        };
+        let mut tx = pool.begin().await?;
+        queries.put_quota(&mut tx, &quota, Utc::now()).await?;
+        queries
+            .publish_many_messages(
+                &mut tx,
+                &[
+                    TestMessage::default().to_raw()?,
+                    TestMessage::default().to_raw()?,
+                ],
+            )
+            .await?;
+        tx.commit().await?;
+
+        let queries = queries.with_quota_enforcement(true);
+        let mut tx = pool.begin().await?;
+        let result = queries
+            .publish_message(&mut tx, TestMessage::default().to_raw()?)
+            .await;
+        assert!(matches!(result, Err(PublishError::QuotaExceeded(_))));
+        tx.rollback().await?;
+
+        let mut tx = pool.begin().await?;
+        assert!(queries.delete_quota(&mut tx, &QuotaScope::Schema).await?);
+        queries
+            .publish_message(&mut tx, TestMessage::default().to_raw()?)
+            .await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_does_not_notify_for_delayed_retries(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let queries = Queries::new("public")?.with_release_notifications(true);
+        let message = claim_with(&pool, &queries, Uuid::now_v7()).await?;
+
+        let mut listener = sqlx::postgres::PgListener::connect_with(&pool).await?;
+        listener.listen(FX_MQ_MESSAGE_NOTIFICATION_CHANNEL).await?;
+        let mut notifications = listener.into_stream();
+
+        let now = Utc::now();
+        let mut tx = pool.begin().await?;
+        queries
+            .report_retryable(&mut tx, &message, now, now + Duration::from_mins(1), "err")
+            .await?;
+        tx.commit().await?;
+
+        tokio::time::timeout(Duration::from_millis(100), notifications.next())
+            .await
+            .expect_err("expected no notification for a delayed retry");
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_notifies_for_immediate_retries(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let queries = Queries::new("public")?.with_release_notifications(true);
+        let message = claim_with(&pool, &queries, Uuid::now_v7()).await?;
+
+        let mut listener = sqlx::postgres::PgListener::connect_with(&pool).await?;
+        listener.listen(FX_MQ_MESSAGE_NOTIFICATION_CHANNEL).await?;
+        let mut notifications = listener.into_stream();
+
+        let now = Utc::now();
+        let mut tx = pool.begin().await?;
+        queries
+            .report_retryable(&mut tx, &message, now, now, "err")
+            .await?;
+        tx.commit().await?;
+
+        let notification = notifications
+            .next()
+            .await
+            .expect("expected a pg_notify to be received")?;
+        assert_eq!(notification.payload(), "1");
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_cancels_claims_waiting_past_the_claim_timeout(
+        pool: sqlx::PgPool,
+    ) -> anyhow::Result<()> {
+        let queries = Queries::new("public")?.with_claim_timeout(Some(Duration::from_millis(100)));
+        crate::queries::publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+
+        // Holds a lock claims can't skip, such as one taken by a migration
+        let mut locking = pool.begin().await?;
+        sqlx::query("LOCK TABLE messages_unattempted IN ACCESS EXCLUSIVE MODE")
+            .execute(&mut *locking)
+            .await?;
+
+        let mut tx = pool.begin().await?;
+        let claimed = queries
+            .get_next_unattempted(&mut tx, Utc::now(), Uuid::now_v7(), Duration::from_mins(1))
+            .await;
+        assert!(claimed.is_err());
+        drop(tx);
+
+        // The backend of the claim stopped waiting for the lock
+        let waiting: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM pg_stat_activity WHERE datname = current_database() AND wait_event_type = 'Lock'",
+        )
+        .fetch_one(&mut *locking)
+        .await?;
+        assert_eq!(waiting, 0);
+        locking.rollback().await?;
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_records_claim_conflicts(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let queries = Queries::new("public")?.with_conflict_recording(Some(pool.clone()));
+        let message = claim_with(&pool, &queries, Uuid::now_v7()).await?;
+
+        // The lease expires and another host reclaims the message
+        let later = Utc::now() + Duration::from_mins(2);
+        let mut tx = pool.begin().await?;
+        queries
+            .get_next_missing(&mut tx, later, Uuid::now_v7(), Duration::from_mins(1))
+            .await?
+            .expect("Expected the message to be missing");
+        tx.commit().await?;
+
+        // The transaction of the rejected report is dropped without committing
+        let mut tx = pool.begin().await?;
+        let result = queries.report_success(&mut tx, &message, later).await;
+        assert!(matches!(result, Err(ReportError::StaleFencingToken(_))));
+        drop(tx);
+
+        let mut tx = pool.begin().await?;
+        let count = queries
+            .count_claim_conflicts(&mut tx, later, later + Duration::from_secs(1))
+            .await?;
+        tx.commit().await?;
+        assert_eq!(count, 1);
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_claims_on_the_detected_server(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let capabilities = crate::compatibility::detect(&pool).await?;
+        let queries = Queries::new("public")?.with_server_capabilities(&capabilities);
+        assert_eq!(
+            queries.split_claims,
+            !capabilities.single_statement_claims()
+        );
+
+        let message = claim_with(&pool, &queries, Uuid::now_v7()).await?;
+        assert!(message.fencing_token.is_some());
+
+        let newer = ServerCapabilities {
+            version_num: (crate::compatibility::MAX_VERIFIED_MAJOR + 1) * 10000,
+            ..capabilities
+        };
+        assert!(
+            Queries::new("public")?
+                .with_server_capabilities(&newer)
+                .split_claims
+        );
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_counts_attempts_beyond_the_error_cap(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let queries = Queries::new("public")?.with_error_history_cap(Some(1));
+        let host_id = Uuid::now_v7();
+        let mut message = claim_with(&pool, &queries, host_id).await?;
+
+        let start = Utc::now();
+        for attempt in 1..=3 {
+            let failed_at = start + Duration::from_secs(attempt);
+            let mut tx = pool.begin().await?;
+            queries
+                .report_retryable(&mut tx, &message, failed_at, failed_at, "failed")
+                .await?;
+            message = queries
+                .get_next_retryable(&mut tx, failed_at, host_id, Duration::from_mins(1))
+                .await?
+                .expect("Expected a retryable message");
+            tx.commit().await?;
+        }
+
+        let mut tx = pool.begin().await?;
+        queries
+            .report_success(&mut tx, &message, start + Duration::from_secs(4))
+            .await?;
+        tx.commit().await?;
+
+        let errors: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM errors WHERE message_id = $1")
+            .bind(message.id)
+            .fetch_one(&pool)
+            .await?;
+        assert_eq!(errors, 1);
+
+        let finished = crate::queries::get_finished_messages(&pool, None, 10).await?;
+        assert_eq!(finished.len(), 1);
+        assert_eq!(finished[0].attempted, 4);
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_caps_and_retains_failed_attempts(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let queries = Queries::new("public")?
+            .with_failed_attempt_cap(Some(2))
+            .with_failed_attempt_retention(Some(1));
+        let host_id = Uuid::now_v7();
+        let mut message = claim_with(&pool, &queries, host_id).await?;
+
+        let start = Utc::now();
+        for attempt in 1..=3 {
+            let failed_at = start + Duration::from_secs(attempt);
+            let mut tx = pool.begin().await?;
+            queries
+                .report_retryable(&mut tx, &message, failed_at, failed_at, "failed")
+                .await?;
+            message = queries
+                .get_next_retryable(&mut tx, failed_at, host_id, Duration::from_mins(1))
+                .await?
+                .expect("Expected a retryable message");
+            tx.commit().await?;
+        }
+
+        let failed: Vec<i32> = sqlx::query_scalar(
+            "SELECT attempted FROM attempts_failed WHERE message_id = $1 ORDER BY attempted",
+        )
+        .bind(message.id)
+        .fetch_all(&pool)
+        .await?;
+        assert_eq!(failed, vec![2, 3]);
+
+        let mut tx = pool.begin().await?;
+        queries
+            .report_success(&mut tx, &message, start + Duration::from_secs(4))
+            .await?;
+        tx.commit().await?;
+
+        let retained: Vec<i32> = sqlx::query_scalar(
+            "SELECT attempted FROM attempts_failed_retained WHERE message_id = $1",
+        )
+        .bind(message.id)
+        .fetch_all(&pool)
+        .await?;
+        assert_eq!(retained, vec![3]);
+
+        let mut tx = pool.begin().await?;
+        let retried = queries
+            .get_next_retryable(
+                &mut tx,
+                start + Duration::from_secs(5),
+                host_id,
+                Duration::from_mins(1),
+            )
+            .await?;
+        tx.commit().await?;
+        assert!(retried.is_none());
+
+        Ok(())
     }
 }
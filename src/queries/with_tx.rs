@@ -0,0 +1,192 @@
+use crate::backoff::ExponentialBackoff;
+use crate::migrator::PgIdentifier;
+use crate::queries::set_schema_for_transaction;
+use chrono::Utc;
+use futures::future::BoxFuture;
+use sqlx::{PgPool, PgTransaction};
+use std::time::Duration;
+
+const SERIALIZATION_FAILURE: &str = "40001";
+const DEADLOCK_DETECTED: &str = "40P01";
+
+/// Returns true if the error is a serialization failure or a deadlock, which may succeed when retried
+pub fn is_retryable_transaction_error(error: &sqlx::Error) -> bool {
+    error
+        .as_database_error()
+        .and_then(|e| e.code())
+        .is_some_and(|code| code == SERIALIZATION_FAILURE || code == DEADLOCK_DETECTED)
+}
+
+/// Retry configuration for [`with_tx`]
+#[derive(Debug)]
+pub struct TransactionRetry {
+    max_attempts: u32,
+    backoff: ExponentialBackoff,
+}
+
+impl TransactionRetry {
+    pub fn new(max_attempts: u32, backoff: ExponentialBackoff) -> Self {
+        Self {
+            max_attempts: max_attempts.max(1),
+            backoff,
+        }
+    }
+}
+
+impl Default for TransactionRetry {
+    fn default() -> Self {
+        Self::new(3, ExponentialBackoff::new(2, Duration::from_millis(10)))
+    }
+}
+
+/// Runs `f` in a transaction with the search path set to `schema`, committing when it returns `Ok`.
+///
+/// If the closure or the commit fails with a serialization failure or deadlock, the transaction is
+/// rolled back and the closure is run again in a new transaction, waiting between attempts according to the
+/// backoff of `retry`. Any other error is returned immediately.
+pub async fn with_tx<T, F>(
+    pool: &PgPool,
+    schema: &PgIdentifier,
+    retry: &TransactionRetry,
+    mut f: F,
+) -> Result<T, sqlx::Error>
+where
+    F: for<'c> FnMut(&'c mut PgTransaction<'static>) -> BoxFuture<'c, Result<T, sqlx::Error>>,
+{
+    let mut attempted: u32 = 0;
+
+    loop {
+        attempted += 1;
+
+        let result = async {
+            let mut tx = pool.begin().await?;
+            set_schema_for_transaction(&mut tx, schema).await?;
+            let value = f(&mut tx).await?;
+            tx.commit().await?;
+            Ok(value)
+        }
+        .await;
+
+        match result {
+            Err(error)
+                if attempted < retry.max_attempts && is_retryable_transaction_error(&error) =>
+            {
+                let now = Utc::now();
+                let delay = (retry.backoff.try_at(attempted as i32, now) - now)
+                    .to_std()
+                    .unwrap_or(Duration::ZERO);
+
+                tracing::debug!(attempted, ?delay, %error, "Retrying transaction");
+                tokio::time::sleep(delay).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Message;
+    use crate::queries::publish_message;
+    use crate::testing_tools::{TestMessage, is_pending};
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    fn raise(code: &str) -> String {
+        format!("DO $$ BEGIN RAISE EXCEPTION 'raised' USING ERRCODE = '{code}'; END $$")
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_commits_the_transaction(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let raw = TestMessage::default().to_raw()?;
+
+        let published = with_tx(
+            &pool,
+            &PgIdentifier::parse("public")?,
+            &TransactionRetry::default(),
+            |tx| {
+                let raw = raw.clone();
+                Box::pin(async move { publish_message(&mut **tx, &raw).await })
+            },
+        )
+        .await?;
+
+        assert_eq!(published.name, TestMessage::NAME);
+        assert!(is_pending(&pool, published.id, Utc::now()).await?);
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_retries_serialization_failures(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let calls = AtomicU32::new(0);
+
+        with_tx(
+            &pool,
+            &PgIdentifier::parse("public")?,
+            &TransactionRetry::default(),
+            |tx| {
+                let call = calls.fetch_add(1, Ordering::SeqCst);
+                Box::pin(async move {
+                    if call == 0 {
+                        sqlx::query(&raise(SERIALIZATION_FAILURE))
+                            .execute(&mut **tx)
+                            .await?;
+                    }
+                    Ok(())
+                })
+            },
+        )
+        .await?;
+
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_gives_up_after_max_attempts(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let calls = AtomicU32::new(0);
+        let retry = TransactionRetry::new(2, ExponentialBackoff::new(2, Duration::from_millis(1)));
+
+        let result = with_tx(&pool, &PgIdentifier::parse("public")?, &retry, |tx| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Box::pin(async move {
+                sqlx::query(&raise(DEADLOCK_DETECTED))
+                    .execute(&mut **tx)
+                    .await?;
+                Ok(())
+            })
+        })
+        .await;
+
+        assert!(result.is_err_and(|e| is_retryable_transaction_error(&e)));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_does_not_retry_other_errors(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let calls = AtomicU32::new(0);
+
+        let result = with_tx(
+            &pool,
+            &PgIdentifier::parse("public")?,
+            &TransactionRetry::default(),
+            |tx| {
+                calls.fetch_add(1, Ordering::SeqCst);
+                Box::pin(async move {
+                    sqlx::query(&raise("P0001")).execute(&mut **tx).await?;
+                    Ok(())
+                })
+            },
+        )
+        .await;
+
+        assert!(result.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        Ok(())
+    }
+}
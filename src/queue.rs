@@ -0,0 +1,297 @@
+use crate::attachments::{self, AttachmentError, BlobStore};
+use crate::migrator::PgIdentifierParsingError;
+use crate::models::{Message, RawMessage};
+use crate::queries::{ErrorClass, PublishError, Queries, SchemaTag, Untagged};
+use chrono::Utc;
+use sqlx::PgTransaction;
+use std::fmt;
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
+
+#[derive(Debug, thiserror::Error)]
+pub enum QueueError {
+    #[error("DatabaseError: {0}")]
+    Database(#[from] sqlx::Error),
+    #[error("PublishError: {0}")]
+    Publish(#[from] PublishError),
+    #[error("SerializationError: {0}")]
+    Serialization(#[from] serde_json::Error),
+    #[error("DecodeError: message {message_id} could not be decoded: {source}")]
+    Decode {
+        message_id: Uuid,
+        source: serde_json::Error,
+    },
+    #[error("AttachmentError: {0}")]
+    Attachment(#[from] AttachmentError),
+}
+
+impl QueueError {
+    pub fn class(&self) -> ErrorClass {
+        match self {
+            Self::Database(error) => ErrorClass::of(error),
+            Self::Publish(error) => error.class(),
+            Self::Serialization(_) | Self::Decode { .. } => ErrorClass::Permanent,
+            Self::Attachment(AttachmentError::Store(_)) => ErrorClass::Transient,
+            Self::Attachment(_) => ErrorClass::Permanent,
+        }
+    }
+}
+
+/// A claimed message decoded into its type
+#[derive(Debug, Clone)]
+pub struct Claimed<T> {
+    /// The claimed message as stored, pass it to reports. The payload of an offloaded message is its blob reference.
+    pub raw: RawMessage,
+    /// The decoded payload
+    pub message: T,
+}
+
+/// A queue of a single message type.
+///
+/// Publishes and claims messages of type `T` only, decoding claimed payloads, for services that handle one
+/// message type. Queues created with [`tagged`](Self::tagged) are bound to the schema of the [`SchemaTag`] `S`.
+pub struct Queue<T: Message, S = Untagged> {
+    queries: Queries<S>,
+    host_id: Uuid,
+    hold_for: Duration,
+    names: [String; 1],
+    blob_store: Option<Arc<dyn BlobStore>>,
+    _message: PhantomData<fn() -> T>,
+}
+
+impl<T: Message, S> fmt::Debug for Queue<T, S>
+where
+    Queries<S>: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Queue")
+            .field("queries", &self.queries)
+            .field("host_id", &self.host_id)
+            .field("hold_for", &self.hold_for)
+            .field("names", &self.names)
+            .field("blob_store", &self.blob_store.is_some())
+            .finish()
+    }
+}
+
+impl<T: Message> Queue<T> {
+    pub fn new(
+        schema: &str,
+        host_id: Uuid,
+        hold_for: Duration,
+    ) -> Result<Self, PgIdentifierParsingError> {
+        Ok(Self {
+            queries: Queries::new(schema)?,
+            host_id,
+            hold_for,
+            names: [T::NAME.to_string()],
+            blob_store: None,
+            _message: PhantomData,
+        })
+    }
+}
+
+impl<T: Message, S: SchemaTag> Queue<T, S> {
+    pub fn tagged(host_id: Uuid, hold_for: Duration) -> Result<Self, PgIdentifierParsingError> {
+        Ok(Self {
+            queries: Queries::tagged()?,
+            host_id,
+            hold_for,
+            names: [T::NAME.to_string()],
+            blob_store: None,
+            _message: PhantomData,
+        })
+    }
+}
+
+impl<T: Message, S> Queue<T, S> {
+    /// Rehydrates claimed messages whose payload was offloaded to `store` before decoding them, see
+    /// [`Attachments`](crate::attachments::Attachments). Without a store, offloaded messages fail to decode.
+    pub fn with_blob_store(mut self, store: Arc<dyn BlobStore>) -> Self {
+        self.blob_store = Some(store);
+        self
+    }
+
+    pub async fn publish(
+        &self,
+        tx: &mut PgTransaction<'_>,
+        message: &T,
+    ) -> Result<RawMessage, QueueError> {
+        let raw = RawMessage::from_message(message)?;
+        Ok(self.queries.publish_message(tx, raw).await?)
+    }
+
+    /// Claims the next unattempted message of type `T`.
+    ///
+    /// If the payload can't be decoded the message remains claimed and [`QueueError::Decode`] is returned,
+    /// typically it should then be reported dead. Offloaded payloads are rehydrated from the blob store first, see
+    /// [`with_blob_store`](Self::with_blob_store).
+    pub async fn claim_next(
+        &self,
+        tx: &mut PgTransaction<'_>,
+    ) -> Result<Option<Claimed<T>>, QueueError> {
+        let Some(raw) = self
+            .queries
+            .get_next_unattempted_named(tx, Utc::now(), self.host_id, self.hold_for, &self.names)
+            .await?
+        else {
+            return Ok(None);
+        };
+
+        let rehydrated = match &self.blob_store {
+            Some(store) => attachments::rehydrate_payload(store.as_ref(), &raw.payload).await?,
+            None => None,
+        };
+        let decoded = match &rehydrated {
+            Some(payload) => T::deserialize(payload),
+            None => raw.decode_borrowed(),
+        };
+        let message = decoded.map_err(|source| QueueError::Decode {
+            message_id: raw.id,
+            source,
+        })?;
+
+        Ok(Some(Claimed { raw, message }))
+    }
+
+    /// Deletes the blob of an offloaded message from the blob store, once its success was committed
+    pub async fn release_attachment(&self, claimed: &Claimed<T>) -> Result<(), QueueError> {
+        if let Some(store) = &self.blob_store {
+            attachments::release(store.as_ref(), &claimed.raw).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attachments::{Attachments, PgBlobStore, blob_reference};
+    use crate::migrator::run_migrations;
+    use crate::testing_tools::TestMessage;
+
+    fn queue() -> Queue<TestMessage> {
+        Queue::new("public", Uuid::now_v7(), Duration::from_mins(1)).expect("valid schema")
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_publishes_and_claims_typed_messages(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let queue = queue();
+        let message = TestMessage::new("typed".to_string(), 7);
+
+        let mut tx = pool.begin().await?;
+        let published = queue.publish(&mut tx, &message).await?;
+        let claimed = queue
+            .claim_next(&mut tx)
+            .await?
+            .expect("Expected a message");
+        tx.commit().await?;
+
+        assert_eq!(claimed.raw.id, published.id);
+        assert_eq!(claimed.message, message);
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_ignores_other_message_types(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let queue = queue();
+        let other = RawMessage {
+            name: "Other".to_string(),
+            ..RawMessage::from_message(&TestMessage::default())?
+        };
+
+        let mut tx = pool.begin().await?;
+        Queries::new("public")?
+            .publish_message(&mut tx, other)
+            .await?;
+        assert!(queue.claim_next(&mut tx).await?.is_none());
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_reports_undecodable_payloads(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let queue = queue();
+        let invalid = RawMessage {
+            payload: serde_json::json!({ "unexpected": true }),
+            ..RawMessage::from_message(&TestMessage::default())?
+        };
+
+        let mut tx = pool.begin().await?;
+        Queries::new("public")?
+            .publish_message(&mut tx, invalid.clone())
+            .await?;
+        let result = queue.claim_next(&mut tx).await;
+        tx.commit().await?;
+
+        assert!(matches!(
+            result,
+            Err(QueueError::Decode { message_id, .. }) if message_id == invalid.id
+        ));
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_claims_offloaded_messages(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let store = Arc::new(PgBlobStore::new(pool.clone(), "public")?);
+        let queue = queue().with_blob_store(store.clone());
+        let attachments = Attachments::new(PgBlobStore::new(pool.clone(), "public")?, 16);
+        let message = TestMessage::new("a large message body".to_string(), 7);
+        let offloaded = attachments
+            .offload(RawMessage::from_message(&message)?)
+            .await?;
+        let blob_id = blob_reference(&offloaded.payload).expect("Expected an offloaded payload");
+
+        let mut tx = pool.begin().await?;
+        Queries::new("public")?
+            .publish_message(&mut tx, offloaded)
+            .await?;
+        let claimed = queue
+            .claim_next(&mut tx)
+            .await?
+            .expect("Expected a message");
+        assert_eq!(claimed.message, message);
+        Queries::new("public")?
+            .report_success(&mut tx, &claimed.raw, Utc::now())
+            .await?;
+        tx.commit().await?;
+
+        queue.release_attachment(&claimed).await?;
+        assert_eq!(store.get(blob_id).await.expect("Expected the store"), None);
+
+        Ok(())
+    }
+
+    #[derive(Debug)]
+    struct Tenant;
+
+    impl SchemaTag for Tenant {
+        const SCHEMA: &str = "tenant";
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_binds_tagged_queues_to_their_schema(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        run_migrations(&pool, "public").await?;
+        run_migrations(&pool, Tenant::SCHEMA).await?;
+        let tagged: Queue<TestMessage, Tenant> =
+            Queue::tagged(Uuid::now_v7(), Duration::from_mins(1))?;
+
+        let mut tx = pool.begin().await?;
+        let published = tagged.publish(&mut tx, &TestMessage::default()).await?;
+        assert!(queue().claim_next(&mut tx).await?.is_none());
+        let claimed = tagged
+            .claim_next(&mut tx)
+            .await?
+            .expect("Expected a message");
+        tx.commit().await?;
+
+        assert_eq!(claimed.raw.id, published.id);
+
+        Ok(())
+    }
+}
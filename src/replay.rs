@@ -0,0 +1,92 @@
+use crate::models::RawMessage;
+use crate::queries::get_finished_messages;
+use sqlx::PgExecutor;
+
+/// Iterates historical (succeeded or dead) messages in their original publish order.
+///
+/// Intended for reproducing incidents locally: the reader only ever reads, it acquires no leases and reports
+/// nothing, so the returned messages may be fed to handlers in a dry-run without affecting the queue.
+#[derive(Debug)]
+pub struct ReplayReader {
+    cursor: Option<i64>,
+    batch_size: i64,
+}
+
+impl ReplayReader {
+    pub fn new(batch_size: i64) -> Self {
+        Self {
+            cursor: None,
+            batch_size,
+        }
+    }
+
+    /// Returns the next batch of historical messages, empty once all messages have been read
+    pub async fn next_batch<'tx, E: PgExecutor<'tx>>(
+        &mut self,
+        tx: E,
+    ) -> Result<Vec<RawMessage>, sqlx::Error> {
+        let messages = get_finished_messages(tx, self.cursor, self.batch_size).await?;
+
+        if let Some(message) = messages.last() {
+            self.cursor = message.seq;
+        }
+
+        Ok(messages)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        queries::{get_next_unattempted, publish_message, report_dead, report_success},
+        testing_tools::TestMessage,
+    };
+    use chrono::Utc;
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_replays_finished_messages_in_publish_order(
+        pool: sqlx::PgPool,
+    ) -> anyhow::Result<()> {
+        let now = Utc::now();
+        let host_id = Uuid::now_v7();
+        let hold_for = Duration::from_mins(1);
+
+        let mut finished = Vec::new();
+        for i in 0..3 {
+            publish_message(&pool, &TestMessage::new(i.to_string(), i).to_raw()?).await?;
+            let message = get_next_unattempted(&pool, now, host_id, hold_for)
+                .await?
+                .unwrap();
+            if i == 1 {
+                report_dead(&pool, message.id, None, now, "err").await?;
+            } else {
+                report_success(&pool, message.id, None, now).await?;
+            }
+            finished.push(message.id);
+        }
+
+        // Pending and in-progress messages are not replayed
+        publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+        publish_message(&pool, &TestMessage::default().to_raw()?).await?;
+        get_next_unattempted(&pool, now, host_id, hold_for).await?;
+
+        let mut reader = ReplayReader::new(2);
+        let mut replayed = Vec::new();
+        loop {
+            let batch = reader.next_batch(&pool).await?;
+            if batch.is_empty() {
+                break;
+            }
+            replayed.extend(batch);
+        }
+
+        assert_eq!(replayed.iter().map(|m| m.id).collect::<Vec<_>>(), finished);
+        assert_eq!(replayed[0].attempted, 1);
+        assert_eq!(replayed[1].attempted, 1);
+
+        Ok(())
+    }
+}
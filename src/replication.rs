@@ -0,0 +1,315 @@
+//! Mirroring of published messages to a warm standby of the queue in another region
+
+use crate::models::{PublishConflict, RawMessage};
+use crate::queries::Queries;
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, PgTransaction};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+/// Capacity and batching of a [`PublishMirror`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MirrorConfig {
+    /// How many messages may be buffered. Messages published while the buffer is full are not mirrored.
+    pub capacity: usize,
+    /// The maximum number of messages mirrored in one transaction
+    pub batch_size: usize,
+    /// How long the replicator waits before mirroring a batch again after it failed
+    pub retry_interval: Duration,
+}
+
+impl Default for MirrorConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 10_000,
+            batch_size: 500,
+            retry_interval: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Counters of a [`PublishMirror`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MirrorStats {
+    /// Messages published to the mirror
+    pub mirrored: u64,
+    /// Messages not mirrored as the buffer was full or the replicator had stopped
+    pub dropped: u64,
+    /// How long the last batch mirrored was buffered, from the publish of its oldest message until it was committed
+    /// to the mirror. None before the first batch.
+    pub lag: Option<Duration>,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    dropped: AtomicU64,
+    replicated: Mutex<(u64, Option<Duration>)>,
+}
+
+#[derive(Debug, Clone)]
+struct Buffered {
+    message: RawMessage,
+    published_at: DateTime<Utc>,
+}
+
+/// Buffers published messages to be published to the queue of a second database by a [`MirrorReplicator`], keeping
+/// a warm standby of the queue, e.g. in another region.
+///
+/// Commit the transaction publishing messages with [`commit`](Self::commit), or [offer](Self::offer) the published
+/// messages once it committed, so that messages of transactions rolled back are never mirrored. Mirroring is
+/// asynchronous and best-effort: messages published but not offered, e.g. as the process stopped right after the
+/// commit, are not mirrored, and messages are dropped rather than slowing publishes down while the buffer is full.
+/// Messages are mirrored with their id, duplicates are ignored. Mark the mirror as a standby with
+/// [`mark_standby`](crate::queries::mark_standby) so that workers fenced with [`Queries::with_standby_fencing`] don't
+/// claim there until it is promoted.
+#[derive(Debug, Clone)]
+pub struct PublishMirror {
+    sender: mpsc::Sender<Buffered>,
+    counters: Arc<Counters>,
+}
+
+impl PublishMirror {
+    /// Creates a mirror and the replicator publishing its messages, which must be [run](MirrorReplicator::run) for
+    /// messages to be mirrored
+    pub fn new(config: MirrorConfig) -> (Self, MirrorReplicator) {
+        let (sender, receiver) = mpsc::channel(config.capacity.max(1));
+        let counters = Arc::new(Counters::default());
+        (
+            Self {
+                sender,
+                counters: counters.clone(),
+            },
+            MirrorReplicator {
+                receiver,
+                config,
+                counters,
+            },
+        )
+    }
+
+    /// Commits `tx` and then offers `published`, the messages published within it. Nothing is offered if the commit
+    /// fails.
+    pub async fn commit(
+        &self,
+        tx: PgTransaction<'_>,
+        published: &[RawMessage],
+    ) -> Result<(), sqlx::Error> {
+        tx.commit().await?;
+        self.offer(published);
+        Ok(())
+    }
+
+    /// Buffers `published` without waiting, counting the messages that don't fit as dropped. Only offer messages
+    /// once the transaction publishing them committed.
+    pub fn offer(&self, published: &[RawMessage]) {
+        let now = Utc::now();
+        for message in published {
+            let buffered = Buffered {
+                message: message.clone(),
+                published_at: now,
+            };
+            if self.sender.try_send(buffered).is_err() {
+                self.counters.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// The number of messages buffered and not yet taken by the replicator
+    pub fn buffered(&self) -> usize {
+        self.sender.max_capacity() - self.sender.capacity()
+    }
+
+    pub fn stats(&self) -> MirrorStats {
+        let (mirrored, lag) = *self.counters.replicated.lock().expect("poisoned");
+        MirrorStats {
+            mirrored,
+            dropped: self.counters.dropped.load(Ordering::Relaxed),
+            lag,
+        }
+    }
+}
+
+/// Publishes the messages buffered by a [`PublishMirror`] to the mirror in batches
+#[derive(Debug)]
+pub struct MirrorReplicator {
+    receiver: mpsc::Receiver<Buffered>,
+    config: MirrorConfig,
+    counters: Arc<Counters>,
+}
+
+impl MirrorReplicator {
+    /// Publishes buffered messages into the schema of `queries` on the mirror `pool` until cancelled. Failed batches
+    /// are logged and retried after the retry interval. Once cancelled, further messages are dropped and the
+    /// messages still buffered are discarded.
+    pub async fn run<S>(
+        mut self,
+        pool: &PgPool,
+        queries: &Queries<S>,
+        cancellation: CancellationToken,
+    ) {
+        let batch_size = self.config.batch_size.max(1);
+        let mut batch = Vec::with_capacity(batch_size);
+
+        loop {
+            if batch.is_empty() {
+                let received = tokio::select! {
+                    _ = cancellation.cancelled() => break,
+                    received = self.receiver.recv_many(&mut batch, batch_size) => received,
+                };
+                if received == 0 {
+                    break;
+                }
+            }
+            if self.replicate(pool, queries, &batch).await {
+                batch.clear();
+            } else {
+                tokio::select! {
+                    _ = cancellation.cancelled() => break,
+                    _ = tokio::time::sleep(self.config.retry_interval) => {}
+                }
+            }
+        }
+
+        self.receiver.close();
+    }
+
+    /// Publishes `batch` to the mirror, returning whether it was committed
+    async fn replicate<S>(&self, pool: &PgPool, queries: &Queries<S>, batch: &[Buffered]) -> bool {
+        let replicated = async {
+            let mut tx = pool.begin().await?;
+            for buffered in batch {
+                queries
+                    .publish_message_on_conflict(
+                        &mut tx,
+                        &buffered.message,
+                        PublishConflict::Ignore,
+                    )
+                    .await?;
+            }
+            tx.commit().await?;
+            Ok::<_, crate::queries::PublishError>(())
+        };
+
+        match replicated.await {
+            Ok(()) => {
+                let oldest = batch.iter().map(|b| b.published_at).min();
+                let lag = oldest.map(|at| (Utc::now() - at).to_std().unwrap_or(Duration::ZERO));
+                let mut replicated = self.counters.replicated.lock().expect("poisoned");
+                replicated.0 += batch.len() as u64;
+                replicated.1 = lag;
+                true
+            }
+            Err(error) => {
+                tracing::warn!(%error, messages = batch.len(), "Could not mirror published messages");
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migrator::run_migrations;
+    use crate::testing_tools::TestMessage;
+
+    async fn count(pool: &PgPool, schema: &str) -> anyhow::Result<i64> {
+        Ok(sqlx::query_scalar(&format!(
+            "SELECT COUNT(*) FROM {schema}.messages_unattempted"
+        ))
+        .fetch_one(pool)
+        .await?)
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_mirrors_published_messages(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        run_migrations(&pool, "primary_region").await?;
+        run_migrations(&pool, "standby_region").await?;
+        let (mirror, replicator) = PublishMirror::new(MirrorConfig::default());
+        let primary = Queries::new("primary_region")?;
+        let standby = Queries::new("standby_region")?;
+
+        let message = TestMessage::default().to_raw()?;
+        let mut tx = pool.begin().await?;
+        let mut published = vec![primary.publish_message(&mut tx, message.clone()).await?];
+        published.extend(
+            primary
+                .publish_many_messages(&mut tx, &[TestMessage::default().to_raw()?])
+                .await?,
+        );
+        mirror.commit(tx, &published).await?;
+        // Offered again, e.g. by a retried producer, the message is mirrored once
+        mirror.offer(&[message]);
+        assert_eq!(mirror.buffered(), 3);
+
+        let cancellation = CancellationToken::new();
+        let replicating = tokio::spawn({
+            let pool = pool.clone();
+            let cancellation = cancellation.clone();
+            async move { replicator.run(&pool, &standby, cancellation).await }
+        });
+        while mirror.stats().mirrored < 3 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        cancellation.cancel();
+        replicating.await?;
+
+        assert_eq!(count(&pool, "standby_region").await?, 2);
+        let stats = mirror.stats();
+        assert_eq!(stats.dropped, 0);
+        assert!(stats.lag.is_some());
+
+        // Once the replicator stopped, messages are dropped
+        mirror.offer(&[TestMessage::default().to_raw()?]);
+        assert_eq!(mirror.stats().dropped, 1);
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_does_not_mirror_rolled_back_publishes(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        run_migrations(&pool, "primary_region").await?;
+        run_migrations(&pool, "standby_region").await?;
+        let (mirror, replicator) = PublishMirror::new(MirrorConfig::default());
+        let primary = Queries::new("primary_region")?;
+        let standby = Queries::new("standby_region")?;
+
+        let mut tx = pool.begin().await?;
+        primary
+            .publish_many_messages(&mut tx, &[TestMessage::default().to_raw()?])
+            .await?;
+        tx.rollback().await?;
+        assert_eq!(mirror.buffered(), 0);
+
+        // Committed, only the messages of the committed transaction are mirrored
+        let mut tx = pool.begin().await?;
+        let committed = primary
+            .publish_message(&mut tx, TestMessage::default().to_raw()?)
+            .await?;
+        mirror.commit(tx, std::slice::from_ref(&committed)).await?;
+        assert_eq!(mirror.buffered(), 1);
+
+        let cancellation = CancellationToken::new();
+        let replicating = tokio::spawn({
+            let pool = pool.clone();
+            let cancellation = cancellation.clone();
+            async move { replicator.run(&pool, &standby, cancellation).await }
+        });
+        while mirror.stats().mirrored < 1 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        cancellation.cancel();
+        replicating.await?;
+
+        let mirrored: Vec<uuid::Uuid> =
+            sqlx::query_scalar("SELECT id FROM standby_region.messages_unattempted")
+                .fetch_all(&pool)
+                .await?;
+        assert_eq!(mirrored, vec![committed.id]);
+
+        Ok(())
+    }
+}
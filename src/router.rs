@@ -0,0 +1,173 @@
+use crate::migrator::PgIdentifierParsingError;
+use crate::models::RawMessage;
+use crate::queries::{ErrorClass, PublishError, Queries};
+use sqlx::PgTransaction;
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+#[derive(Debug, thiserror::Error)]
+pub enum RouterError {
+    #[error("DatabaseError: {0}")]
+    Database(#[from] sqlx::Error),
+    #[error("PublishError: {0}")]
+    Publish(#[from] PublishError),
+    #[error("InvalidSchemaError: {0}")]
+    InvalidSchema(#[from] PgIdentifierParsingError),
+    #[error("UnroutableError: no schema for message {0}")]
+    Unroutable(Uuid),
+}
+
+impl RouterError {
+    pub fn class(&self) -> ErrorClass {
+        match self {
+            Self::Database(error) => ErrorClass::of(error),
+            Self::Publish(error) => error.class(),
+            Self::InvalidSchema(_) | Self::Unroutable(_) => ErrorClass::Permanent,
+        }
+    }
+}
+
+/// Publishes messages into the queues of the schemas chosen by a routing function, such as per tenant schemas.
+///
+/// Messages are batched per schema, with one insert and one notification per schema, all within the transaction
+/// passed to [`publish_many`](Self::publish_many).
+pub struct Router<F>
+where
+    F: Fn(&RawMessage) -> Option<String>,
+{
+    route: F,
+    schema_channels: bool,
+}
+
+impl<F> Router<F>
+where
+    F: Fn(&RawMessage) -> Option<String>,
+{
+    /// Creates a router, `route` returns the schema of a message or None if it can't be routed
+    pub fn new(route: F) -> Self {
+        Self {
+            route,
+            schema_channels: false,
+        }
+    }
+
+    /// Notifies on the channel of each schema rather than the shared channel, see [`Queries::with_schema_channel`]
+    pub fn with_schema_channels(mut self) -> Self {
+        self.schema_channels = true;
+        self
+    }
+
+    pub async fn publish(
+        &self,
+        tx: &mut PgTransaction<'_>,
+        message: RawMessage,
+    ) -> Result<RawMessage, RouterError> {
+        let mut published = self.publish_many(tx, &[message]).await?;
+        Ok(published.remove(0))
+    }
+
+    /// Publishes `messages`, returning them in the given order.
+    ///
+    /// Fails without publishing anything if a message can't be routed or is routed to an invalid schema.
+    pub async fn publish_many(
+        &self,
+        tx: &mut PgTransaction<'_>,
+        messages: &[RawMessage],
+    ) -> Result<Vec<RawMessage>, RouterError> {
+        let mut batches: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+        for (index, message) in messages.iter().enumerate() {
+            let schema = (self.route)(message).ok_or(RouterError::Unroutable(message.id))?;
+            batches.entry(schema).or_default().push(index);
+        }
+
+        let batches = batches
+            .into_iter()
+            .map(|(schema, indices)| Ok((self.queries(&schema)?, indices)))
+            .collect::<Result<Vec<_>, RouterError>>()?;
+
+        let mut published: Vec<Option<RawMessage>> = vec![None; messages.len()];
+        for (queries, indices) in batches {
+            let batch: Vec<RawMessage> = indices.iter().map(|&i| messages[i].clone()).collect();
+            let batch = queries.publish_many_messages(tx, &batch).await?;
+
+            for (index, message) in indices.into_iter().zip(batch) {
+                published[index] = Some(message);
+            }
+        }
+
+        Ok(published.into_iter().flatten().collect())
+    }
+
+    fn queries(&self, schema: &str) -> Result<Queries, PgIdentifierParsingError> {
+        let queries = Queries::new(schema)?;
+        Ok(if self.schema_channels {
+            queries.with_schema_channel()
+        } else {
+            queries
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migrator::run_migrations;
+    use crate::testing_tools::TestMessage;
+    use serde_json::json;
+
+    fn by_tenant(message: &RawMessage) -> Option<String> {
+        let tenant = message.payload.get("tenant")?.as_str()?;
+        Some(format!("tenant_{tenant}"))
+    }
+
+    fn for_tenant(tenant: &str) -> anyhow::Result<RawMessage> {
+        Ok(RawMessage {
+            payload: json!({ "tenant": tenant }),
+            ..TestMessage::default().to_raw()?
+        })
+    }
+
+    async fn count(pool: &sqlx::PgPool, schema: &str) -> anyhow::Result<i64> {
+        Ok(sqlx::query_scalar(&format!(
+            "SELECT COUNT(*) FROM {schema}.messages_unattempted"
+        ))
+        .fetch_one(pool)
+        .await?)
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_publishes_into_the_routed_schemas(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        run_migrations(&pool, "tenant_a").await?;
+        run_migrations(&pool, "tenant_b").await?;
+        let router = Router::new(by_tenant);
+
+        let messages = vec![for_tenant("a")?, for_tenant("b")?, for_tenant("a")?];
+        let mut tx = pool.begin().await?;
+        let published = router.publish_many(&mut tx, &messages).await?;
+        tx.commit().await?;
+
+        let ids: Vec<Uuid> = published.iter().map(|m| m.id).collect();
+        assert_eq!(ids, messages.iter().map(|m| m.id).collect::<Vec<_>>());
+        assert_eq!(count(&pool, "tenant_a").await?, 2);
+        assert_eq!(count(&pool, "tenant_b").await?, 1);
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_rejects_unroutable_messages(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        run_migrations(&pool, "tenant_a").await?;
+        let router = Router::new(by_tenant);
+
+        let unroutable = TestMessage::default().to_raw()?;
+        let messages = vec![for_tenant("a")?, unroutable.clone()];
+        let mut tx = pool.begin().await?;
+        let result = router.publish_many(&mut tx, &messages).await;
+        tx.commit().await?;
+
+        assert!(matches!(result, Err(RouterError::Unroutable(id)) if id == unroutable.id));
+        assert_eq!(count(&pool, "tenant_a").await?, 0);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,272 @@
+//! JSON Schemas of message payloads, exported so that producers and consumers can validate their contracts
+
+use crate::models::Message;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// The JSON Schema of a message type, with the `NAME` and `HASH` it is published under
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RegisteredSchema {
+    pub name: String,
+    pub hash: i32,
+    pub schema: Value,
+}
+
+/// A difference between a producer's and a consumer's schema that may break the consumer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Incompatibility {
+    /// The consumer handles a message the producer doesn't publish
+    MissingMessage { name: String },
+    /// The message is published under a different hash than the consumer expects
+    HashMismatch {
+        name: String,
+        producer: i32,
+        consumer: i32,
+    },
+    /// The consumer requires a field the producer may omit
+    MissingRequired { name: String, path: String },
+    /// A field has a different type in the producer's schema than in the consumer's
+    TypeMismatch {
+        name: String,
+        path: String,
+        producer: Value,
+        consumer: Value,
+    },
+}
+
+/// The schemas of registered message types, keyed by message name.
+///
+/// Serializes to a JSON object of [`RegisteredSchema`]s, to be exported by producers and checked by consumers with
+/// [`check_compatibility`](Self::check_compatibility).
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct SchemaRegistry {
+    schemas: BTreeMap<String, RegisteredSchema>,
+}
+
+impl SchemaRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers the schema of `M`, replacing any schema registered under the same name
+    pub fn register<M: Message + JsonSchema>(&mut self) -> &mut Self {
+        self.schemas.insert(
+            M::NAME.to_string(),
+            RegisteredSchema {
+                name: M::NAME.to_string(),
+                hash: M::HASH,
+                schema: schemars::schema_for!(M).to_value(),
+            },
+        );
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<&RegisteredSchema> {
+        self.schemas.get(name)
+    }
+
+    pub fn get_by_hash(&self, hash: i32) -> Option<&RegisteredSchema> {
+        self.schemas.values().find(|schema| schema.hash == hash)
+    }
+
+    pub fn schemas(&self) -> impl Iterator<Item = &RegisteredSchema> {
+        self.schemas.values()
+    }
+
+    /// Checks the schemas of `consumer` against this registry of a producer.
+    ///
+    /// This is a structural check of the fields a consumer relies on: every field it requires must be required by
+    /// the producer, with the same type, recursing into nested objects. It is not a full comparison of JSON Schemas,
+    /// constraints such as formats or ranges are not compared.
+    pub fn check_compatibility(&self, consumer: &SchemaRegistry) -> Vec<Incompatibility> {
+        let mut incompatibilities = Vec::new();
+
+        for expected in consumer.schemas() {
+            let Some(published) = self.get(&expected.name) else {
+                incompatibilities.push(Incompatibility::MissingMessage {
+                    name: expected.name.clone(),
+                });
+                continue;
+            };
+
+            if published.hash != expected.hash {
+                incompatibilities.push(Incompatibility::HashMismatch {
+                    name: expected.name.clone(),
+                    producer: published.hash,
+                    consumer: expected.hash,
+                });
+            }
+
+            compare(
+                &expected.name,
+                "",
+                &published.schema,
+                &expected.schema,
+                &mut incompatibilities,
+            );
+        }
+
+        incompatibilities
+    }
+}
+
+fn compare(
+    name: &str,
+    path: &str,
+    producer: &Value,
+    consumer: &Value,
+    incompatibilities: &mut Vec<Incompatibility>,
+) {
+    if let Some(expected) = consumer.get("type")
+        && producer.get("type") != Some(expected)
+    {
+        incompatibilities.push(Incompatibility::TypeMismatch {
+            name: name.to_string(),
+            path: path.to_string(),
+            producer: producer.get("type").cloned().unwrap_or(Value::Null),
+            consumer: expected.clone(),
+        });
+        return;
+    }
+
+    let required = |schema: &Value| -> Vec<String> {
+        schema
+            .get("required")
+            .and_then(Value::as_array)
+            .map(|fields| {
+                fields
+                    .iter()
+                    .filter_map(|field| field.as_str().map(str::to_string))
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+
+    let published = required(producer);
+    for field in required(consumer) {
+        if !published.contains(&field) {
+            incompatibilities.push(Incompatibility::MissingRequired {
+                name: name.to_string(),
+                path: format!("{path}/{field}"),
+            });
+        }
+    }
+
+    let Some(properties) = consumer.get("properties").and_then(Value::as_object) else {
+        return;
+    };
+    for (field, expected) in properties {
+        if let Some(published) = producer.get("properties").and_then(|p| p.get(field)) {
+            compare(
+                name,
+                &format!("{path}/{field}"),
+                published,
+                expected,
+                incompatibilities,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    mod producer {
+        use super::*;
+
+        #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+        pub struct Order {
+            pub id: String,
+            pub amount: i64,
+            pub note: Option<String>,
+        }
+
+        impl Message for Order {
+            const NAME: &str = "Order";
+        }
+    }
+
+    mod consumer {
+        use super::*;
+
+        #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+        pub struct Order {
+            pub id: String,
+            pub amount: String,
+            pub note: String,
+        }
+
+        impl Message for Order {
+            const NAME: &str = "Order";
+        }
+
+        #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+        pub struct Refund {
+            pub id: String,
+        }
+
+        impl Message for Refund {
+            const NAME: &str = "Refund";
+        }
+    }
+
+    #[test]
+    fn it_exports_schemas_keyed_by_name() -> anyhow::Result<()> {
+        let mut registry = SchemaRegistry::new();
+        registry.register::<producer::Order>();
+
+        let exported = serde_json::to_value(&registry)?;
+        assert_eq!(exported["Order"]["hash"], producer::Order::HASH);
+        assert_eq!(
+            exported["Order"]["schema"]["properties"]["amount"]["type"],
+            "integer"
+        );
+
+        let imported: SchemaRegistry = serde_json::from_value(exported)?;
+        assert_eq!(imported, registry);
+        assert_eq!(
+            imported
+                .get_by_hash(producer::Order::HASH)
+                .map(|s| s.name.as_str()),
+            Some("Order")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_reports_incompatible_consumers() {
+        let mut published = SchemaRegistry::new();
+        published.register::<producer::Order>();
+
+        let mut compatible = SchemaRegistry::new();
+        compatible.register::<producer::Order>();
+        assert!(published.check_compatibility(&compatible).is_empty());
+
+        let mut expected = SchemaRegistry::new();
+        expected
+            .register::<consumer::Order>()
+            .register::<consumer::Refund>();
+
+        let incompatibilities = published.check_compatibility(&expected);
+        assert!(
+            incompatibilities.contains(&Incompatibility::MissingMessage {
+                name: "Refund".to_string()
+            })
+        );
+        assert!(
+            incompatibilities.contains(&Incompatibility::MissingRequired {
+                name: "Order".to_string(),
+                path: "/note".to_string()
+            })
+        );
+        assert!(incompatibilities.iter().any(|incompatibility| matches!(
+            incompatibility,
+            Incompatibility::TypeMismatch { path, .. } if path == "/amount"
+        )));
+    }
+}
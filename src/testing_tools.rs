@@ -1,10 +1,12 @@
 use crate::{
+    migrator::{PgIdentifier, PgIdentifierParsingError},
     models::{Message, RawMessage},
-    queries::set_schema_for_transaction,
+    queries::{Queries, get_next_unattempted, publish_message, set_schema_for_transaction},
 };
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use sqlx::{PgExecutor, PgTransaction};
+use sqlx::{PgExecutor, PgPool, PgTransaction};
+use std::time::Duration;
 use uuid::Uuid;
 
 #[derive(Debug, PartialEq, Eq)]
@@ -74,14 +76,14 @@ impl RawState {
                 false, // is_succeeded
                 false, // is_dead
             ) => State::Missing,
-            // FAILED: attempted and failed with errors without any lease
+            // FAILED: attempted and failed, with errors unless deferred, without any lease
             (
                 false, // is_unattempted
                 true,  // is_attempted
                 false, // has_any_lease
                 false, // has_active_lease
                 true,  // has_failed_attempts
-                true,  // has_errors
+                _,     // has_errors
                 false, // is_succeeded
                 false, // is_dead
             ) => State::Failed,
@@ -281,7 +283,10 @@ pub async fn get_all_messages<'c, E: PgExecutor<'c>>(
             name "name!",
             hash "hash!",
             payload "payload!",
-            (SELECT COUNT(*)::INTEGER FROM attempts_failed af WHERE af.message_id = id) "attempted!"
+            (SELECT COUNT(*)::INTEGER FROM attempts_failed af WHERE af.message_id = id) "attempted!",
+            NULL::BIGINT "fencing_token",
+            seq "seq?",
+            (SELECT qs.max_attempts FROM queue_settings qs WHERE qs.name = messages_unattempted.name) "max_attempts?"
         FROM messages_unattempted
         UNION ALL
         SELECT
@@ -289,7 +294,10 @@ pub async fn get_all_messages<'c, E: PgExecutor<'c>>(
             name "name!",
             hash "hash!",
             payload "payload!",
-            (SELECT COUNT(*)::INTEGER FROM attempts_failed af WHERE af.message_id = id) "attempted!"
+            (SELECT COUNT(*)::INTEGER FROM attempts_failed af WHERE af.message_id = id) "attempted!",
+            NULL::BIGINT "fencing_token",
+            seq "seq?",
+            (SELECT qs.max_attempts FROM queue_settings qs WHERE qs.name = messages_attempted.name) "max_attempts?"
         FROM messages_attempted
         "#
     )
@@ -299,7 +307,7 @@ pub async fn get_all_messages<'c, E: PgExecutor<'c>>(
     Ok(messages)
 }
 
-#[derive(Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct TestMessage {
     pub message: String,
     pub value: i32,
@@ -311,14 +319,17 @@ impl TestMessage {
     }
 
     pub fn to_raw(&self) -> anyhow::Result<RawMessage> {
-        let payload = serde_json::to_value(&self)?;
+        let payload = serde_json::to_value(self)?;
 
         Ok(RawMessage {
             id: Uuid::now_v7(),
             name: TestMessage::NAME.to_string(),
             hash: TestMessage::HASH,
-            payload: payload,
+            payload,
             attempted: 0,
+            fencing_token: None,
+            seq: None,
+            max_attempts: None,
         })
     }
 }
@@ -336,15 +347,58 @@ impl Default for TestMessage {
     }
 }
 
+/// Publishes `message` and claims the next unattempted message for `host_id`, leased for `hold_for`
+pub async fn publish_and_claim(
+    pool: &PgPool,
+    message: &RawMessage,
+    host_id: Uuid,
+    hold_for: Duration,
+) -> anyhow::Result<RawMessage> {
+    publish_message(pool, message).await?;
+    get_next_unattempted(pool, Utc::now(), host_id, hold_for)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("Expected a message to be claimed"))
+}
+
+/// Publishes a default [`TestMessage`] and claims it for `host_id`, leased for a minute
+pub async fn claim(pool: &PgPool, host_id: Uuid) -> anyhow::Result<RawMessage> {
+    publish_and_claim(
+        pool,
+        &TestMessage::default().to_raw()?,
+        host_id,
+        Duration::from_mins(1),
+    )
+    .await
+}
+
+/// Publishes a default [`TestMessage`] and claims it for `host_id` through `queries`, so that its schema and options
+/// apply, leased for a minute
+pub async fn claim_with<S>(
+    pool: &PgPool,
+    queries: &Queries<S>,
+    host_id: Uuid,
+) -> anyhow::Result<RawMessage> {
+    let mut tx = pool.begin().await?;
+    queries
+        .publish_message(&mut tx, TestMessage::default().to_raw()?)
+        .await?;
+    let message = queries
+        .get_next_unattempted(&mut tx, Utc::now(), host_id, Duration::from_mins(1))
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("Expected a message to be claimed"))?;
+    tx.commit().await?;
+    Ok(message)
+}
+
 pub struct TestQueries {
-    schema: String,
+    schema: PgIdentifier,
 }
 
 impl TestQueries {
-    pub fn new(schema: &str) -> Self {
-        Self {
-            schema: schema.to_string(),
-        }
+    pub fn new(schema: &str) -> Result<Self, PgIdentifierParsingError> {
+        Ok(Self {
+            schema: PgIdentifier::parse(schema)?,
+        })
     }
 
     pub async fn is_pending(
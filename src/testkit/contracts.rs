@@ -0,0 +1,211 @@
+use super::TestkitError;
+use crate::models::{Message, RawMessage};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// A published message as recorded by a producer
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ContractFixture {
+    pub name: String,
+    pub hash: i32,
+    pub payload: serde_json::Value,
+}
+
+/// Messages a producer publishes, shared with its consumers to catch breaking payload changes in CI.
+///
+/// The producer records an example of each message it publishes and writes the fixtures to a file, e.g. in a
+/// test, and commits or publishes the file. Consumers load it and check that their handlers decode every message
+/// they handle with a [`ContractVerifier`].
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ContractFixtures {
+    fixtures: Vec<ContractFixture>,
+}
+
+impl ContractFixtures {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records an example of a published message
+    pub fn record<M: Message>(&mut self, message: &M) -> Result<(), serde_json::Error> {
+        self.record_raw(&RawMessage::from_message(message)?);
+        Ok(())
+    }
+
+    /// Records an example of a published raw message, e.g. as returned by a publish
+    pub fn record_raw(&mut self, message: &RawMessage) {
+        self.fixtures.push(ContractFixture {
+            name: message.name.clone(),
+            hash: message.hash,
+            payload: message.payload.clone(),
+        });
+    }
+
+    pub fn fixtures(&self) -> &[ContractFixture] {
+        &self.fixtures
+    }
+
+    /// Writes the fixtures as JSON, ordered by name so that the file diffs well
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<(), TestkitError> {
+        let mut sorted = self.clone();
+        sorted.fixtures.sort_by(|a, b| a.name.cmp(&b.name));
+        std::fs::write(path, serde_json::to_vec_pretty(&sorted)?)?;
+        Ok(())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, TestkitError> {
+        Ok(serde_json::from_slice(&std::fs::read(path)?)?)
+    }
+
+    /// Returns a verifier of the handlers of a consumer against these fixtures
+    pub fn verifier(&self) -> ContractVerifier<'_> {
+        ContractVerifier {
+            fixtures: self,
+            decoders: BTreeMap::new(),
+        }
+    }
+}
+
+/// A fixture a consumer is not compatible with
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ContractViolation {
+    #[error("{name}: the producer hashes the name as {produced}, the consumer as {consumed}")]
+    HashMismatch {
+        name: String,
+        produced: i32,
+        consumed: i32,
+    },
+    #[error("{name}: the payload can't be decoded: {error}")]
+    Undecodable { name: String, error: String },
+}
+
+type Decoder = Box<dyn Fn(&serde_json::Value) -> Result<(), serde_json::Error>>;
+
+/// Checks that the handlers of a consumer decode the messages recorded by a producer
+pub struct ContractVerifier<'a> {
+    fixtures: &'a ContractFixtures,
+    decoders: BTreeMap<&'static str, (i32, Decoder)>,
+}
+
+impl ContractVerifier<'_> {
+    /// Registers a handled message type. Fixtures of names without a registered type are not checked, as consumers
+    /// typically handle some of the messages of a producer.
+    pub fn handles<M: Message>(mut self) -> Self {
+        let decoder: Decoder =
+            Box::new(|payload| serde_json::from_value::<M>(payload.clone()).map(drop));
+        self.decoders.insert(M::NAME, (M::HASH, decoder));
+        self
+    }
+
+    /// Returns the fixtures of handled message types that the consumer is not compatible with
+    pub fn violations(&self) -> Vec<ContractViolation> {
+        self.fixtures
+            .fixtures
+            .iter()
+            .filter_map(|fixture| {
+                let (hash, decode) = self.decoders.get(fixture.name.as_str())?;
+                if *hash != fixture.hash {
+                    return Some(ContractViolation::HashMismatch {
+                        name: fixture.name.clone(),
+                        produced: fixture.hash,
+                        consumed: *hash,
+                    });
+                }
+                decode(&fixture.payload)
+                    .err()
+                    .map(|error| ContractViolation::Undecodable {
+                        name: fixture.name.clone(),
+                        error: error.to_string(),
+                    })
+            })
+            .collect()
+    }
+
+    /// Panics with the violations unless the consumer is compatible, for use in tests
+    pub fn assert_compatible(&self) {
+        let violations = self.violations();
+        assert!(
+            violations.is_empty(),
+            "Incompatible with the producer:\n{}",
+            violations
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join("\n")
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    mod producer {
+        use super::*;
+
+        #[derive(Debug, Clone, Serialize, Deserialize)]
+        pub struct OrderPlaced {
+            pub order_id: u32,
+            pub total: String,
+        }
+
+        impl Message for OrderPlaced {
+            const NAME: &str = "OrderPlaced";
+        }
+    }
+
+    mod consumer {
+        use super::*;
+
+        #[derive(Debug, Clone, Serialize, Deserialize)]
+        pub struct OrderPlaced {
+            pub order_id: u32,
+            pub total: f64,
+        }
+
+        impl Message for OrderPlaced {
+            const NAME: &str = "OrderPlaced";
+        }
+
+        #[derive(Debug, Clone, Serialize, Deserialize)]
+        pub struct OrderPlacedV1 {
+            pub order_id: u32,
+        }
+
+        impl Message for OrderPlacedV1 {
+            const NAME: &str = "OrderPlaced";
+        }
+    }
+
+    #[test]
+    fn it_verifies_consumers_against_recorded_fixtures() -> anyhow::Result<()> {
+        let mut recorded = ContractFixtures::new();
+        recorded.record(&producer::OrderPlaced {
+            order_id: 1,
+            total: "9.99".to_string(),
+        })?;
+
+        let path = std::env::temp_dir().join(format!("contracts-{}.json", uuid::Uuid::now_v7()));
+        recorded.write(&path)?;
+        let fixtures = ContractFixtures::load(&path)?;
+        std::fs::remove_file(&path)?;
+        assert_eq!(fixtures, recorded);
+
+        fixtures
+            .verifier()
+            .handles::<consumer::OrderPlacedV1>()
+            .assert_compatible();
+
+        let violations = fixtures
+            .verifier()
+            .handles::<consumer::OrderPlaced>()
+            .violations();
+        assert!(matches!(
+            violations.as_slice(),
+            [ContractViolation::Undecodable { name, .. }] if name == "OrderPlaced"
+        ));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,201 @@
+mod contracts;
+
+pub use contracts::{ContractFixture, ContractFixtures, ContractVerifier, ContractViolation};
+
+use crate::migrator::{MigratorError, PgIdentifier, migrator, run_migrations};
+use const_fnv1a_hash::fnv1a_hash_64;
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+use sqlx::{ConnectOptions, Connection, PgConnection, PgPool};
+use uuid::Uuid;
+
+// Arbitrary key of the advisory lock serializing template creation across test processes
+const TEMPLATE_LOCK_KEY: i64 = 0x66_78_6d_71_74_6d_70_6c;
+
+#[derive(Debug, thiserror::Error)]
+pub enum TestkitError {
+    #[error("DatabaseError: {0}")]
+    Database(#[from] sqlx::Error),
+    #[error("MigratorError: {0}")]
+    Migrator(#[from] MigratorError),
+    #[error("IoError: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("SerializationError: {0}")]
+    Serialization(#[from] serde_json::Error),
+}
+
+/// A database with the migrations of this crate applied, used as a template for test databases.
+///
+/// Migrating a fresh database for every test is slow. Instead the template is migrated once and each
+/// test gets a copy made with `CREATE DATABASE ... TEMPLATE`, which only copies files.
+///
+/// The template is named after a hash of the embedded migrations and the schema, so it is reused across
+/// test runs until the migrations change. Templates of outdated migrations are not removed.
+#[derive(Debug)]
+pub struct TemplateDatabase {
+    options: PgConnectOptions,
+    name: String,
+}
+
+impl TemplateDatabase {
+    /// Creates the template database unless it already exists, migrating `schema` within it.
+    ///
+    /// `options` must connect as a role that may create databases. Concurrent calls, also from other
+    /// processes, are serialized with an advisory lock.
+    pub async fn create(options: PgConnectOptions, schema: &str) -> Result<Self, TestkitError> {
+        let name = template_name(schema);
+        let mut admin = options.connect().await?;
+
+        sqlx::query("SELECT pg_advisory_lock($1)")
+            .bind(TEMPLATE_LOCK_KEY)
+            .execute(&mut admin)
+            .await?;
+
+        let result = create_template(&mut admin, &options, &name, schema).await;
+
+        sqlx::query("SELECT pg_advisory_unlock($1)")
+            .bind(TEMPLATE_LOCK_KEY)
+            .execute(&mut admin)
+            .await?;
+        admin.close().await?;
+
+        result?;
+
+        Ok(Self { options, name })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Creates a new uniquely named database as a copy of the template
+    pub async fn clone_database(&self) -> Result<TestDatabase, TestkitError> {
+        let name = format!("fx_mq_test_{}", Uuid::now_v7().simple());
+        let ident = PgIdentifier::parse(&name).map_err(MigratorError::from)?;
+        let template = PgIdentifier::parse(&self.name).map_err(MigratorError::from)?;
+
+        let mut admin = self.options.connect().await?;
+        sqlx::query(&format!("CREATE DATABASE {ident} TEMPLATE {template}"))
+            .execute(&mut admin)
+            .await?;
+        admin.close().await?;
+
+        let pool = PgPoolOptions::new()
+            .connect_with(self.options.clone().database(&name))
+            .await?;
+
+        Ok(TestDatabase {
+            options: self.options.clone(),
+            name,
+            pool,
+        })
+    }
+}
+
+/// A database cloned from a [`TemplateDatabase`]
+#[derive(Debug)]
+pub struct TestDatabase {
+    options: PgConnectOptions,
+    name: String,
+    pool: PgPool,
+}
+
+impl TestDatabase {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn pool(&self) -> &PgPool {
+        &self.pool
+    }
+
+    /// Closes the pool and drops the database
+    pub async fn drop_database(self) -> Result<(), TestkitError> {
+        self.pool.close().await;
+
+        let ident = PgIdentifier::parse(&self.name).map_err(MigratorError::from)?;
+        let mut admin = self.options.connect().await?;
+        // Backends of the closed pool may not have exited yet
+        sqlx::query(&format!("DROP DATABASE IF EXISTS {ident} WITH (FORCE)"))
+            .execute(&mut admin)
+            .await?;
+        admin.close().await?;
+
+        Ok(())
+    }
+}
+
+fn template_name(schema: &str) -> String {
+    let mut bytes = schema.as_bytes().to_vec();
+    for migration in migrator().iter() {
+        bytes.extend_from_slice(&migration.version.to_be_bytes());
+        bytes.extend_from_slice(&migration.checksum);
+    }
+
+    format!("fx_mq_template_{:016x}", fnv1a_hash_64(&bytes, None))
+}
+
+async fn create_template(
+    admin: &mut PgConnection,
+    options: &PgConnectOptions,
+    name: &str,
+    schema: &str,
+) -> Result<(), TestkitError> {
+    let exists: bool =
+        sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM pg_database WHERE datname = $1)")
+            .bind(name)
+            .fetch_one(&mut *admin)
+            .await?;
+
+    if exists {
+        return Ok(());
+    }
+
+    let ident = PgIdentifier::parse(name).map_err(MigratorError::from)?;
+    sqlx::query(&format!("CREATE DATABASE {ident}"))
+        .execute(&mut *admin)
+        .await?;
+
+    // The template may not have open connections while it is being copied
+    let migrated = async {
+        let mut conn = options.clone().database(name).connect().await?;
+        run_migrations(&mut conn, schema).await?;
+        conn.close().await?;
+        Ok::<_, TestkitError>(())
+    }
+    .await;
+
+    if migrated.is_err() {
+        sqlx::query(&format!("DROP DATABASE IF EXISTS {ident}"))
+            .execute(&mut *admin)
+            .await?;
+    }
+
+    migrated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[tokio::test]
+    async fn it_clones_migrated_databases() -> anyhow::Result<()> {
+        let options = PgConnectOptions::from_str(&std::env::var("DATABASE_URL")?)?;
+        let template = TemplateDatabase::create(options, "fx_mq").await?;
+
+        let a = template.clone_database().await?;
+        let b = template.clone_database().await?;
+        assert_ne!(a.name(), b.name());
+
+        for db in [&a, &b] {
+            sqlx::query("SELECT 1 FROM fx_mq.messages_unattempted")
+                .execute(db.pool())
+                .await?;
+        }
+
+        a.drop_database().await?;
+        b.drop_database().await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,238 @@
+//! Export of what a listener registers, for documentation kept in sync with the code
+
+use crate::backoff::simulate_retry_schedule;
+use crate::listener::{BlockingHandlers, HandlerNames, InlineRegistry, QueueSettingsCache};
+use crate::queries::Queries;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+/// How the handler of a message name is run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Execution {
+    /// On the runtime of the listener
+    Async,
+    /// On the blocking thread pool, see [`BlockingHandlers`]
+    Blocking,
+    /// In the transaction of the publisher, see [`InlineRegistry`]
+    Inline,
+}
+
+/// Where the retry policy of a message name comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PolicySource {
+    /// Stored in the `queue_settings` table
+    Stored,
+    /// The defaults of the worker
+    Default,
+}
+
+/// The retry policy of a message name, with durations in milliseconds
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RetryPolicy {
+    pub source: PolicySource,
+    pub backoff_base: u32,
+    pub backoff_base_delay_ms: u64,
+    pub max_attempts: i32,
+    pub hold_for_ms: u64,
+    /// The delays before each retry, from the first failed attempt until the message is reported dead
+    pub retry_delays_ms: Vec<u64>,
+}
+
+/// A registered message name and how it is handled
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HandlerTopology {
+    pub name: String,
+    pub execution: Execution,
+    pub retry: RetryPolicy,
+}
+
+/// The queue of a listener and the message names it handles, serializable to a JSON document that teams can commit
+/// or render.
+///
+/// Built from the same registries the listener claims and handles with, so the document lists what the code
+/// registers rather than what someone remembered to write down.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Topology {
+    pub schema: String,
+    pub channel: String,
+    /// Registered message names in alphabetical order
+    pub handlers: Vec<HandlerTopology>,
+}
+
+impl Topology {
+    /// Describes the queue of `queries` and the names registered in `names`, with the retry policies of `settings`
+    pub fn new<S>(
+        queries: &Queries<S>,
+        names: &HandlerNames,
+        settings: &QueueSettingsCache,
+    ) -> Self {
+        let mut topology = Self {
+            schema: queries.schema().unquoted().to_string(),
+            channel: queries.channel().to_string(),
+            handlers: Vec::new(),
+        };
+        for name in names.filter().iter() {
+            topology.insert(name, Execution::Async, settings);
+        }
+        topology
+    }
+
+    /// Adds the names registered in `blocking`, marking them as run on the blocking pool
+    pub fn with_blocking(
+        mut self,
+        blocking: &BlockingHandlers,
+        settings: &QueueSettingsCache,
+    ) -> Self {
+        for name in blocking.names() {
+            self.insert(name, Execution::Blocking, settings);
+        }
+        self
+    }
+
+    /// Adds the names registered in `inline`, marking them as run inline if the registry runs handlers inline
+    pub fn with_inline<C>(
+        mut self,
+        inline: &InlineRegistry<C>,
+        settings: &QueueSettingsCache,
+    ) -> Self {
+        let execution = if inline.is_inline() {
+            Execution::Inline
+        } else {
+            Execution::Async
+        };
+        for name in inline.names() {
+            self.insert(name, execution, settings);
+        }
+        self
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::to_value(self).expect("a topology serializes to JSON")
+    }
+
+    /// Inserts or replaces the handler of `name`, keeping handlers sorted by name
+    fn insert(&mut self, name: &str, execution: Execution, settings: &QueueSettingsCache) {
+        let handler = HandlerTopology {
+            name: name.to_string(),
+            execution,
+            retry: retry_policy(name, settings),
+        };
+        match self
+            .handlers
+            .binary_search_by(|h| h.name.as_str().cmp(name))
+        {
+            Ok(index) => self.handlers[index] = handler,
+            Err(index) => self.handlers.insert(index, handler),
+        }
+    }
+}
+
+fn retry_policy(name: &str, settings: &QueueSettingsCache) -> RetryPolicy {
+    let queue_settings = settings.get(name);
+
+    // Relative to an arbitrary first attempt, only the delays between attempts are exported
+    let mut previous = DateTime::<Utc>::UNIX_EPOCH;
+    let schedule = simulate_retry_schedule(
+        &queue_settings.backoff(),
+        queue_settings.max_attempts,
+        previous,
+    );
+    let retry_delays_ms = schedule
+        .into_iter()
+        .map(|retry_at| {
+            let delay = (retry_at - previous).num_milliseconds().max(0) as u64;
+            previous = retry_at;
+            delay
+        })
+        .collect();
+
+    RetryPolicy {
+        source: if settings.is_stored(name) {
+            PolicySource::Stored
+        } else {
+            PolicySource::Default
+        },
+        backoff_base: queue_settings.backoff_base,
+        backoff_base_delay_ms: queue_settings.backoff_base_delay.as_millis() as u64,
+        max_attempts: queue_settings.max_attempts,
+        hold_for_ms: queue_settings.hold_for.as_millis() as u64,
+        retry_delays_ms,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::listener::{DefaultQueueSettings, HandlerResult};
+    use crate::models::{QueueSettings, RawMessage};
+    use chrono::Utc;
+    use std::time::Duration;
+
+    fn defaults() -> DefaultQueueSettings {
+        DefaultQueueSettings {
+            backoff_base: 2,
+            backoff_base_delay: Duration::from_secs(1),
+            max_attempts: 4,
+            hold_for: Duration::from_mins(1),
+        }
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn it_exports_registered_handlers(pool: sqlx::PgPool) -> anyhow::Result<()> {
+        let queries = Queries::new("public")?;
+        let stored = QueueSettings {
+            name: "resize".to_string(),
+            backoff_base: 3,
+            backoff_base_delay: Duration::from_secs(2),
+            max_attempts: 2,
+            hold_for: Duration::from_mins(5),
+        };
+        let mut tx = pool.begin().await?;
+        queries
+            .put_queue_settings(&mut tx, &stored, Utc::now())
+            .await?;
+        tx.commit().await?;
+
+        let settings = QueueSettingsCache::new(defaults());
+        settings.refresh(&pool, &queries).await?;
+
+        let names = HandlerNames::new();
+        names.register("welcome");
+        names.register("resize");
+        let blocking = BlockingHandlers::new(crate::listener::BlockingConfig {
+            hold_for: Duration::from_mins(1),
+            renew_every: Duration::from_secs(10),
+            max_concurrent: 1,
+            retry_panics_after: Duration::from_secs(1),
+        })
+        .register("resize", |_: &RawMessage| HandlerResult::Success);
+
+        let topology =
+            Topology::new(&queries, &names, &settings).with_blocking(&blocking, &settings);
+
+        assert_eq!(topology.schema, "public");
+        let exported: Vec<_> = topology
+            .handlers
+            .iter()
+            .map(|h| (h.name.as_str(), h.execution, h.retry.source))
+            .collect();
+        assert_eq!(
+            exported,
+            vec![
+                ("resize", Execution::Blocking, PolicySource::Stored),
+                ("welcome", Execution::Async, PolicySource::Default),
+            ]
+        );
+        assert_eq!(topology.handlers[0].retry.retry_delays_ms.len(), 1);
+        assert_eq!(topology.handlers[1].retry.retry_delays_ms.len(), 3);
+
+        let json = topology.to_json();
+        assert_eq!(json["handlers"][0]["execution"], "blocking");
+        assert_eq!(json["handlers"][1]["retry"]["source"], "default");
+        assert_eq!(json["handlers"][1]["retry"]["max_attempts"], 4);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,211 @@
+//! Asserts that the claim queries use their indexes against representative data volumes, so that a change to a
+//! claim predicate that can't use an index fails here rather than in production
+
+use chrono::{DateTime, Utc};
+use fx_mq_building_blocks::migrator::create_payload_index;
+use fx_mq_building_blocks::models::ClaimPredicate;
+use fx_mq_building_blocks::queries::{
+    Queries, get_next_missing, get_next_retryable, get_next_unattempted,
+};
+use sqlx::PgConnection;
+use std::time::Duration;
+use uuid::Uuid;
+
+const MESSAGES: i32 = 20_000;
+
+/// Fills the queue the way a long-running one looks: a backlog of unattempted messages, and mostly succeeded
+/// attempted messages with a few failed ones waiting for their retry and a few with expired leases.
+async fn populate(conn: &mut PgConnection, now: DateTime<Utc>) -> anyhow::Result<()> {
+    sqlx::query(
+        r#"
+        INSERT INTO messages_unattempted (id, name, hash, payload, published_at)
+        SELECT gen_random_uuid(), 'Unattempted', 0, '{}', $1 - make_interval(secs => i)
+        FROM generate_series(1, $2) i
+        "#,
+    )
+    .bind(now)
+    .bind(MESSAGES)
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO messages_attempted (id, name, hash, payload, published_at)
+        SELECT gen_random_uuid(), 'Attempted', 0, '{}', $1 - make_interval(days => 1, secs => i)
+        FROM generate_series(1, $2) i
+        "#,
+    )
+    .bind(now)
+    .bind(MESSAGES)
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO leases (message_id, acquired_at, acquired_by, expires_at)
+        SELECT id, published_at, gen_random_uuid(), published_at + INTERVAL '1 minute'
+        FROM messages_attempted
+        "#,
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    // All but every 100th message succeeded, of those every 1000th expired without a report and the rest failed,
+    // most of them waiting for their retry
+    sqlx::query(
+        r#"
+        WITH numbered AS (
+            SELECT id, published_at, row_number() OVER (ORDER BY published_at) n
+            FROM messages_attempted
+        ),
+        succeeded AS (
+            INSERT INTO attempts_succeeded (message_id, succeeded_at)
+            SELECT id, published_at + INTERVAL '1 second' FROM numbered WHERE n % 100 <> 0
+        )
+        INSERT INTO attempts_failed (id, message_id, failed_at, attempted, retry_earliest_at)
+        SELECT
+            gen_random_uuid(),
+            id,
+            published_at + INTERVAL '1 second',
+            1,
+            CASE WHEN n % 500 = 0 THEN $1 - INTERVAL '1 second' ELSE $1 + INTERVAL '1 hour' END
+        FROM numbered
+        WHERE n % 100 = 0 AND n % 1000 <> 0
+        "#,
+    )
+    .bind(now)
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query("ANALYZE").execute(&mut *conn).await?;
+    Ok(())
+}
+
+/// The number of scans of `index` so far, including those of the statements run on `conn` until now
+async fn index_scans(conn: &mut PgConnection, index: &str) -> anyhow::Result<i64> {
+    // Statistics are flushed at the end of a transaction. Servers from PG15 may be forced to flush them immediately,
+    // older ones send them to the statistics collector, which takes a moment to receive them
+    let server_version_num: i32 =
+        sqlx::query_scalar("SELECT current_setting('server_version_num')::INTEGER")
+            .fetch_one(&mut *conn)
+            .await?;
+    if server_version_num >= 150000 {
+        sqlx::query("SELECT pg_stat_force_next_flush()")
+            .execute(&mut *conn)
+            .await?;
+    }
+    Ok(sqlx::query_scalar(
+        "SELECT COALESCE(idx_scan, 0) FROM pg_stat_user_indexes WHERE indexrelname = $1",
+    )
+    .bind(index)
+    .fetch_one(&mut *conn)
+    .await?)
+}
+
+/// Polls the number of scans of `index` until it exceeds `before`, returning the last count read once it does or
+/// after 5 seconds
+async fn index_scans_after(
+    conn: &mut PgConnection,
+    index: &str,
+    before: i64,
+) -> anyhow::Result<i64> {
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+    loop {
+        let scans = index_scans(conn, index).await?;
+        if scans > before || tokio::time::Instant::now() >= deadline {
+            return Ok(scans);
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn unattempted_claims_use_the_claim_index(pool: sqlx::PgPool) -> anyhow::Result<()> {
+    let now = Utc::now();
+    let mut conn = pool.acquire().await?;
+    populate(&mut conn, now).await?;
+
+    let before = index_scans(&mut conn, "idx_messages_unattempted_claim").await?;
+    get_next_unattempted(&mut *conn, now, Uuid::now_v7(), Duration::from_mins(1))
+        .await?
+        .expect("Expected a message");
+    let after = index_scans_after(&mut conn, "idx_messages_unattempted_claim", before).await?;
+
+    assert!(after > before);
+    Ok(())
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn retryable_claims_use_the_retry_index(pool: sqlx::PgPool) -> anyhow::Result<()> {
+    let now = Utc::now();
+    let mut conn = pool.acquire().await?;
+    populate(&mut conn, now).await?;
+
+    let before = index_scans(&mut conn, "idx_attempts_failed_retry_earliest_at").await?;
+    get_next_retryable(&mut *conn, now, Uuid::now_v7(), Duration::from_mins(1))
+        .await?
+        .expect("Expected a message");
+    let after =
+        index_scans_after(&mut conn, "idx_attempts_failed_retry_earliest_at", before).await?;
+
+    assert!(after > before);
+    Ok(())
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn missing_claims_use_the_expiry_index(pool: sqlx::PgPool) -> anyhow::Result<()> {
+    let now = Utc::now();
+    let mut conn = pool.acquire().await?;
+    populate(&mut conn, now).await?;
+
+    let before = index_scans(&mut conn, "idx_leases_expires_at").await?;
+    get_next_missing(&mut *conn, now, Uuid::now_v7(), Duration::from_mins(1))
+        .await?
+        .expect("Expected a message");
+    let after = index_scans_after(&mut conn, "idx_leases_expires_at", before).await?;
+
+    assert!(after > before);
+    Ok(())
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn predicate_claims_use_the_payload_index(pool: sqlx::PgPool) -> anyhow::Result<()> {
+    let now = Utc::now();
+    let mut conn = pool.acquire().await?;
+
+    // The few matching messages are the newest, behind a backlog of others
+    sqlx::query(
+        r#"
+        INSERT INTO messages_unattempted (id, name, hash, payload, published_at)
+        SELECT
+            gen_random_uuid(),
+            'Unattempted',
+            0,
+            jsonb_build_object('region', CASE WHEN i <= 10 THEN 'eu' ELSE 'us' END),
+            $1 - make_interval(secs => i)
+        FROM generate_series(1, $2) i
+        "#,
+    )
+    .bind(now)
+    .bind(MESSAGES)
+    .execute(&mut *conn)
+    .await?;
+    create_payload_index(&pool, "public", "region").await?;
+    sqlx::query("ANALYZE").execute(&mut *conn).await?;
+
+    let index = "idx_messages_unattempted_payload_region";
+    let queries = Queries::new("public")?
+        .with_claim_predicate(Some(ClaimPredicate::field_equals("region", "eu")));
+    let before = index_scans(&mut conn, index).await?;
+    let mut tx = pool.begin().await?;
+    let claimed = queries
+        .get_next_unattempted(&mut tx, now, Uuid::now_v7(), Duration::from_mins(1))
+        .await?
+        .expect("Expected a message");
+    tx.commit().await?;
+    let after = index_scans_after(&mut conn, index, before).await?;
+
+    assert_eq!(claimed.payload["region"], "eu");
+    assert!(after > before);
+    Ok(())
+}
@@ -0,0 +1,50 @@
+//! Runs the examples against a test database, keeping them compiling and correct
+
+// Both examples include `examples/common`
+#![allow(clippy::duplicate_mod)]
+
+#[allow(dead_code)]
+#[path = "../examples/producer.rs"]
+mod producer;
+#[allow(dead_code)]
+#[path = "../examples/worker.rs"]
+mod worker;
+
+use chrono::Utc;
+use fx_mq_building_blocks::testing_tools::TestQueries;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+#[sqlx::test(migrations = false)]
+async fn the_worker_handles_what_the_producer_publishes(pool: sqlx::PgPool) -> anyhow::Result<()> {
+    let names = ["ada", "flaky", ""].map(String::from);
+    let published = producer::run(&pool, "examples", &names).await?;
+
+    let shutdown = CancellationToken::new();
+    let worker = tokio::spawn(worker::run(pool.clone(), "examples", shutdown.clone()));
+
+    let queries = TestQueries::new("examples")?;
+    let (ada, flaky, nobody) = (published[0].id, published[1].id, published[2].id);
+
+    tokio::time::timeout(Duration::from_secs(10), async {
+        loop {
+            let mut tx = pool.begin().await?;
+            let now = Utc::now();
+            let done = queries.is_succeeded(&mut tx, ada, now).await?
+                && queries.is_succeeded(&mut tx, flaky, now).await?
+                && queries.is_dead(&mut tx, nobody, now).await?;
+            tx.rollback().await?;
+
+            if done {
+                return Ok::<_, anyhow::Error>(());
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await??;
+
+    shutdown.cancel();
+    worker.await??;
+
+    Ok(())
+}